@@ -10,7 +10,7 @@ use quote::ToTokens;
 use syn::spanned::Spanned;
 use syn::{Error, Ident, ItemUse};
 
-use super::ops::defer_tick::DEFER_TICK;
+use super::ops::next_iteration::NEXT_ITERATION;
 use super::ops::FloType;
 use super::{DfirGraph, GraphEdgeId, GraphLoopId, GraphNode, GraphNodeId, PortIndexValue};
 use crate::diagnostic::{Diagnostic, Level};
@@ -73,6 +73,9 @@ pub struct FlatGraphBuilder {
     /// If the flat graph is being loaded as a module, then two initial ModuleBoundary nodes are inserted into the graph. One
     /// for the input into the module and one for the output out of the module.
     module_boundary_nodes: Option<(GraphNodeId, GraphNodeId)>,
+
+    /// Loop context that [`Self::add_statement`] adds statements to.
+    current_loop: Option<GraphLoopId>,
 }
 
 impl FlatGraphBuilder {
@@ -106,9 +109,21 @@ impl FlatGraphBuilder {
         (self.flat_graph, self.uses, self.diagnostics)
     }
 
-    /// Add a single [`HfStatement`] line to this `HydroflowGraph` in the root context.
+    /// Add a single [`HfStatement`] line to this `HydroflowGraph` in the current loop context,
+    /// which is the root context unless changed with [`Self::set_current_loop`].
     pub fn add_statement(&mut self, stmt: HfStatement) {
-        self.add_statement_with_loop(stmt, None)
+        self.add_statement_with_loop(stmt, self.current_loop)
+    }
+
+    /// Creates a new `loop { ... }` context nested within `parent_loop` (or at the root level if
+    /// `None`), for programmatically building loops without `HfStatement::Loop`.
+    pub fn insert_loop(&mut self, parent_loop: Option<GraphLoopId>) -> GraphLoopId {
+        self.flat_graph.insert_loop(parent_loop)
+    }
+
+    /// Sets the loop context used by [`Self::add_statement`], returning the previous one.
+    pub fn set_current_loop(&mut self, current_loop: Option<GraphLoopId>) -> Option<GraphLoopId> {
+        std::mem::replace(&mut self.current_loop, current_loop)
     }
 
     /// Add a single [`HfStatement`] line to this `HydroflowGraph` in the given loop context.
@@ -880,24 +895,24 @@ impl FlatGraphBuilder {
             }
         }
 
-        // Must be a DAG (excluding `next_tick()` operators).
+        // Must be a DAG (excluding `next_iteration()` operators).
         // TODO(mingwei): Nested loop blocks should count as a single node.
         for (loop_id, loop_nodes) in self.flat_graph.loops() {
-            // Filter out `defer_tick()` operators.
-            let filter_defer_tick = |&node_id: &GraphNodeId| {
+            // Filter out `next_iteration()` operators.
+            let filter_next_iteration = |&node_id: &GraphNodeId| {
                 self.flat_graph
                     .node_op_inst(node_id)
-                    .map(|op_inst| DEFER_TICK.name != op_inst.op_constraints.name)
+                    .map(|op_inst| NEXT_ITERATION.name != op_inst.op_constraints.name)
                     .unwrap_or(true)
             };
 
             let topo_sort_result = graph_algorithms::topo_sort(
-                loop_nodes.iter().copied().filter(filter_defer_tick),
+                loop_nodes.iter().copied().filter(filter_next_iteration),
                 |dst| {
                     self.flat_graph
                         .node_predecessor_nodes(dst)
                         .filter(|&src| Some(loop_id) == self.flat_graph.node_loop(src))
-                        .filter(filter_defer_tick)
+                        .filter(filter_next_iteration)
                 },
             );
             if let Err(cycle) = topo_sort_result {
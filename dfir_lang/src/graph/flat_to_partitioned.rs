@@ -8,7 +8,9 @@ use syn::parse_quote;
 
 use super::hydroflow_graph::DfirGraph;
 use super::ops::{find_node_op_constraints, DelayType};
-use super::{graph_algorithms, Color, GraphEdgeId, GraphNode, GraphNodeId, GraphSubgraphId};
+use super::{
+    graph_algorithms, Color, GraphEdgeId, GraphLoopId, GraphNode, GraphNodeId, GraphSubgraphId,
+};
 use crate::diagnostic::{Diagnostic, Level};
 use crate::union_find::UnionFind;
 
@@ -290,6 +292,139 @@ fn can_connect_colorize(
     can_connect
 }
 
+/// Graph of subgraphs, used for stratification.
+// TODO: use DiMulGraph here?
+#[derive(Default)]
+struct SubgraphGraph {
+    preds: BTreeMap<GraphSubgraphId, Vec<GraphSubgraphId>>,
+    succs: BTreeMap<GraphSubgraphId, Vec<GraphSubgraphId>>,
+}
+impl SubgraphGraph {
+    fn insert_edge(&mut self, src: GraphSubgraphId, dst: GraphSubgraphId) {
+        self.preds.entry(dst).or_default().push(src);
+        self.succs.entry(src).or_default().push(dst);
+    }
+}
+
+/// A unit of stratum assignment within a loop context (or the root context): either a single
+/// subgraph, or an entire nested `loop { ... }` block.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum StratumUnit {
+    Subgraph(GraphSubgraphId),
+    Loop(GraphLoopId),
+}
+
+/// Assigns strata to the subgraphs within the loop context `loop_opt` (recursively), starting at
+/// `base_stratum`. Returns the last stratum used.
+///
+/// Each nested loop is assigned a contiguous range of strata, which starts after all of its
+/// predecessors and ends before any of its successors, so that the loop can be re-run for each
+/// iteration without re-running anything outside of it. Loops in `iteration_loops` get an extra
+/// stratum at their end, which is recorded in `loop_end_strata`.
+fn assign_strata(
+    partitioned_graph: &mut DfirGraph,
+    subgraph_graph: &SubgraphGraph,
+    subgraph_stratum_barriers: &BTreeSet<(GraphSubgraphId, GraphSubgraphId)>,
+    iteration_loops: &BTreeSet<GraphLoopId>,
+    loop_end_strata: &mut SecondaryMap<GraphLoopId, usize>,
+    loop_opt: Option<GraphLoopId>,
+    base_stratum: usize,
+) -> usize {
+    // Finds the unit within `loop_opt` containing the subgraph, if any.
+    let unit_of = |partitioned_graph: &DfirGraph, sg_id: GraphSubgraphId| {
+        let mut sg_loop = partitioned_graph.subgraph_loop(sg_id);
+        if sg_loop == loop_opt {
+            return Some(StratumUnit::Subgraph(sg_id));
+        }
+        while let Some(loop_id) = sg_loop {
+            let parent = partitioned_graph.loop_parent(loop_id);
+            if parent == loop_opt {
+                return Some(StratumUnit::Loop(loop_id));
+            }
+            sg_loop = parent;
+        }
+        None
+    };
+
+    // Edges between units, and whether each is a barrier. Edges into or out of a loop are
+    // always barriers.
+    let mut unit_preds: BTreeMap<StratumUnit, Vec<(StratumUnit, bool)>> = Default::default();
+    let mut unit_succs: BTreeMap<StratumUnit, Vec<StratumUnit>> = Default::default();
+    let units: BTreeSet<StratumUnit> = partitioned_graph
+        .subgraph_ids()
+        .filter_map(|sg_id| unit_of(partitioned_graph, sg_id))
+        .collect();
+    for (&src_sg, succs) in subgraph_graph.succs.iter() {
+        let Some(src_unit) = unit_of(partitioned_graph, src_sg) else {
+            continue;
+        };
+        for &dst_sg in succs {
+            let Some(dst_unit) = unit_of(partitioned_graph, dst_sg) else {
+                continue;
+            };
+            let is_barrier = subgraph_stratum_barriers.contains(&(src_sg, dst_sg))
+                || matches!(src_unit, StratumUnit::Loop(_))
+                || matches!(dst_unit, StratumUnit::Loop(_));
+            if src_unit != dst_unit || matches!(src_unit, StratumUnit::Subgraph(_)) {
+                unit_preds
+                    .entry(dst_unit)
+                    .or_default()
+                    .push((src_unit, is_barrier));
+                unit_succs.entry(src_unit).or_default().push(dst_unit);
+            }
+        }
+    }
+
+    // Topological sort (of strongly connected components) is how we find the (nondecreasing)
+    // order of strata.
+    let topo_sort_order = graph_algorithms::topo_sort_scc(
+        || units.iter().copied(),
+        |v| unit_preds.get(&v).into_iter().flatten().map(|&(u, _)| u),
+        |u| unit_succs.get(&u).into_iter().flatten().copied(),
+    );
+
+    // The last stratum of each unit that has been assigned.
+    let mut unit_end_strata: BTreeMap<StratumUnit, usize> = Default::default();
+    for unit in topo_sort_order {
+        let stratum = unit_preds
+            .get(&unit)
+            .into_iter()
+            .flatten()
+            .filter_map(|&(pred_unit, is_barrier)| {
+                unit_end_strata
+                    .get(&pred_unit)
+                    .map(|&end_stratum| end_stratum + (is_barrier as usize))
+            })
+            .max()
+            .unwrap_or(0)
+            .max(base_stratum);
+        let end_stratum = match unit {
+            StratumUnit::Subgraph(sg_id) => {
+                partitioned_graph.set_subgraph_stratum(sg_id, stratum);
+                stratum
+            }
+            StratumUnit::Loop(loop_id) => {
+                let mut end_stratum = assign_strata(
+                    partitioned_graph,
+                    subgraph_graph,
+                    subgraph_stratum_barriers,
+                    iteration_loops,
+                    loop_end_strata,
+                    Some(loop_id),
+                    stratum,
+                );
+                if iteration_loops.contains(&loop_id) {
+                    end_stratum += 1;
+                }
+                loop_end_strata.insert(loop_id, end_stratum);
+                end_stratum
+            }
+        };
+        unit_end_strata.insert(unit, end_stratum);
+    }
+    unit_end_strata.into_values().max().unwrap_or(base_stratum)
+}
+
 /// Stratification is surprisingly tricky. Basically it is topological sort, but with some nuance.
 ///
 /// Returns an error if there is a cycle thru negation.
@@ -305,18 +440,6 @@ fn find_subgraph_strata(
 
     // Generate a subgraph graph. I.e. each node is a subgraph.
     // Edges are connections between subgraphs, ignoring tick-crossers.
-    // TODO: use DiMulGraph here?
-    #[derive(Default)]
-    struct SubgraphGraph {
-        preds: BTreeMap<GraphSubgraphId, Vec<GraphSubgraphId>>,
-        succs: BTreeMap<GraphSubgraphId, Vec<GraphSubgraphId>>,
-    }
-    impl SubgraphGraph {
-        fn insert_edge(&mut self, src: GraphSubgraphId, dst: GraphSubgraphId) {
-            self.preds.entry(dst).or_default().push(src);
-            self.succs.entry(src).or_default().push(dst);
-        }
-    }
     let mut subgraph_graph = SubgraphGraph::default();
 
     // Negative (next stratum) connections between subgraphs. (Ignore `defer_tick()` connections).
@@ -334,8 +457,10 @@ fn find_subgraph_strata(
                 .edge_barrier_crossers
                 .get(succ_edge)
                 .copied();
-            // Ignore tick edges.
-            if let Some(DelayType::Tick | DelayType::TickLazy) = succ_edge_delaytype {
+            // Ignore tick and iteration edges.
+            if let Some(DelayType::Tick | DelayType::TickLazy | DelayType::Iteration) =
+                succ_edge_delaytype
+            {
                 continue;
             }
 
@@ -363,37 +488,33 @@ fn find_subgraph_strata(
         subgraph_stratum_barriers.insert((pred_sg, succ_sg));
     }
 
-    // Topological sort (of strongly connected components) is how we find the (nondecreasing)
-    // order of strata.
-    let topo_sort_order = graph_algorithms::topo_sort_scc(
-        || partitioned_graph.subgraph_ids(),
-        |v| subgraph_graph.preds.get(&v).into_iter().flatten().cloned(),
-        |u| subgraph_graph.succs.get(&u).into_iter().flatten().cloned(),
-    );
+    // Loops containing a `next_iteration()` may need an extra stratum at their end, for delayer
+    // subgraphs.
+    let iteration_loops: BTreeSet<GraphLoopId> = barrier_crossers
+        .edge_barrier_crossers
+        .iter()
+        .filter(|&(_edge_id, &delay_type)| DelayType::Iteration == delay_type)
+        .filter_map(|(edge_id, _delay_type)| {
+            let (_hoff, dst) = partitioned_graph.edge(edge_id);
+            partitioned_graph.node_loop(dst)
+        })
+        .collect();
 
     // Each subgraph's stratum number is the same as it's predecessors. Unless there is a negative
-    // edge, then we increment.
-    for sg_id in topo_sort_order {
-        let stratum = subgraph_graph
-            .preds
-            .get(&sg_id)
-            .into_iter()
-            .flatten()
-            .filter_map(|&pred_sg_id| {
-                partitioned_graph
-                    .subgraph_stratum(pred_sg_id)
-                    .map(|stratum| {
-                        stratum
-                            + (subgraph_stratum_barriers.contains(&(pred_sg_id, sg_id)) as usize)
-                    })
-            })
-            .max()
-            .unwrap_or(0);
-        partitioned_graph.set_subgraph_stratum(sg_id, stratum);
-    }
+    // edge, then we increment. Each loop occupies its own contiguous range of strata.
+    let mut loop_end_strata = SecondaryMap::new();
+    let max_stratum = assign_strata(
+        partitioned_graph,
+        &subgraph_graph,
+        &subgraph_stratum_barriers,
+        &iteration_loops,
+        &mut loop_end_strata,
+        None,
+        0,
+    );
 
     // Re-introduce the `defer_tick()` edges, ensuring they actually go to the next tick.
-    let extra_stratum = partitioned_graph.max_stratum().unwrap_or(0) + 1; // Used for `defer_tick()` delayer subgraphs.
+    let extra_stratum = max_stratum + 1; // Used for `defer_tick()` delayer subgraphs.
     for (edge_id, &delay_type) in barrier_crossers.edge_barrier_crossers.iter() {
         let (hoff, dst) = partitioned_graph.edge(edge_id);
         let (_hoff_port, dst_port) = partitioned_graph.edge_ports(edge_id);
@@ -447,6 +568,39 @@ fn find_subgraph_strata(
                     partitioned_graph.set_subgraph_laziness(new_subgraph_id, is_lazy);
                 }
             }
+            DelayType::Iteration => {
+                let Some(loop_id) = partitioned_graph.node_loop(dst) else {
+                    return Err(Diagnostic::spanned(
+                        dst_port.span(),
+                        Level::Error,
+                        "`next_iteration()` must be used within a `loop { ... }` block.",
+                    ));
+                };
+                // If iteration edge goes forward in stratum, need to buffer until the end of the
+                // loop iteration, same as `defer_tick()` above.
+                if src_stratum <= dst_stratum {
+                    // Before: A (src) -> H -> B (dst)
+                    let (new_node_id, new_edge_id) = partitioned_graph.insert_intermediate_node(
+                        edge_id,
+                        GraphNode::Operator(parse_quote! { identity() }),
+                    );
+                    let hoff = GraphNode::Handoff {
+                        src_span: Span::call_site(),
+                        dst_span: Span::call_site(),
+                    };
+                    let (_hoff_node_id, _hoff_edge_id) =
+                        partitioned_graph.insert_intermediate_node(new_edge_id, hoff);
+                    // After: A (src) -> H -> ID -> H' -> B (dst)
+
+                    partitioned_graph.set_node_loop(new_node_id, loop_id);
+                    let new_subgraph_id = partitioned_graph
+                        .insert_subgraph(vec![new_node_id])
+                        .unwrap();
+                    // Runs as the last stratum of the loop.
+                    partitioned_graph
+                        .set_subgraph_stratum(new_subgraph_id, loop_end_strata[loop_id]);
+                }
+            }
             DelayType::Stratum => {
                 // Any negative edges which go onto the same or previous stratum are bad.
                 // Indicates an unbroken negative cycle.
@@ -57,6 +57,18 @@ pub(crate) trait GraphWrite {
     fn write_epilogue(&mut self) -> Result<(), Self::Err>;
 }
 
+/// Mermaid directive setting the theme, including the colors of subgraph clusters.
+pub const MERMAID_INIT: &str =
+    r"%%{init:{'theme':'base','themeVariables':{'clusterBkg':'#ddd','clusterBorder':'#888'}}}%%";
+/// Mermaid style shared by all node classes, which are distinguished by their fill.
+pub const MERMAID_NODE_STYLE: &str = "stroke:#000,text-align:left,white-space:pre";
+/// Mermaid fill of nodes that are neither push nor pull.
+pub const MERMAID_OTHER_FILL: &str = "#fdc";
+/// Mermaid style of edges without a delay.
+pub const MERMAID_LINK_STYLE: &str = "stroke:#aaa";
+/// DOT font list for nodes and edges, which prefers monospace fonts for code.
+pub const DOT_FONTS: &str = "\"Monaco,Menlo,Consolas,&quot;Droid Sans Mono&quot;,Inconsolata,&quot;Courier New&quot;,monospace\"";
+
 /// Escapes a string for use in a mermaid graph label.
 pub fn escape_mermaid(string: &str) -> String {
     string
@@ -99,25 +111,25 @@ where
     type Err = std::fmt::Error;
 
     fn write_prologue(&mut self) -> Result<(), Self::Err> {
-        writeln!(
-            self.write,
-            r"%%{{init:{{'theme':'base','themeVariables':{{'clusterBkg':'#ddd','clusterBorder':'#888'}}}}}}%%",
-        )?;
+        writeln!(self.write, "{}", MERMAID_INIT)?;
         writeln!(self.write, "flowchart TD")?;
         writeln!(
             self.write,
-            "classDef pullClass fill:#8af,stroke:#000,text-align:left,white-space:pre",
+            "classDef pullClass fill:#8af,{}",
+            MERMAID_NODE_STYLE
         )?;
         writeln!(
             self.write,
-            "classDef pushClass fill:#ff8,stroke:#000,text-align:left,white-space:pre",
+            "classDef pushClass fill:#ff8,{}",
+            MERMAID_NODE_STYLE
         )?;
         writeln!(
             self.write,
-            "classDef otherClass fill:#fdc,stroke:#000,text-align:left,white-space:pre",
+            "classDef otherClass fill:{},{}",
+            MERMAID_OTHER_FILL, MERMAID_NODE_STYLE
         )?;
 
-        writeln!(self.write, "linkStyle default stroke:#aaa")?;
+        writeln!(self.write, "linkStyle default {}", MERMAID_LINK_STYLE)?;
         Ok(())
     }
 
@@ -176,7 +188,7 @@ where
             arrow_head = match delay_type {
                 None | Some(DelayType::MonotoneAccum) => ">",
                 Some(DelayType::Stratum) => "x",
-                Some(DelayType::Tick | DelayType::TickLazy) => "o",
+                Some(DelayType::Tick | DelayType::TickLazy | DelayType::Iteration) => "o",
             },
             label = if let Some(label) = &label {
                 Cow::Owned(format!("|{}|", escape_mermaid(label.trim())))
@@ -191,7 +203,10 @@ where
                 "; linkStyle {} stroke:{}",
                 self.link_count,
                 match delay_type {
-                    DelayType::Stratum | DelayType::Tick | DelayType::TickLazy => "red",
+                    DelayType::Stratum
+                    | DelayType::Tick
+                    | DelayType::TickLazy
+                    | DelayType::Iteration => "red",
                     DelayType::MonotoneAccum => "#060",
                 }
             )?;
@@ -286,9 +301,12 @@ where
 
     fn write_prologue(&mut self) -> Result<(), Self::Err> {
         writeln!(self.write, "digraph {{")?;
-        const FONTS: &str = "\"Monaco,Menlo,Consolas,&quot;Droid Sans Mono&quot;,Inconsolata,&quot;Courier New&quot;,monospace\"";
-        writeln!(self.write, "    node [fontname={}, style=filled];", FONTS)?;
-        writeln!(self.write, "    edge [fontname={}];", FONTS)?;
+        writeln!(
+            self.write,
+            "    node [fontname={}, style=filled];",
+            DOT_FONTS
+        )?;
+        writeln!(self.write, "    edge [fontname={}];", DOT_FONTS)?;
         Ok(())
     }
 
@@ -751,6 +751,11 @@ impl DfirGraph {
         Ident::new(&format!("singleton_op_{:?}", node_id.data()), span)
     }
 
+    /// For per-loop codegen. Helper to generate a deterministic `Ident` for the given loop.
+    fn loop_as_ident(&self, loop_id: GraphLoopId) -> Ident {
+        Ident::new(&format!("loop_{:?}", loop_id.data()), Span::call_site())
+    }
+
     /// Resolve the singletons via [`Self::node_singleton_references`] for the given `node_id`.
     fn helper_resolve_singletons(&self, node_id: GraphNodeId, span: Span) -> Vec<Ident> {
         self.node_singleton_references(node_id)
@@ -831,6 +836,53 @@ impl DfirGraph {
                 }
             });
 
+        // Loops are created parents-first.
+        let mut loop_ids = self.loop_ids().collect::<Vec<_>>();
+        loop_ids.sort_by_key(|&loop_id| {
+            std::iter::successors(Some(loop_id), |&loop_id| self.loop_parent(loop_id)).count()
+        });
+        let loops = loop_ids.iter().map(|&loop_id| {
+            let ident = self.loop_as_ident(loop_id);
+            let parent = match self.loop_parent(loop_id) {
+                Some(parent_id) => {
+                    let parent_ident = self.loop_as_ident(parent_id);
+                    quote! { ::std::option::Option::Some(#parent_ident) }
+                }
+                None => quote! { ::std::option::Option::None },
+            };
+            quote! {
+                let #ident = #hf.add_loop(#parent);
+            }
+        });
+
+        // Handoffs into `last_iteration()` only keep the latest iteration's items, and handoffs
+        // into `next_iteration()` are cleared when the loop exits.
+        let handoff_resets = self
+            .nodes
+            .iter()
+            .filter(|(_node_id, node)| matches!(node, GraphNode::Handoff { .. }))
+            .filter_map(|(node_id, _node)| {
+                let (_edge_id, pred) = self.node_predecessors(node_id).next()?;
+                let (_edge_id, succ) = self.node_successors(node_id).next()?;
+                let ident_recv = self.node_as_ident(node_id, true);
+                match self.node_op_inst(succ)?.op_constraints.name {
+                    "last_iteration" => {
+                        let loop_ident = self.loop_as_ident(self.node_loop(pred)?);
+                        Some(quote! {
+                            #hf.reset_handoff_each_iteration(#loop_ident, &#ident_recv);
+                        })
+                    }
+                    "next_iteration" => {
+                        let loop_ident = self.loop_as_ident(self.node_loop(succ)?);
+                        Some(quote! {
+                            #hf.reset_handoff_on_loop_exit(#loop_ident, &#ident_recv);
+                        })
+                    }
+                    _ => None,
+                }
+            })
+            .collect::<Vec<_>>();
+
         let subgraph_handoffs = self.helper_collect_subgraph_handoffs();
 
         // we first generate the subgraphs that have no inputs to guide type inference
@@ -860,6 +912,16 @@ impl DfirGraph {
                     .map(|&hoff_id| self.node_as_ident(hoff_id, false))
                     .collect();
 
+                let loop_ident = self
+                    .subgraph_loop(subgraph_id)
+                    .map(|loop_id| self.loop_as_ident(loop_id));
+                // State added by operators in a loop is owned by that loop.
+                if let Some(loop_ident) = &loop_ident {
+                    op_prologue_code.push(quote! {
+                        #hf.__set_prologue_loop(::std::option::Option::Some(#loop_ident));
+                    });
+                }
+
                 let recv_port_code = recv_ports.iter().map(|ident| {
                     quote! {
                         let mut #ident = #ident.borrow_mut_swap();
@@ -1156,26 +1218,49 @@ impl DfirGraph {
                     }
                 };
 
+                if loop_ident.is_some() {
+                    op_prologue_code.push(quote! {
+                        #hf.__set_prologue_loop(::std::option::Option::None);
+                    });
+                }
+
                 let hoff_name = Literal::string(&format!("Subgraph {:?}", subgraph_id));
                 let stratum = Literal::usize_unsuffixed(
                     self.subgraph_stratum.get(subgraph_id).cloned().unwrap_or(0),
                 );
                 let laziness = self.subgraph_laziness(subgraph_id);
-                subgraphs.push(quote! {
-                    #hf.add_subgraph_stratified(
-                        #hoff_name,
-                        #stratum,
-                        var_expr!( #( #recv_ports ),* ),
-                        var_expr!( #( #send_ports ),* ),
-                        #laziness,
-                        move |#context, var_args!( #( #recv_ports ),* ), var_args!( #( #send_ports ),* )| {
-                            #( #recv_port_code )*
-                            #( #send_port_code )*
-                            #( #subgraph_op_iter_code )*
-                            #( #subgraph_op_iter_after_code )*
-                        },
-                    );
-                });
+                let subgraph_fn = quote! {
+                    move |#context, var_args!( #( #recv_ports ),* ), var_args!( #( #send_ports ),* )| {
+                        #( #recv_port_code )*
+                        #( #send_port_code )*
+                        #( #subgraph_op_iter_code )*
+                        #( #subgraph_op_iter_after_code )*
+                    }
+                };
+                if let Some(loop_ident) = loop_ident {
+                    subgraphs.push(quote! {
+                        #hf.add_subgraph_full(
+                            #hoff_name,
+                            #stratum,
+                            var_expr!( #( #recv_ports ),* ),
+                            var_expr!( #( #send_ports ),* ),
+                            #laziness,
+                            ::std::option::Option::Some(#loop_ident),
+                            #subgraph_fn,
+                        );
+                    });
+                } else {
+                    subgraphs.push(quote! {
+                        #hf.add_subgraph_stratified(
+                            #hoff_name,
+                            #stratum,
+                            var_expr!( #( #recv_ports ),* ),
+                            var_expr!( #( #send_ports ),* ),
+                            #laziness,
+                            #subgraph_fn,
+                        );
+                    });
+                }
             }
         }
 
@@ -1185,6 +1270,8 @@ impl DfirGraph {
         // -Mingwei
         let code = quote! {
             #( #handoffs )*
+            #( #loops )*
+            #( #handoff_resets )*
             #( #op_prologue_code )*
             #( #subgraphs )*
         };
@@ -1565,6 +1652,22 @@ impl DfirGraph {
         self.node_loops.get(node_id).copied()
     }
 
+    /// Add a node to a loop context. Panics if the node is already in a loop.
+    pub fn set_node_loop(&mut self, node_id: GraphNodeId, loop_id: GraphLoopId) {
+        assert!(
+            self.node_loops.insert(node_id, loop_id).is_none(),
+            "Node is already in a loop."
+        );
+        self.loop_nodes[loop_id].push(node_id);
+    }
+
+    /// Get a subgraph's loop context (or `None` for root). All nodes in a subgraph share the
+    /// same loop context.
+    pub fn subgraph_loop(&self, subgraph_id: GraphSubgraphId) -> Option<GraphLoopId> {
+        let &node_id = self.subgraph(subgraph_id).first().unwrap();
+        self.node_loop(node_id)
+    }
+
     /// Get a loop context's parent loop context (or `None` for root).
     pub fn loop_parent(&self, loop_id: GraphLoopId) -> Option<GraphLoopId> {
         self.loop_parent.get(loop_id).copied()
@@ -30,6 +30,10 @@ pub use di_mul_graph::DiMulGraph;
 pub use eliminate_extra_unions_tees::eliminate_extra_unions_tees;
 pub use flat_graph_builder::FlatGraphBuilder;
 pub use flat_to_partitioned::partition_graph;
+pub use graph_write::{
+    escape_dot, escape_mermaid, DOT_FONTS, MERMAID_INIT, MERMAID_LINK_STYLE, MERMAID_NODE_STYLE,
+    MERMAID_OTHER_FILL,
+};
 pub use hydroflow_graph::{DfirGraph, WriteConfig, WriteGraphType};
 
 pub mod graph_algorithms;
@@ -195,7 +199,7 @@ pub struct OpInstGenerics {
 /// Gets the generic arguments for the operator.
 ///
 /// This helper method is useful due to the special handling of persistence lifetimes (`'static`,
-/// `'tick`, `'mutable`) which must come before other generic parameters.
+/// `'tick`, `'mutable`, `'durable`) which must come before other generic parameters.
 pub fn get_operator_generics(
     diagnostics: &mut Vec<Diagnostic>,
     operator: &Operator,
@@ -208,11 +212,12 @@ pub fn get_operator_generics(
                     "static" => Some(Persistence::Static),
                     "tick" => Some(Persistence::Tick),
                     "mutable" => Some(Persistence::Mutable),
+                    "durable" => Some(Persistence::Durable),
                     _ => {
                         diagnostics.push(Diagnostic::spanned(
                             generic_arg.span(),
                             Level::Error,
-                            format!("Unknown lifetime generic argument `'{}`, expected `'tick`, `'static`, or `'mutable`.", lifetime.ident),
+                            format!("Unknown lifetime generic argument `'{}`, expected `'tick`, `'static`, `'mutable`, or `'durable`.", lifetime.ident),
                         ));
                         // TODO(mingwei): should really keep going and not short circuit?
                         None
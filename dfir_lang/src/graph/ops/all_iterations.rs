@@ -0,0 +1,34 @@
+use super::{
+    FloType, OperatorCategory, OperatorConstraints, IDENTITY_WRITE_FN, RANGE_0, RANGE_1,
+};
+
+/// > 1 input stream of type T, 1 output stream of type T
+///
+/// An un-windowing operator, which passes out the items produced by every iteration of the
+/// `loop { ... }` context it follows.
+///
+/// ```dfir
+/// words = source_iter(["hello", "world"]);
+/// loop {
+///     batched = words -> batch() -> flatten();
+/// }
+/// batched -> all_iterations() -> assert_eq(["hello", "world"]);
+/// ```
+pub const ALL_ITERATIONS: OperatorConstraints = OperatorConstraints {
+    name: "all_iterations",
+    categories: &[OperatorCategory::Unwindowing],
+    hard_range_inn: RANGE_1,
+    soft_range_inn: RANGE_1,
+    hard_range_out: RANGE_1,
+    soft_range_out: RANGE_1,
+    num_args: 0,
+    persistence_args: RANGE_0,
+    type_args: RANGE_0,
+    is_external_input: false,
+    has_singleton_output: false,
+    flo_type: Some(FloType::Unwindowing),
+    ports_inn: None,
+    ports_out: None,
+    input_delaytype_fn: |_| None,
+    write_fn: IDENTITY_WRITE_FN,
+};
@@ -80,7 +80,7 @@ pub const ANTI_JOIN: OperatorConstraints = OperatorConstraints {
                         #root::util::monotonic_map::MonotonicMap::<_, #root::rustc_hash::FxHashSet<_>>::default()
                     },
                     quote_spanned! {op_span=>
-                        &mut *#borrow_ident.get_mut_clear(#context.current_tick())
+                        &mut *#borrow_ident.get_mut_clear((#context.current_tick(), #context.current_loop_epoch()))
                     },
                 ),
                 Persistence::Static => (
@@ -91,11 +91,11 @@ pub const ANTI_JOIN: OperatorConstraints = OperatorConstraints {
                         &mut *#borrow_ident
                     },
                 ),
-                Persistence::Mutable => {
+                persistence @ (Persistence::Mutable | Persistence::Durable) => {
                     diagnostics.push(Diagnostic::spanned(
                         op_span,
                         Level::Error,
-                        "An implementation of 'mutable does not exist",
+                        format!("An implementation of {} does not exist", persistence),
                     ));
                     return Err(());
                 }
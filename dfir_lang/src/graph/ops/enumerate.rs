@@ -13,7 +13,8 @@ use crate::diagnostic::{Diagnostic, Level};
 /// `enumerate` can also be provided with one generic lifetime persistence argument, either
 /// `'tick` or `'static`, to specify if indexing resets. If `'tick` (the default) is specified, indexing will
 /// restart at zero at the start of each tick. Otherwise `'static` will never reset
-/// and count monotonically upwards.
+/// and count monotonically upwards. `'durable` is like `'static` but the index is also
+/// checkpointed, so it continues where it left off after a restart.
 ///
 /// ```dfir
 /// source_iter(vec!["hello", "world"])
@@ -62,7 +63,7 @@ pub const ENUMERATE: OperatorConstraints = OperatorConstraints {
                 diagnostics.push(Diagnostic::spanned(
                     op_span,
                     Level::Error,
-                    "An implementation of 'mutable does not exist",
+                    format!("An implementation of {} does not exist", Persistence::Mutable),
                 ));
                 Persistence::Tick
             },
@@ -78,10 +79,16 @@ pub const ENUMERATE: OperatorConstraints = OperatorConstraints {
         let mut write_prologue = quote_spanned! {op_span=>
             let #counter_ident = #hydroflow.add_state(::std::cell::RefCell::new(0..));
         };
-        if Persistence::Tick == persistence {
-            write_prologue.extend(quote_spanned! {op_span=>
-                #hydroflow.set_state_tick_hook(#counter_ident, |rcell| { rcell.replace(0..); });
-            });
+        match persistence {
+            Persistence::Tick => {
+                write_prologue.extend(quote_spanned! {op_span=>
+                    #hydroflow.set_state_tick_hook(#counter_ident, |rcell| { rcell.replace(0..); });
+                });
+            }
+            Persistence::Durable => {
+                write_prologue.extend(wc.make_durable_state(&counter_ident));
+            }
+            Persistence::Static | Persistence::Mutable => {}
         }
 
         let map_fn = quote_spanned! {op_span=>
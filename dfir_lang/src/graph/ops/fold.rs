@@ -75,11 +75,11 @@ pub const FOLD: OperatorConstraints = OperatorConstraints {
             [a] => a,
             _ => unreachable!(),
         };
-        if Persistence::Mutable == persistence {
+        if let Persistence::Mutable | Persistence::Durable = persistence {
             diagnostics.push(Diagnostic::spanned(
                 op_span,
                 Level::Error,
-                "An implementation of 'mutable does not exist",
+                format!("An implementation of {} does not exist", persistence),
             ));
             return Err(());
         }
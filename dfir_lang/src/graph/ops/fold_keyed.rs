@@ -28,10 +28,11 @@ use super::{
 /// ```
 ///
 /// `fold_keyed` can be provided with one generic lifetime persistence argument, either
-/// `'tick` or `'static`, to specify how data persists. With `'tick`, values will only be collected
-/// within the same tick. With `'static`, values will be remembered across ticks and will be
-/// aggregated with pairs arriving in later ticks. When not explicitly specified persistence
-/// defaults to `'tick`.
+/// `'tick`, `'static`, or `'durable`, to specify how data persists. With `'tick`, values will only
+/// be collected within the same tick. With `'static`, values will be remembered across ticks and
+/// will be aggregated with pairs arriving in later ticks. `'durable` behaves like `'static` but
+/// also checkpoints the accumulated values so they survive process restarts. When not explicitly
+/// specified persistence defaults to `'tick`.
 ///
 /// `fold_keyed` can also be provided with two type arguments, the key type `K` and aggregated
 /// output value type `V2`. This is required when using `'static` persistence if the compiler
@@ -173,10 +174,13 @@ pub const FOLD_KEYED: OperatorConstraints = OperatorConstraints {
                     Default::default(),
                 )
             }
-            Persistence::Static => {
+            Persistence::Static | Persistence::Durable => {
+                let durable = (Persistence::Durable == persistence)
+                    .then(|| wc.make_durable_state(&groupbydata_ident));
                 (
                     quote_spanned! {op_span=>
                         let #groupbydata_ident = #hydroflow.add_state(::std::cell::RefCell::new(#root::rustc_hash::FxHashMap::<#( #generic_type_args ),*>::default()));
+                        #durable
                     },
                     quote_spanned! {op_span=>
                         let mut #hashtable_ident = #context.state_ref(#groupbydata_ident).borrow_mut();
@@ -142,11 +142,11 @@ pub const JOIN: OperatorConstraints = OperatorConstraints {
                     #hydroflow.set_state_tick_hook(#joindata_ident, |rcell| #root::util::clear::Clear::clear(rcell.get_mut()));
                 },
                 Persistence::Static => Default::default(),
-                Persistence::Mutable => {
+                persistence @ (Persistence::Mutable | Persistence::Durable) => {
                     diagnostics.push(Diagnostic::spanned(
                         op_span,
                         Level::Error,
-                        "An implementation of 'mutable does not exist",
+                        format!("An implementation of {} does not exist", persistence),
                     ));
                     return Err(());
                 }
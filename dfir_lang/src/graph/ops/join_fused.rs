@@ -308,7 +308,7 @@ pub(crate) fn make_joindata(
                 ));
             },
             quote_spanned! {op_span=>
-                #borrow_ident.get_mut_clear(#context.current_tick())
+                #borrow_ident.get_mut_clear((#context.current_tick(), #context.current_loop_epoch()))
             },
         ),
         Persistence::Static => (
@@ -321,11 +321,11 @@ pub(crate) fn make_joindata(
                 #borrow_ident
             },
         ),
-        Persistence::Mutable => {
+        persistence @ (Persistence::Mutable | Persistence::Durable) => {
             return Err(Diagnostic::spanned(
                 op_span,
                 Level::Error,
-                "An implementation of 'mutable does not exist",
+                format!("An implementation of {} does not exist", persistence),
             ));
         }
     };
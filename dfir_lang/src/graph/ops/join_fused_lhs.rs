@@ -85,11 +85,11 @@ pub const JOIN_FUSED_LHS: OperatorConstraints = OperatorConstraints {
                     ::std::vec::Vec::new()
                 ));
             },
-            Persistence::Mutable => {
+            persistence @ (Persistence::Mutable | Persistence::Durable) => {
                 diagnostics.push(Diagnostic::spanned(
                     op_span,
                     Level::Error,
-                    "An implementation of 'mutable does not exist",
+                    format!("An implementation of {} does not exist", persistence),
                 ));
                 return Err(());
             }
@@ -146,11 +146,11 @@ pub const JOIN_FUSED_LHS: OperatorConstraints = OperatorConstraints {
                     .filter_map(|(k, v2)| #lhs_borrow.table.get(k).map(|v1| (k.clone(), (v1.clone(), v2.clone()))))
                 };
             },
-            Persistence::Mutable => {
+            persistence @ (Persistence::Mutable | Persistence::Durable) => {
                 diagnostics.push(Diagnostic::spanned(
                     op_span,
                     Level::Error,
-                    "An implementation of 'mutable does not exist",
+                    format!("An implementation of {} does not exist", persistence),
                 ));
                 return Err(());
             }
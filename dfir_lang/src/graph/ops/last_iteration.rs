@@ -0,0 +1,37 @@
+use super::{
+    FloType, OperatorCategory, OperatorConstraints, IDENTITY_WRITE_FN, RANGE_0, RANGE_1,
+};
+
+/// > 1 input stream of type T, 1 output stream of type T
+///
+/// An un-windowing operator, which passes out only the items produced by the final iteration of
+/// the `loop { ... }` context it follows. Compare to [`all_iterations`](#all_iterations).
+///
+/// ```dfir
+/// init = source_iter([1]) -> tee();
+/// loop {
+///     counter = union() -> tee();
+///     init -> batch() -> flatten() -> counter;
+///     counter -> map(|x| x * 2) -> next_iteration() -> counter;
+///     init -> repeat_n(4) -> for_each(|_| {}); // Run four iterations.
+/// }
+/// counter -> last_iteration() -> assert_eq([8]);
+/// ```
+pub const LAST_ITERATION: OperatorConstraints = OperatorConstraints {
+    name: "last_iteration",
+    categories: &[OperatorCategory::Unwindowing],
+    hard_range_inn: RANGE_1,
+    soft_range_inn: RANGE_1,
+    hard_range_out: RANGE_1,
+    soft_range_out: RANGE_1,
+    num_args: 0,
+    persistence_args: RANGE_0,
+    type_args: RANGE_0,
+    is_external_input: false,
+    has_singleton_output: false,
+    flo_type: Some(FloType::Unwindowing),
+    ports_inn: None,
+    ports_out: None,
+    input_delaytype_fn: |_| None,
+    write_fn: IDENTITY_WRITE_FN,
+};
@@ -29,6 +29,8 @@ pub enum DelayType {
     Tick,
     /// Input must be collected over the previous tick but also not cause a new tick to occur.
     TickLazy,
+    /// Input must be collected over the previous iteration of the enclosing `loop { ... }`.
+    Iteration,
 }
 
 /// Specification of the named (or unnamed) ports for an operator's inputs or outputs.
@@ -242,6 +244,7 @@ macro_rules! declare_ops {
     };
 }
 declare_ops![
+    all_iterations::ALL_ITERATIONS,
     all_once::ALL_ONCE,
     anti_join::ANTI_JOIN,
     anti_join_multiset::ANTI_JOIN_MULTISET,
@@ -274,6 +277,7 @@ declare_ops![
     join_fused_lhs::JOIN_FUSED_LHS,
     join_fused_rhs::JOIN_FUSED_RHS,
     join_multiset::JOIN_MULTISET,
+    last_iteration::LAST_ITERATION,
     fold_keyed::FOLD_KEYED,
     reduce_keyed::REDUCE_KEYED,
     lattice_bimorphism::LATTICE_BIMORPHISM,
@@ -284,6 +288,7 @@ declare_ops![
     map::MAP,
     union::UNION,
     multiset_delta::MULTISET_DELTA,
+    next_iteration::NEXT_ITERATION,
     next_stratum::NEXT_STRATUM,
     defer_signal::DEFER_SIGNAL,
     defer_tick::DEFER_TICK,
@@ -295,6 +300,7 @@ declare_ops![
     persist_mut_keyed::PERSIST_MUT_KEYED,
     py_udf::PY_UDF,
     reduce::REDUCE,
+    repeat_n::REPEAT_N,
     spin::SPIN,
     sort::SORT,
     sort_by_key::SORT_BY_KEY,
@@ -309,6 +315,7 @@ declare_ops![
     state_by::STATE_BY,
     tee::TEE,
     unique::UNIQUE,
+    until_fixpoint::UNTIL_FIXPOINT,
     unzip::UNZIP,
     zip::ZIP,
     zip_longest::ZIP_LONGEST,
@@ -394,6 +401,21 @@ impl WriteContextArgs<'_> {
             self.op_span,
         )
     }
+
+    /// Generate code registering the `RefCell` state `state_ident` as durable, so that it is
+    /// checkpointed to (and restored from) the graph's state backend.
+    ///
+    /// The state key is derived from the node ID and operator name, so it is stable as long as
+    /// the graph itself does not change.
+    pub fn make_durable_state(&self, state_ident: &Ident) -> TokenStream {
+        let &Self {
+            hydroflow, op_span, ..
+        } = self;
+        let key = Literal::string(&format!("{:?}_{}", self.node_id.data(), self.op_name));
+        quote_spanned! {op_span=>
+            #hydroflow.add_durable_state(#state_ident, #key);
+        }
+    }
 }
 
 /// An object-safe version of [`RangeBounds`].
@@ -461,7 +483,7 @@ where
     }
 }
 
-/// Persistence lifetimes: `'tick`, `'static`, or `'mutable`.
+/// Persistence lifetimes: `'tick`, `'static`, `'mutable`, or `'durable`.
 #[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Debug, Serialize, Deserialize)]
 pub enum Persistence {
     /// Persistence for one tick at-a-time only.
@@ -470,6 +492,20 @@ pub enum Persistence {
     Static,
     /// Mutability.
     Mutable,
+    /// Persistence across all ticks, checkpointed to the graph's
+    /// [`StateBackend`](https://hydro.run/rustdoc/dfir_rs/scheduled/state/trait.StateBackend.html)
+    /// so the state survives process restarts.
+    Durable,
+}
+impl Display for Persistence {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Persistence::Tick => write!(f, "'tick"),
+            Persistence::Static => write!(f, "'static"),
+            Persistence::Mutable => write!(f, "'mutable"),
+            Persistence::Durable => write!(f, "'durable"),
+        }
+    }
 }
 
 /// Helper which creates a error message string literal for when the Tokio runtime is not found.
@@ -0,0 +1,44 @@
+use super::{
+    DelayType, OperatorCategory, OperatorConstraints, IDENTITY_WRITE_FN, RANGE_0, RANGE_1,
+};
+
+/// Buffers all input items and releases them in the next iteration of the enclosing
+/// `loop { ... }` block. Used to carry state between iterations, forming the back edge of a
+/// cycle within the loop.
+///
+/// `next_iteration()` does not cause another iteration to run by itself. The loop must be kept
+/// iterating by another operator such as [`repeat_n`](#repeat_n) or
+/// [`until_fixpoint`](#until_fixpoint). Items sent during the final iteration of the loop are
+/// dropped.
+///
+/// ```dfir
+/// init = source_iter([1]) -> tee();
+/// loop {
+///     counter = union() -> tee();
+///     init -> batch() -> flatten() -> counter;
+///     counter -> map(|x| x * 2) -> next_iteration() -> counter;
+///     init -> repeat_n(4) -> for_each(|_| {}); // Run four iterations.
+/// }
+/// counter -> all_iterations() -> assert_eq([1, 2, 4, 8]);
+/// ```
+///
+/// You can also supply a type parameter `next_iteration::<MyType>()` to specify what items flow
+/// through the the pipeline. This can be useful for helping the compiler infer types.
+pub const NEXT_ITERATION: OperatorConstraints = OperatorConstraints {
+    name: "next_iteration",
+    categories: &[OperatorCategory::Control],
+    hard_range_inn: RANGE_1,
+    soft_range_inn: RANGE_1,
+    hard_range_out: RANGE_1,
+    soft_range_out: RANGE_1,
+    num_args: 0,
+    persistence_args: RANGE_0,
+    type_args: &(0..=1),
+    is_external_input: false,
+    has_singleton_output: false,
+    flo_type: None,
+    ports_inn: None,
+    ports_out: None,
+    input_delaytype_fn: |_| Some(DelayType::Iteration),
+    write_fn: IDENTITY_WRITE_FN,
+};
@@ -17,6 +17,11 @@ use crate::diagnostic::{Diagnostic, Level};
 ///     -> assert_eq(["hello"]);
 /// ```
 ///
+/// With `persist::<'durable>()` the stored items are also checkpointed at the end of each tick to
+/// the graph's [`StateBackend`](https://hydro.run/rustdoc/dfir_rs/scheduled/state/trait.StateBackend.html),
+/// and restored by [`Dfir::restore_from`](https://hydro.run/rustdoc/dfir_rs/scheduled/graph/struct.Dfir.html#method.restore_from).
+/// Items must implement `Serialize` and `Deserialize`.
+///
 /// `persist()` can be used to introduce statefulness into stateless pipelines. In the example below, the
 /// join only stores data for single tick. The `persist::<'static>()` operator introduces statefulness
 /// across ticks. This can be useful for optimization transformations within the dfir
@@ -74,21 +79,28 @@ pub const PERSIST: OperatorConstraints = OperatorConstraints {
                    ..
                },
                diagnostics| {
-        if [Persistence::Static] != persistence_args[..] {
-            diagnostics.push(Diagnostic::spanned(
-                op_span,
-                Level::Error,
-                format!("{} only supports `'static`.", op_name),
-            ));
-        }
+        let persistence = match persistence_args[..] {
+            [a @ (Persistence::Static | Persistence::Durable)] => a,
+            _ => {
+                diagnostics.push(Diagnostic::spanned(
+                    op_span,
+                    Level::Error,
+                    format!("{} only supports `'static` or `'durable`.", op_name),
+                ));
+                Persistence::Static
+            }
+        };
 
         let persistdata_ident = singleton_output_ident;
         let vec_ident = wc.make_ident("persistvec");
-        let write_prologue = quote_spanned! {op_span=>
+        let mut write_prologue = quote_spanned! {op_span=>
             let #persistdata_ident = #hydroflow.add_state(::std::cell::RefCell::new(
                 ::std::vec::Vec::new(),
             ));
         };
+        if Persistence::Durable == persistence {
+            write_prologue.extend(wc.make_durable_state(persistdata_ident));
+        }
 
         let write_iterator = if is_pull {
             let input = &inputs[0];
@@ -12,6 +12,9 @@ use crate::diagnostic::{Diagnostic, Level};
 /// Deletions/persists happen in the order they are received in the stream.
 /// For example, `[Persist(1), Delete(1), Persist(1)]` will result in a a single `1` value being stored.
 ///
+/// Like `persist()`, `persist_mut::<'durable>()` checkpoints the stored items at the end of each
+/// tick so they can be restored after a restart.
+///
 /// ```dfir
 /// use dfir_rs::util::Persistence;
 ///
@@ -65,21 +68,28 @@ pub const PERSIST_MUT: OperatorConstraints = OperatorConstraints {
                diagnostics| {
         assert!(is_pull);
 
-        if [Persistence::Static] != persistence_args[..] {
-            diagnostics.push(Diagnostic::spanned(
-                op_span,
-                Level::Error,
-                format!("{} only supports `'static`.", op_name),
-            ));
-        }
+        let persistence = match persistence_args[..] {
+            [a @ (Persistence::Static | Persistence::Durable)] => a,
+            _ => {
+                diagnostics.push(Diagnostic::spanned(
+                    op_span,
+                    Level::Error,
+                    format!("{} only supports `'static` or `'durable`.", op_name),
+                ));
+                Persistence::Static
+            }
+        };
 
         let persistdata_ident = wc.make_ident("persistdata");
         let vec_ident = wc.make_ident("persistvec");
-        let write_prologue = quote_spanned! {op_span=>
+        let mut write_prologue = quote_spanned! {op_span=>
             let #persistdata_ident = #hydroflow.add_state(::std::cell::RefCell::new(
                 #root::util::sparse_vec::SparseVec::default(),
             ));
         };
+        if Persistence::Durable == persistence {
+            write_prologue.extend(wc.make_durable_state(&persistdata_ident));
+        }
 
         let write_iterator = {
             let input = &inputs[0];
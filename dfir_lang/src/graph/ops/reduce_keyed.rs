@@ -213,11 +213,11 @@ pub const REDUCE_KEYED: OperatorConstraints = OperatorConstraints {
                 )
             }
 
-            Persistence::Mutable => {
+            persistence @ (Persistence::Mutable | Persistence::Durable) => {
                 diagnostics.push(Diagnostic::spanned(
                     op_span,
                     Level::Error,
-                    "An implementation of 'mutable does not exist",
+                    format!("An implementation of {} does not exist", persistence),
                 ));
                 return Err(());
             }
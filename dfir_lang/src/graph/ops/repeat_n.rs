@@ -0,0 +1,84 @@
+use quote::quote_spanned;
+
+use super::{
+    FloType, OperatorCategory, OperatorConstraints, OperatorWriteOutput, WriteContextArgs, RANGE_0,
+    RANGE_1,
+};
+
+/// > 1 input stream of type T, 1 output stream of type `Vec<T>`
+///
+/// A windowing operator which collects its input into a single batch (like [`batch`](#batch)),
+/// and emits that same batch in each of the first `n` iterations of the enclosing
+/// `loop { ... }` block. The loop is kept iterating until at least `n` iterations have run.
+///
+/// ```dfir
+/// words = source_iter(["hello", "world"]);
+/// loop {
+///     repeated = words -> repeat_n(3) -> flatten();
+/// }
+/// repeated -> all_iterations() -> assert_eq(["hello", "world", "hello", "world", "hello", "world"]);
+/// ```
+pub const REPEAT_N: OperatorConstraints = OperatorConstraints {
+    name: "repeat_n",
+    categories: &[OperatorCategory::Fold, OperatorCategory::Windowing],
+    hard_range_inn: RANGE_1,
+    soft_range_inn: RANGE_1,
+    hard_range_out: RANGE_1,
+    soft_range_out: RANGE_1,
+    num_args: 1,
+    persistence_args: RANGE_0,
+    type_args: RANGE_0,
+    is_external_input: false,
+    has_singleton_output: false,
+    flo_type: Some(FloType::Windowing),
+    ports_inn: None,
+    ports_out: None,
+    input_delaytype_fn: |_| None,
+    write_fn: |wc @ &WriteContextArgs {
+                   context,
+                   hydroflow,
+                   op_span,
+                   ident,
+                   is_pull,
+                   inputs,
+                   arguments,
+                   ..
+               },
+               _diagnostics| {
+        assert!(is_pull, "Should not happen - `repeat_n` must be at ingress to a loop, therefore ingress to a subgraph, so would be pull-based.");
+
+        let count = &arguments[0];
+        let input = &inputs[0];
+        let count_ident = wc.make_ident("count");
+        let state_ident = wc.make_ident("state");
+        let vec_ident = wc.make_ident("vec");
+
+        // The batch is held across iterations, and only replaced in the first iteration.
+        let write_prologue = quote_spanned! {op_span=>
+            let #state_ident = #hydroflow.add_state(
+                ::std::cell::RefCell::new(::std::vec::Vec::new())
+            );
+        };
+
+        let write_iterator = quote_spanned! {op_span=>
+            let #count_ident: usize = #count;
+            let mut #vec_ident = #context.state_ref(#state_ident).borrow_mut();
+            if #context.is_first_loop_iteration() {
+                ::std::vec::Vec::clear(&mut *#vec_ident);
+            }
+            ::std::iter::Extend::extend(&mut *#vec_ident, #input);
+            if #context.current_loop_iteration() + 1 < #count_ident {
+                #context.allow_another_iteration();
+            }
+            let #ident = (#context.current_loop_iteration() < #count_ident)
+                .then(|| ::std::clone::Clone::clone(&*#vec_ident))
+                .into_iter();
+        };
+
+        Ok(OperatorWriteOutput {
+            write_prologue,
+            write_iterator,
+            ..Default::default()
+        })
+    },
+};
@@ -36,7 +36,7 @@ pub const STATE_BY: OperatorConstraints = OperatorConstraints {
     ports_inn: None,
     ports_out: None,
     input_delaytype_fn: |_| None,
-    write_fn: |&WriteContextArgs {
+    write_fn: |wc @ &WriteContextArgs {
                    root,
                    context,
                    hydroflow,
@@ -86,10 +86,16 @@ pub const STATE_BY: OperatorConstraints = OperatorConstraints {
                 <#lattice_type as ::std::default::Default>::default()
             ));
         };
-        if Persistence::Tick == persistence {
-            write_prologue.extend(quote_spanned! {op_span=>
-                #hydroflow.set_state_tick_hook(#state_ident, |rcell| { rcell.take(); }); // Resets state to `Default::default()`.
-            });
+        match persistence {
+            Persistence::Tick => {
+                write_prologue.extend(quote_spanned! {op_span=>
+                    #hydroflow.set_state_tick_hook(#state_ident, |rcell| { rcell.take(); }); // Resets state to `Default::default()`.
+                });
+            }
+            Persistence::Durable => {
+                write_prologue.extend(wc.make_durable_state(state_ident));
+            }
+            Persistence::Static | Persistence::Mutable => {}
         }
 
         let func = &arguments[0];
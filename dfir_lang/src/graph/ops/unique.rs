@@ -98,7 +98,7 @@ pub const UNIQUE: OperatorConstraints = OperatorConstraints {
                 };
                 let get_set = quote_spanned! {op_span=>
                     let mut borrow = #context.state_ref(#uniquedata_ident).borrow_mut();
-                    let set = borrow.get_mut_clear((#context.current_tick(), #context.current_stratum()));
+                    let set = borrow.get_mut_clear((#context.current_tick(), #context.current_stratum(), #context.current_loop_epoch()));
                 };
                 (write_prologue, get_set)
             }
@@ -111,11 +111,11 @@ pub const UNIQUE: OperatorConstraints = OperatorConstraints {
                 };
                 (write_prologue, get_set)
             }
-            Persistence::Mutable => {
+            persistence @ (Persistence::Mutable | Persistence::Durable) => {
                 diagnostics.push(Diagnostic::spanned(
                     op_span,
                     Level::Error,
-                    "An implementation of 'mutable does not exist",
+                    format!("An implementation of {} does not exist", persistence),
                 ));
                 return Err(());
             }
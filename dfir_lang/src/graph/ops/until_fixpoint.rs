@@ -0,0 +1,98 @@
+use quote::quote_spanned;
+
+use super::{
+    DelayType, OperatorCategory, OperatorConstraints, OperatorWriteOutput, WriteContextArgs,
+    RANGE_0, RANGE_1,
+};
+
+/// > 1 input stream of type T, 1 output stream of type T
+///
+/// Passes through its input, and keeps the enclosing `loop { ... }` block iterating until the
+/// input stops changing. In each iteration the input is compared (as a multiset) to the input of
+/// the previous iteration, and another iteration is requested if they differ. Items must be
+/// `Clone + Eq + Hash`.
+///
+/// `until_fixpoint` is blocking, as the entire input for the iteration must be collected before
+/// it can be compared.
+///
+/// ```dfir
+/// init = source_iter([1]);
+/// loop {
+///     reached = union() -> until_fixpoint() -> tee();
+///     init -> batch() -> flatten() -> reached;
+///     reached
+///         -> flat_map(|x| [x, std::cmp::min(x + 1, 4)])
+///         -> sort()
+///         -> unique::<'tick>()
+///         -> next_iteration()
+///         -> reached;
+/// }
+/// reached -> last_iteration() -> assert_eq([1, 2, 3, 4]);
+/// ```
+pub const UNTIL_FIXPOINT: OperatorConstraints = OperatorConstraints {
+    name: "until_fixpoint",
+    categories: &[OperatorCategory::Control],
+    hard_range_inn: RANGE_1,
+    soft_range_inn: RANGE_1,
+    hard_range_out: RANGE_1,
+    soft_range_out: RANGE_1,
+    num_args: 0,
+    persistence_args: RANGE_0,
+    type_args: RANGE_0,
+    is_external_input: false,
+    has_singleton_output: false,
+    flo_type: None,
+    ports_inn: None,
+    ports_out: None,
+    input_delaytype_fn: |_| Some(DelayType::Stratum),
+    write_fn: |wc @ &WriteContextArgs {
+                   context,
+                   hydroflow,
+                   op_span,
+                   ident,
+                   inputs,
+                   is_pull,
+                   ..
+               },
+               _| {
+        assert!(is_pull);
+
+        let input = &inputs[0];
+        let prev_ident = wc.make_ident("prev");
+        let items_ident = wc.make_ident("items");
+        let counts_ident = wc.make_ident("counts");
+
+        // The previous iteration's counts are held across iterations.
+        let write_prologue = quote_spanned! {op_span=>
+            let #prev_ident = #hydroflow.add_state(
+                ::std::cell::RefCell::new(::std::collections::HashMap::new())
+            );
+        };
+
+        let write_iterator = quote_spanned! {op_span=>
+            let #ident = {
+                let #items_ident = #input.collect::<::std::vec::Vec<_>>();
+                let mut #counts_ident = ::std::collections::HashMap::new();
+                for item in #items_ident.iter() {
+                    *#counts_ident.entry(::std::clone::Clone::clone(item)).or_insert(0_usize) += 1;
+                }
+                let mut prev = #context.state_ref(#prev_ident).borrow_mut();
+                let changed = if #context.is_first_loop_iteration() {
+                    !#counts_ident.is_empty()
+                } else {
+                    *prev != #counts_ident
+                };
+                *prev = #counts_ident;
+                if changed {
+                    #context.allow_another_iteration();
+                }
+                #items_ident.into_iter()
+            };
+        };
+        Ok(OperatorWriteOutput {
+            write_prologue,
+            write_iterator,
+            ..Default::default()
+        })
+    },
+};
@@ -3,6 +3,7 @@
 //! Provides APIs for state and scheduling.
 
 use std::any::Any;
+use std::cell::Cell;
 use std::collections::VecDeque;
 use std::future::Future;
 use std::marker::PhantomData;
@@ -14,7 +15,7 @@ use tokio::task::JoinHandle;
 use web_time::SystemTime;
 
 use super::state::StateHandle;
-use super::{StateId, SubgraphId};
+use super::{LoopId, StateId, SubgraphId};
 use crate::scheduled::ticks::TickInstant;
 
 /// The main state and scheduler of the Hydroflow instance. Provided as the `context` API to each
@@ -46,6 +47,18 @@ pub struct Context {
     pub(super) current_tick_start: SystemTime,
     pub(super) subgraph_last_tick_run_in: Option<TickInstant>,
 
+    /// The iteration count of the innermost `loop { ... }` containing the currently running
+    /// subgraph, or zero if it is not in a loop.
+    pub(super) loop_iter_count: usize,
+    /// The [`Self::current_loop_epoch`] of the currently running subgraph.
+    pub(super) loop_epoch: usize,
+    /// If the currently running subgraph has not yet run during this loop iteration.
+    pub(super) is_first_run_this_loop_iteration: bool,
+    /// Set by operators to request another iteration of the current loop.
+    pub(super) allow_another_iteration: Cell<bool>,
+    /// The loop of the operator whose prologue is being run, which owns any state it adds.
+    pub(super) prologue_loop: Option<LoopId>,
+
     /// The SubgraphId of the currently running operator. When this context is
     /// not being forwarded to a running operator, this field is meaningless.
     pub(super) subgraph_id: SubgraphId,
@@ -53,7 +66,7 @@ pub struct Context {
     tasks_to_spawn: Vec<Pin<Box<dyn Future<Output = ()> + 'static>>>,
 
     /// Join handles for spawned tasks.
-    task_join_handles: Vec<JoinHandle<()>>,
+    pub(super) task_join_handles: Vec<JoinHandle<()>>,
 }
 /// Public APIs.
 impl Context {
@@ -67,12 +80,41 @@ impl Context {
         self.current_tick_start
     }
 
-    /// Gets whether this is the first time this subgraph is being scheduled for this tick
+    /// Gets whether this is the first time this subgraph is being scheduled for this tick, or
+    /// for this iteration if the subgraph is within a `loop { ... }` block.
     pub fn is_first_run_this_tick(&self) -> bool {
-        self.subgraph_last_tick_run_in
-            .map_or(true, |tick_last_run_in| {
-                self.current_tick > tick_last_run_in
-            })
+        self.is_first_run_this_loop_iteration
+            || self
+                .subgraph_last_tick_run_in
+                .map_or(true, |tick_last_run_in| {
+                    self.current_tick > tick_last_run_in
+                })
+    }
+
+    /// Gets the iteration count (starting at zero) of the innermost `loop { ... }` block
+    /// containing the current subgraph. Zero if the subgraph is not within a loop.
+    pub fn current_loop_iteration(&self) -> usize {
+        self.loop_iter_count
+    }
+
+    /// Gets a counter which increases each time an iteration of the innermost `loop { ... }` block
+    /// containing the current subgraph ends, including the final iteration. Together with
+    /// [`Self::current_tick`] this identifies the current loop iteration, and can be used to key
+    /// state which should be cleared each iteration. Zero if the subgraph is not within a loop.
+    pub fn current_loop_epoch(&self) -> usize {
+        self.loop_epoch
+    }
+
+    /// Gets whether the current subgraph is running in the first iteration of its loop.
+    pub fn is_first_loop_iteration(&self) -> bool {
+        0 == self.loop_iter_count
+    }
+
+    /// Requests another iteration of the innermost `loop { ... }` block containing the current
+    /// subgraph. A loop keeps iterating (within the same tick) as long as some operator requests
+    /// another iteration during each iteration.
+    pub fn allow_another_iteration(&self) {
+        self.allow_another_iteration.set(true);
     }
 
     /// Gets the current stratum nubmer.
@@ -120,9 +162,12 @@ impl Context {
     where
         T: Any,
     {
-        self.states
+        let state_data = self
+            .states
             .get(handle.state_id.0)
-            .expect("Failed to find state with given handle.")
+            .expect("Failed to find state with given handle.");
+        state_data.accessed.set(true);
+        state_data
             .state
             .downcast_ref()
             .expect("StateHandle wrong type T for casting.")
@@ -133,14 +178,27 @@ impl Context {
     where
         T: Any,
     {
-        self.states
+        let state_data = self
+            .states
             .get_mut(handle.state_id.0)
-            .expect("Failed to find state with given handle.")
+            .expect("Failed to find state with given handle.");
+        state_data.accessed.set(true);
+        state_data
             .state
             .downcast_mut()
             .expect("StateHandle wrong type T for casting.")
     }
 
+    /// Returns if the state was accessed (and so may have changed) since the last call, and
+    /// resets this flag.
+    pub(super) fn take_state_accessed(&self, state_id: StateId) -> bool {
+        self.states
+            .get(state_id.0)
+            .expect("Failed to find state with given handle.")
+            .accessed
+            .take()
+    }
+
     /// Adds state to the context and returns the handle.
     pub fn add_state<T>(&mut self, state: T) -> StateHandle<T>
     where
@@ -151,6 +209,8 @@ impl Context {
         let state_data = StateData {
             state: Box::new(state),
             tick_reset: None,
+            loop_id: self.prologue_loop,
+            accessed: Cell::new(false),
         };
         self.states.push(state_data);
 
@@ -238,6 +298,12 @@ impl Default for Context {
             current_tick_start: SystemTime::now(),
             subgraph_last_tick_run_in: None,
 
+            loop_iter_count: 0,
+            loop_epoch: 0,
+            is_first_run_this_loop_iteration: false,
+            allow_another_iteration: Cell::new(false),
+            prologue_loop: None,
+
             subgraph_id: SubgraphId(0),
 
             tasks_to_spawn: Vec::new(),
@@ -257,17 +323,46 @@ impl Context {
 
     /// Call this at the end of a tick,
     pub(super) fn reset_state_at_end_of_tick(&mut self) {
-        for StateData { state, tick_reset } in self.states.iter_mut() {
+        for StateData {
+            state, tick_reset, ..
+        } in self.states.iter_mut()
+        {
             if let Some(tick_reset) = tick_reset {
                 (tick_reset)(Box::deref_mut(state));
             }
         }
     }
+
+    /// Call this at the end of a loop iteration, to reset the state owned by operators within
+    /// the loops selected by `in_loop`.
+    pub(super) fn reset_state_at_end_of_loop_iteration(
+        &mut self,
+        mut in_loop: impl FnMut(LoopId) -> bool,
+    ) {
+        for StateData {
+            state,
+            tick_reset,
+            loop_id,
+            ..
+        } in self.states.iter_mut()
+        {
+            if let (Some(tick_reset), Some(loop_id)) = (tick_reset, loop_id) {
+                if (in_loop)(*loop_id) {
+                    (tick_reset)(Box::deref_mut(state));
+                }
+            }
+        }
+    }
 }
 
 /// Internal struct containing a pointer to [`Hydroflow`]-owned state.
 struct StateData {
     state: Box<dyn Any>,
     tick_reset: Option<TickResetFn>,
+    /// The loop containing the operator which owns this state, if any. The state is reset at
+    /// the end of each iteration of that loop, as well as at the end of each tick.
+    loop_id: Option<LoopId>,
+    /// Set whenever the state is accessed, see [`Context::take_state_accessed`].
+    accessed: Cell<bool>,
 }
 type TickResetFn = Box<dyn FnMut(&mut dyn Any)>;
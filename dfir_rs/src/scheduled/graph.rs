@@ -2,8 +2,9 @@
 
 use std::any::Any;
 use std::borrow::Cow;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::future::Future;
+use std::io;
 use std::marker::PhantomData;
 
 #[cfg(feature = "meta")]
@@ -11,6 +12,8 @@ use dfir_lang::diagnostic::{Diagnostic, SerdeSpan};
 #[cfg(feature = "meta")]
 use dfir_lang::graph::DfirGraph;
 use ref_cast::RefCast;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
 use smallvec::SmallVec;
 use web_time::SystemTime;
 
@@ -19,9 +22,9 @@ use super::handoff::handoff_list::PortList;
 use super::handoff::{Handoff, HandoffMeta, TeeingHandoff};
 use super::port::{RecvCtx, RecvPort, SendCtx, SendPort, RECV, SEND};
 use super::reactor::Reactor;
-use super::state::StateHandle;
+use super::state::{StateBackend, StateHandle};
 use super::subgraph::Subgraph;
-use super::{HandoffId, SubgraphId};
+use super::{HandoffId, LoopId, StateId, SubgraphId};
 use crate::scheduled::ticks::{TickDuration, TickInstant};
 use crate::Never;
 
@@ -32,6 +35,15 @@ pub struct Dfir<'a> {
     pub(super) context: Context,
 
     handoffs: Vec<HandoffData>,
+    loops: Vec<LoopData>,
+
+    /// State checkpointed to [`Self::state_backend`], see [`Self::add_durable_state`].
+    durable_states: Vec<DurableStateData>,
+    /// Set by [`Self::restore_from`].
+    state_backend: Option<Box<dyn StateBackend>>,
+    /// The last error from the automatic [`Self::checkpoint`] at the end of a tick, see
+    /// [`Self::take_checkpoint_error`].
+    checkpoint_error: Option<io::Error>,
 
     #[cfg(feature = "meta")]
     /// See [`Self::meta_graph()`].
@@ -277,8 +289,28 @@ impl<'a> Dfir<'a> {
 
                 self.context.subgraph_id = sg_id;
                 self.context.subgraph_last_tick_run_in = sg_data.last_tick_run_in;
+                let loop_epoch = sg_data.loop_id.map(|loop_id| {
+                    let loop_data = &self.loops[loop_id.0];
+                    self.context.loop_iter_count = loop_data.iter_count;
+                    self.context.loop_epoch = loop_data.epoch;
+                    self.context.is_first_run_this_loop_iteration =
+                        sg_data.last_loop_epoch_run_in != Some(loop_data.epoch);
+                    loop_data.epoch
+                });
+                if loop_epoch.is_none() {
+                    self.context.loop_iter_count = 0;
+                    self.context.loop_epoch = 0;
+                    self.context.is_first_run_this_loop_iteration = false;
+                }
                 sg_data.subgraph.run(&mut self.context, &mut self.handoffs);
                 sg_data.last_tick_run_in = Some(current_tick);
+                sg_data.last_loop_epoch_run_in = loop_epoch;
+
+                if self.context.allow_another_iteration.take() {
+                    if let Some(loop_id) = sg_data.loop_id {
+                        self.loops[loop_id.0].allow_another_iteration = true;
+                    }
+                }
             }
 
             let sg_data = &self.subgraphs[sg_id.0];
@@ -288,7 +320,12 @@ impl<'a> Dfir<'a> {
                     for &succ_id in handoff.succs.iter() {
                         let succ_sg_data = &self.subgraphs[succ_id.0];
                         // If we have sent data to the next tick, then we can start the next tick.
-                        if succ_sg_data.stratum < self.context.current_stratum && !sg_data.is_lazy {
+                        // Unless the data was sent to the next iteration of the same loop.
+                        if succ_sg_data.stratum < self.context.current_stratum
+                            && !sg_data.is_lazy
+                            && (sg_data.loop_id.is_none()
+                                || sg_data.loop_id != succ_sg_data.loop_id)
+                        {
                             self.context.can_start_tick = true;
                         }
                         // Add subgraph to stratum queue if it is not already scheduled.
@@ -354,6 +391,12 @@ impl<'a> Dfir<'a> {
                 return true;
             }
 
+            // If the current stratum ends an iteration of a loop which should run again, go back to
+            // the start of the loop.
+            if self.next_loop_iteration() {
+                continue;
+            }
+
             // Increment stratum counter.
             self.context.current_stratum += 1;
             if self.context.current_stratum >= self.context.stratum_queues.len() {
@@ -364,6 +407,13 @@ impl<'a> Dfir<'a> {
                     self.context.current_tick + TickDuration::SINGLE_TICK,
                 );
                 self.context.reset_state_at_end_of_tick();
+                if let Err(err) = self.checkpoint() {
+                    tracing::error!(
+                        "Failed to checkpoint durable state, retrying next tick: {}",
+                        err
+                    );
+                    self.checkpoint_error = Some(err);
+                }
 
                 self.context.current_stratum = 0;
                 self.context.current_tick += TickDuration::SINGLE_TICK;
@@ -408,6 +458,94 @@ impl<'a> Dfir<'a> {
         }
     }
 
+    /// Called when the current stratum has no more work. Checks each loop ending at the current
+    /// stratum, innermost first. If one has had another iteration requested, starts that
+    /// iteration and moves back to the loop's first stratum, returning `true`. Otherwise the
+    /// loops are finished and `false` is returned.
+    fn next_loop_iteration(&mut self) -> bool {
+        let current_stratum = self.context.current_stratum;
+        let mut ending_loops = self
+            .loops
+            .iter()
+            .enumerate()
+            .filter(|(_, loop_data)| {
+                loop_data
+                    .strata
+                    .map_or(false, |(_, last)| last == current_stratum)
+            })
+            .map(|(idx, loop_data)| (loop_data.depth, LoopId(idx)))
+            .collect::<Vec<_>>();
+        ending_loops.sort_by(|a, b| b.cmp(a));
+
+        for (_depth, loop_id) in ending_loops {
+            let loop_data = &mut self.loops[loop_id.0];
+            let (first_stratum, last_stratum) = loop_data.strata.unwrap();
+            loop_data.epoch += 1;
+
+            if !std::mem::take(&mut loop_data.allow_another_iteration) {
+                tracing::trace!(loop_id = loop_id.0, "Loop finished.");
+                loop_data.iter_count = 0;
+                for reset_handoff in loop_data.reset_handoffs_on_exit.iter() {
+                    (reset_handoff)(&self.handoffs);
+                }
+
+                // Drop any subgraphs scheduled for an iteration which will not happen.
+                for stratum in first_stratum..=last_stratum {
+                    self.context.stratum_queues[stratum].retain(|sg_id| {
+                        let sg_data = &self.subgraphs[sg_id.0];
+                        let in_loop = sg_data.loop_id.map_or(false, |sg_loop| {
+                            Self::loop_contains(&self.loops, loop_id, sg_loop)
+                        });
+                        if in_loop {
+                            sg_data.is_scheduled.set(false);
+                        }
+                        !in_loop
+                    });
+                }
+                continue;
+            }
+
+            loop_data.iter_count += 1;
+            tracing::trace!(
+                loop_id = loop_id.0,
+                iter_count = loop_data.iter_count,
+                "Starting next loop iteration."
+            );
+            for reset_handoff in loop_data.reset_handoffs_each_iteration.iter() {
+                (reset_handoff)(&self.handoffs);
+            }
+
+            let loops = &self.loops;
+            self.context
+                .reset_state_at_end_of_loop_iteration(|state_loop| {
+                    Self::loop_contains(loops, loop_id, state_loop)
+                });
+
+            // Every subgraph within the loop runs in each iteration.
+            for &sg_id in self.loops[loop_id.0].subgraphs.iter() {
+                let sg_data = &self.subgraphs[sg_id.0];
+                if !sg_data.is_scheduled.replace(true) {
+                    self.context.stratum_queues[sg_data.stratum].push_back(sg_id);
+                }
+            }
+            self.context.current_stratum = first_stratum;
+            return true;
+        }
+        false
+    }
+
+    /// If `inner` is `outer` or is nested within `outer`.
+    fn loop_contains(loops: &[LoopData], outer: LoopId, inner: LoopId) -> bool {
+        let mut loop_id = Some(inner);
+        while let Some(id) = loop_id {
+            if id == outer {
+                return true;
+            }
+            loop_id = loops[id.0].parent;
+        }
+        false
+    }
+
     /// Runs the dataflow graph forever.
     ///
     /// TODO(mingwei): Currently blocks forever, no notion of "completion."
@@ -594,6 +732,30 @@ impl<'a> Dfir<'a> {
         recv_ports: R,
         send_ports: W,
         laziness: bool,
+        subgraph: F,
+    ) -> SubgraphId
+    where
+        Name: Into<Cow<'static, str>>,
+        R: 'static + PortList<RECV>,
+        W: 'static + PortList<SEND>,
+        F: 'a + for<'ctx> FnMut(&'ctx mut Context, R::Ctx<'ctx>, W::Ctx<'ctx>),
+    {
+        self.add_subgraph_full(
+            name, stratum, recv_ports, send_ports, laziness, None, subgraph,
+        )
+    }
+
+    /// Adds a new compiled subgraph with the specified inputs, outputs, stratum number, and
+    /// optionally the [`LoopId`] of the innermost loop containing it (see [`Self::add_loop`]).
+    #[expect(clippy::too_many_arguments, reason = "internal use")]
+    pub fn add_subgraph_full<Name, R, W, F>(
+        &mut self,
+        name: Name,
+        stratum: usize,
+        recv_ports: R,
+        send_ports: W,
+        laziness: bool,
+        loop_id: Option<LoopId>,
         mut subgraph: F,
     ) -> SubgraphId
     where
@@ -625,9 +787,95 @@ impl<'a> Dfir<'a> {
         self.context.init_stratum(stratum);
         self.context.stratum_queues[stratum].push_back(sg_id);
 
+        if let Some(loop_id) = loop_id {
+            self.subgraphs[sg_id.0].loop_id = Some(loop_id);
+            // Register the subgraph with its loop and all enclosing loops.
+            let mut loop_opt = Some(loop_id);
+            while let Some(loop_id) = loop_opt {
+                let loop_data = &mut self.loops[loop_id.0];
+                loop_data.subgraphs.push(sg_id);
+                loop_data.strata = Some(
+                    loop_data
+                        .strata
+                        .map_or((stratum, stratum), |(first, last)| {
+                            (first.min(stratum), last.max(stratum))
+                        }),
+                );
+                loop_opt = loop_data.parent;
+            }
+        }
+
         sg_id
     }
 
+    /// Adds a new `loop { ... }` context, optionally nested within `parent`, and returns its
+    /// [`LoopId`]. Subgraphs are added to the loop with [`Self::add_subgraph_full`].
+    ///
+    /// Each time the scheduler finishes the last stratum containing any of the loop's
+    /// subgraphs, it checks whether any of them called [`Context::allow_another_iteration`]. If
+    /// so, every subgraph in the loop is run again for the next iteration before the stratum
+    /// advances past the loop.
+    pub fn add_loop(&mut self, parent: Option<LoopId>) -> LoopId {
+        let loop_id = LoopId(self.loops.len());
+        let depth = parent.map_or(0, |parent| self.loops[parent.0].depth + 1);
+        self.loops.push(LoopData {
+            parent,
+            depth,
+            subgraphs: Vec::new(),
+            strata: None,
+            iter_count: 0,
+            epoch: 0,
+            allow_another_iteration: false,
+            reset_handoffs_each_iteration: Vec::new(),
+            reset_handoffs_on_exit: Vec::new(),
+        });
+        loop_id
+    }
+
+    /// Clears the handoff each time `loop_id` starts another iteration, so that its receiver only
+    /// sees the items sent during the final iteration of the loop. Used by `last_iteration()`.
+    pub fn reset_handoff_each_iteration<H>(&mut self, loop_id: LoopId, recv_port: &RecvPort<H>)
+    where
+        H: 'static + Handoff,
+    {
+        let reset = Self::make_handoff_reset(recv_port);
+        self.loops[loop_id.0]
+            .reset_handoffs_each_iteration
+            .push(reset);
+    }
+
+    /// Clears the handoff each time `loop_id` finishes its final iteration, dropping any items
+    /// which were sent to an iteration that will not run. Used by `next_iteration()`.
+    pub fn reset_handoff_on_loop_exit<H>(&mut self, loop_id: LoopId, recv_port: &RecvPort<H>)
+    where
+        H: 'static + Handoff,
+    {
+        let reset = Self::make_handoff_reset(recv_port);
+        self.loops[loop_id.0].reset_handoffs_on_exit.push(reset);
+    }
+
+    fn make_handoff_reset<H>(recv_port: &RecvPort<H>) -> HandoffResetFn
+    where
+        H: 'static + Handoff,
+    {
+        let handoff_id = recv_port.handoff_id;
+        Box::new(move |handoffs| {
+            handoffs[handoff_id.0]
+                .handoff
+                .any_ref()
+                .downcast_ref::<H>()
+                .expect("Attempted to cast handoff to wrong type.")
+                .take_inner();
+        })
+    }
+
+    /// Sets the loop which owns any state added by operator prologues until the next call, so
+    /// that the state is reset at the end of each iteration of that loop.
+    #[doc(hidden)]
+    pub fn __set_prologue_loop(&mut self, loop_id: Option<LoopId>) {
+        self.context.prologue_loop = loop_id;
+    }
+
     /// Adds a new compiled subgraph with a variable number of inputs and outputs of the same respective handoff types.
     pub fn add_subgraph_n_m<Name, R, W, F>(
         &mut self,
@@ -769,6 +1017,98 @@ impl<'a> Dfir<'a> {
         self.context.set_state_tick_hook(handle, tick_hook_fn)
     }
 
+    /// Registers the state of `handle` as durable under the given unique `key`. Once a backend is
+    /// set by [`Self::restore_from`], the state is restored from it and then checkpointed to it at
+    /// the end of every tick.
+    ///
+    /// This is part of the "state API", and is used by `'durable` operators.
+    pub fn add_durable_state<T>(&mut self, handle: StateHandle<RefCell<T>>, key: impl Into<String>)
+    where
+        T: 'static + Serialize + DeserializeOwned,
+    {
+        let key = key.into();
+        assert!(
+            self.durable_states.iter().all(|durable| durable.key != key),
+            "Duplicate durable state key {:?}.",
+            key,
+        );
+        self.durable_states.push(DurableStateData {
+            key,
+            state_id: handle.state_id,
+            dirty: true,
+            checkpoint: Box::new(move |context| {
+                bincode::serialize(&*context.state_ref(handle).borrow())
+            }),
+            restore: Box::new(move |context, bytes| {
+                *context.state_ref(handle).borrow_mut() = bincode::deserialize(bytes)?;
+                Ok(())
+            }),
+            last_checkpoint: None,
+        });
+    }
+
+    /// Restores all durable state from `backend`, which will then be used to checkpoint durable
+    /// state at the end of each tick.
+    ///
+    /// Should be called after the graph is built but before it is run.
+    ///
+    /// This is part of the "state API".
+    pub fn restore_from(&mut self, mut backend: impl 'static + StateBackend) -> io::Result<()> {
+        for durable in self.durable_states.iter_mut() {
+            if let Some(bytes) = backend.load(&durable.key)? {
+                (durable.restore)(&self.context, &bytes)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                durable.last_checkpoint = Some(bytes);
+            }
+        }
+        self.state_backend = Some(Box::new(backend));
+        Ok(())
+    }
+
+    /// Checkpoints all changed durable state to the backend set by [`Self::restore_from`], if
+    /// any, then commits the backend. Called automatically at the end of each tick, in which case
+    /// errors are reported by [`Self::take_checkpoint_error`] and the checkpoint is retried at the
+    /// end of the next tick.
+    ///
+    /// Only state accessed since the last successful checkpoint is serialized.
+    ///
+    /// This is part of the "state API".
+    pub fn checkpoint(&mut self) -> io::Result<()> {
+        let Some(backend) = self.state_backend.as_mut() else {
+            return Ok(());
+        };
+        for durable in self.durable_states.iter_mut() {
+            durable.dirty |= self.context.take_state_accessed(durable.state_id);
+        }
+        let mut checkpointed = Vec::new();
+        for (i, durable) in self.durable_states.iter().enumerate() {
+            if !durable.dirty {
+                continue;
+            }
+            let bytes = (durable.checkpoint)(&self.context).map_err(io::Error::other)?;
+            // Serializing only reads the state.
+            self.context.take_state_accessed(durable.state_id);
+            if durable.last_checkpoint.as_ref() != Some(&bytes) {
+                backend.store(&durable.key, &bytes)?;
+            }
+            checkpointed.push((i, bytes));
+        }
+        backend.commit()?;
+        for (i, bytes) in checkpointed {
+            let durable = &mut self.durable_states[i];
+            durable.dirty = false;
+            durable.last_checkpoint = Some(bytes);
+        }
+        Ok(())
+    }
+
+    /// Takes the error from the last failed automatic checkpoint at the end of a tick, if any.
+    ///
+    /// This is part of the "state API".
+    pub fn take_checkpoint_error(&mut self) -> Option<io::Error> {
+        self.checkpoint_error.take()
+    }
+
     /// Gets a exclusive (mut) ref to the internal context, setting the subgraph ID.
     pub fn context_mut(&mut self, sg_id: SubgraphId) -> &mut Context {
         self.context.subgraph_id = sg_id;
@@ -794,6 +1134,17 @@ impl Dfir<'_> {
     pub fn join_tasks(&mut self) -> impl '_ + Future {
         self.context.join_tasks()
     }
+
+    /// Drops this flow and waits for its spawned tasks to complete, instead of aborting them.
+    ///
+    /// The tasks of `dest_sink` operators complete once they have flushed every item sent to
+    /// them, so this makes sure all output has been written before the flow is gone.
+    pub async fn close(mut self) {
+        self.context.spawn_tasks();
+        let tasks = std::mem::take(&mut self.context.task_join_handles);
+        drop(self);
+        futures::future::join_all(tasks).await;
+    }
 }
 
 impl Drop for Dfir<'_> {
@@ -802,6 +1153,20 @@ impl Drop for Dfir<'_> {
     }
 }
 
+/// State registered by [`Dfir::add_durable_state`].
+struct DurableStateData {
+    key: String,
+    state_id: StateId,
+    /// If the state may have changed since it was last checkpointed.
+    dirty: bool,
+    checkpoint: CheckpointFn,
+    restore: RestoreFn,
+    /// The most recently checkpointed (or restored) bytes, to skip storing unchanged state.
+    last_checkpoint: Option<Vec<u8>>,
+}
+type CheckpointFn = Box<dyn Fn(&Context) -> bincode::Result<Vec<u8>>>;
+type RestoreFn = Box<dyn Fn(&Context, &[u8]) -> bincode::Result<()>>;
+
 /// A handoff and its input and output [SubgraphId]s.
 ///
 /// Internal use: used to track the dfir graph structure.
@@ -885,6 +1250,11 @@ pub(super) struct SubgraphData<'a> {
 
     /// If this subgraph is marked as lazy, then sending data back to a lower stratum does not trigger a new tick to be run.
     is_lazy: bool,
+
+    /// The innermost loop containing this subgraph, if any.
+    loop_id: Option<LoopId>,
+    /// The [`LoopData::epoch`] of the loop iteration this subgraph last ran in.
+    last_loop_epoch_run_in: Option<usize>,
 }
 impl<'a> SubgraphData<'a> {
     pub fn new(
@@ -905,6 +1275,33 @@ impl<'a> SubgraphData<'a> {
             is_scheduled: Cell::new(is_scheduled),
             last_tick_run_in: None,
             is_lazy: laziness,
+            loop_id: None,
+            last_loop_epoch_run_in: None,
         }
     }
 }
+
+/// A `loop { ... }` context, see [`Dfir::add_loop`].
+struct LoopData {
+    /// The enclosing loop, if any.
+    parent: Option<LoopId>,
+    /// Number of enclosing loops.
+    depth: usize,
+    /// All subgraphs in this loop, including those in nested loops.
+    subgraphs: Vec<SubgraphId>,
+    /// The first and last strata containing subgraphs of this loop.
+    strata: Option<(usize, usize)>,
+
+    /// The current iteration count, starting at zero.
+    iter_count: usize,
+    /// Incremented at the end of every iteration, to track which subgraphs have run in the
+    /// current iteration.
+    epoch: usize,
+    /// If another iteration has been requested during the current iteration.
+    allow_another_iteration: bool,
+    /// Clears handoffs which only hold items from the latest iteration.
+    reset_handoffs_each_iteration: Vec<HandoffResetFn>,
+    /// Clears handoffs which hold items for the next iteration.
+    reset_handoffs_on_exit: Vec<HandoffResetFn>,
+}
+type HandoffResetFn = Box<dyn Fn(&[HandoffData])>;
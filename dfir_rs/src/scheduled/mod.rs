@@ -43,6 +43,17 @@ impl Display for HandoffId {
     }
 }
 
+/// A loop's ID. Invalid if used in a different [`graph::Dfir`]
+/// instance than the original that created it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[repr(transparent)]
+pub struct LoopId(pub(crate) usize);
+impl Display for LoopId {
+    fn fmt(&self, f: &mut Formatter) -> Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 /// A staten handle's ID. Invalid if used in a different [`graph::Dfir`]
 /// instance than the original that created it.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::{entry_len, read_entry, write_entry, StateBackend};
+
+/// Record tag for a key-value entry.
+const TAG_ENTRY: u8 = 0;
+/// Record tag marking the end of a commit.
+const TAG_COMMIT: u8 = 1;
+
+/// Don't bother compacting logs smaller than this.
+const COMPACT_MIN_BYTES: u64 = 1 << 20;
+/// Compact once the log is this many times larger than the live data.
+const COMPACT_RATIO: u64 = 2;
+
+/// A [`StateBackend`] which appends each commit to a single log file.
+///
+/// The log is replayed into memory when opened. Entries after the last complete commit (e.g. from
+/// a crash mid-commit) are discarded. The log is periodically compacted by rewriting only the
+/// latest value of each key to a new file, which then atomically replaces the old log.
+#[derive(Debug)]
+pub struct FileBackend {
+    path: PathBuf,
+    log: BufWriter<File>,
+    /// Committed values.
+    index: HashMap<String, Vec<u8>>,
+    /// Stored values not yet committed.
+    pending: HashMap<String, Vec<u8>>,
+    /// Total length of the log file.
+    log_len: u64,
+    /// Encoded length of the latest committed entries.
+    live_len: u64,
+}
+
+impl FileBackend {
+    /// Opens (or creates) the log file at `path`, replaying its committed contents.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+
+        // Remove any compaction left incomplete by a crash.
+        match std::fs::remove_file(compact_path(&path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => {}
+        }
+
+        let contents = match std::fs::read(&path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+
+        let mut index = HashMap::new();
+        let mut uncommitted = Vec::new();
+        let mut buf = &*contents;
+        let mut log_len = 0;
+        while let Some((&tag, rest)) = buf.split_first() {
+            buf = rest;
+            match tag {
+                TAG_ENTRY => match read_entry(&mut buf)? {
+                    Some(entry) => uncommitted.push(entry),
+                    None => break,
+                },
+                TAG_COMMIT => {
+                    index.extend(uncommitted.drain(..));
+                    log_len = (contents.len() - buf.len()) as u64;
+                }
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Unknown record tag {} in state log {:?}.", tag, path),
+                    ))
+                }
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        // Drop any partial or uncommitted tail.
+        file.set_len(log_len)?;
+        let mut log = BufWriter::new(file);
+        io::Seek::seek(&mut log, io::SeekFrom::End(0))?;
+
+        let live_len = index.iter().map(|(k, v)| 1 + entry_len(k, v)).sum();
+        Ok(Self {
+            path,
+            log,
+            index,
+            pending: HashMap::new(),
+            log_len,
+            live_len,
+        })
+    }
+
+    /// Rewrites the log to contain only the latest committed value of each key.
+    pub fn compact(&mut self) -> io::Result<()> {
+        let compact_path = compact_path(&self.path);
+        let mut writer = BufWriter::new(File::create(&compact_path)?);
+        let mut log_len = 0;
+        for (key, value) in self.index.iter() {
+            writer.write_all(&[TAG_ENTRY])?;
+            log_len += 1 + write_entry(&mut writer, key, value)?;
+        }
+        writer.write_all(&[TAG_COMMIT])?;
+        log_len += 1;
+        let file = writer
+            .into_inner()
+            .map_err(io::IntoInnerError::into_error)?;
+        file.sync_all()?;
+        std::fs::rename(&compact_path, &self.path)?;
+        // Make the rename itself durable.
+        #[cfg(unix)]
+        if let Some(dir) = self.path.parent() {
+            let dir = if dir.as_os_str().is_empty() {
+                Path::new(".")
+            } else {
+                dir
+            };
+            File::open(dir)?.sync_all()?;
+        }
+
+        self.log = BufWriter::new(file);
+        self.log_len = log_len;
+        Ok(())
+    }
+}
+
+impl StateBackend for FileBackend {
+    fn load(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(self
+            .pending
+            .get(key)
+            .or_else(|| self.index.get(key))
+            .cloned())
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.pending.insert(key.to_owned(), value.to_vec());
+        Ok(())
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        for (key, value) in self.pending.iter() {
+            self.log.write_all(&[TAG_ENTRY])?;
+            self.log_len += 1 + write_entry(&mut self.log, key, value)?;
+        }
+        self.log.write_all(&[TAG_COMMIT])?;
+        self.log_len += 1;
+        self.log.flush()?;
+        self.log.get_ref().sync_data()?;
+
+        for (key, value) in self.pending.drain() {
+            if let Some(old_value) = self.index.get(&key) {
+                self.live_len -= 1 + entry_len(&key, old_value);
+            }
+            self.live_len += 1 + entry_len(&key, &value);
+            self.index.insert(key, value);
+        }
+        if COMPACT_MIN_BYTES <= self.log_len && COMPACT_RATIO * self.live_len < self.log_len {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+/// Path of the temporary file used while compacting the log at `path`.
+fn compact_path(path: &Path) -> PathBuf {
+    let mut compact_path = OsString::from(path.as_os_str());
+    compact_path.push(".compact");
+    compact_path.into()
+}
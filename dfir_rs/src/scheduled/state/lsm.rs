@@ -0,0 +1,243 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use super::{write_entry, StateBackend};
+
+/// File extension of complete segment files.
+const SEGMENT_EXT: &str = "sst";
+/// File extension of segment files still being written.
+const TMP_EXT: &str = "tmp";
+
+/// Merge all segments into one once there are more than this many.
+const DEFAULT_MAX_SEGMENTS: usize = 8;
+
+/// An embedded log-structured merge (LSM) tree [`StateBackend`], stored in a directory.
+///
+/// Stored values are buffered in an in-memory table. Each commit writes the table out as a new
+/// immutable, sorted segment file. Only the keys of each segment (and the offsets of their values)
+/// are kept in memory; values are read from disk when loaded. Newer segments shadow older ones,
+/// and segments are periodically merged to bound their number.
+///
+/// Segments are written to a temporary file which is atomically renamed once complete, so a
+/// crash never leaves a partial segment behind.
+#[derive(Debug)]
+pub struct LsmBackend {
+    dir: PathBuf,
+    /// Stored values not yet committed.
+    memtable: BTreeMap<String, Vec<u8>>,
+    /// Segments, oldest first.
+    segments: Vec<Segment>,
+    /// Sequence number for the next segment.
+    next_seq: u64,
+    max_segments: usize,
+}
+
+/// An immutable sorted segment file.
+#[derive(Debug)]
+struct Segment {
+    path: PathBuf,
+    file: File,
+    /// Maps each key to the offset and length of its value.
+    index: BTreeMap<String, (u64, u32)>,
+}
+
+impl LsmBackend {
+    /// Opens (or creates) an LSM tree in the directory `dir`.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        std::fs::create_dir_all(&dir)?;
+
+        let mut seqs = Vec::new();
+        for dir_entry in std::fs::read_dir(&dir)? {
+            let path = dir_entry?.path();
+            let ext = path.extension().and_then(|ext| ext.to_str());
+            if Some(TMP_EXT) == ext {
+                std::fs::remove_file(&path)?;
+            } else if Some(SEGMENT_EXT) == ext {
+                if let Some(seq) = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<u64>().ok())
+                {
+                    seqs.push(seq);
+                }
+            }
+        }
+        seqs.sort_unstable();
+
+        let segments = seqs
+            .iter()
+            .map(|&seq| Segment::open(segment_path(&dir, seq)))
+            .collect::<io::Result<Vec<_>>>()?;
+        Ok(Self {
+            dir,
+            memtable: BTreeMap::new(),
+            segments,
+            next_seq: seqs.last().map_or(0, |seq| seq + 1),
+            max_segments: DEFAULT_MAX_SEGMENTS,
+        })
+    }
+
+    /// Sets the number of segments above which all segments are merged into one.
+    pub fn with_max_segments(mut self, max_segments: usize) -> Self {
+        self.max_segments = max_segments.max(1);
+        self
+    }
+
+    /// The number of segment files currently on disk.
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Merges all segments into a single segment, keeping only the newest value of each key.
+    pub fn compact(&mut self) -> io::Result<()> {
+        if self.segments.len() <= 1 {
+            return Ok(());
+        }
+        // Which segment holds the newest value of each key.
+        let mut newest = BTreeMap::new();
+        for (i, segment) in self.segments.iter().enumerate() {
+            for key in segment.index.keys() {
+                newest.insert(key.clone(), i);
+            }
+        }
+
+        let seq = self.next_seq;
+        let segments = &mut self.segments;
+        let merged = write_segment(
+            &self.dir,
+            seq,
+            newest.into_iter().map(|(key, i)| {
+                let value = segments[i].read(&key)?.unwrap();
+                Ok((key, value))
+            }),
+        )?;
+        self.next_seq += 1;
+
+        for old in std::mem::replace(&mut self.segments, vec![merged]) {
+            std::fs::remove_file(&old.path)?;
+        }
+        Ok(())
+    }
+}
+
+impl StateBackend for LsmBackend {
+    fn load(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        if let Some(value) = self.memtable.get(key) {
+            return Ok(Some(value.clone()));
+        }
+        for segment in self.segments.iter_mut().rev() {
+            if let Some(value) = segment.read(key)? {
+                return Ok(Some(value));
+            }
+        }
+        Ok(None)
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.memtable.insert(key.to_owned(), value.to_vec());
+        Ok(())
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        if self.memtable.is_empty() {
+            return Ok(());
+        }
+        let memtable = std::mem::take(&mut self.memtable);
+        let segment = write_segment(&self.dir, self.next_seq, memtable.into_iter().map(Ok))?;
+        self.next_seq += 1;
+        self.segments.push(segment);
+
+        if self.max_segments < self.segments.len() {
+            self.compact()?;
+        }
+        Ok(())
+    }
+}
+
+impl Segment {
+    /// Opens a segment file, reading its keys into memory.
+    fn open(path: PathBuf) -> io::Result<Self> {
+        let truncated = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Truncated state segment {:?}.", path),
+            )
+        };
+
+        let mut reader = BufReader::new(File::open(&path)?);
+        let file_len = reader.get_ref().metadata()?.len();
+        let mut index = BTreeMap::new();
+        let mut offset = 0;
+        while offset < file_len {
+            let key_len = read_u32(&mut reader).map_err(|_| truncated())?;
+            let mut key = vec![0; key_len as usize];
+            reader.read_exact(&mut key).map_err(|_| truncated())?;
+            let key = String::from_utf8(key)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let value_len = read_u32(&mut reader).map_err(|_| truncated())?;
+            let value_offset = offset + 8 + key_len as u64;
+            reader.seek_relative(value_len.into())?;
+            offset = value_offset + value_len as u64;
+            if file_len < offset {
+                return Err(truncated());
+            }
+            index.insert(key, (value_offset, value_len));
+        }
+
+        Ok(Self {
+            path,
+            file: reader.into_inner(),
+            index,
+        })
+    }
+
+    /// Reads the value of `key` from disk, if present in this segment.
+    fn read(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let Some(&(offset, len)) = self.index.get(key) else {
+            return Ok(None);
+        };
+        let mut value = vec![0; len as usize];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut value)?;
+        Ok(Some(value))
+    }
+}
+
+/// Writes a new segment from sorted `entries` and atomically moves it into place.
+fn write_segment(
+    dir: &Path,
+    seq: u64,
+    entries: impl IntoIterator<Item = io::Result<(String, Vec<u8>)>>,
+) -> io::Result<Segment> {
+    let path = segment_path(dir, seq);
+    let tmp_path = path.with_extension(TMP_EXT);
+
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    for entry in entries {
+        let (key, value) = entry?;
+        write_entry(&mut writer, &key, &value)?;
+    }
+    let file = writer
+        .into_inner()
+        .map_err(io::IntoInnerError::into_error)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, &path)?;
+    // Make the rename itself durable.
+    #[cfg(unix)]
+    File::open(dir)?.sync_all()?;
+
+    Segment::open(path)
+}
+
+fn segment_path(dir: &Path, seq: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", seq, SEGMENT_EXT))
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
@@ -0,0 +1,52 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io;
+use std::rc::Rc;
+
+use super::StateBackend;
+
+/// An in-memory [`StateBackend`], mainly for testing.
+///
+/// Clones share the same committed data, so a clone kept aside can be used to "restart" a graph
+/// within a single process. Values stored but not yet committed are not shared.
+#[derive(Clone, Default, Debug)]
+pub struct MemoryBackend {
+    committed: Rc<RefCell<HashMap<String, Vec<u8>>>>,
+    pending: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryBackend {
+    /// Creates a new empty backend.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The number of committed keys.
+    pub fn len(&self) -> usize {
+        self.committed.borrow().len()
+    }
+
+    /// If there are no committed keys.
+    pub fn is_empty(&self) -> bool {
+        self.committed.borrow().is_empty()
+    }
+}
+
+impl StateBackend for MemoryBackend {
+    fn load(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        if let Some(value) = self.pending.get(key) {
+            return Ok(Some(value.clone()));
+        }
+        Ok(self.committed.borrow().get(key).cloned())
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.pending.insert(key.to_owned(), value.to_vec());
+        Ok(())
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        self.committed.borrow_mut().extend(self.pending.drain());
+        Ok(())
+    }
+}
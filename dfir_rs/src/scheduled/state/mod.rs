@@ -0,0 +1,247 @@
+//! Module for [`StateHandle`], part of the "state API", and the [`StateBackend`]s used to
+//! checkpoint durable (`'durable`) operator state.
+
+use std::any::{Any, TypeId};
+use std::io;
+use std::marker::PhantomData;
+
+use super::StateId;
+
+mod file;
+mod lsm;
+mod memory;
+
+pub use file::FileBackend;
+pub use lsm::LsmBackend;
+pub use memory::MemoryBackend;
+
+/// A handle into a particular [`Hydroflow`](super::graph::Dfir) instance, referring to data
+/// inserted by [`add_state`](super::graph::Dfir::add_state).
+///
+/// If you need to store state handles in a data structure see [`StateHandleErased`] which hides
+/// the generic type parameter.
+#[must_use]
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StateHandle<T> {
+    pub(crate) state_id: StateId,
+    pub(crate) _phantom: PhantomData<*mut T>,
+}
+impl<T> Copy for StateHandle<T> {}
+impl<T> Clone for StateHandle<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+/// A state handle with the generic type parameter erased, allowing it to be stored in omogenous
+/// data structures. The type is tracked internally as data via [`TypeId`].
+///
+/// Use [`StateHandleErased::from(state_handle)`](StateHandleErased::from) to create an instance
+/// from a typed [`StateHandle<T>`].
+///
+/// Use [`StateHandle::<T>::try_from()`](StateHandle::try_from) to convert the `StateHandleErased`
+/// back into a `StateHandle<T>` of the given type `T`. If `T` is the wrong type then the original
+/// `StateHandleErased` will be returned as the `Err`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StateHandleErased {
+    state_id: StateId,
+    type_id: TypeId,
+}
+
+/// See [`StateHandleErased`].
+impl<T> TryFrom<StateHandleErased> for StateHandle<T>
+where
+    T: Any,
+{
+    type Error = StateHandleErased;
+
+    fn try_from(value: StateHandleErased) -> Result<Self, Self::Error> {
+        if TypeId::of::<T>() == value.type_id {
+            Ok(Self {
+                state_id: value.state_id,
+                _phantom: PhantomData,
+            })
+        } else {
+            Err(value)
+        }
+    }
+}
+/// See [`StateHandleErased`].
+impl<T> From<StateHandle<T>> for StateHandleErased
+where
+    T: Any,
+{
+    fn from(value: StateHandle<T>) -> Self {
+        Self {
+            state_id: value.state_id,
+            type_id: TypeId::of::<T>(),
+        }
+    }
+}
+
+/// A key-value store which durable operator state is checkpointed to.
+///
+/// Each durable operator stores its serialized state under its own key via [`Self::store`]. At
+/// the end of each tick the [`Dfir`](super::graph::Dfir) instance calls [`Self::commit`], after
+/// which all the stored values must survive a process restart. On startup
+/// [`Dfir::restore_from`](super::graph::Dfir::restore_from) uses [`Self::load`] to rebuild the
+/// state.
+pub trait StateBackend {
+    /// Loads the most recently stored value for `key`, if any.
+    fn load(&mut self, key: &str) -> io::Result<Option<Vec<u8>>>;
+
+    /// Stores `value` for `key`, replacing any previous value. Not durable until
+    /// [`Self::commit`] is called.
+    fn store(&mut self, key: &str, value: &[u8]) -> io::Result<()>;
+
+    /// Atomically makes all values stored since the last commit durable.
+    ///
+    /// Called at the end of every tick, even if nothing was stored.
+    fn commit(&mut self) -> io::Result<()>;
+}
+
+/// Writes a length-prefixed key-value entry, returning the number of bytes written.
+fn write_entry(writer: &mut impl io::Write, key: &str, value: &[u8]) -> io::Result<u64> {
+    writer.write_all(
+        &u32::try_from(key.len())
+            .map_err(io::Error::other)?
+            .to_le_bytes(),
+    )?;
+    writer.write_all(key.as_bytes())?;
+    writer.write_all(
+        &u32::try_from(value.len())
+            .map_err(io::Error::other)?
+            .to_le_bytes(),
+    )?;
+    writer.write_all(value)?;
+    Ok(entry_len(key, value))
+}
+
+/// The encoded length of an entry written by [`write_entry`].
+fn entry_len(key: &str, value: &[u8]) -> u64 {
+    8 + key.len() as u64 + value.len() as u64
+}
+
+/// Reads a length-prefixed byte string. Returns `None` if `buf` ends before the byte string does.
+fn read_bytes<'a>(buf: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let (len, rest) = buf.split_first_chunk::<4>()?;
+    let len = u32::from_le_bytes(*len) as usize;
+    if rest.len() < len {
+        return None;
+    }
+    let (bytes, rest) = rest.split_at(len);
+    *buf = rest;
+    Some(bytes)
+}
+
+/// Reads an entry written by [`write_entry`]. Returns `Ok(None)` if `buf` is truncated.
+fn read_entry(buf: &mut &[u8]) -> io::Result<Option<(String, Vec<u8>)>> {
+    let Some(key) = read_bytes(buf) else {
+        return Ok(None);
+    };
+    let Some(value) = read_bytes(buf) else {
+        return Ok(None);
+    };
+    let key = String::from_utf8(key.to_vec())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Ok(Some((key, value.to_vec())))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_erasure() {
+        let handle = StateHandle::<String> {
+            state_id: StateId(0),
+            _phantom: PhantomData,
+        };
+        let handle_erased = StateHandleErased::from(handle);
+        let handle_good = StateHandle::<String>::try_from(handle_erased);
+        let handle_bad = StateHandle::<&'static str>::try_from(handle_erased);
+
+        assert_eq!(Ok(handle), handle_good);
+        assert_eq!(Err(handle_erased), handle_bad);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn check_backend<B: StateBackend>(mut open: impl FnMut() -> B) {
+        let mut backend = (open)();
+        assert_eq!(None, backend.load("a").unwrap());
+        backend.store("a", b"1").unwrap();
+        backend.store("b", b"2").unwrap();
+        assert_eq!(Some(b"1".to_vec()), backend.load("a").unwrap());
+        backend.commit().unwrap();
+        backend.store("a", b"3").unwrap();
+        backend.commit().unwrap();
+        // Not committed, lost on reopen.
+        backend.store("b", b"4").unwrap();
+        drop(backend);
+
+        let mut backend = (open)();
+        assert_eq!(Some(b"3".to_vec()), backend.load("a").unwrap());
+        assert_eq!(Some(b"2".to_vec()), backend.load("b").unwrap());
+        assert_eq!(None, backend.load("c").unwrap());
+    }
+
+    #[test]
+    fn test_memory_backend() {
+        let backend = MemoryBackend::new();
+        check_backend(|| backend.clone());
+        assert_eq!(2, backend.len());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_file_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.log");
+        check_backend(|| FileBackend::open(&path).unwrap());
+
+        // Torn write at the end of the log is ignored.
+        let len = std::fs::metadata(&path).unwrap().len();
+        {
+            let mut backend = FileBackend::open(&path).unwrap();
+            backend.store("a", b"5").unwrap();
+            backend.commit().unwrap();
+        }
+        let full_len = std::fs::metadata(&path).unwrap().len();
+        let file = std::fs::OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(full_len - 1).unwrap();
+        drop(file);
+        let mut backend = FileBackend::open(&path).unwrap();
+        assert_eq!(Some(b"3".to_vec()), backend.load("a").unwrap());
+        assert_eq!(len, std::fs::metadata(&path).unwrap().len());
+
+        backend.compact().unwrap();
+        drop(backend);
+        let mut backend = FileBackend::open(&path).unwrap();
+        assert_eq!(Some(b"3".to_vec()), backend.load("a").unwrap());
+        assert_eq!(Some(b"2".to_vec()), backend.load("b").unwrap());
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    #[test]
+    fn test_lsm_backend() {
+        let dir = tempfile::tempdir().unwrap();
+        check_backend(|| LsmBackend::open(dir.path()).unwrap());
+
+        let mut backend = LsmBackend::open(dir.path()).unwrap().with_max_segments(3);
+        assert_eq!(2, backend.segment_count());
+        backend.store("c", &[0]).unwrap();
+        backend.commit().unwrap();
+        assert_eq!(3, backend.segment_count());
+        // The fourth segment triggers a merge.
+        backend.store("c", &[1]).unwrap();
+        backend.commit().unwrap();
+        assert_eq!(1, backend.segment_count());
+        drop(backend);
+
+        let mut backend = LsmBackend::open(dir.path()).unwrap();
+        assert_eq!(1, backend.segment_count());
+        assert_eq!(Some(b"3".to_vec()), backend.load("a").unwrap());
+        assert_eq!(Some(b"2".to_vec()), backend.load("b").unwrap());
+        assert_eq!(Some(vec![1]), backend.load("c").unwrap());
+    }
+}
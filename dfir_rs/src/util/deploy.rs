@@ -2,9 +2,14 @@
 
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
 
+use futures::future::LocalBoxFuture;
+use futures::{Sink, SinkExt, Stream, StreamExt};
 pub use hydroflow_deploy_integration::*;
 use serde::de::DeserializeOwned;
+use tokio::sync::watch;
 
 use crate::scheduled::graph::Dfir;
 
@@ -24,25 +29,139 @@ macro_rules! launch {
 
 pub use crate::launch;
 
+static MESSAGES_SENT: AtomicU64 = AtomicU64::new(0);
+static MESSAGES_RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+type ShutdownHook = Box<dyn FnOnce() -> LocalBoxFuture<'static, ()>>;
+
+thread_local! {
+    static SHUTDOWN: watch::Sender<bool> = watch::channel(false).0;
+    static SHUTDOWN_HOOKS: RefCell<Vec<ShutdownHook>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Counts the messages sent on a network edge between services, see [`DrainStatus`].
+pub fn count_sent<T, S: Sink<T, Error = std::io::Error> + Unpin>(
+    sink: S,
+) -> impl Sink<T, Error = std::io::Error> + Unpin {
+    sink.with(|item| {
+        MESSAGES_SENT.fetch_add(1, Ordering::Relaxed);
+        futures::future::ready(Ok::<_, std::io::Error>(item))
+    })
+}
+
+/// Counts the messages received on a network edge between services, see [`DrainStatus`].
+pub fn count_received<S: Stream + Unpin>(source: S) -> impl Stream<Item = S::Item> + Unpin {
+    source.inspect(|_| {
+        MESSAGES_RECEIVED.fetch_add(1, Ordering::Relaxed);
+    })
+}
+
+/// Signals the flows running on this thread to shut down, see [`shutdown_signal`].
+pub fn request_shutdown() {
+    SHUTDOWN.with(|shutdown| shutdown.send_replace(true));
+}
+
+/// A stream that emits a single element once shutdown has been requested for the flows running
+/// on this thread, either by [`request_shutdown`] or by Hydro Deploy stopping the service
+/// gracefully.
+pub fn shutdown_signal() -> impl Stream<Item = ()> + Unpin {
+    let mut receiver = SHUTDOWN.with(|shutdown| shutdown.subscribe());
+    Box::pin(futures::stream::once(async move {
+        // the sender lives as long as the thread, so this only fails during thread teardown
+        let _ = receiver.wait_for(|shutdown| *shutdown).await;
+    }))
+}
+
+/// Registers a hook that is run when the flow on this thread finishes a graceful shutdown,
+/// after it has drained but before its `dest_sink`s are flushed and closed.
+pub fn on_shutdown<F: Future<Output = ()> + 'static>(hook: impl FnOnce() -> F + 'static) {
+    SHUTDOWN_HOOKS.with(|hooks| {
+        hooks.borrow_mut().push(Box::new(move || {
+            Box::pin(hook()) as LocalBoxFuture<'static, ()>
+        }))
+    });
+}
+
+/// Runs (and removes) the hooks registered with [`on_shutdown`], in registration order.
+pub async fn run_shutdown_hooks() {
+    let hooks = SHUTDOWN_HOOKS.with(|hooks| std::mem::take(&mut *hooks.borrow_mut()));
+    for hook in hooks {
+        hook().await;
+    }
+}
+
+enum LaunchEvent {
+    Command(Option<String>),
+    Events,
+}
+
+/// Runs a deployed flow, handling the commands sent by Hydro Deploy on stdin:
+///
+/// * `stop` exits immediately.
+/// * `shutdown` triggers [`shutdown_signal`] and acknowledges with `ack shutdown`.
+/// * `drain` reports the [`DrainStatus`] of the flow as `drain: {json}`.
+/// * `finish` runs the [`on_shutdown`] hooks, waits for the flow's sinks to be flushed and exits.
 pub async fn launch_flow(mut flow: Dfir<'_>) {
-    let stop = tokio::sync::oneshot::channel();
-    tokio::task::spawn_blocking(|| {
+    let (command_send, mut commands) = tokio::sync::mpsc::unbounded_channel::<String>();
+    tokio::task::spawn_blocking(move || loop {
         let mut line = String::new();
-        std::io::stdin().read_line(&mut line).unwrap();
-        if line.starts_with("stop") {
-            stop.0.send(()).unwrap();
-        } else {
-            eprintln!("Unexpected stdin input: {:?}", line);
+        if std::io::stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+
+        let is_last = line.starts_with("stop") || line.starts_with("finish");
+        if command_send.send(line).is_err() || is_last {
+            break;
         }
     });
 
     let local_set = tokio::task::LocalSet::new();
-    let flow = local_set.run_until(flow.run_async());
-
-    tokio::select! {
-        _ = stop.1 => {},
-        _ = flow => {}
-    }
+    local_set
+        .run_until(async move {
+            let mut stdin_open = true;
+            loop {
+                flow.run_available_async().await;
+
+                let event = tokio::select! {
+                    biased;
+                    command = commands.recv(), if stdin_open => LaunchEvent::Command(command),
+                    _ = flow.recv_events_async() => LaunchEvent::Events,
+                };
+
+                let LaunchEvent::Command(command) = event else {
+                    continue;
+                };
+                let Some(command) = command else {
+                    stdin_open = false;
+                    continue;
+                };
+
+                match command.trim() {
+                    "stop" => return,
+                    "shutdown" => {
+                        request_shutdown();
+                        println!("ack shutdown");
+                    }
+                    "drain" => {
+                        // let tasks such as network sinks pick up the items sent to them
+                        tokio::task::yield_now().await;
+                        let status = DrainStatus {
+                            idle: flow.try_recv_events() == 0,
+                            sent: MESSAGES_SENT.load(Ordering::Relaxed),
+                            received: MESSAGES_RECEIVED.load(Ordering::Relaxed),
+                        };
+                        println!("drain: {}", serde_json::to_string(&status).unwrap());
+                    }
+                    "finish" => {
+                        run_shutdown_hooks().await;
+                        flow.close().await;
+                        return;
+                    }
+                    other => eprintln!("Unexpected stdin input: {:?}", other),
+                }
+            }
+        })
+        .await
 }
 
 /// Contains runtime information passed by Hydro Deploy to a program,
@@ -3,6 +3,8 @@ use std::collections::HashMap;
 use std::hash::Hash;
 use std::iter::FusedIterator;
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
 /// A vector that supports efficient deletion without reordering all subsequent items.
 pub struct SparseVec<T> {
     items: Vec<Option<T>>,
@@ -43,6 +45,23 @@ impl<T: Clone + Eq + Hash> SparseVec<T> {
     }
 }
 
+/// Serializes only the live (non-deleted) items, in order.
+impl<T: Serialize> Serialize for SparseVec<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.items.iter().flatten().collect::<Vec<_>>())
+    }
+}
+
+impl<'de, T: Clone + Eq + Hash + Deserialize<'de>> Deserialize<'de> for SparseVec<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut out = Self::default();
+        for item in Vec::<T>::deserialize(deserializer)? {
+            out.push(item);
+        }
+        Ok(out)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -73,4 +92,22 @@ mod test {
         assert_eq!(x.items.len(), 0);
         assert_eq!(x.item_locs.len(), 0);
     }
+
+    #[test]
+    fn serde_roundtrip_skips_deleted() {
+        let mut x = SparseVec::default();
+
+        x.push(0);
+        x.push(1);
+        x.push(2);
+
+        x.delete(&1);
+
+        let bytes = bincode::serialize(&x).unwrap();
+        let mut y: SparseVec<i32> = bincode::deserialize(&bytes).unwrap();
+        assert_eq!(collect(&y), vec![0, 2]);
+
+        y.delete(&2);
+        assert_eq!(collect(&y), vec![0]);
+    }
 }
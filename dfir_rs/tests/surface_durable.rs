@@ -0,0 +1,235 @@
+use std::collections::HashSet;
+use std::io;
+
+use dfir_rs::lattices::set_union::{SetUnionHashSet, SetUnionSingletonSet};
+use dfir_rs::scheduled::state::{MemoryBackend, StateBackend};
+use dfir_rs::util::{collect_ready, Persistence};
+use dfir_rs::{assert_graphvis_snapshots, dfir_syntax};
+use multiplatform_test::multiplatform_test;
+
+#[multiplatform_test]
+pub fn test_persist_durable() {
+    let backend = MemoryBackend::new();
+
+    let (input_send, input_recv) = dfir_rs::util::unbounded_channel::<u32>();
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<u32>();
+    let mut hf = dfir_syntax! {
+        source_stream(input_recv)
+            -> persist::<'durable>()
+            -> for_each(|x| result_send.send(x).unwrap());
+    };
+    assert_graphvis_snapshots!(hf);
+    hf.restore_from(backend.clone()).unwrap();
+
+    input_send.send(1).unwrap();
+    input_send.send(2).unwrap();
+    hf.run_tick();
+    assert_eq!(&[1, 2], &*collect_ready::<Vec<_>, _>(&mut result_recv));
+    assert_eq!(1, backend.len());
+    drop(hf);
+
+    // "Restart" with the same backend.
+    let (input_send, input_recv) = dfir_rs::util::unbounded_channel::<u32>();
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<u32>();
+    let mut hf = dfir_syntax! {
+        source_stream(input_recv)
+            -> persist::<'durable>()
+            -> for_each(|x| result_send.send(x).unwrap());
+    };
+    hf.restore_from(backend).unwrap();
+
+    input_send.send(3).unwrap();
+    hf.run_tick();
+    assert_eq!(&[1, 2, 3], &*collect_ready::<Vec<_>, _>(&mut result_recv));
+}
+
+#[multiplatform_test]
+pub fn test_persist_mut_durable() {
+    let backend = MemoryBackend::new();
+
+    let (input_send, input_recv) = dfir_rs::util::unbounded_channel::<Persistence<u32>>();
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<u32>();
+    let mut hf = dfir_syntax! {
+        source_stream(input_recv)
+            -> persist_mut::<'durable>()
+            -> for_each(|x| result_send.send(x).unwrap());
+    };
+    hf.restore_from(backend.clone()).unwrap();
+
+    input_send.send(Persistence::Persist(1)).unwrap();
+    input_send.send(Persistence::Persist(2)).unwrap();
+    input_send.send(Persistence::Persist(3)).unwrap();
+    input_send.send(Persistence::Delete(2)).unwrap();
+    hf.run_tick();
+    assert_eq!(&[1, 3], &*collect_ready::<Vec<_>, _>(&mut result_recv));
+    drop(hf);
+
+    let (input_send, input_recv) = dfir_rs::util::unbounded_channel::<Persistence<u32>>();
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<u32>();
+    let mut hf = dfir_syntax! {
+        source_stream(input_recv)
+            -> persist_mut::<'durable>()
+            -> for_each(|x| result_send.send(x).unwrap());
+    };
+    hf.restore_from(backend).unwrap();
+
+    input_send.send(Persistence::Delete(1)).unwrap();
+    input_send.send(Persistence::Persist(4)).unwrap();
+    hf.run_tick();
+    assert_eq!(&[3, 4], &*collect_ready::<Vec<_>, _>(&mut result_recv));
+}
+
+#[multiplatform_test]
+pub fn test_fold_keyed_durable() {
+    let backend = MemoryBackend::new();
+
+    let (input_send, input_recv) = dfir_rs::util::unbounded_channel::<(String, u32)>();
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<(String, u32)>();
+    let mut hf = dfir_syntax! {
+        source_stream(input_recv)
+            -> fold_keyed::<'durable>(|| 0, |acc: &mut u32, x| *acc += x)
+            -> for_each(|kv| result_send.send(kv).unwrap());
+    };
+    hf.restore_from(backend.clone()).unwrap();
+
+    input_send.send(("a".to_owned(), 1)).unwrap();
+    input_send.send(("b".to_owned(), 2)).unwrap();
+    input_send.send(("a".to_owned(), 3)).unwrap();
+    hf.run_tick();
+    assert_eq!(
+        HashSet::from_iter([("a".to_owned(), 4), ("b".to_owned(), 2)]),
+        collect_ready::<HashSet<_>, _>(&mut result_recv)
+    );
+    drop(hf);
+
+    let (input_send, input_recv) = dfir_rs::util::unbounded_channel::<(String, u32)>();
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<(String, u32)>();
+    let mut hf = dfir_syntax! {
+        source_stream(input_recv)
+            -> fold_keyed::<'durable>(|| 0, |acc: &mut u32, x| *acc += x)
+            -> for_each(|kv| result_send.send(kv).unwrap());
+    };
+    hf.restore_from(backend).unwrap();
+
+    input_send.send(("b".to_owned(), 5)).unwrap();
+    hf.run_tick();
+    assert_eq!(
+        HashSet::from_iter([("a".to_owned(), 4), ("b".to_owned(), 7)]),
+        collect_ready::<HashSet<_>, _>(&mut result_recv)
+    );
+}
+
+#[multiplatform_test]
+pub fn test_state_durable() {
+    let backend = MemoryBackend::new();
+
+    let (input_send, input_recv) = dfir_rs::util::unbounded_channel::<u32>();
+    let mut hf = dfir_syntax! {
+        source_stream(input_recv)
+            -> map(SetUnionSingletonSet::new_from)
+            -> state::<'durable, SetUnionHashSet<u32>>();
+    };
+    hf.restore_from(backend.clone()).unwrap();
+
+    input_send.send(1).unwrap();
+    input_send.send(2).unwrap();
+    hf.run_tick();
+    drop(hf);
+
+    let (input_send, input_recv) = dfir_rs::util::unbounded_channel::<u32>();
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<HashSet<u32>>();
+    let mut hf = dfir_syntax! {
+        my_state = source_stream(input_recv)
+            -> map(SetUnionSingletonSet::new_from)
+            -> state::<'durable, SetUnionHashSet<u32>>();
+
+        source_iter([()])
+            -> for_each(|()| result_send.send(#my_state.as_reveal_ref().clone()).unwrap());
+    };
+    hf.restore_from(backend).unwrap();
+
+    input_send.send(3).unwrap();
+    hf.run_tick();
+    assert_eq!(
+        &[HashSet::from_iter([1, 2, 3])],
+        &*collect_ready::<Vec<_>, _>(&mut result_recv)
+    );
+}
+
+#[multiplatform_test]
+pub fn test_enumerate_durable() {
+    let backend = MemoryBackend::new();
+
+    for expected in [[(0, 'a'), (1, 'b')], [(2, 'a'), (3, 'b')]] {
+        let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<(usize, char)>();
+        let mut hf = dfir_syntax! {
+            source_iter(['a', 'b'])
+                -> enumerate::<'durable>()
+                -> for_each(|x| result_send.send(x).unwrap());
+        };
+        hf.restore_from(backend.clone()).unwrap();
+        hf.run_tick();
+        assert_eq!(&expected, &*collect_ready::<Vec<_>, _>(&mut result_recv));
+    }
+}
+
+/// A backend whose commits always fail.
+struct FailingBackend;
+impl StateBackend for FailingBackend {
+    fn load(&mut self, _key: &str) -> io::Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+
+    fn store(&mut self, _key: &str, _value: &[u8]) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        Err(io::Error::other("disk full"))
+    }
+}
+
+#[multiplatform_test]
+pub fn test_checkpoint_error() {
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<u32>();
+    let mut hf = dfir_syntax! {
+        source_iter([1, 2])
+            -> persist::<'durable>()
+            -> for_each(|x| result_send.send(x).unwrap());
+    };
+    hf.restore_from(FailingBackend).unwrap();
+
+    // The failed checkpoint is reported instead of stopping the graph.
+    hf.run_tick();
+    assert_eq!("disk full", hf.take_checkpoint_error().unwrap().to_string());
+    hf.run_tick();
+    assert_eq!(
+        &[1, 2, 1, 2],
+        &*collect_ready::<Vec<_>, _>(&mut result_recv)
+    );
+    assert!(hf.take_checkpoint_error().is_some());
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[test]
+pub fn test_persist_durable_lsm() {
+    use dfir_rs::scheduled::state::LsmBackend;
+
+    let dir = tempfile::tempdir().unwrap();
+
+    for tick in 0..3 {
+        let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<u32>();
+        let mut hf = dfir_syntax! {
+            source_iter([tick])
+                -> persist::<'durable>()
+                -> for_each(|x| result_send.send(x).unwrap());
+        };
+        hf.restore_from(LsmBackend::open(dir.path()).unwrap())
+            .unwrap();
+        hf.run_tick();
+        assert_eq!(
+            &*(0..=tick).collect::<Vec<_>>(),
+            &*collect_ready::<Vec<_>, _>(&mut result_recv)
+        );
+    }
+}
@@ -36,3 +36,144 @@ pub fn test_flo_nested() {
     assert_graphvis_snapshots!(df);
     df.run_available();
 }
+
+#[multiplatform_test]
+pub fn test_flo_all_iterations() {
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<(usize, &str)>();
+
+    let mut df = dfir_syntax! {
+        users = source_iter(["alice", "bob"]);
+        loop {
+            batched = users -> batch() -> flatten() -> map(|user| (user.len(), user));
+        }
+        batched -> all_iterations() -> for_each(|x| result_send.send(x).unwrap());
+    };
+    assert_graphvis_snapshots!(df);
+    df.run_available();
+
+    assert_eq!(
+        &[(5, "alice"), (3, "bob")],
+        &*dfir_rs::util::collect_ready::<Vec<_>, _>(&mut result_recv)
+    );
+}
+
+#[multiplatform_test]
+pub fn test_repeat_n() {
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<(usize, usize)>();
+
+    let mut df = dfir_syntax! {
+        nums = source_iter([1, 2]);
+        loop {
+            nums -> repeat_n(3)
+                -> flatten()
+                -> map(|x| (context.current_loop_iteration(), x))
+                -> for_each(|x| result_send.send(x).unwrap());
+        }
+    };
+    assert_graphvis_snapshots!(df);
+    df.run_available();
+
+    assert_eq!(
+        &[(0, 1), (0, 2), (1, 1), (1, 2), (2, 1), (2, 2)],
+        &*dfir_rs::util::collect_ready::<Vec<_>, _>(&mut result_recv)
+    );
+}
+
+#[multiplatform_test]
+pub fn test_next_iteration() {
+    let (input_send, input_recv) = dfir_rs::util::unbounded_channel::<usize>();
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<(usize, usize)>();
+
+    let mut df = dfir_syntax! {
+        init = source_stream(input_recv) -> tee();
+        loop {
+            counter = union() -> tee();
+            init -> batch() -> flatten() -> counter;
+            counter -> map(|x| x * 2) -> next_iteration() -> counter;
+            init -> repeat_n(3) -> for_each(|_| {});
+        }
+        counter
+            -> all_iterations()
+            -> map(|x| (context.current_tick().0 as usize, x))
+            -> for_each(|x| result_send.send(x).unwrap());
+    };
+    assert_graphvis_snapshots!(df);
+
+    input_send.send(1).unwrap();
+    df.run_available();
+    assert_eq!(
+        &[(0, 1), (0, 2), (0, 4)],
+        &*dfir_rs::util::collect_ready::<Vec<_>, _>(&mut result_recv)
+    );
+
+    // Items sent during the final iteration are not carried into the next tick.
+    input_send.send(3).unwrap();
+    df.run_available();
+    assert_eq!(
+        &[(1, 3), (1, 6), (1, 12)],
+        &*dfir_rs::util::collect_ready::<Vec<_>, _>(&mut result_recv)
+    );
+}
+
+#[multiplatform_test]
+pub fn test_until_fixpoint() {
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<(usize, usize)>();
+
+    let mut df = dfir_syntax! {
+        edges = source_iter([(1, 2), (2, 3), (3, 4), (5, 6)]);
+        roots = source_iter([1]);
+        loop {
+            reached = union() -> until_fixpoint() -> tee();
+            roots -> batch() -> flatten() -> reached;
+            edges_batch = edges -> batch() -> flatten();
+            reached -> map(|v| (v, ())) -> [0]edges_join;
+            edges_batch -> [1]edges_join;
+            // The edges are only batched in the first iteration, so are kept with 'static.
+            edges_join = join::<'tick, 'static>()
+                -> map(|(_v, ((), next))| next)
+                -> [0]next;
+            reached -> [1]next;
+            next = union() -> unique::<'tick>() -> next_iteration() -> reached;
+        }
+        reached
+            -> last_iteration()
+            -> map(|v| (context.current_tick().0 as usize, v))
+            -> for_each(|x| result_send.send(x).unwrap());
+    };
+    assert_graphvis_snapshots!(df);
+    df.run_available();
+
+    let mut result = dfir_rs::util::collect_ready::<Vec<_>, _>(&mut result_recv);
+    result.sort();
+    assert_eq!(&[(0, 1), (0, 2), (0, 3), (0, 4)], &*result);
+}
+
+#[multiplatform_test]
+pub fn test_last_iteration_nested() {
+    let (result_send, mut result_recv) = dfir_rs::util::unbounded_channel::<(usize, usize)>();
+
+    let mut df = dfir_syntax! {
+        init = source_iter([1]);
+        loop {
+            outer = init -> repeat_n(2) -> flatten() -> map(|x| x * 10) -> tee();
+            loop {
+                inner = union() -> tee();
+                outer -> batch() -> flatten() -> inner;
+                inner -> map(|x| x + 1) -> next_iteration() -> inner;
+                outer -> repeat_n(3) -> for_each(|_| {});
+            }
+            // The final inner iteration, in each outer iteration.
+            inner
+                -> last_iteration()
+                -> map(|x| (context.current_loop_iteration(), x))
+                -> for_each(|x| result_send.send(x).unwrap());
+        }
+    };
+    assert_graphvis_snapshots!(df);
+    df.run_available();
+
+    assert_eq!(
+        &[(0, 12), (1, 12)],
+        &*dfir_rs::util::collect_ready::<Vec<_>, _>(&mut result_recv)
+    );
+}
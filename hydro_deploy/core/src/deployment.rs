@@ -1,17 +1,19 @@
 use std::collections::HashMap;
 use std::future::Future;
 use std::sync::{Arc, Weak};
+use std::time::Duration;
 
 use anyhow::Result;
 use futures::{FutureExt, StreamExt, TryStreamExt};
 use tokio::sync::RwLock;
+use tokio::time::Instant;
 
 use super::gcp::GcpNetwork;
 use super::{
     progress, CustomService, GcpComputeEngineHost, Host, LocalhostHost, ResourcePool,
     ResourceResult, Service,
 };
-use crate::{AzureHost, ServiceBuilder};
+use crate::{AzureHost, DrainStatus, ServiceBuilder};
 
 pub struct Deployment {
     pub hosts: Vec<Weak<dyn Host>>,
@@ -23,6 +25,17 @@ pub struct Deployment {
     next_service_id: usize,
 }
 
+/// The outcome of [`Deployment::stop_graceful`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GracefulStopReport {
+    /// Whether every service finished processing its in-flight messages before the timeout.
+    pub drained: bool,
+    /// Messages that had been sent but not yet received when the services were stopped.
+    pub in_flight: u64,
+    /// Services that were still processing input when the services were stopped.
+    pub busy: usize,
+}
+
 impl Default for Deployment {
     fn default() -> Self {
         Self::new()
@@ -192,6 +205,102 @@ impl Deployment {
         .await?;
         Ok(())
     }
+
+    /// Restarts the services that have staged upgrades with their new programs, while the other
+    /// services keep running and reconnect to the restarted ones.
+    pub async fn upgrade(&mut self) -> Result<()> {
+        self.services.retain(|weak| weak.strong_count() > 0);
+
+        progress::ProgressTracker::with_group("upgrade", None, || {
+            let all_services_upgrade = self.services.iter().filter_map(Weak::upgrade).map(
+                |service: Arc<RwLock<dyn Service>>| async move {
+                    service.write().await.upgrade().await?;
+                    Ok(()) as Result<()>
+                },
+            );
+
+            futures::future::try_join_all(all_services_upgrade)
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Stops all services after letting in-flight messages finish processing.
+    ///
+    /// Services are first asked to shut down, which fires their shutdown signals, and are then
+    /// polled until every service is idle and every message sent between services has been
+    /// received, for two consecutive rounds. Services are then asked to run their shutdown
+    /// hooks and exit. If the deployment has not drained within `timeout`, services are stopped
+    /// immediately, as in [`Deployment::stop`].
+    pub async fn stop_graceful(&mut self, timeout: Duration) -> Result<GracefulStopReport> {
+        self.services.retain(|weak| weak.strong_count() > 0);
+        let services = self
+            .services
+            .iter()
+            .filter_map(Weak::upgrade)
+            .collect::<Vec<_>>();
+        let deadline = Instant::now() + timeout;
+
+        progress::ProgressTracker::with_group("shutdown", None, || {
+            futures::future::try_join_all(
+                services
+                    .iter()
+                    .map(|service| async move { service.write().await.shutdown().await }),
+            )
+        })
+        .await?;
+
+        let mut previous = None;
+        let report = loop {
+            let statuses = futures::future::try_join_all(
+                services
+                    .iter()
+                    .map(|service| async move { service.write().await.drain_status().await }),
+            )
+            .await?;
+
+            let statuses = statuses.into_iter().flatten().collect::<Vec<DrainStatus>>();
+            let sent = statuses.iter().map(|s| s.sent).sum::<u64>();
+            let received = statuses.iter().map(|s| s.received).sum::<u64>();
+            let report = GracefulStopReport {
+                drained: false,
+                in_flight: sent.saturating_sub(received),
+                busy: statuses.iter().filter(|s| !s.idle).count(),
+            };
+
+            // counters that are unchanged across two rounds rule out messages that were
+            // received after one service was polled but sent before another was
+            if report.in_flight == 0 && report.busy == 0 && previous.as_ref() == Some(&statuses) {
+                break GracefulStopReport {
+                    drained: true,
+                    ..report
+                };
+            }
+
+            if Instant::now() >= deadline {
+                break report;
+            }
+
+            previous = Some(statuses);
+            tokio::time::sleep(Duration::from_millis(50).min(deadline - Instant::now())).await;
+        };
+
+        if report.drained {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            progress::ProgressTracker::with_group("finish", None, || {
+                futures::future::try_join_all(
+                    services.iter().map(|service| async move {
+                        service.write().await.finish(remaining).await
+                    }),
+                )
+            })
+            .await?;
+        } else {
+            self.stop().await?;
+        }
+
+        Ok(report)
+    }
 }
 
 impl Deployment {
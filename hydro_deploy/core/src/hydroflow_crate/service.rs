@@ -6,16 +6,18 @@ use std::time::Duration;
 use anyhow::{bail, Context, Result};
 use async_trait::async_trait;
 use futures::Future;
-use hydroflow_deploy_integration::{InitConfig, ServerPort};
+use hydroflow_deploy_integration::{DrainStatus, InitConfig, ServerPort};
 use serde::Serialize;
 use tokio::sync::{mpsc, RwLock};
 
 use super::build::{build_crate_memoized, BuildError, BuildOutput, BuildParams};
 use super::ports::{self, HydroflowPortConfig, HydroflowSink, SourcePath};
 use super::tracing_options::TracingOptions;
+use super::HydroflowCrate;
 use crate::progress::ProgressTracker;
 use crate::{
     Host, LaunchedBinary, LaunchedHost, ResourceBatch, ResourceResult, ServerStrategy, Service,
+    ServiceBuilder,
 };
 
 pub struct HydroflowCrateService {
@@ -44,6 +46,13 @@ pub struct HydroflowCrateService {
 
     launched_binary: Option<Box<dyn LaunchedBinary>>,
     started: bool,
+
+    /// The ports this service connects to, as sent to the binary when it was started, so that
+    /// an upgraded binary can be started with the same connections.
+    sink_defns: Option<String>,
+
+    /// The build to switch to on the next [`Service::upgrade`], see [`Self::stage_upgrade`].
+    staged_upgrade: Option<(BuildParams, Option<TracingOptions>, Option<Vec<String>>)>,
 }
 
 impl HydroflowCrateService {
@@ -93,6 +102,8 @@ impl HydroflowCrateService {
             server_defns: Arc::new(RwLock::new(HashMap::new())),
             launched_binary: None,
             started: false,
+            sink_defns: None,
+            staged_upgrade: None,
         }
     }
 
@@ -152,6 +163,16 @@ impl HydroflowCrateService {
         }
     }
 
+    /// Stages `upgrade` to replace the binary of this service on the next [`Service::upgrade`].
+    ///
+    /// The new binary must use the same ports as the current one. It keeps the host and
+    /// connections of this service, and rebinds the addresses the current binary listens on so
+    /// that other services can reconnect to it.
+    pub fn stage_upgrade(&mut self, upgrade: HydroflowCrate) {
+        let upgraded = upgrade.build(self.id);
+        self.staged_upgrade = Some((upgraded.build_params, upgraded.tracing, upgraded.args));
+    }
+
     pub fn stdout(&self) -> mpsc::UnboundedReceiver<String> {
         self.launched_binary.as_ref().unwrap().stdout()
     }
@@ -170,6 +191,126 @@ impl HydroflowCrateService {
     }
 }
 
+impl HydroflowCrateService {
+    fn display_name(&self) -> String {
+        self.display_id
+            .clone()
+            .unwrap_or_else(|| format!("service/{}", self.id))
+    }
+
+    /// Sends `command` to the launched binary and returns the rest of the first line it prints
+    /// that starts with `reply`.
+    async fn send_command(&self, command: &str, reply: &str) -> Result<String> {
+        let launched_binary = self.launched_binary.as_ref().unwrap();
+        let stdout_receiver = launched_binary.deploy_stdout_matching(reply);
+        launched_binary.stdin().send(format!("{command}\n"))?;
+
+        let line = tokio::time::timeout(Duration::from_secs(60), stdout_receiver)
+            .await
+            .with_context(|| format!("{} did not reply to {command}", self.display_name()))??;
+        Ok(line[reply.len()..].to_string())
+    }
+
+    /// Launches the binary and waits for it to bind its ports. If `rebind` is set, ports are
+    /// bound at the addresses in it instead of fresh ones.
+    async fn launch(&mut self, rebind: Option<&HashMap<String, ServerPort>>) -> Result<()> {
+        let launched_host = self.launched_host.as_ref().unwrap();
+
+        let built = self.build().await?;
+        let args = self.args.as_ref().cloned().unwrap_or_default();
+
+        let binary = launched_host
+            .launch_binary(
+                self.display_name(),
+                built,
+                &args,
+                self.tracing.clone(),
+            )
+            .await?;
+
+        let mut bind_config = HashMap::new();
+        for (port_name, bind_type) in self.port_to_bind.iter() {
+            let port_config = launched_host.server_config(bind_type);
+            let port_config = match rebind.and_then(|previous| previous.get(port_name)) {
+                Some(previous) => port_config.rebind(previous),
+                None => port_config,
+            };
+            bind_config.insert(port_name.clone(), port_config);
+        }
+
+        let formatted_bind_config =
+            serde_json::to_string::<InitConfig>(&(bind_config, self.meta.clone())).unwrap();
+
+        // request stdout before sending config so we don't miss the "ready" response
+        let stdout_receiver = binary.deploy_stdout();
+
+        binary.stdin().send(format!("{formatted_bind_config}\n"))?;
+
+        let ready_line = ProgressTracker::leaf(
+            "waiting for ready",
+            tokio::time::timeout(Duration::from_secs(60), stdout_receiver),
+        )
+        .await
+        .context("Timed out waiting for ready")?
+        .context("Program unexpectedly quit")?;
+        if ready_line.starts_with("ready: ") {
+            *self.server_defns.try_write().unwrap() =
+                serde_json::from_str(ready_line.trim_start_matches("ready: ")).unwrap();
+        } else {
+            bail!("expected ready");
+        }
+
+        self.launched_binary = Some(binary);
+
+        Ok(())
+    }
+
+    /// Sends the ports this service connects to to the binary, which then starts its flow.
+    async fn send_start(&self) -> Result<()> {
+        let formatted_defns = self.sink_defns.as_ref().unwrap();
+
+        let stdout_receiver = self.launched_binary.as_ref().unwrap().deploy_stdout();
+
+        self.launched_binary
+            .as_ref()
+            .unwrap()
+            .stdin()
+            .send(format!("start: {formatted_defns}\n"))
+            .unwrap();
+
+        let start_ack_line = ProgressTracker::leaf(
+            self.display_name() + " / waiting for ack start",
+            tokio::time::timeout(Duration::from_secs(60), stdout_receiver),
+        )
+        .await??;
+        if !start_ack_line.starts_with("ack start") {
+            bail!("expected ack start");
+        }
+
+        Ok(())
+    }
+
+    /// Asks the binary to stop, and kills it if it has not exited within a minute.
+    async fn stop_binary(&mut self) -> Result<()> {
+        let launched_binary = self.launched_binary.as_mut().unwrap();
+        launched_binary.stdin().send("stop\n".to_string())?;
+
+        let timeout_result = ProgressTracker::leaf(
+            "waiting for exit",
+            tokio::time::timeout(Duration::from_secs(60), launched_binary.wait()),
+        )
+        .await;
+        match timeout_result {
+            Err(_timeout) => {} // `wait()` timed out, but stop will force quit.
+            Ok(Err(unexpected_error)) => return Err(unexpected_error), // `wait()` errored.
+            Ok(Ok(_exit_status)) => {}
+        }
+        launched_binary.stop().await?;
+
+        Ok(())
+    }
+}
+
 #[async_trait]
 impl Service for HydroflowCrateService {
     fn collect_resources(&self, _resource_batch: &mut ResourceBatch) {
@@ -221,61 +362,7 @@ impl Service for HydroflowCrateService {
             return Ok(());
         }
 
-        ProgressTracker::with_group(
-            self.display_id
-                .clone()
-                .unwrap_or_else(|| format!("service/{}", self.id)),
-            None,
-            || async {
-                let launched_host = self.launched_host.as_ref().unwrap();
-
-                let built = self.build().await?;
-                let args = self.args.as_ref().cloned().unwrap_or_default();
-
-                let binary = launched_host
-                    .launch_binary(
-                        self.display_id
-                            .clone()
-                            .unwrap_or_else(|| format!("service/{}", self.id)),
-                        built,
-                        &args,
-                        self.tracing.clone(),
-                    )
-                    .await?;
-
-                let mut bind_config = HashMap::new();
-                for (port_name, bind_type) in self.port_to_bind.iter() {
-                    bind_config.insert(port_name.clone(), launched_host.server_config(bind_type));
-                }
-
-                let formatted_bind_config =
-                    serde_json::to_string::<InitConfig>(&(bind_config, self.meta.clone())).unwrap();
-
-                // request stdout before sending config so we don't miss the "ready" response
-                let stdout_receiver = binary.deploy_stdout();
-
-                binary.stdin().send(format!("{formatted_bind_config}\n"))?;
-
-                let ready_line = ProgressTracker::leaf(
-                    "waiting for ready",
-                    tokio::time::timeout(Duration::from_secs(60), stdout_receiver),
-                )
-                .await
-                .context("Timed out waiting for ready")?
-                .context("Program unexpectedly quit")?;
-                if ready_line.starts_with("ready: ") {
-                    *self.server_defns.try_write().unwrap() =
-                        serde_json::from_str(ready_line.trim_start_matches("ready: ")).unwrap();
-                } else {
-                    bail!("expected ready");
-                }
-
-                self.launched_binary = Some(binary);
-
-                Ok(())
-            },
-        )
-        .await
+        ProgressTracker::with_group(self.display_name(), None, || self.launch(None)).await
     }
 
     async fn start(&mut self) -> Result<()> {
@@ -288,58 +375,88 @@ impl Service for HydroflowCrateService {
             sink_ports.insert(port_name.clone(), outgoing.load_instantiated(&|p| p).await);
         }
 
-        let formatted_defns = serde_json::to_string(&sink_ports).unwrap();
+        self.sink_defns = Some(serde_json::to_string(&sink_ports).unwrap());
+        self.send_start().await?;
 
-        let stdout_receiver = self.launched_binary.as_ref().unwrap().deploy_stdout();
+        self.started = true;
+        Ok(())
+    }
 
-        self.launched_binary
-            .as_ref()
-            .unwrap()
-            .stdin()
-            .send(format!("start: {formatted_defns}\n"))
-            .unwrap();
+    async fn stop(&mut self) -> Result<()> {
+        ProgressTracker::with_group(self.display_name(), None, || self.stop_binary()).await
+    }
 
-        let start_ack_line = ProgressTracker::leaf(
-            self.display_id
-                .clone()
-                .unwrap_or_else(|| format!("service/{}", self.id))
-                + " / waiting for ack start",
-            tokio::time::timeout(Duration::from_secs(60), stdout_receiver),
-        )
-        .await??;
-        if !start_ack_line.starts_with("ack start") {
-            bail!("expected ack start");
+    async fn upgrade(&mut self) -> Result<()> {
+        let Some((build_params, tracing, args)) = self.staged_upgrade.take() else {
+            return Ok(());
+        };
+
+        ProgressTracker::with_group(self.display_name(), None, || async {
+            self.build_params = build_params;
+            self.tracing = tracing;
+            self.args = args;
+
+            let built = ProgressTracker::leaf("build", self.build()).await?;
+            let launched_host = self.launched_host.clone().unwrap();
+            launched_host.copy_binary(built).await?;
+
+            // the new binary binds the same addresses, so the old one must have exited first
+            let previous_defns = self.server_defns.read().await.clone();
+            if self.launched_binary.is_some() {
+                self.stop_binary().await?;
+            }
+            self.launch(Some(&previous_defns)).await?;
+
+            if self.started {
+                self.send_start().await?;
+            }
+
+            Ok(())
+        })
+        .await
+    }
+
+    async fn shutdown(&mut self) -> Result<()> {
+        if !self.started {
+            return Ok(());
         }
 
-        self.started = true;
+        self.send_command("shutdown", "ack shutdown").await?;
         Ok(())
     }
 
-    async fn stop(&mut self) -> Result<()> {
-        ProgressTracker::with_group(
-            self.display_id
-                .clone()
-                .unwrap_or_else(|| format!("service/{}", self.id)),
-            None,
-            || async {
-                let launched_binary = self.launched_binary.as_mut().unwrap();
-                launched_binary.stdin().send("stop\n".to_string())?;
-
-                let timeout_result = ProgressTracker::leaf(
-                    "waiting for exit",
-                    tokio::time::timeout(Duration::from_secs(60), launched_binary.wait()),
-                )
-                .await;
-                match timeout_result {
-                    Err(_timeout) => {} // `wait()` timed out, but stop will force quit.
-                    Ok(Err(unexpected_error)) => return Err(unexpected_error), // `wait()` errored.
-                    Ok(Ok(_exit_status)) => {}
-                }
-                launched_binary.stop().await?;
+    async fn drain_status(&mut self) -> Result<Option<DrainStatus>> {
+        if !self.started {
+            return Ok(None);
+        }
 
-                Ok(())
-            },
-        )
+        let status = self.send_command("drain", "drain: ").await?;
+        Ok(Some(serde_json::from_str(&status)?))
+    }
+
+    async fn finish(&mut self, timeout: Duration) -> Result<()> {
+        if !self.started {
+            return self.stop().await;
+        }
+
+        ProgressTracker::with_group(self.display_name(), None, || async {
+            let launched_binary = self.launched_binary.as_mut().unwrap();
+            launched_binary.stdin().send("finish\n".to_string())?;
+
+            let timeout_result = ProgressTracker::leaf(
+                "waiting for exit",
+                tokio::time::timeout(timeout, launched_binary.wait()),
+            )
+            .await;
+            match timeout_result {
+                Err(_timeout) => {} // `wait()` timed out, but stop will force quit.
+                Ok(Err(unexpected_error)) => return Err(unexpected_error), // `wait()` errored.
+                Ok(Ok(_exit_status)) => {}
+            }
+            launched_binary.stop().await?;
+
+            Ok(())
+        })
         .await
     }
 }
@@ -1,14 +1,16 @@
 use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 
 use anyhow::Result;
 use async_trait::async_trait;
 use hydroflow_crate::tracing_options::TracingOptions;
+pub use hydroflow_deploy_integration::DrainStatus;
 use hydroflow_deploy_integration::ServerBindConfig;
 
 pub mod deployment;
-pub use deployment::Deployment;
+pub use deployment::{Deployment, GracefulStopReport};
 
 pub mod progress;
 
@@ -78,7 +80,13 @@ pub trait LaunchedBinary: Send + Sync {
     /// with the guarantee that as long as deploy is holding on
     /// to a handle, none of the messages will also be broadcast
     /// to the user-facing [`LaunchedBinary::stdout`] channel.
-    fn deploy_stdout(&self) -> oneshot::Receiver<String>;
+    fn deploy_stdout(&self) -> oneshot::Receiver<String> {
+        self.deploy_stdout_matching("")
+    }
+
+    /// Like [`LaunchedBinary::deploy_stdout`], but only captures the first line that starts
+    /// with `prefix`. Other lines are still broadcast to [`LaunchedBinary::stdout`].
+    fn deploy_stdout_matching(&self, prefix: &str) -> oneshot::Receiver<String>;
 
     fn stdout(&self) -> mpsc::UnboundedReceiver<String>;
     fn stderr(&self) -> mpsc::UnboundedReceiver<String>;
@@ -207,6 +215,31 @@ pub trait Service: Send + Sync {
 
     /// Stops the service by having it disconnect from other services and stop computations.
     async fn stop(&mut self) -> Result<()>;
+
+    /// Asks the service to stop accepting new input, as the first step of
+    /// [`Deployment::stop_graceful`]. Services without graceful shutdown ignore this.
+    async fn shutdown(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Reports how far the service has drained since [`Service::shutdown`], or `None` if the
+    /// service does not track this, in which case it is always considered drained.
+    async fn drain_status(&mut self) -> Result<Option<DrainStatus>> {
+        Ok(None)
+    }
+
+    /// Completes a graceful shutdown by letting the service flush its output and exit,
+    /// stopping it forcefully if it has not exited within `timeout`.
+    async fn finish(&mut self, timeout: Duration) -> Result<()> {
+        let _ = timeout;
+        self.stop().await
+    }
+
+    /// Replaces the running program of this service with the one staged for an upgrade, if any,
+    /// without disconnecting other services from it for longer than the restart takes.
+    async fn upgrade(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub trait ServiceBuilder {
@@ -20,14 +20,14 @@ use tokio_util::io::SyncIoBridge;
 use crate::hydroflow_crate::flamegraph::handle_fold_data;
 use crate::hydroflow_crate::tracing_options::TracingOptions;
 use crate::progress::ProgressTracker;
-use crate::util::prioritized_broadcast;
+use crate::util::{prioritized_broadcast, PriorityReceiver};
 use crate::LaunchedBinary;
 
 pub struct LaunchedLocalhostBinary {
     child: Mutex<async_process::Child>,
     tracing: Option<TracingOptions>,
     stdin_sender: mpsc::UnboundedSender<String>,
-    stdout_deploy_receivers: Arc<Mutex<Option<oneshot::Sender<String>>>>,
+    stdout_deploy_receivers: Arc<Mutex<PriorityReceiver>>,
     stdout_receivers: Arc<Mutex<Vec<mpsc::UnboundedSender<String>>>>,
     stderr_receivers: Arc<Mutex<Vec<mpsc::UnboundedSender<String>>>>,
 }
@@ -96,15 +96,18 @@ impl LaunchedBinary for LaunchedLocalhostBinary {
         self.stdin_sender.clone()
     }
 
-    fn deploy_stdout(&self) -> oneshot::Receiver<String> {
+    fn deploy_stdout_matching(&self, prefix: &str) -> oneshot::Receiver<String> {
         let mut receivers = self.stdout_deploy_receivers.lock().unwrap();
 
-        if receivers.is_some() {
+        if receivers
+            .as_ref()
+            .is_some_and(|(_, sender)| !sender.is_closed())
+        {
             panic!("Only one deploy stdout receiver is allowed at a time");
         }
 
         let (sender, receiver) = oneshot::channel::<String>();
-        *receivers = Some(sender);
+        *receivers = Some((prefix.to_string(), sender));
         receiver
     }
 
@@ -28,7 +28,7 @@ use super::{LaunchedBinary, LaunchedHost, ResourceResult, ServerStrategy};
 use crate::hydroflow_crate::build::BuildOutput;
 use crate::hydroflow_crate::flamegraph::handle_fold_data;
 use crate::hydroflow_crate::tracing_options::TracingOptions;
-use crate::util::prioritized_broadcast;
+use crate::util::{prioritized_broadcast, PriorityReceiver};
 
 const PERF_OUTFILE: &str = "__profile.perf.data";
 
@@ -38,7 +38,7 @@ struct LaunchedSshBinary {
     channel: AsyncChannel<TcpStream>,
     stdin_sender: mpsc::UnboundedSender<String>,
     stdout_receivers: Arc<Mutex<Vec<mpsc::UnboundedSender<String>>>>,
-    stdout_deploy_receivers: Arc<Mutex<Option<oneshot::Sender<String>>>>,
+    stdout_deploy_receivers: Arc<Mutex<PriorityReceiver>>,
     stderr_receivers: Arc<Mutex<Vec<mpsc::UnboundedSender<String>>>>,
     tracing: Option<TracingOptions>,
 }
@@ -49,15 +49,18 @@ impl LaunchedBinary for LaunchedSshBinary {
         self.stdin_sender.clone()
     }
 
-    fn deploy_stdout(&self) -> oneshot::Receiver<String> {
+    fn deploy_stdout_matching(&self, prefix: &str) -> oneshot::Receiver<String> {
         let mut receivers = self.stdout_deploy_receivers.lock().unwrap();
 
-        if receivers.is_some() {
+        if receivers
+            .as_ref()
+            .is_some_and(|(_, sender)| !sender.is_closed())
+        {
             panic!("Only one deploy stdout receiver is allowed at a time");
         }
 
         let (sender, receiver) = oneshot::channel::<String>();
-        *receivers = Some(sender);
+        *receivers = Some((prefix.to_string(), sender));
         receiver
     }
 
@@ -23,8 +23,11 @@ pub async fn async_retry<T, F: Future<Output = Result<T>>>(
     thunk().await
 }
 
+/// A receiver for the first line that starts with the given prefix.
+pub type PriorityReceiver = Option<(String, oneshot::Sender<String>)>;
+
 type PriorityBroadcacst = (
-    Arc<Mutex<Option<oneshot::Sender<String>>>>,
+    Arc<Mutex<PriorityReceiver>>,
     Arc<Mutex<Vec<mpsc::UnboundedSender<String>>>>,
 );
 
@@ -32,7 +35,7 @@ pub fn prioritized_broadcast<T: Stream<Item = io::Result<String>> + Send + Unpin
     mut lines: T,
     default: impl Fn(String) + Send + 'static,
 ) -> PriorityBroadcacst {
-    let priority_receivers = Arc::new(Mutex::new(None::<oneshot::Sender<String>>));
+    let priority_receivers = Arc::new(Mutex::new(None::<(String, oneshot::Sender<String>)>));
     let receivers = Arc::new(Mutex::new(Vec::<mpsc::UnboundedSender<String>>::new()));
 
     let weak_priority_receivers = Arc::downgrade(&priority_receivers);
@@ -43,10 +46,12 @@ pub fn prioritized_broadcast<T: Stream<Item = io::Result<String>> + Send + Unpin
             if let Some(deploy_receivers) = weak_priority_receivers.upgrade() {
                 let mut deploy_receivers = deploy_receivers.lock().unwrap();
 
-                let successful_send = if let Some(r) = deploy_receivers.take() {
-                    r.send(line.clone()).is_ok()
-                } else {
-                    false
+                let successful_send = match deploy_receivers.take() {
+                    Some((prefix, r)) if line.starts_with(&prefix) => r.send(line.clone()).is_ok(),
+                    other => {
+                        *deploy_receivers = other;
+                        false
+                    }
                 };
                 drop(deploy_receivers);
 
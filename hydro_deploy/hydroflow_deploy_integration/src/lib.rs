@@ -24,13 +24,28 @@ use tokio_util::codec::{Framed, LengthDelimitedCodec};
 
 pub type InitConfig = (HashMap<String, ServerBindConfig>, Option<String>);
 
+/// Reported by a service that is shutting down gracefully, so that the deployment can tell when
+/// all of its services have drained.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct DrainStatus {
+    /// Whether the service had no pending work when it was asked.
+    pub idle: bool,
+    /// The number of messages the service has sent to other services.
+    pub sent: u64,
+    /// The number of messages the service has received from other services.
+    pub received: u64,
+}
+
 #[cfg(not(unix))]
 type UnixStream = std::convert::Infallible;
 
 #[cfg(not(unix))]
-#[expect(dead_code, reason = "conditional compilation placeholder")]
 type UnixListener = std::convert::Infallible;
 
+/// How long a [`Reconnecting`] connection keeps trying to reach a peer that has gone away.
+const RECONNECT_ATTEMPTS: usize = 600;
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
 /// Describes how to connect to a service which is listening on some port.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ServerPort {
@@ -50,8 +65,8 @@ impl ServerPort {
 
 #[derive(Debug)]
 pub enum RealizedServerPort {
-    UnixSocket(JoinHandle<io::Result<UnixStream>>),
-    TcpPort(JoinHandle<io::Result<TcpStream>>),
+    UnixSocket(JoinHandle<io::Result<UnixStream>>, PathBuf),
+    TcpPort(JoinHandle<io::Result<TcpStream>>, SocketAddr),
     Demux(HashMap<u32, RealizedServerPort>),
     Merge(Vec<RealizedServerPort>),
     Tagged(Box<RealizedServerPort>, u32),
@@ -65,7 +80,7 @@ impl From<&ServerPort> for RealizedServerPort {
                 #[cfg(unix)]
                 {
                     let bound = UnixStream::connect(path.clone());
-                    RealizedServerPort::UnixSocket(tokio::spawn(bound))
+                    RealizedServerPort::UnixSocket(tokio::spawn(bound), path.clone())
                 }
 
                 #[cfg(not(unix))]
@@ -81,7 +96,7 @@ impl From<&ServerPort> for RealizedServerPort {
                     10,
                     Duration::from_secs(1),
                 );
-                RealizedServerPort::TcpPort(tokio::spawn(bound))
+                RealizedServerPort::TcpPort(tokio::spawn(bound), *addr)
             }
             ServerPort::Demux(bindings) => {
                 RealizedServerPort::Demux(bindings.iter().map(|(k, v)| (*k, v.into())).collect())
@@ -108,9 +123,53 @@ pub enum ServerBindConfig {
     Merge(Vec<ServerBindConfig>),
     Tagged(Box<ServerBindConfig>, u32),
     Null,
+    /// A Unix socket at a fixed path, see [`ServerBindConfig::rebind`].
+    UnixSocketAt(PathBuf),
+    /// A TCP port at a fixed address, see [`ServerBindConfig::rebind`].
+    TcpPortAt(SocketAddr),
 }
 
 impl ServerBindConfig {
+    /// Binds at the addresses of `previous`, which was bound from this same configuration by an
+    /// earlier instance of the service, so that peers of an upgraded service can reconnect to it.
+    pub fn rebind(self, previous: &ServerPort) -> ServerBindConfig {
+        match (self, previous) {
+            (ServerBindConfig::UnixSocket, ServerPort::UnixSocket(path)) => {
+                ServerBindConfig::UnixSocketAt(path.clone())
+            }
+            (ServerBindConfig::TcpPort(_), ServerPort::TcpPort(addr)) => {
+                ServerBindConfig::TcpPortAt(*addr)
+            }
+            (ServerBindConfig::Demux(bindings), ServerPort::Demux(previous)) => {
+                ServerBindConfig::Demux(
+                    bindings
+                        .into_iter()
+                        .map(|(key, bind)| match previous.get(&key) {
+                            Some(previous) => (key, bind.rebind(previous)),
+                            None => (key, bind),
+                        })
+                        .collect(),
+                )
+            }
+            (ServerBindConfig::Merge(bindings), ServerPort::Merge(previous)) => {
+                ServerBindConfig::Merge(
+                    bindings
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, bind)| match previous.get(i) {
+                            Some(previous) => bind.rebind(previous),
+                            None => bind,
+                        })
+                        .collect(),
+                )
+            }
+            (ServerBindConfig::Tagged(underlying, id), ServerPort::Tagged(previous, _)) => {
+                ServerBindConfig::Tagged(Box::new(underlying.rebind(previous)), id)
+            }
+            (bind, _) => bind,
+        }
+    }
+
     #[async_recursion]
     pub async fn bind(self) -> BoundConnection {
         match self {
@@ -119,11 +178,8 @@ impl ServerBindConfig {
                 {
                     let dir = tempfile::tempdir().unwrap();
                     let socket_path = dir.path().join("socket");
-                    let bound = UnixListener::bind(socket_path).unwrap();
-                    BoundConnection::UnixSocket(
-                        tokio::spawn(async move { Ok(bound.accept().await?.0) }),
-                        dir,
-                    )
+                    let bound = UnixListener::bind(&socket_path).unwrap();
+                    BoundConnection::UnixSocket(bound, socket_path, Some(dir))
                 }
 
                 #[cfg(not(unix))]
@@ -154,6 +210,29 @@ impl ServerBindConfig {
                 BoundConnection::Tagged(Box::new(underlying.bind().await), id)
             }
             ServerBindConfig::Null => BoundConnection::Null,
+            ServerBindConfig::UnixSocketAt(socket_path) => {
+                #[cfg(unix)]
+                {
+                    if let Some(dir) = socket_path.parent() {
+                        std::fs::create_dir_all(dir).unwrap();
+                    }
+                    // the socket file of the previous instance may still exist
+                    let _ = std::fs::remove_file(&socket_path);
+                    let bound = UnixListener::bind(&socket_path).unwrap();
+                    BoundConnection::UnixSocket(bound, socket_path, None)
+                }
+
+                #[cfg(not(unix))]
+                {
+                    let _ = socket_path;
+                    panic!("Unix sockets are not supported on this platform")
+                }
+            }
+            ServerBindConfig::TcpPortAt(addr) => {
+                let listener = TcpListener::bind(addr).await.unwrap();
+                let addr = listener.local_addr().unwrap();
+                BoundConnection::TcpPort(TcpListenerStream::new(listener), addr)
+            }
         }
     }
 }
@@ -219,7 +298,7 @@ pub trait ConnectedSource {
 
 #[derive(Debug)]
 pub enum BoundConnection {
-    UnixSocket(JoinHandle<io::Result<UnixStream>>, tempfile::TempDir),
+    UnixSocket(UnixListener, PathBuf, Option<tempfile::TempDir>),
     TcpPort(TcpListenerStream, SocketAddr),
     Demux(HashMap<u32, BoundConnection>),
     Merge(Vec<BoundConnection>),
@@ -230,15 +309,15 @@ pub enum BoundConnection {
 impl BoundConnection {
     pub fn sink_port(&self) -> ServerPort {
         match self {
-            BoundConnection::UnixSocket(_, tempdir) => {
+            BoundConnection::UnixSocket(_, socket_path, _) => {
                 #[cfg(unix)]
                 {
-                    ServerPort::UnixSocket(tempdir.path().join("socket"))
+                    ServerPort::UnixSocket(socket_path.clone())
                 }
 
                 #[cfg(not(unix))]
                 {
-                    let _ = tempdir;
+                    let _ = socket_path;
                     panic!("Unix sockets are not supported on this platform")
                 }
             }
@@ -274,12 +353,18 @@ impl BoundConnection {
 #[async_recursion]
 async fn accept(bound: BoundConnection) -> ConnectedDirect {
     match bound {
-        BoundConnection::UnixSocket(listener, _) => {
+        BoundConnection::UnixSocket(listener, _, tempdir) => {
             #[cfg(unix)]
             {
-                let stream = listener.await.unwrap().unwrap();
+                let stream = listener.accept().await.unwrap().0;
                 ConnectedDirect {
-                    stream_sink: Some(Box::pin(unix_bytes(stream))),
+                    stream_sink: Some(Box::pin(Reconnecting {
+                        current: Some(Box::pin(unix_bytes(stream))),
+                        redial: Redial::AcceptUnix {
+                            listener,
+                            _dir: tempdir,
+                        },
+                    })),
                     source_only: None,
                     sink_only: None,
                 }
@@ -287,14 +372,17 @@ async fn accept(bound: BoundConnection) -> ConnectedDirect {
 
             #[cfg(not(unix))]
             {
-                drop(listener);
+                drop((listener, tempdir));
                 panic!("Unix sockets are not supported on this platform")
             }
         }
         BoundConnection::TcpPort(mut listener, _) => {
             let stream = listener.next().await.unwrap().unwrap();
             ConnectedDirect {
-                stream_sink: Some(Box::pin(tcp_bytes(stream))),
+                stream_sink: Some(Box::pin(Reconnecting {
+                    current: Some(Box::pin(tcp_bytes(stream))),
+                    redial: Redial::AcceptTcp(listener),
+                })),
                 source_only: None,
                 sink_only: None,
             }
@@ -333,6 +421,166 @@ fn unix_bytes(stream: UnixStream) -> impl StreamSink {
     Framed::new(stream, LengthDelimitedCodec::new())
 }
 
+/// Where a [`Reconnecting`] connection gets its next stream from once the current one closes.
+enum Redial {
+    AcceptTcp(TcpListenerStream),
+    #[cfg(unix)]
+    AcceptUnix {
+        listener: UnixListener,
+        /// Holds the directory of the socket until the listener is dropped.
+        _dir: Option<tempfile::TempDir>,
+    },
+    ConnectTcp(SocketAddr, Option<JoinHandle<io::Result<TcpStream>>>),
+    #[cfg(unix)]
+    ConnectUnix(PathBuf, Option<JoinHandle<io::Result<UnixStream>>>),
+}
+
+impl Redial {
+    fn poll_redial(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<DynStreamSink>> {
+        match self {
+            Redial::AcceptTcp(listener) => match ready!(Pin::new(listener).poll_next(cx)) {
+                Some(stream) => {
+                    let stream = stream?;
+                    stream.set_nodelay(true)?;
+                    Poll::Ready(Ok(Box::pin(tcp_bytes(stream))))
+                }
+                None => Poll::Ready(Err(io::ErrorKind::NotConnected.into())),
+            },
+            #[cfg(unix)]
+            Redial::AcceptUnix { listener, .. } => {
+                let (stream, _) = ready!(listener.poll_accept(cx))?;
+                Poll::Ready(Ok(Box::pin(unix_bytes(stream))))
+            }
+            Redial::ConnectTcp(addr, connecting) => {
+                let addr = *addr;
+                let handle = connecting.get_or_insert_with(|| {
+                    tokio::spawn(async_retry(
+                        move || TcpStream::connect(addr),
+                        RECONNECT_ATTEMPTS,
+                        RECONNECT_DELAY,
+                    ))
+                });
+                let connected = ready!(Pin::new(handle).poll(cx));
+                *connecting = None;
+
+                let stream = connected.map_err(io::Error::other)??;
+                stream.set_nodelay(true)?;
+                Poll::Ready(Ok(Box::pin(tcp_bytes(stream))))
+            }
+            #[cfg(unix)]
+            Redial::ConnectUnix(path, connecting) => {
+                let handle = connecting.get_or_insert_with(|| {
+                    let path = path.clone();
+                    tokio::spawn(async_retry(
+                        move || UnixStream::connect(path.clone()),
+                        RECONNECT_ATTEMPTS,
+                        RECONNECT_DELAY,
+                    ))
+                });
+                let connected = ready!(Pin::new(handle).poll(cx));
+                *connecting = None;
+
+                Poll::Ready(Ok(Box::pin(unix_bytes(
+                    connected.map_err(io::Error::other)??,
+                ))))
+            }
+        }
+    }
+}
+
+/// A connection that replaces its stream when the peer on the other end goes away, for example
+/// because the peer was stopped to be upgraded, by accepting or dialing a new one. Messages that
+/// were buffered on the old stream are lost.
+struct Reconnecting {
+    current: Option<DynStreamSink>,
+    redial: Redial,
+}
+
+impl Reconnecting {
+    fn poll_connected(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.current.is_none() {
+            self.current = Some(ready!(self.redial.poll_redial(cx))?);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl Stream for Reconnecting {
+    type Item = Result<BytesMut, io::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        loop {
+            if let Err(e) = ready!(this.poll_connected(cx)) {
+                return Poll::Ready(Some(Err(e)));
+            }
+
+            match ready!(this.current.as_mut().unwrap().as_mut().poll_next(cx)) {
+                Some(Ok(item)) => return Poll::Ready(Some(Ok(item))),
+                Some(Err(_)) | None => this.current = None,
+            }
+        }
+    }
+}
+
+impl Sink<Bytes> for Reconnecting {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        loop {
+            ready!(this.poll_connected(cx))?;
+
+            // the peer never writes to a connection we send on, so a read only completes
+            // once the peer has closed it
+            let current = this.current.as_mut().unwrap();
+            match current.as_mut().poll_next(cx) {
+                Poll::Ready(Some(Ok(_))) => continue,
+                Poll::Ready(Some(Err(_)) | None) => {
+                    this.current = None;
+                    continue;
+                }
+                Poll::Pending => {}
+            }
+
+            match ready!(current.as_mut().poll_ready(cx)) {
+                Ok(()) => return Poll::Ready(Ok(())),
+                Err(_) => this.current = None,
+            }
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Bytes) -> Result<(), Self::Error> {
+        let this = self.get_mut();
+        if let Some(current) = this.current.as_mut() {
+            if current.as_mut().start_send(item).is_err() {
+                this.current = None;
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if let Some(current) = this.current.as_mut() {
+            if ready!(current.as_mut().poll_flush(cx)).is_err() {
+                this.current = None;
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.get_mut();
+        if let Some(current) = this.current.as_mut() {
+            // the peer may already be gone, in which case there is nothing left to close
+            let _ = ready!(current.as_mut().poll_close(cx));
+            this.current = None;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
 struct IoErrorDrain<T> {
     marker: PhantomData<T>,
 }
@@ -384,12 +632,15 @@ pub struct ConnectedDirect {
 impl Connected for ConnectedDirect {
     async fn from_defn(pipe: ServerOrBound) -> Self {
         match pipe {
-            ServerOrBound::Server(RealizedServerPort::UnixSocket(stream)) => {
+            ServerOrBound::Server(RealizedServerPort::UnixSocket(stream, path)) => {
                 #[cfg(unix)]
                 {
                     let stream = stream.await.unwrap().unwrap();
                     ConnectedDirect {
-                        stream_sink: Some(Box::pin(unix_bytes(stream))),
+                        stream_sink: Some(Box::pin(Reconnecting {
+                            current: Some(Box::pin(unix_bytes(stream))),
+                            redial: Redial::ConnectUnix(path, None),
+                        })),
                         source_only: None,
                         sink_only: None,
                     }
@@ -397,15 +648,18 @@ impl Connected for ConnectedDirect {
 
                 #[cfg(not(unix))]
                 {
-                    drop(stream);
+                    drop((stream, path));
                     panic!("Unix sockets are not supported on this platform");
                 }
             }
-            ServerOrBound::Server(RealizedServerPort::TcpPort(stream)) => {
+            ServerOrBound::Server(RealizedServerPort::TcpPort(stream, addr)) => {
                 let stream = stream.await.unwrap().unwrap();
                 stream.set_nodelay(true).unwrap();
                 ConnectedDirect {
-                    stream_sink: Some(Box::pin(tcp_bytes(stream))),
+                    stream_sink: Some(Box::pin(Reconnecting {
+                        current: Some(Box::pin(tcp_bytes(stream))),
+                        redial: Redial::ConnectTcp(addr, None),
+                    })),
                     source_only: None,
                     sink_only: None,
                 }
@@ -0,0 +1,100 @@
+//! Captures where parts of the IR are created, so that analyses and profiles can point back at
+//! the user code that created them.
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::rc::Rc;
+
+/// The call stack at the point where part of the IR was created. Symbols are only resolved
+/// when [`Backtrace::user_location`] is first called, since that is comparatively expensive.
+///
+/// The default backtrace is empty, which is used for IR that was deserialized.
+#[derive(Clone, Default)]
+pub struct Backtrace(Option<Rc<RefCell<::backtrace::Backtrace>>>);
+
+impl Backtrace {
+    #[inline(never)]
+    pub fn capture() -> Backtrace {
+        Backtrace(Some(Rc::new(RefCell::new(
+            ::backtrace::Backtrace::new_unresolved(),
+        ))))
+    }
+
+    /// The innermost frame outside of Hydro, `stageleft`, and the standard library, formatted
+    /// as `file:line:column` with the file relative to the current directory when possible.
+    /// Returns `None` if the backtrace is empty or no debug info is available for that frame.
+    pub fn user_location(&self) -> Option<String> {
+        let mut backtrace = self.0.as_ref()?.borrow_mut();
+        backtrace.resolve();
+        let symbol = backtrace
+            .frames()
+            .iter()
+            .flat_map(|frame| frame.symbols())
+            .find(|symbol| {
+                symbol
+                    .name()
+                    .is_some_and(|name| is_user_fn(&format!("{:#}", name)))
+            })?;
+
+        let file = symbol.filename()?;
+        let file = std::env::current_dir()
+            .ok()
+            .and_then(|dir| file.strip_prefix(dir).ok())
+            .unwrap_or(file)
+            .display();
+        let line = symbol.lineno()?;
+        Some(match symbol.colno() {
+            Some(column) => format!("{}:{}:{}", file, line, column),
+            None => format!("{}:{}", file, line),
+        })
+    }
+}
+
+/// Backtraces are not printed, so that the IR prints the same wherever it was created.
+impl Debug for Backtrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Backtrace")
+    }
+}
+
+fn is_user_fn(name: &str) -> bool {
+    // Hydro's own tests count as user code
+    if name.contains("::tests::") {
+        return true;
+    }
+
+    // trait methods are printed as `<Type as Trait>::method`, and are internal if either the
+    // type or the trait is (blanket impls print the type as a bare generic parameter)
+    let name = name.trim_start_matches('<');
+    let (self_ty, trait_path) = name.split_once(" as ").unwrap_or((name, ""));
+    ![self_ty, trait_path].iter().any(|path| {
+        [
+            "backtrace::",
+            "hydro_lang::",
+            "stageleft::",
+            "core::",
+            "std::",
+            "alloc::",
+        ]
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Backtrace;
+
+    #[test]
+    fn user_location_skips_hydro_frames() {
+        let location = Backtrace::capture().user_location().unwrap();
+        assert!(location.contains("backtrace.rs"), "{}", location);
+        assert!(!super::is_user_fn("hydro_lang::stream::Stream<T>::map"));
+        assert!(!super::is_user_fn(
+            "<hydro_lang::ir::DebugExpr as core::convert::From<syn::expr::Expr>>::from"
+        ));
+        assert!(!super::is_user_fn("<T as core::convert::Into<U>>::into"));
+        assert!(super::is_user_fn("hydro_test::cluster::paxos::paxos_core"));
+        assert_eq!(Backtrace::default().user_location(), None);
+    }
+}
@@ -2,11 +2,14 @@ use std::collections::{BTreeMap, HashMap};
 use std::marker::PhantomData;
 
 use dfir_lang::graph::{eliminate_extra_unions_tees, DfirGraph};
+use dfir_rs::serde_json;
+use serde::{Deserialize, Serialize};
 
 use super::compiled::CompiledFlow;
 use super::deploy::{DeployFlow, DeployResult};
+use super::upgrade::{FlowDiff, FlowFingerprint, UpgradeError};
 use crate::deploy::{ClusterSpec, Deploy, ExternalSpec, IntoProcessSpec, LocalDeploy};
-use crate::ir::HydroLeaf;
+use crate::ir::{serde_dedup_tee, HydroLeaf};
 use crate::location::{Cluster, ExternalProcess, Process};
 use crate::staging_util::Invariant;
 
@@ -27,12 +30,48 @@ impl Drop for BuiltFlow<'_> {
     }
 }
 
-pub(crate) fn build_inner(ir: &mut Vec<HydroLeaf>) -> BTreeMap<usize, DfirGraph> {
+/// The textual format produced by [`BuiltFlow::serialize`].
+#[derive(Serialize)]
+struct SerializeFlow<'a> {
+    processes: &'a Vec<usize>,
+    clusters: &'a Vec<usize>,
+    ir: &'a Vec<HydroLeaf>,
+}
+
+#[derive(Deserialize)]
+struct DeserializeFlow {
+    processes: Vec<usize>,
+    clusters: Vec<usize>,
+    ir: Vec<HydroLeaf>,
+}
+
+/// Emits the DFIR graph of each location. If `snapshot_initiator` is set, the graphs take
+/// distributed snapshots (see [`crate::snapshot_runtime`]), started by that process.
+pub(crate) fn build_inner(
+    ir: &mut Vec<HydroLeaf>,
+    snapshot_initiator: Option<usize>,
+) -> BTreeMap<usize, DfirGraph> {
     let mut builders = BTreeMap::new();
     let mut built_tees = HashMap::new();
+    let mut built_loops = HashMap::new();
     let mut next_stmt_id = 0;
     for leaf in ir {
-        leaf.emit(&mut builders, &mut built_tees, &mut next_stmt_id);
+        leaf.emit(
+            &mut builders,
+            &mut built_tees,
+            &mut built_loops,
+            &mut next_stmt_id,
+            snapshot_initiator.is_some(),
+        );
+    }
+
+    if let Some(initiator) = snapshot_initiator {
+        builders
+            .entry(initiator)
+            .or_default()
+            .add_statement(syn::parse_quote! {
+                source_stream(__hydro_lang_snapshots.initiator()) -> null();
+            });
     }
 
     builders
@@ -50,6 +89,54 @@ impl<'a> BuiltFlow<'a> {
         &self.ir
     }
 
+    /// Serializes the IR of this flow (along with its locations) to JSON, which can be
+    /// saved, diffed, or loaded back with [`BuiltFlow::deserialize`].
+    ///
+    /// Expressions are stored as their token text, and tees that are shared between
+    /// multiple consumers are written out once and then referred to by id.
+    pub fn serialize(&self) -> String {
+        serde_dedup_tee(|| {
+            serde_json::to_string_pretty(&SerializeFlow {
+                processes: &self.processes,
+                clusters: &self.clusters,
+                ir: &self.ir,
+            })
+        })
+        .unwrap()
+    }
+
+    /// Checks whether this flow can hot-upgrade a running deployment of `previous`, returning
+    /// the processes and clusters that would be replaced, see [`DeployFlow::upgrade`].
+    ///
+    /// Upgrades must keep the same locations and network edges, and each edge must keep
+    /// (de)serializing the same type, since the peers of a replaced location keep running.
+    pub fn diff(&self, previous: &BuiltFlow<'a>) -> Result<FlowDiff, UpgradeError> {
+        FlowFingerprint::of(&previous.ir).diff(&FlowFingerprint::of(&self.ir))
+    }
+
+    /// Reloads a flow previously saved with [`BuiltFlow::serialize`], which can then be
+    /// optimized and compiled without re-running the staged builder.
+    pub fn deserialize(text: &str) -> Result<BuiltFlow<'a>, serde_json::Error> {
+        let flow: DeserializeFlow = serde_dedup_tee(|| serde_json::from_str(text))?;
+        Ok(BuiltFlow {
+            ir: flow.ir,
+            processes: flow.processes,
+            clusters: flow.clusters,
+            used: false,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Renders the IR of this flow as a Mermaid flowchart, grouped by location.
+    pub fn to_mermaid(&self) -> String {
+        crate::viz::ir_to_mermaid(&self.ir)
+    }
+
+    /// Renders the IR of this flow as a DOT graph, grouped by location.
+    pub fn to_dot(&self) -> String {
+        crate::viz::ir_to_dot(&self.ir)
+    }
+
     pub fn optimize_with(mut self, f: impl FnOnce(Vec<HydroLeaf>) -> Vec<HydroLeaf>) -> Self {
         self.used = true;
         BuiltFlow {
@@ -91,6 +178,7 @@ impl<'a> BuiltFlow<'a> {
             nodes: processes,
             clusters,
             externals: HashMap::new(),
+            snapshots: None,
             used: false,
             _phantom: PhantomData,
         }
@@ -135,3 +223,115 @@ impl<'a> BuiltFlow<'a> {
         self.into_deploy::<D>().deploy(env)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use dfir_rs::serde_json;
+    use stageleft::*;
+
+    use super::BuiltFlow;
+    use crate::deploy::{DeployRuntime, MultiGraph};
+    use crate::ir::{dbg_dedup_tee, serde_dedup_tee};
+    use crate::location::Location;
+    use crate::FlowBuilder;
+
+    struct P1 {}
+    struct C2 {}
+
+    #[test]
+    fn serialize_simple() {
+        let flow = FlowBuilder::new();
+        let process = flow.process::<P1>();
+
+        process
+            .source_iter(q!(0..10))
+            .map(q!(|v| v + 1))
+            .for_each(q!(|n| println!("{}", n)));
+
+        let built = flow.finalize();
+        insta::assert_snapshot!(built.serialize());
+
+        let _ = built
+            .with_default_optimize::<MultiGraph>()
+            .compile_no_network();
+    }
+
+    #[test]
+    fn serialize_round_trip() {
+        let flow = FlowBuilder::new();
+        let process = flow.process::<P1>();
+        let cluster = flow.cluster::<C2>();
+        let tick = process.tick();
+
+        let numbers = process.source_iter(q!(0..10));
+        let (complete_cycle, cycle) = tick.cycle::<crate::Stream<_, _, _>>();
+        let batch = unsafe { numbers.timestamped(&tick).tick_batch() };
+        complete_cycle.complete_next_tick(batch.clone().chain(cycle).filter(q!(|v| *v < 5)));
+
+        batch
+            .clone()
+            .all_ticks()
+            .drop_timestamp()
+            .broadcast_bincode(&cluster)
+            .for_each(q!(|n| println!("{}", n)));
+        batch.all_ticks().for_each(q!(|n| println!("{}", n)));
+
+        let built = flow.finalize();
+        let serialized = built.serialize();
+        let reloaded = BuiltFlow::deserialize(&serialized).unwrap();
+
+        assert_eq!(reloaded.serialize(), serialized);
+        assert_eq!(
+            dbg_dedup_tee(|| format!("{:?}", reloaded.ir())),
+            dbg_dedup_tee(|| format!("{:?}", built.ir()))
+        );
+
+        let _ = built
+            .with_default_optimize::<DeployRuntime>()
+            .compile(&RuntimeData::new("FAKE"));
+        let _ = reloaded
+            .with_default_optimize::<DeployRuntime>()
+            .compile(&RuntimeData::new("FAKE"));
+    }
+
+    #[test]
+    fn serialize_nested_and_invalid_ident() {
+        let flow = FlowBuilder::new();
+        let process = flow.process::<P1>();
+        let tick = process.tick();
+
+        let (complete_cycle, cycle) = tick.cycle::<crate::Stream<_, _, _>>();
+        let batch = unsafe {
+            process
+                .source_iter(q!(0..10))
+                .timestamped(&tick)
+                .tick_batch()
+        };
+        complete_cycle.complete_next_tick(batch.clone().chain(cycle));
+        batch.all_ticks().for_each(q!(|n: i32| println!("{}", n)));
+
+        let built = flow.finalize();
+        let serialized = built.serialize();
+
+        serde_dedup_tee(|| {
+            let first = serde_json::to_string(built.ir()).unwrap();
+            assert_eq!(built.serialize(), serialized);
+            // the enclosing context still knows which tees it has already written
+            let second = serde_json::to_string(built.ir()).unwrap();
+            assert!(second.len() < first.len());
+        });
+
+        let _ = std::panic::catch_unwind(|| serde_dedup_tee(|| panic!("interrupted")));
+        assert!(serde_json::to_string(built.ir()).is_err());
+
+        let start = serialized.find("\"ident\": \"").unwrap() + "\"ident\": \"".len();
+        let end = start + serialized[start..].find('"').unwrap();
+        let invalid = format!("{}not an ident{}", &serialized[..start], &serialized[end..]);
+        let err = BuiltFlow::deserialize(&invalid).err().unwrap();
+        assert!(err.is_data(), "{}", err);
+
+        let _ = built
+            .with_default_optimize::<MultiGraph>()
+            .compile_no_network();
+    }
+}
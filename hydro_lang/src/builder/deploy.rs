@@ -1,7 +1,9 @@
-use std::collections::{BTreeMap, HashMap};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::io::Error;
 use std::marker::PhantomData;
 use std::pin::Pin;
+use std::rc::Rc;
 
 use dfir_rs::bytes::Bytes;
 use dfir_rs::futures::{Sink, Stream};
@@ -12,15 +14,17 @@ use stageleft::QuotedWithContext;
 
 use super::built::build_inner;
 use super::compiled::CompiledFlow;
+use super::upgrade::{FlowDiff, FlowFingerprint, UpgradeError};
 use crate::deploy::{
     ClusterSpec, Deploy, ExternalSpec, IntoProcessSpec, LocalDeploy, Node, ProcessSpec,
-    RegisterPort,
+    RegisterPort, ServiceClient,
 };
-use crate::ir::HydroLeaf;
+use crate::ir::{HydroLeaf, HydroNode};
 use crate::location::external_process::{
-    ExternalBincodeSink, ExternalBincodeStream, ExternalBytesPort,
+    ExternalBincodeSink, ExternalBincodeStream, ExternalBytesPort, ExternalService,
 };
 use crate::location::{Cluster, ExternalProcess, Location, LocationId, Process};
+use crate::snapshot_runtime::{SnapshotConfig, SnapshotManifest};
 use crate::staging_util::Invariant;
 
 pub struct DeployFlow<'a, D: LocalDeploy<'a>> {
@@ -28,11 +32,39 @@ pub struct DeployFlow<'a, D: LocalDeploy<'a>> {
     pub(super) nodes: HashMap<usize, D::Process>,
     pub(super) externals: HashMap<usize, D::ExternalProcess>,
     pub(super) clusters: HashMap<usize, D::Cluster>,
+    pub(super) snapshots: Option<SnapshotSetup>,
     pub(super) used: bool,
 
     pub(super) _phantom: Invariant<'a, D>,
 }
 
+/// Distributed snapshot settings, see [`DeployFlow::with_snapshots`].
+pub(super) struct SnapshotSetup {
+    initiator: usize,
+    config: SnapshotConfig,
+    restore: Option<SnapshotManifest>,
+}
+
+/// Returns the first operator reachable from `node` whose state is not captured by snapshots.
+fn uncaptured_state(
+    node: &HydroNode,
+    seen_tees: &mut HashSet<*const RefCell<HydroNode>>,
+) -> Option<&'static str> {
+    match node {
+        HydroNode::DeferTick(_) => return Some("defer_tick"),
+        HydroNode::Delta(_) => return Some("delta"),
+        HydroNode::Tee { inner } if !seen_tees.insert(Rc::as_ptr(&inner.0)) => return None,
+        _ => {}
+    }
+    let mut found = None;
+    node.visit_inputs(|input| {
+        if found.is_none() {
+            found = uncaptured_state(input, seen_tees);
+        }
+    });
+    found
+}
+
 impl<'a, D: LocalDeploy<'a>> Drop for DeployFlow<'a, D> {
     fn drop(&mut self) {
         if !self.used {
@@ -77,11 +109,58 @@ impl<'a, D: LocalDeploy<'a>> DeployFlow<'a, D> {
         self
     }
 
+    /// Periodically takes a consistent snapshot of all processes and clusters, including the
+    /// messages in flight between them, using the Chandy-Lamport algorithm. Snapshots are
+    /// started by `initiator` and written to `config.dir`, see [`crate::snapshot_runtime`].
+    ///
+    /// Persisted state is kept in `'durable` operators so that it can be captured. Panics if the
+    /// flow uses `defer_tick` (including tick cycles) or `delta`, whose state is not captured.
+    pub fn with_snapshots<P>(mut self, initiator: &Process<P>, mut config: SnapshotConfig) -> Self {
+        let mut seen_tees = HashSet::new();
+        for leaf in self.ir.iter() {
+            if let Some(op) = uncaptured_state(leaf.input(), &mut seen_tees) {
+                panic!(
+                    "Snapshots do not capture the state of `{}`, which is used by this flow.",
+                    op
+                );
+            }
+        }
+
+        // participants may run in different working directories
+        config.dir = std::path::absolute(&config.dir).unwrap_or_else(|err| {
+            panic!(
+                "Failed to resolve snapshot directory {}: {}",
+                config.dir.display(),
+                err
+            )
+        });
+        self.snapshots = Some(SnapshotSetup {
+            initiator: initiator.id,
+            config,
+            restore: None,
+        });
+        self
+    }
+
+    /// Restarts all processes and clusters from the complete snapshot described by `manifest`,
+    /// which must have been taken from the same flow with the same cluster members.
+    ///
+    /// Must be called after [`Self::with_snapshots`]; snapshots continue to be taken after the
+    /// restart.
+    pub fn restore_snapshot(mut self, manifest: SnapshotManifest) -> Self {
+        let snapshots = self
+            .snapshots
+            .as_mut()
+            .expect("`with_snapshots` must be called before `restore_snapshot`");
+        snapshots.restore = Some(manifest);
+        self
+    }
+
     pub fn compile_no_network(mut self) -> CompiledFlow<'a, D::GraphId> {
         self.used = true;
 
         CompiledFlow {
-            hydroflow_ir: build_inner(&mut self.ir),
+            hydroflow_ir: build_inner(&mut self.ir, None),
             extra_stmts: BTreeMap::new(),
             _phantom: PhantomData,
         }
@@ -92,8 +171,13 @@ impl<'a, D: Deploy<'a>> DeployFlow<'a, D> {
     pub fn compile(mut self, env: &D::CompileEnv) -> CompiledFlow<'a, D::GraphId> {
         self.used = true;
 
+        let gated_ir =
+            crate::rewrites::batch_gates::insert_batch_gates(std::mem::take(&mut self.ir), |id| {
+                D::local_batch_gate(env, id)
+            });
+
         let mut seen_tees: HashMap<_, _> = HashMap::new();
-        let mut flow_state_networked: Vec<HydroLeaf> = std::mem::take(&mut self.ir)
+        let mut flow_state_networked: Vec<HydroLeaf> = gated_ir
             .into_iter()
             .map(|leaf| {
                 leaf.compile_network::<D>(
@@ -109,15 +193,16 @@ impl<'a, D: Deploy<'a>> DeployFlow<'a, D> {
         let extra_stmts = self.extra_stmts(env);
 
         CompiledFlow {
-            hydroflow_ir: build_inner(&mut flow_state_networked),
+            hydroflow_ir: build_inner(
+                &mut flow_state_networked,
+                self.snapshots.as_ref().map(|snapshots| snapshots.initiator),
+            ),
             extra_stmts,
             _phantom: PhantomData,
         }
     }
 
     fn extra_stmts(&self, env: &<D as Deploy<'a>>::CompileEnv) -> BTreeMap<usize, Vec<syn::Stmt>> {
-        let all_locations_count = self.nodes.len() + self.clusters.len();
-
         let mut extra_stmts: BTreeMap<usize, Vec<syn::Stmt>> = BTreeMap::new();
         for &c_id in self.clusters.keys() {
             let self_id_ident = syn::Ident::new(
@@ -132,7 +217,8 @@ impl<'a, D: Deploy<'a>> DeployFlow<'a, D> {
                     let #self_id_ident = #self_id_expr;
                 });
 
-            for other_location in 0..all_locations_count {
+            // location IDs are shared with external processes, so they may not be contiguous
+            for &other_location in self.nodes.keys().chain(self.clusters.keys()) {
                 let other_id_ident = syn::Ident::new(
                     &format!("__hydro_lang_cluster_ids_{}", c_id),
                     Span::call_site(),
@@ -146,14 +232,103 @@ impl<'a, D: Deploy<'a>> DeployFlow<'a, D> {
                     });
             }
         }
+
+        if let Some(snapshots) = &self.snapshots {
+            self.snapshot_stmts(snapshots, &mut extra_stmts);
+        }
+
         extra_stmts
     }
+
+    /// Installs the [`SnapshotRuntime`](crate::snapshot_runtime::SnapshotRuntime) of each process
+    /// and cluster member, as `__hydro_lang_snapshots`.
+    fn snapshot_stmts(
+        &self,
+        snapshots: &SnapshotSetup,
+        extra_stmts: &mut BTreeMap<usize, Vec<syn::Stmt>>,
+    ) {
+        let mut process_ids = self.nodes.keys().copied().collect::<Vec<_>>();
+        process_ids.sort();
+        let mut cluster_ids = self.clusters.keys().copied().collect::<Vec<_>>();
+        cluster_ids.sort();
+
+        let process_names = process_ids.iter().map(|id| format!("loc{}", id));
+        let cluster_members = cluster_ids.iter().map(|id| {
+            let ids_ident = syn::Ident::new(
+                &format!("__hydro_lang_cluster_ids_{}", id),
+                Span::call_site(),
+            );
+            let name_format = format!("loc{}_{{}}", id);
+            quote::quote!(#ids_ident.iter().map(|member| format!(#name_format, member)))
+        });
+        let participants: syn::Expr = syn::parse_quote! {{
+            let mut participants: Vec<String> = vec![#(#process_names.to_owned()),*];
+            #(participants.extend(#cluster_members);)*
+            participants
+        }};
+
+        let dir = snapshots
+            .config
+            .dir
+            .to_str()
+            .expect("Snapshot directory must be valid UTF-8.");
+        let restore: syn::Expr = match &snapshots.restore {
+            Some(manifest) => {
+                let id = manifest.id;
+                let restore_dir = manifest
+                    .dir
+                    .to_str()
+                    .expect("Snapshot directory must be valid UTF-8.");
+                syn::parse_quote!(Some((#id, #restore_dir)))
+            }
+            None => syn::parse_quote!(None),
+        };
+
+        for (&location_id, is_cluster) in process_ids
+            .iter()
+            .map(|id| (id, false))
+            .chain(cluster_ids.iter().map(|id| (id, true)))
+        {
+            let participant: syn::Expr = if is_cluster {
+                let self_id_ident = syn::Ident::new(
+                    &format!("__hydro_lang_cluster_self_id_{}", location_id),
+                    Span::call_site(),
+                );
+                let name_format = format!("loc{}_{{}}", location_id);
+                syn::parse_quote!(format!(#name_format, #self_id_ident))
+            } else {
+                let name = format!("loc{}", location_id);
+                syn::parse_quote!(#name.to_owned())
+            };
+
+            let interval: syn::Expr = if location_id == snapshots.initiator {
+                let interval_nanos = snapshots.config.interval.as_nanos() as u64;
+                syn::parse_quote!(Some(std::time::Duration::from_nanos(#interval_nanos)))
+            } else {
+                syn::parse_quote!(None)
+            };
+
+            extra_stmts
+                .entry(location_id)
+                .or_default()
+                .push(syn::parse_quote! {
+                    let __hydro_lang_snapshots = hydro_lang::snapshot_runtime::SnapshotRuntime::install(
+                        #dir,
+                        #participant,
+                        #participants,
+                        #interval,
+                        #restore,
+                    );
+                });
+        }
+    }
 }
 
 impl<'a, D: Deploy<'a, CompileEnv = ()>> DeployFlow<'a, D> {
     #[must_use]
     pub fn deploy(mut self, env: &mut D::InstantiateEnv) -> DeployResult<'a, D> {
         self.used = true;
+        let fingerprint = FlowFingerprint::of(&self.ir);
 
         let mut seen_tees_instantiate: HashMap<_, _> = HashMap::new();
         let mut flow_state_networked: Vec<HydroLeaf> = std::mem::take(&mut self.ir)
@@ -169,7 +344,10 @@ impl<'a, D: Deploy<'a, CompileEnv = ()>> DeployFlow<'a, D> {
             })
             .collect();
 
-        let mut compiled = build_inner(&mut flow_state_networked);
+        let mut compiled = build_inner(
+            &mut flow_state_networked,
+            self.snapshots.as_ref().map(|snapshots| snapshots.initiator),
+        );
         let mut extra_stmts = self.extra_stmts(&());
         let mut meta = D::Meta::default();
 
@@ -233,7 +411,64 @@ impl<'a, D: Deploy<'a, CompileEnv = ()>> DeployFlow<'a, D> {
             processes,
             clusters,
             externals,
+            fingerprint,
+        }
+    }
+
+    /// Upgrades `deployed`, which was deployed from an earlier version of this flow, by
+    /// replacing only the processes and clusters whose code changed. Their peers keep running
+    /// and reconnect to them; messages in flight to a replaced location may be lost. The
+    /// replacement happens when the deployment is upgraded, e.g. with
+    /// `Deployment::upgrade` in Hydro Deploy.
+    ///
+    /// All locations must be given specs as in [`Self::deploy`], but the specs of unchanged
+    /// locations are ignored. Returns an error, without changing the deployment, if the
+    /// network edges or the types sent over them changed, see [`BuiltFlow::diff`](super::built::BuiltFlow::diff).
+    pub fn upgrade(
+        mut self,
+        deployed: &mut DeployResult<'a, D>,
+        env: &mut D::InstantiateEnv,
+    ) -> Result<FlowDiff, UpgradeError> {
+        self.used = true;
+        let fingerprint = FlowFingerprint::of(&self.ir);
+        let diff = deployed.fingerprint.diff(&fingerprint)?;
+
+        let mut seen_tees: HashMap<_, _> = HashMap::new();
+        let mut flow_state_networked: Vec<HydroLeaf> = std::mem::take(&mut self.ir)
+            .into_iter()
+            .map(|leaf| {
+                leaf.compile_network::<D>(
+                    &(),
+                    &mut seen_tees,
+                    &self.nodes,
+                    &self.clusters,
+                    &self.externals,
+                )
+            })
+            .collect();
+
+        let mut compiled = build_inner(
+            &mut flow_state_networked,
+            self.snapshots.as_ref().map(|snapshots| snapshots.initiator),
+        );
+        let mut extra_stmts = self.extra_stmts(&());
+
+        for location in &diff.changed {
+            let id = match location {
+                LocationId::Process(id) | LocationId::Cluster(id) => *id,
+                _ => unreachable!(),
+            };
+            let graph = compiled.remove(&id).unwrap();
+            let stmts = extra_stmts.remove(&id).unwrap_or_default();
+            if let LocationId::Process(_) = location {
+                self.nodes[&id].upgrade(&deployed.processes[&id], env, graph, stmts);
+            } else {
+                self.clusters[&id].upgrade(&deployed.clusters[&id], env, graph, stmts);
+            }
         }
+
+        deployed.fingerprint = fingerprint;
+        Ok(diff)
     }
 }
 
@@ -241,6 +476,7 @@ pub struct DeployResult<'a, D: Deploy<'a>> {
     processes: HashMap<usize, D::Process>,
     clusters: HashMap<usize, D::Cluster>,
     externals: HashMap<usize, D::ExternalProcess>,
+    fingerprint: FlowFingerprint,
 }
 
 impl<'a, D: Deploy<'a>> DeployResult<'a, D> {
@@ -316,4 +552,20 @@ impl<'a, D: Deploy<'a>> DeployResult<'a, D> {
             .as_bincode_source(port.port_id)
             .await
     }
+
+    /// Connects a typed client to a service created with
+    /// [`ExternalProcess::source_external_service`].
+    pub async fn connect_service<Req, Resp>(
+        &self,
+        service: ExternalService<Req, Resp>,
+    ) -> ServiceClient<Req, Resp>
+    where
+        Req: Serialize + DeserializeOwned + 'static,
+        Resp: Serialize + DeserializeOwned + 'static,
+    {
+        ServiceClient::new(
+            self.connect_sink_bincode(service.requests).await,
+            self.connect_source_bincode(service.responses).await,
+        )
+    }
 }
@@ -21,6 +21,8 @@ pub mod built;
 pub mod compiled;
 #[cfg(feature = "build")]
 pub mod deploy;
+#[cfg(feature = "build")]
+pub mod upgrade;
 
 pub struct FlowStateInner {
     /// Tracks the leaves of the dataflow IR. This is referenced by
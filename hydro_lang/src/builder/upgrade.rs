@@ -0,0 +1,424 @@
+//! Compares two versions of a flow to decide which locations can be upgraded in place, see
+//! [`DeployFlow::upgrade`](super::deploy::DeployFlow::upgrade).
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
+
+use crate::ir::{DebugExpr, HydroLeaf, HydroNode};
+use crate::location::LocationId;
+
+/// Which locations differ between two versions of a flow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlowDiff {
+    /// The processes and clusters whose code changed, which are restarted to upgrade them.
+    pub changed: Vec<LocationId>,
+}
+
+/// Why a new version of a flow cannot replace a running one without restarting all of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UpgradeError {
+    /// Processes, clusters or external processes were added or removed.
+    LocationsChanged,
+    /// Network edges to or from `location` were added, removed or reordered, so its peers
+    /// would have to be reconnected differently.
+    EdgesChanged { location: LocationId },
+    /// The network edge from `from` to `to` now (de)serializes a different type, so the
+    /// running peer could not understand the upgraded one.
+    IncompatibleEdge {
+        from: LocationId,
+        to: LocationId,
+        old: String,
+        new: String,
+    },
+    /// The code of `location` changed, but it cannot be restarted on its own.
+    Unsupported { location: LocationId },
+}
+
+impl Display for UpgradeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            UpgradeError::LocationsChanged => {
+                write!(f, "the set of locations changed")
+            }
+            UpgradeError::EdgesChanged { location } => {
+                write!(f, "the network edges of {:?} changed", location)
+            }
+            UpgradeError::IncompatibleEdge { from, to, old, new } => write!(
+                f,
+                "the network edge from {:?} to {:?} changed from `{}` to `{}`",
+                from, to, old, new
+            ),
+            UpgradeError::Unsupported { location } => {
+                write!(
+                    f,
+                    "{:?} changed, but cannot be upgraded on its own",
+                    location
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for UpgradeError {}
+
+/// One end of a network edge, as seen from the location at that end.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct EdgeEnd {
+    outgoing: bool,
+    peer: LocationId,
+    /// The serialization and deserialization code of the edge.
+    schema: String,
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+struct LocationFingerprint {
+    /// The pipelines that run at this location, cut at network edges.
+    pipelines: Vec<String>,
+    /// The network edges of this location, in the order their ports are allocated.
+    edges: Vec<EdgeEnd>,
+}
+
+/// Summarizes the code and network edges of each location of a flow.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct FlowFingerprint {
+    locations: BTreeMap<LocationId, LocationFingerprint>,
+}
+
+/// Renumbers tees and cycles in order of appearance within each location, so that changes to
+/// one location do not change the fingerprint of the others.
+#[derive(Default)]
+struct Numbering {
+    tees: HashMap<*const RefCell<HydroNode>, usize>,
+    cycles: BTreeMap<(LocationId, String), usize>,
+    counts: BTreeMap<LocationId, usize>,
+}
+
+impl Numbering {
+    fn next(&mut self, location: &LocationId) -> usize {
+        let count = self.counts.entry(location.clone()).or_default();
+        *count += 1;
+        *count
+    }
+
+    fn cycle(&mut self, location: &LocationId, ident: &syn::Ident) -> usize {
+        let key = (location.clone(), ident.to_string());
+        if let Some(&n) = self.cycles.get(&key) {
+            n
+        } else {
+            let n = self.next(location);
+            self.cycles.insert(key, n);
+            n
+        }
+    }
+}
+
+fn schema(serialize_fn: &Option<DebugExpr>, deserialize_fn: &Option<DebugExpr>) -> String {
+    format!("{:?} / {:?}", serialize_fn, deserialize_fn)
+}
+
+/// The process, cluster or external process where `node` runs.
+fn location_of(node: &HydroNode) -> LocationId {
+    match node {
+        HydroNode::Source { location_kind, .. }
+        | HydroNode::CycleSource { location_kind, .. }
+        | HydroNode::Nondeterministic { location_kind, .. }
+        | HydroNode::BatchGate { location_kind, .. }
+        | HydroNode::EnterIteration { location_kind, .. }
+        | HydroNode::ExitIteration { location_kind, .. } => location_kind.root().clone(),
+        HydroNode::Network { to_location, .. } => to_location.root().clone(),
+        _ => {
+            let mut location = None;
+            node.visit_inputs(|input| {
+                if location.is_none() {
+                    location = Some(location_of(input));
+                }
+            });
+            location.unwrap()
+        }
+    }
+}
+
+impl FlowFingerprint {
+    pub(crate) fn of(ir: &[HydroLeaf]) -> FlowFingerprint {
+        let mut fingerprint = FlowFingerprint::default();
+        let mut numbering = Numbering::default();
+
+        // edges are collected in the same order as `compile_network` allocates ports
+        let mut seen_tees = HashSet::new();
+        for leaf in ir {
+            fingerprint.collect_edges(leaf.input(), &mut seen_tees);
+        }
+
+        for leaf in ir {
+            let location = match leaf {
+                HydroLeaf::CycleSink { location_kind, .. } => location_kind.root().clone(),
+                _ => location_of(leaf.input()),
+            };
+            let root = match leaf {
+                HydroLeaf::CycleSink { ident, .. } => {
+                    format!("CycleSink({})", numbering.cycle(&location, ident))
+                }
+                _ => leaf.print_root(),
+            };
+            let input = fingerprint.print(leaf.input(), &location, &mut numbering);
+            fingerprint
+                .locations
+                .entry(location)
+                .or_default()
+                .pipelines
+                .push(format!("{}({})", root, input));
+        }
+
+        fingerprint
+    }
+
+    fn collect_edges(
+        &mut self,
+        node: &HydroNode,
+        seen_tees: &mut HashSet<*const RefCell<HydroNode>>,
+    ) {
+        if let HydroNode::Tee { inner } = node {
+            if !seen_tees.insert(Rc::as_ptr(&inner.0)) {
+                return;
+            }
+        }
+
+        node.visit_inputs(|input| self.collect_edges(input, seen_tees));
+
+        if let HydroNode::Network {
+            from_location,
+            to_location,
+            serialize_fn,
+            deserialize_fn,
+            ..
+        } = node
+        {
+            let schema = schema(serialize_fn, deserialize_fn);
+            for (location, peer, outgoing) in [
+                (from_location, to_location, true),
+                (to_location, from_location, false),
+            ] {
+                self.locations
+                    .entry(location.root().clone())
+                    .or_default()
+                    .edges
+                    .push(EdgeEnd {
+                        outgoing,
+                        peer: peer.root().clone(),
+                        schema: schema.clone(),
+                    });
+            }
+        }
+    }
+
+    /// Prints the pipeline ending at `node`, which runs at `location`. The pipelines that send
+    /// to `location` over the network are added to the fingerprints of their senders.
+    fn print(
+        &mut self,
+        node: &HydroNode,
+        location: &LocationId,
+        numbering: &mut Numbering,
+    ) -> String {
+        match node {
+            HydroNode::Tee { inner } => {
+                let ptr = Rc::as_ptr(&inner.0);
+                if let Some(n) = numbering.tees.get(&ptr) {
+                    return format!("Tee({})", n);
+                }
+                let n = numbering.next(location);
+                numbering.tees.insert(ptr, n);
+                let input = self.print(&inner.0.borrow(), location, numbering);
+                format!("Tee({}, {})", n, input)
+            }
+            HydroNode::CycleSource { ident, .. } => {
+                format!("CycleSource({})", numbering.cycle(location, ident))
+            }
+            HydroNode::Network {
+                from_location,
+                to_location,
+                serialize_fn,
+                deserialize_fn,
+                input,
+                ..
+            } => {
+                let from = from_location.root().clone();
+                let schema = schema(serialize_fn, deserialize_fn);
+                let sent = self.print(input, &from, numbering);
+                self.locations
+                    .entry(from.clone())
+                    .or_default()
+                    .pipelines
+                    .push(format!(
+                        "Network(to {:?}, {})({})",
+                        to_location, schema, sent
+                    ));
+                format!("Network(from {:?}, {})", from, schema)
+            }
+            _ => {
+                let mut inputs = vec![];
+                node.visit_inputs(|input| inputs.push(self.print(input, location, numbering)));
+                format!("{}({})", node.print_root(), inputs.join(", "))
+            }
+        }
+    }
+
+    /// Checks whether `new` can replace the running flow described by `self` by restarting
+    /// only the locations whose code changed.
+    pub(crate) fn diff(&self, new: &FlowFingerprint) -> Result<FlowDiff, UpgradeError> {
+        if !self.locations.keys().eq(new.locations.keys()) {
+            return Err(UpgradeError::LocationsChanged);
+        }
+
+        for ((location, old), new) in self.locations.iter().zip(new.locations.values()) {
+            let same_topology = old.edges.len() == new.edges.len()
+                && old
+                    .edges
+                    .iter()
+                    .zip(new.edges.iter())
+                    .all(|(old, new)| old.outgoing == new.outgoing && old.peer == new.peer);
+            if !same_topology {
+                return Err(UpgradeError::EdgesChanged {
+                    location: location.clone(),
+                });
+            }
+
+            if let Some((old, new)) = old
+                .edges
+                .iter()
+                .zip(new.edges.iter())
+                .find(|(old, new)| old.schema != new.schema)
+            {
+                let (from, to) = if old.outgoing {
+                    (location.clone(), old.peer.clone())
+                } else {
+                    (old.peer.clone(), location.clone())
+                };
+                return Err(UpgradeError::IncompatibleEdge {
+                    from,
+                    to,
+                    old: old.schema.clone(),
+                    new: new.schema.clone(),
+                });
+            }
+        }
+
+        let mut changed = vec![];
+        for ((location, old), new) in self.locations.iter().zip(new.locations.values()) {
+            if old.pipelines != new.pipelines {
+                if let LocationId::ExternalProcess(_) = location {
+                    return Err(UpgradeError::Unsupported {
+                        location: location.clone(),
+                    });
+                }
+                changed.push(location.clone());
+            }
+        }
+
+        Ok(FlowDiff { changed })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stageleft::*;
+
+    use super::{FlowDiff, UpgradeError};
+    use crate::builder::built::BuiltFlow;
+    use crate::deploy::DeployRuntime;
+    use crate::location::{Location, LocationId};
+    use crate::{FlowBuilder, Process};
+
+    struct P1 {}
+    struct P2 {}
+
+    fn build<'a>(
+        flow: impl FnOnce(&Process<'a, P1>, &Process<'a, P2>),
+    ) -> (BuiltFlow<'a>, LocationId, LocationId) {
+        let builder = FlowBuilder::new();
+        let p1 = builder.process::<P1>();
+        let p2 = builder.process::<P2>();
+        flow(&p1, &p2);
+        (builder.finalize(), p1.id(), p2.id())
+    }
+
+    fn finish(built: BuiltFlow<'_>) {
+        let _ = built
+            .with_default_optimize::<DeployRuntime>()
+            .compile(&RuntimeData::new("FAKE"));
+    }
+
+    fn numbers<'a>(p1: &Process<'a, P1>, p2: &Process<'a, P2>, offset: u32) {
+        p1.source_iter(q!(0..10u32))
+            .send_bincode(p2)
+            .map(q!(move |n| n + offset))
+            .for_each(q!(|n| println!("{}", n)));
+    }
+
+    #[test]
+    fn diff_changed_location() {
+        let (old, _, _) = build(|p1, p2| numbers(p1, p2, 0));
+        let (new, _, p2) = build(|p1, p2| numbers(p1, p2, 1));
+        assert_eq!(new.diff(&old), Ok(FlowDiff { changed: vec![p2] }));
+
+        let (same, _, _) = build(|p1, p2| numbers(p1, p2, 0));
+        assert_eq!(same.diff(&old), Ok(FlowDiff { changed: vec![] }));
+
+        finish(old);
+        finish(new);
+        finish(same);
+    }
+
+    #[test]
+    fn diff_rejects_incompatible_edge() {
+        let (old, _, _) = build(|p1, p2| numbers(p1, p2, 0));
+        let (new, p1, p2) = build(|p1, p2| {
+            p1.source_iter(q!(0..10u64))
+                .send_bincode(p2)
+                .for_each(q!(|n| println!("{}", n)));
+        });
+
+        match new.diff(&old) {
+            Err(UpgradeError::IncompatibleEdge { from, to, old, new }) => {
+                assert_eq!((from, to), (p1, p2));
+                assert!(
+                    old.contains("u32") && new.contains("u64"),
+                    "{} {}",
+                    old,
+                    new
+                );
+            }
+            other => panic!("unexpected diff: {:?}", other),
+        }
+
+        finish(old);
+        finish(new);
+    }
+
+    #[test]
+    fn diff_rejects_changed_edges() {
+        let (old, _, _) = build(|p1, p2| numbers(p1, p2, 0));
+        let (new, p1, _) = build(|p1, p2| {
+            numbers(p1, p2, 0);
+            p2.source_iter(q!(0..10u32))
+                .send_bincode(p1)
+                .for_each(q!(|n| println!("{}", n)));
+        });
+        assert_eq!(
+            new.diff(&old),
+            Err(UpgradeError::EdgesChanged { location: p1 })
+        );
+
+        let builder = FlowBuilder::new();
+        let p1 = builder.process::<P1>();
+        p1.source_iter(q!(0..10u32))
+            .for_each(q!(|n| println!("{}", n)));
+        let single = builder.finalize();
+        assert_eq!(single.diff(&old), Err(UpgradeError::LocationsChanged));
+
+        finish(old);
+        finish(new);
+        finish(single);
+    }
+}
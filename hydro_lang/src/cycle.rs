@@ -3,6 +3,7 @@ use crate::staging_util::Invariant;
 
 pub enum ForwardRefMarker {}
 pub enum TickCycleMarker {}
+pub enum IterationCycleMarker {}
 
 pub trait DeferTick {
     fn defer_tick(self) -> Self;
@@ -53,3 +54,18 @@ impl<'a, S: CycleComplete<'a, TickCycleMarker> + DeferTick> TickCycle<'a, S> {
         S::complete(stream.defer_tick(), ident, self.expected_location)
     }
 }
+
+/// A loop-carried collection in an iteration scope, created by
+/// [`Iteration::loop_carried`](crate::location::Iteration::loop_carried).
+pub struct IterationCycle<'a, S: CycleComplete<'a, IterationCycleMarker>> {
+    pub(crate) ident: syn::Ident,
+    pub(crate) expected_location: LocationId,
+    pub(crate) _phantom: Invariant<'a, S>,
+}
+
+impl<'a, S: CycleComplete<'a, IterationCycleMarker>> IterationCycle<'a, S> {
+    pub fn complete_next_iteration(self, stream: S) {
+        let ident = self.ident;
+        S::complete(stream, ident, self.expected_location)
+    }
+}
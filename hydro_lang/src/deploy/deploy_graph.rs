@@ -678,16 +678,36 @@ impl Node for DeployNode {
         graph: DfirGraph,
         extra_stmts: Vec<syn::Stmt>,
     ) {
-        let service = match self.service_spec.borrow_mut().take().unwrap() {
+        let service = self.create_service(graph, extra_stmts);
+        *self.underlying.borrow_mut() = Some(env.add_service(service));
+    }
+
+    fn upgrade(
+        &self,
+        previous: &Self,
+        _env: &mut Self::InstantiateEnv,
+        graph: DfirGraph,
+        extra_stmts: Vec<syn::Stmt>,
+    ) {
+        let service = self.create_service(graph, extra_stmts);
+        previous
+            .underlying()
+            .try_write()
+            .unwrap()
+            .stage_upgrade(service);
+    }
+}
+
+impl DeployNode {
+    fn create_service(&self, graph: DfirGraph, extra_stmts: Vec<syn::Stmt>) -> HydroflowCrate {
+        match self.service_spec.borrow_mut().take().unwrap() {
             CrateOrTrybuild::Crate(c) => c,
             CrateOrTrybuild::Trybuild(trybuild) => {
                 let (bin_name, (dir, target_dir, features)) =
                     create_graph_trybuild(graph, extra_stmts, &trybuild.name_hint);
                 create_trybuild_service(trybuild, &dir, &target_dir, &features, &bin_name)
             }
-        };
-
-        *self.underlying.borrow_mut() = Some(env.add_service(service));
+        }
     }
 }
 
@@ -736,38 +756,10 @@ impl Node for DeployCluster {
         graph: DfirGraph,
         extra_stmts: Vec<syn::Stmt>,
     ) {
-        let has_trybuild = self
-            .cluster_spec
-            .borrow()
-            .as_ref()
-            .unwrap()
-            .iter()
-            .any(|spec| matches!(spec, CrateOrTrybuild::Trybuild { .. }));
-
-        let maybe_trybuild = if has_trybuild {
-            Some(create_graph_trybuild(graph, extra_stmts, &self.name_hint))
-        } else {
-            None
-        };
-
         let cluster_nodes = self
-            .cluster_spec
-            .borrow_mut()
-            .take()
-            .unwrap()
+            .create_services(graph, extra_stmts)
             .into_iter()
-            .map(|spec| {
-                let service = match spec {
-                    CrateOrTrybuild::Crate(c) => c,
-                    CrateOrTrybuild::Trybuild(trybuild) => {
-                        let (bin_name, (dir, target_dir, features)) =
-                            maybe_trybuild.as_ref().unwrap();
-                        create_trybuild_service(trybuild, dir, target_dir, features, bin_name)
-                    }
-                };
-
-                env.add_service(service)
-            })
+            .map(|service| env.add_service(service))
             .collect::<Vec<_>>();
         meta.insert(self.id, (0..(cluster_nodes.len() as u32)).collect());
         *self.members.borrow_mut() = cluster_nodes
@@ -776,6 +768,30 @@ impl Node for DeployCluster {
             .collect();
     }
 
+    fn upgrade(
+        &self,
+        previous: &Self,
+        _env: &mut Self::InstantiateEnv,
+        graph: DfirGraph,
+        extra_stmts: Vec<syn::Stmt>,
+    ) {
+        let services = self.create_services(graph, extra_stmts);
+        let members = previous.members();
+        assert_eq!(
+            services.len(),
+            members.len(),
+            "Hot upgrades cannot change the number of cluster members."
+        );
+
+        for (service, member) in services.into_iter().zip(members) {
+            member
+                .underlying
+                .try_write()
+                .unwrap()
+                .stage_upgrade(service);
+        }
+    }
+
     fn update_meta(&mut self, meta: &Self::Meta) {
         for (cluster_id, node) in self.members.borrow().iter().enumerate() {
             let mut n = node.underlying.try_write().unwrap();
@@ -788,6 +804,42 @@ impl Node for DeployCluster {
     }
 }
 
+impl DeployCluster {
+    fn create_services(
+        &self,
+        graph: DfirGraph,
+        extra_stmts: Vec<syn::Stmt>,
+    ) -> Vec<HydroflowCrate> {
+        let has_trybuild = self
+            .cluster_spec
+            .borrow()
+            .as_ref()
+            .unwrap()
+            .iter()
+            .any(|spec| matches!(spec, CrateOrTrybuild::Trybuild { .. }));
+
+        let maybe_trybuild = if has_trybuild {
+            Some(create_graph_trybuild(graph, extra_stmts, &self.name_hint))
+        } else {
+            None
+        };
+
+        self.cluster_spec
+            .borrow_mut()
+            .take()
+            .unwrap()
+            .into_iter()
+            .map(|spec| match spec {
+                CrateOrTrybuild::Crate(c) => c,
+                CrateOrTrybuild::Trybuild(trybuild) => {
+                    let (bin_name, (dir, target_dir, features)) = maybe_trybuild.as_ref().unwrap();
+                    create_trybuild_service(trybuild, dir, target_dir, features, bin_name)
+                }
+            })
+            .collect()
+    }
+}
+
 #[derive(Clone)]
 pub struct DeployProcessSpec(HydroflowCrate);
 
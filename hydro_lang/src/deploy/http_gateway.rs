@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::io::Result;
+use std::rc::Rc;
+
+use dfir_rs::futures::future::LocalBoxFuture;
+use dfir_rs::futures::stream::FuturesUnordered;
+use dfir_rs::futures::StreamExt;
+use dfir_rs::serde_json;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+use super::{ServiceClient, ServiceError};
+
+const MAX_HEADERS: usize = 64;
+
+struct HttpResponse {
+    status: u16,
+    reason: &'static str,
+    body: Vec<u8>,
+}
+
+impl HttpResponse {
+    fn error(status: u16, reason: &'static str, message: impl ToString) -> HttpResponse {
+        HttpResponse {
+            status,
+            reason,
+            body: serde_json::to_vec(&serde_json::json!({ "error": message.to_string() })).unwrap(),
+        }
+    }
+}
+
+type RouteHandler = Box<dyn Fn(Vec<u8>) -> LocalBoxFuture<'static, HttpResponse>>;
+
+/// Serves [`ExternalService`](crate::location::external_process::ExternalService)s over
+/// HTTP/1.1, so that they can be called with JSON bodies from tools like `curl`.
+///
+/// Each route forwards the deserialized body of a request to its service, and responds with
+/// the service's response serialized as JSON. Malformed bodies are rejected with `400`, calls
+/// that time out with `504`, and calls to a service that has shut down with `502`.
+#[derive(Default)]
+pub struct HttpGateway {
+    routes: HashMap<String, HashMap<String, RouteHandler>>,
+}
+
+impl HttpGateway {
+    pub fn new() -> HttpGateway {
+        HttpGateway::default()
+    }
+
+    /// Routes requests with the given method (e.g. `POST`) and path to `client`'s service.
+    pub fn route<Req: DeserializeOwned + 'static, Resp: Serialize + 'static>(
+        mut self,
+        method: &str,
+        path: &str,
+        client: ServiceClient<Req, Resp>,
+    ) -> HttpGateway {
+        let client = Rc::new(client);
+        let handler: RouteHandler = Box::new(move |body| {
+            let client = client.clone();
+            Box::pin(async move {
+                let request = match serde_json::from_slice::<Req>(&body) {
+                    Ok(request) => request,
+                    Err(e) => return HttpResponse::error(400, "Bad Request", e),
+                };
+
+                match client.call(request).await {
+                    Ok(response) => HttpResponse {
+                        status: 200,
+                        reason: "OK",
+                        body: serde_json::to_vec(&response).unwrap(),
+                    },
+                    Err(e @ ServiceError::Timeout) => {
+                        HttpResponse::error(504, "Gateway Timeout", e)
+                    }
+                    Err(e) => HttpResponse::error(502, "Bad Gateway", e),
+                }
+            })
+        });
+
+        let previous = self
+            .routes
+            .entry(path.to_string())
+            .or_default()
+            .insert(method.to_ascii_uppercase(), handler);
+        assert!(previous.is_none(), "duplicate route {} {}", method, path);
+        self
+    }
+
+    /// Accepts connections on `listener` and serves requests on them until an error occurs
+    /// while accepting. Connections are served concurrently on the current task.
+    pub async fn serve(&self, listener: TcpListener) -> Result<()> {
+        let mut connections = FuturesUnordered::new();
+        loop {
+            tokio::select! {
+                accepted = listener.accept() => {
+                    let (stream, _) = accepted?;
+                    connections.push(self.serve_connection(stream));
+                }
+                Some(_) = connections.next(), if !connections.is_empty() => {}
+            }
+        }
+    }
+
+    async fn serve_connection(&self, mut stream: TcpStream) -> Result<()> {
+        let mut buf = Vec::new();
+        loop {
+            let (method, path, content_length, keep_alive, header_len) = loop {
+                let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS];
+                let mut request = httparse::Request::new(&mut headers);
+                match request.parse(&buf) {
+                    Ok(httparse::Status::Complete(header_len)) => {
+                        let header = |name: &str| {
+                            request
+                                .headers
+                                .iter()
+                                .find(|h| h.name.eq_ignore_ascii_case(name))
+                                .and_then(|h| std::str::from_utf8(h.value).ok())
+                        };
+
+                        let Some(content_length) = header("Content-Length")
+                            .map_or(Some(0), |v| v.trim().parse::<usize>().ok())
+                        else {
+                            let response =
+                                HttpResponse::error(400, "Bad Request", "invalid Content-Length");
+                            return write_response(&mut stream, response, false).await;
+                        };
+
+                        let keep_alive = match header("Connection") {
+                            Some(c) if c.eq_ignore_ascii_case("close") => false,
+                            Some(c) if c.eq_ignore_ascii_case("keep-alive") => true,
+                            _ => request.version == Some(1),
+                        };
+
+                        let path = request.path.unwrap();
+                        let path = path.split_once('?').map_or(path, |(path, _)| path);
+                        break (
+                            request.method.unwrap().to_string(),
+                            path.to_string(),
+                            content_length,
+                            keep_alive,
+                            header_len,
+                        );
+                    }
+                    Ok(httparse::Status::Partial) => {
+                        if stream.read_buf(&mut buf).await? == 0 {
+                            return Ok(());
+                        }
+                    }
+                    Err(e) => {
+                        let response = HttpResponse::error(400, "Bad Request", e);
+                        return write_response(&mut stream, response, false).await;
+                    }
+                }
+            };
+
+            while buf.len() < header_len + content_length {
+                if stream.read_buf(&mut buf).await? == 0 {
+                    return Ok(());
+                }
+            }
+            let body = buf[header_len..header_len + content_length].to_vec();
+            buf.drain(..header_len + content_length);
+
+            let response = match self.routes.get(&path) {
+                Some(methods) => match methods.get(&method) {
+                    Some(handler) => handler(body).await,
+                    None => HttpResponse::error(405, "Method Not Allowed", &method),
+                },
+                None => HttpResponse::error(404, "Not Found", &path),
+            };
+
+            write_response(&mut stream, response, keep_alive).await?;
+            if !keep_alive {
+                return Ok(());
+            }
+        }
+    }
+}
+
+async fn write_response(
+    stream: &mut TcpStream,
+    response: HttpResponse,
+    keep_alive: bool,
+) -> Result<()> {
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: {}\r\n\r\n",
+        response.status,
+        response.reason,
+        response.body.len(),
+        if keep_alive { "keep-alive" } else { "close" },
+    );
+    stream.write_all(head.as_bytes()).await?;
+    stream.write_all(&response.body).await?;
+    stream.flush().await
+}
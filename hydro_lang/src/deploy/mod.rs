@@ -29,9 +29,28 @@ pub mod deploy_graph;
 #[cfg(feature = "deploy")]
 pub use deploy_graph::*;
 
+#[cfg(feature = "deploy")]
+pub mod profile_collector;
+
+#[cfg(feature = "deploy")]
+pub use profile_collector::ProfileCollector;
+
 pub mod in_memory_graph;
 pub use in_memory_graph::*;
 
+pub mod service_client;
+pub use service_client::{ServiceClient, ServiceError};
+
+#[cfg(feature = "http")]
+pub mod http_gateway;
+#[cfg(feature = "http")]
+pub use http_gateway::HttpGateway;
+
+#[cfg(feature = "sim")]
+pub mod sim;
+#[cfg(feature = "sim")]
+pub use sim::*;
+
 pub trait LocalDeploy<'a> {
     type Process: Node<Meta = Self::Meta>;
     type Cluster: Node<Meta = Self::Meta>;
@@ -172,6 +191,13 @@ pub trait Deploy<'a> {
         of_cluster: usize,
     ) -> impl QuotedWithContext<'a, &'a Vec<u32>, ()> + Copy + 'a;
     fn cluster_self_id(env: &Self::CompileEnv) -> impl QuotedWithContext<'a, u32, ()> + Copy + 'a;
+
+    /// An expression for the `id`th gate deciding which elements enter each batch of a
+    /// `tick_batch` over local data, see [`crate::ir::HydroNode::BatchGate`]. By default,
+    /// batch boundaries are left to the runtime.
+    fn local_batch_gate(_env: &Self::CompileEnv, _id: usize) -> Option<syn::Expr> {
+        None
+    }
 }
 
 impl<
@@ -243,6 +269,19 @@ pub trait Node {
         graph: DfirGraph,
         extra_stmts: Vec<syn::Stmt>,
     );
+
+    /// Prepares to replace the running program of `previous`, which was instantiated from an
+    /// earlier version of the flow, with `graph`. The replacement happens when the environment
+    /// is upgraded, and must keep the network ports of `previous` so that peers can reconnect.
+    fn upgrade(
+        &self,
+        _previous: &Self,
+        _env: &mut Self::InstantiateEnv,
+        _graph: DfirGraph,
+        _extra_stmts: Vec<syn::Stmt>,
+    ) {
+        panic!("This deployment does not support hot upgrades.");
+    }
 }
 
 pub trait RegisterPort<'a, D: Deploy<'a> + ?Sized>: Clone {
@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use dfir_rs::serde_json;
+
+use super::DeployCrateWrapper;
+use crate::rewrites::profiler::{OperatorProfile, ProfileReport, ProfiledOperator, PROFILE_PREFIX};
+
+/// Collects the profiles printed by deployed processes that were instrumented with
+/// [`crate::rewrites::profiler::profiling_stdout`].
+#[derive(Clone, Default)]
+pub struct ProfileCollector {
+    latest: Arc<Mutex<BTreeMap<String, Vec<OperatorProfile>>>>,
+}
+
+impl ProfileCollector {
+    pub fn new() -> ProfileCollector {
+        ProfileCollector::default()
+    }
+
+    /// Starts collecting the profiles printed by `process`, which must already be deployed. Each
+    /// profile replaces the previous one from the same `name`.
+    pub async fn watch(&self, name: impl Into<String>, process: &impl DeployCrateWrapper) {
+        let name = name.into();
+        let latest = self.latest.clone();
+        let mut stdout = process.stdout().await;
+        tokio::spawn(async move {
+            while let Some(line) = stdout.recv().await {
+                let Some(profiles) = line.strip_prefix(PROFILE_PREFIX) else {
+                    continue;
+                };
+                if let Ok(profiles) = serde_json::from_str(profiles) {
+                    latest.lock().unwrap().insert(name.clone(), profiles);
+                }
+            }
+        });
+    }
+
+    /// Combines the latest profile of every watched process, summing the operators that ran in
+    /// several processes (such as the members of a cluster).
+    pub fn report(&self, operators: &[ProfiledOperator]) -> ProfileReport {
+        let mut report = ProfileReport::new(operators);
+        for profiles in self.latest.lock().unwrap().values() {
+            for profile in profiles {
+                report.merge(profile);
+            }
+        }
+        report
+    }
+}
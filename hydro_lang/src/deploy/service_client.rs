@@ -0,0 +1,148 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::io::Error;
+use std::pin::Pin;
+use std::time::Duration;
+
+use dfir_rs::futures::{Sink, SinkExt, Stream, StreamExt};
+use tokio::sync::{oneshot, Mutex, Semaphore};
+
+use crate::location::external_process::RequestId;
+
+type RequestSink<Req> = Pin<Box<dyn Sink<(RequestId, Req), Error = Error>>>;
+type ResponseStream<Resp> = Pin<Box<dyn Stream<Item = (RequestId, Resp)>>>;
+
+/// Why a [`ServiceClient::call`] did not produce a response.
+#[derive(Debug)]
+pub enum ServiceError {
+    /// No response arrived within the client's timeout.
+    Timeout,
+    /// The service stopped sending responses.
+    Closed,
+    /// The request could not be sent.
+    Send(Error),
+}
+
+impl Display for ServiceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ServiceError::Timeout => write!(f, "timed out waiting for a response"),
+            ServiceError::Closed => write!(f, "the service closed its responses"),
+            ServiceError::Send(e) => write!(f, "failed to send request: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ServiceError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ServiceError::Send(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+/// A typed client for an [`ExternalService`](crate::location::external_process::ExternalService),
+/// which tags each request with a fresh [`RequestId`] and matches responses back to callers.
+///
+/// Concurrent calls share the connection: whichever call is waiting reads the next response and
+/// hands it to the call that made the matching request.
+pub struct ServiceClient<Req, Resp> {
+    requests: Mutex<RequestSink<Req>>,
+    responses: Mutex<ResponseStream<Resp>>,
+    pending: RefCell<HashMap<RequestId, oneshot::Sender<Resp>>>,
+    next_id: Cell<u64>,
+    timeout: Option<Duration>,
+    concurrency: Option<Semaphore>,
+}
+
+impl<Req, Resp> ServiceClient<Req, Resp> {
+    pub fn new(requests: RequestSink<Req>, responses: ResponseStream<Resp>) -> Self {
+        ServiceClient {
+            requests: Mutex::new(requests),
+            responses: Mutex::new(responses),
+            pending: RefCell::new(HashMap::new()),
+            next_id: Cell::new(0),
+            timeout: None,
+            concurrency: None,
+        }
+    }
+
+    /// Fails calls that have not received a response after `timeout`. Late responses to
+    /// those calls are dropped.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Limits the number of calls that are waiting on the service at once; further calls wait
+    /// until an earlier one completes before sending their request.
+    pub fn with_max_concurrency(mut self, limit: usize) -> Self {
+        self.concurrency = Some(Semaphore::new(limit));
+        self
+    }
+
+    /// Sends `request` to the service and waits for its response.
+    pub async fn call(&self, request: Req) -> Result<Resp, ServiceError> {
+        let _permit = match &self.concurrency {
+            Some(semaphore) => Some(semaphore.acquire().await.unwrap()),
+            None => None,
+        };
+
+        let id = RequestId(self.next_id.get());
+        self.next_id.set(id.0 + 1);
+
+        let (sender, receiver) = oneshot::channel();
+        self.pending.borrow_mut().insert(id, sender);
+
+        let result = match self.timeout {
+            Some(timeout) => {
+                tokio::time::timeout(timeout, self.send_and_wait(id, request, receiver))
+                    .await
+                    .unwrap_or(Err(ServiceError::Timeout))
+            }
+            None => self.send_and_wait(id, request, receiver).await,
+        };
+
+        self.pending.borrow_mut().remove(&id);
+        result
+    }
+
+    async fn send_and_wait(
+        &self,
+        id: RequestId,
+        request: Req,
+        mut receiver: oneshot::Receiver<Resp>,
+    ) -> Result<Resp, ServiceError> {
+        self.requests
+            .lock()
+            .await
+            .send((id, request))
+            .await
+            .map_err(ServiceError::Send)?;
+
+        loop {
+            // if another call is reading responses, it forwards ours once it arrives
+            let mut responses = tokio::select! {
+                response = &mut receiver => return response.map_err(|_| ServiceError::Closed),
+                responses = self.responses.lock() => responses,
+            };
+
+            if let Ok(response) = receiver.try_recv() {
+                return Ok(response);
+            }
+
+            match responses.next().await {
+                Some((response_id, response)) if response_id == id => return Ok(response),
+                Some((response_id, response)) => {
+                    // responses to calls that already timed out have no pending entry
+                    if let Some(sender) = self.pending.borrow_mut().remove(&response_id) {
+                        let _ = sender.send(response);
+                    }
+                }
+                None => return Err(ServiceError::Closed),
+            }
+        }
+    }
+}
@@ -0,0 +1,316 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+
+use dfir_lang::graph::DfirGraph;
+use dfir_rs::bytes::Bytes;
+use dfir_rs::futures::{sink, stream, Sink, Stream};
+use stageleft::{QuotedWithContext, RuntimeData};
+
+use crate::deploy::{ClusterSpec, Deploy, ExternalSpec, Node, ProcessSpec, RegisterPort};
+use crate::sim_runtime::SimPorts;
+
+/// Compiles each location into a graph that is connected to a deterministic simulator,
+/// see [`crate::sim_runtime`].
+///
+/// Like [`super::DeployRuntime`], this is meant to be used inside a `stageleft::entry`, where
+/// the flow is compiled with a [`RuntimeData`] referring to the [`SimPorts`] of the instance
+/// being created, and the graph is selected with `with_dynamic_id(q!(ports.subgraph_id))`.
+///
+/// External processes have no counterpart in a simulation, so a flow that sends data to or
+/// from one fails to compile with an error explaining why.
+pub struct SimDeploy {}
+
+const EXTERNAL_UNSUPPORTED: &str = "external processes are not supported in simulation";
+
+fn external_unsupported_expr() -> syn::Expr {
+    syn::parse_quote!(::core::compile_error!(#EXTERNAL_UNSUPPORTED))
+}
+
+fn external_unsupported_error() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Unsupported, EXTERNAL_UNSUPPORTED)
+}
+
+impl<'a> Deploy<'a> for SimDeploy {
+    type InstantiateEnv = ();
+    type CompileEnv = RuntimeData<&'a SimPorts>;
+    type Process = SimNode;
+    type Cluster = SimNode;
+    type ExternalProcess = SimNode;
+    type Port = String;
+    type ExternalRawPort = ();
+    type Meta = ();
+    type GraphId = usize;
+
+    fn has_trivial_node() -> bool {
+        true
+    }
+
+    fn trivial_process(id: usize) -> Self::Process {
+        SimNode::new(id)
+    }
+
+    fn trivial_cluster(id: usize) -> Self::Cluster {
+        SimNode::new(id)
+    }
+
+    fn allocate_process_port(process: &Self::Process) -> Self::Port {
+        process.next_port()
+    }
+
+    fn allocate_cluster_port(cluster: &Self::Cluster) -> Self::Port {
+        cluster.next_port()
+    }
+
+    fn allocate_external_port(external: &Self::ExternalProcess) -> Self::Port {
+        external.next_port()
+    }
+
+    fn o2o_sink_source(
+        env: &Self::CompileEnv,
+        _p1: &Self::Process,
+        _p1_port: &Self::Port,
+        p2: &Self::Process,
+        p2_port: &Self::Port,
+    ) -> (syn::Expr, syn::Expr) {
+        crate::sim_runtime::sim_o2o(*env, p2.id, p2_port.as_str())
+    }
+
+    fn o2o_connect(
+        _p1: &Self::Process,
+        _p1_port: &Self::Port,
+        _p2: &Self::Process,
+        _p2_port: &Self::Port,
+    ) -> Box<dyn FnOnce()> {
+        Box::new(|| panic!())
+    }
+
+    fn o2m_sink_source(
+        env: &Self::CompileEnv,
+        _p1: &Self::Process,
+        _p1_port: &Self::Port,
+        c2: &Self::Cluster,
+        c2_port: &Self::Port,
+    ) -> (syn::Expr, syn::Expr) {
+        crate::sim_runtime::sim_o2m(*env, c2.id, c2_port.as_str())
+    }
+
+    fn o2m_connect(
+        _p1: &Self::Process,
+        _p1_port: &Self::Port,
+        _c2: &Self::Cluster,
+        _c2_port: &Self::Port,
+    ) -> Box<dyn FnOnce()> {
+        Box::new(|| panic!())
+    }
+
+    fn m2o_sink_source(
+        env: &Self::CompileEnv,
+        _c1: &Self::Cluster,
+        _c1_port: &Self::Port,
+        p2: &Self::Process,
+        p2_port: &Self::Port,
+    ) -> (syn::Expr, syn::Expr) {
+        crate::sim_runtime::sim_m2o(*env, p2.id, p2_port.as_str())
+    }
+
+    fn m2o_connect(
+        _c1: &Self::Cluster,
+        _c1_port: &Self::Port,
+        _p2: &Self::Process,
+        _p2_port: &Self::Port,
+    ) -> Box<dyn FnOnce()> {
+        Box::new(|| panic!())
+    }
+
+    fn m2m_sink_source(
+        env: &Self::CompileEnv,
+        _c1: &Self::Cluster,
+        _c1_port: &Self::Port,
+        c2: &Self::Cluster,
+        c2_port: &Self::Port,
+    ) -> (syn::Expr, syn::Expr) {
+        crate::sim_runtime::sim_m2m(*env, c2.id, c2_port.as_str())
+    }
+
+    fn m2m_connect(
+        _c1: &Self::Cluster,
+        _c1_port: &Self::Port,
+        _c2: &Self::Cluster,
+        _c2_port: &Self::Port,
+    ) -> Box<dyn FnOnce()> {
+        Box::new(|| panic!())
+    }
+
+    fn e2o_source(
+        _compile_env: &Self::CompileEnv,
+        _p1: &Self::ExternalProcess,
+        _p1_port: &Self::Port,
+        _p2: &Self::Process,
+        _p2_port: &Self::Port,
+    ) -> syn::Expr {
+        external_unsupported_expr()
+    }
+
+    fn e2o_connect(
+        _p1: &Self::ExternalProcess,
+        _p1_port: &Self::Port,
+        _p2: &Self::Process,
+        _p2_port: &Self::Port,
+    ) -> Box<dyn FnOnce()> {
+        // the flow does not compile, so there is nothing to connect
+        Box::new(|| {})
+    }
+
+    fn o2e_sink(
+        _compile_env: &Self::CompileEnv,
+        _p1: &Self::Process,
+        _p1_port: &Self::Port,
+        _p2: &Self::ExternalProcess,
+        _p2_port: &Self::Port,
+    ) -> syn::Expr {
+        external_unsupported_expr()
+    }
+
+    fn o2e_connect(
+        _p1: &Self::Process,
+        _p1_port: &Self::Port,
+        _p2: &Self::ExternalProcess,
+        _p2_port: &Self::Port,
+    ) -> Box<dyn FnOnce()> {
+        // the flow does not compile, so there is nothing to connect
+        Box::new(|| {})
+    }
+
+    fn cluster_ids(
+        env: &Self::CompileEnv,
+        of_cluster: usize,
+    ) -> impl QuotedWithContext<'a, &'a Vec<u32>, ()> + Copy + 'a {
+        crate::sim_runtime::cluster_members(*env, of_cluster)
+    }
+
+    fn cluster_self_id(env: &Self::CompileEnv) -> impl QuotedWithContext<'a, u32, ()> + Copy + 'a {
+        crate::sim_runtime::cluster_self_id(*env)
+    }
+
+    fn local_batch_gate(env: &Self::CompileEnv, id: usize) -> Option<syn::Expr> {
+        Some(crate::sim_runtime::sim_batch_gate(*env, id))
+    }
+}
+
+#[derive(Clone)]
+pub struct SimNode {
+    id: usize,
+    next_port: Rc<RefCell<usize>>,
+}
+
+impl SimNode {
+    fn new(id: usize) -> SimNode {
+        SimNode {
+            id,
+            next_port: Rc::new(RefCell::new(0)),
+        }
+    }
+}
+
+impl<'a> RegisterPort<'a, SimDeploy> for SimNode {
+    fn register(&self, _key: usize, _port: <SimDeploy as Deploy>::Port) {}
+
+    fn raw_port(&self, _key: usize) -> <SimDeploy as Deploy>::ExternalRawPort {}
+
+    #[expect(
+        clippy::manual_async_fn,
+        reason = "buggy Clippy lint for lifetime bounds"
+    )]
+    fn as_bytes_sink(
+        &self,
+        _key: usize,
+    ) -> impl Future<Output = Pin<Box<dyn Sink<Bytes, Error = std::io::Error>>>> + 'a {
+        async {
+            Box::pin(sink::unfold((), |(), _: Bytes| async {
+                Err(external_unsupported_error())
+            })) as Pin<Box<dyn Sink<Bytes, Error = std::io::Error>>>
+        }
+    }
+
+    #[expect(
+        clippy::manual_async_fn,
+        reason = "buggy Clippy lint for lifetime bounds"
+    )]
+    fn as_bincode_sink<T: serde::Serialize + 'static>(
+        &self,
+        _key: usize,
+    ) -> impl Future<Output = Pin<Box<dyn Sink<T, Error = std::io::Error>>>> + 'a {
+        async {
+            Box::pin(sink::unfold((), |(), _: T| async {
+                Err(external_unsupported_error())
+            })) as Pin<Box<dyn Sink<T, Error = std::io::Error>>>
+        }
+    }
+
+    #[expect(
+        clippy::manual_async_fn,
+        reason = "buggy Clippy lint for lifetime bounds"
+    )]
+    fn as_bytes_source(
+        &self,
+        _key: usize,
+    ) -> impl Future<Output = Pin<Box<dyn Stream<Item = Bytes>>>> + 'a {
+        // flows that use external ports do not compile, so nothing is ever received
+        async { Box::pin(stream::empty()) as Pin<Box<dyn Stream<Item = Bytes>>> }
+    }
+
+    #[expect(
+        clippy::manual_async_fn,
+        reason = "buggy Clippy lint for lifetime bounds"
+    )]
+    fn as_bincode_source<T: serde::de::DeserializeOwned + 'static>(
+        &self,
+        _key: usize,
+    ) -> impl Future<Output = Pin<Box<dyn Stream<Item = T>>>> + 'a {
+        async { Box::pin(stream::empty()) as Pin<Box<dyn Stream<Item = T>>> }
+    }
+}
+
+impl Node for SimNode {
+    type Port = String;
+    type Meta = ();
+    type InstantiateEnv = ();
+
+    fn next_port(&self) -> String {
+        let next_send_port = *self.next_port.borrow();
+        *self.next_port.borrow_mut() += 1;
+        format!("port_{}", next_send_port)
+    }
+
+    fn update_meta(&mut self, _meta: &Self::Meta) {}
+
+    fn instantiate(
+        &self,
+        _env: &mut Self::InstantiateEnv,
+        _meta: &mut Self::Meta,
+        _graph: DfirGraph,
+        _extra_stmts: Vec<syn::Stmt>,
+    ) {
+        panic!(".deploy() cannot be called on a SimNode");
+    }
+}
+
+impl ProcessSpec<'_, SimDeploy> for () {
+    fn build(self, id: usize, _name_hint: &str) -> SimNode {
+        SimNode::new(id)
+    }
+}
+
+impl ClusterSpec<'_, SimDeploy> for () {
+    fn build(self, id: usize, _name_hint: &str) -> SimNode {
+        SimNode::new(id)
+    }
+}
+
+impl ExternalSpec<'_, SimDeploy> for () {
+    fn build(self, id: usize, _name_hint: &str) -> SimNode {
+        SimNode::new(id)
+    }
+}
@@ -99,6 +99,20 @@ pub fn compile_graph_trybuild(graph: DfirGraph, extra_stmts: Vec<syn::Stmt>) ->
         &mut diagnostics,
     );
 
+    // snapshots need the flow to be restored before it runs, see `DeployFlow::with_snapshots`
+    let has_snapshots = extra_stmts.iter().any(|stmt| match stmt {
+        syn::Stmt::Local(syn::Local {
+            pat: syn::Pat::Ident(pat),
+            ..
+        }) => pat.ident == "__hydro_lang_snapshots",
+        _ => false,
+    });
+    let launch_flow = if has_snapshots {
+        quote!(hydro_lang::snapshot_runtime::launch_flow)
+    } else {
+        quote!(hydro_lang::dfir_rs::util::deploy::launch_flow)
+    };
+
     let source_ast: syn::File = syn::parse_quote! {
         #![allow(unused_imports, unused_crate_dependencies, missing_docs, non_snake_case)]
         use hydro_lang::*;
@@ -114,7 +128,7 @@ pub fn compile_graph_trybuild(graph: DfirGraph, extra_stmts: Vec<syn::Stmt>) ->
             let ports = hydro_lang::dfir_rs::util::deploy::init_no_ack_start().await;
             let flow = __hydro_runtime(&ports);
             println!("ack start");
-            hydro_lang::dfir_rs::util::deploy::launch_flow(flow).await;
+            #launch_flow(flow).await;
         }
     };
     source_ast
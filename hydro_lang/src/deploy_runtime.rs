@@ -1,5 +1,6 @@
 use std::collections::HashMap;
 
+use dfir_rs::util::deploy;
 use dfir_rs::util::deploy::{
     ConnectedDemux, ConnectedDirect, ConnectedSink, ConnectedSource, ConnectedTagged, DeployPorts,
 };
@@ -37,17 +38,21 @@ pub fn deploy_o2o(
     (
         {
             q!({
-                env.port(p1_port)
-                    .connect_local_blocking::<ConnectedDirect>()
-                    .into_sink()
+                deploy::count_sent(
+                    env.port(p1_port)
+                        .connect_local_blocking::<ConnectedDirect>()
+                        .into_sink(),
+                )
             })
             .splice_untyped_ctx(&())
         },
         {
             q!({
-                env.port(p2_port)
-                    .connect_local_blocking::<ConnectedDirect>()
-                    .into_source()
+                deploy::count_received(
+                    env.port(p2_port)
+                        .connect_local_blocking::<ConnectedDirect>()
+                        .into_source(),
+                )
             })
             .splice_untyped_ctx(&())
         },
@@ -62,17 +67,21 @@ pub fn deploy_o2m(
     (
         {
             q!({
-                env.port(p1_port)
-                    .connect_local_blocking::<ConnectedDemux<ConnectedDirect>>()
-                    .into_sink()
+                deploy::count_sent(
+                    env.port(p1_port)
+                        .connect_local_blocking::<ConnectedDemux<ConnectedDirect>>()
+                        .into_sink(),
+                )
             })
             .splice_untyped_ctx(&())
         },
         {
             q!({
-                env.port(c2_port)
-                    .connect_local_blocking::<ConnectedDirect>()
-                    .into_source()
+                deploy::count_received(
+                    env.port(c2_port)
+                        .connect_local_blocking::<ConnectedDirect>()
+                        .into_source(),
+                )
             })
             .splice_untyped_ctx(&())
         },
@@ -87,17 +96,21 @@ pub fn deploy_m2o(
     (
         {
             q!({
-                env.port(c1_port)
-                    .connect_local_blocking::<ConnectedDirect>()
-                    .into_sink()
+                deploy::count_sent(
+                    env.port(c1_port)
+                        .connect_local_blocking::<ConnectedDirect>()
+                        .into_sink(),
+                )
             })
             .splice_untyped_ctx(&())
         },
         {
             q!({
-                env.port(p2_port)
-                    .connect_local_blocking::<ConnectedTagged<ConnectedDirect>>()
-                    .into_source()
+                deploy::count_received(
+                    env.port(p2_port)
+                        .connect_local_blocking::<ConnectedTagged<ConnectedDirect>>()
+                        .into_source(),
+                )
             })
             .splice_untyped_ctx(&())
         },
@@ -112,17 +125,21 @@ pub fn deploy_m2m(
     (
         {
             q!({
-                env.port(c1_port)
-                    .connect_local_blocking::<ConnectedDemux<ConnectedDirect>>()
-                    .into_sink()
+                deploy::count_sent(
+                    env.port(c1_port)
+                        .connect_local_blocking::<ConnectedDemux<ConnectedDirect>>()
+                        .into_sink(),
+                )
             })
             .splice_untyped_ctx(&())
         },
         {
             q!({
-                env.port(c2_port)
-                    .connect_local_blocking::<ConnectedTagged<ConnectedDirect>>()
-                    .into_source()
+                deploy::count_received(
+                    env.port(c2_port)
+                        .connect_local_blocking::<ConnectedTagged<ConnectedDirect>>()
+                        .into_source(),
+                )
             })
             .splice_untyped_ctx(&())
         },
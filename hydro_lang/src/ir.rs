@@ -8,24 +8,31 @@ use std::ops::Deref;
 use std::rc::Rc;
 
 #[cfg(feature = "build")]
-use dfir_lang::graph::FlatGraphBuilder;
+use dfir_lang::graph::{FlatGraphBuilder, GraphLoopId};
+#[cfg(feature = "build")]
+use dfir_lang::parse::HfStatement;
 #[cfg(feature = "build")]
 use proc_macro2::Span;
 use proc_macro2::TokenStream;
 use quote::ToTokens;
+use serde::de::Error as _;
+use serde::ser::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 #[cfg(feature = "build")]
 use syn::parse_quote;
 
+use crate::backtrace::Backtrace;
 #[cfg(feature = "build")]
 use crate::deploy::{Deploy, RegisterPort};
-use crate::location::LocationId;
+use crate::location::{IterationLimit, LocationId};
 
+/// An expression in the IR, along with where it was created (unless it was deserialized).
 #[derive(Clone)]
-pub struct DebugExpr(pub syn::Expr);
+pub struct DebugExpr(pub syn::Expr, pub Backtrace);
 
 impl From<syn::Expr> for DebugExpr {
     fn from(expr: syn::Expr) -> DebugExpr {
-        DebugExpr(expr)
+        DebugExpr(expr, Backtrace::capture())
     }
 }
 
@@ -49,6 +56,40 @@ impl Debug for DebugExpr {
     }
 }
 
+/// Expressions are serialized as their token text, which is parsed back into an expression
+/// when deserializing.
+impl Serialize for DebugExpr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0.to_token_stream().to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for DebugExpr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        syn::parse_str(&text)
+            .map(|expr| DebugExpr(expr, Backtrace::default()))
+            .map_err(|e| D::Error::custom(format!("invalid expression `{}`: {}", text, e)))
+    }
+}
+
+mod serde_ident {
+    use proc_macro2::Span;
+    use serde::de::Error as _;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(ident: &syn::Ident, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&ident.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<syn::Ident, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        let mut ident = syn::parse_str::<syn::Ident>(&text).map_err(D::Error::custom)?;
+        ident.set_span(Span::call_site());
+        Ok(ident)
+    }
+}
+
 pub enum DebugInstantiate {
     Building(),
     Finalized(syn::Expr, syn::Expr, Option<Box<dyn FnOnce()>>),
@@ -60,8 +101,28 @@ impl Debug for DebugInstantiate {
     }
 }
 
+/// Only networks that have not yet been instantiated can be serialized, since
+/// the instantiated sinks and sources are specific to a deployment.
+impl Serialize for DebugInstantiate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            DebugInstantiate::Building() => serializer.serialize_unit(),
+            DebugInstantiate::Finalized(..) => Err(S::Error::custom(
+                "cannot serialize a network that has already been instantiated",
+            )),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for DebugInstantiate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        <()>::deserialize(deserializer)?;
+        Ok(DebugInstantiate::Building())
+    }
+}
+
 /// A source in a Hydro graph, where data enters the graph.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum HydroSource {
     Stream(DebugExpr),
     ExternalNetwork(),
@@ -72,7 +133,7 @@ pub enum HydroSource {
 /// An leaf in a Hydro graph, which is an pipeline that doesn't emit
 /// any downstream values. Traversals over the dataflow graph and
 /// generating Hydroflow IR start from leaves.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum HydroLeaf {
     ForEach {
         f: DebugExpr,
@@ -83,6 +144,7 @@ pub enum HydroLeaf {
         input: Box<HydroNode>,
     },
     CycleSink {
+        #[serde(with = "serde_ident")]
         ident: syn::Ident,
         location_kind: LocationId,
         input: Box<HydroNode>,
@@ -116,6 +178,23 @@ impl HydroLeaf {
         )
     }
 
+    pub fn input(&self) -> &HydroNode {
+        match self {
+            HydroLeaf::ForEach { input, .. }
+            | HydroLeaf::DestSink { input, .. }
+            | HydroLeaf::CycleSink { input, .. } => input,
+        }
+    }
+
+    /// Prints only the operator at the root of this leaf, without its input.
+    pub fn print_root(&self) -> String {
+        match self {
+            HydroLeaf::ForEach { f, .. } => format!("ForEach({:?})", f),
+            HydroLeaf::DestSink { sink, .. } => format!("DestSink({:?})", sink),
+            HydroLeaf::CycleSink { ident, .. } => format!("CycleSink({})", ident),
+        }
+    }
+
     pub fn transform_children(
         self,
         mut transform: impl FnMut(&mut HydroNode, &mut SeenTees),
@@ -150,12 +229,19 @@ impl HydroLeaf {
         &self,
         graph_builders: &mut BTreeMap<usize, FlatGraphBuilder>,
         built_tees: &mut HashMap<*const RefCell<HydroNode>, (syn::Ident, usize)>,
+        built_loops: &mut HashMap<usize, GraphLoopId>,
         next_stmt_id: &mut usize,
+        snapshots: bool,
     ) {
         match self {
             HydroLeaf::ForEach { f, input } => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 graph_builders
                     .entry(input_location_id)
@@ -166,8 +252,13 @@ impl HydroLeaf {
             }
 
             HydroLeaf::DestSink { sink, input } => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 graph_builders
                     .entry(input_location_id)
@@ -182,9 +273,6 @@ impl HydroLeaf {
                 location_kind,
                 input,
             } => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
-
                 let location_id = match location_kind.root() {
                     LocationId::Process(id) => id,
                     LocationId::Cluster(id) => id,
@@ -192,22 +280,60 @@ impl HydroLeaf {
                     LocationId::ExternalProcess(_) => panic!(),
                 };
 
+                // the input of a loop-carried collection is emitted inside its loop
+                let builder = graph_builders.entry(*location_id).or_default();
+                let loop_id = iteration_loop(builder, built_loops, location_kind);
+                let outer_loop = loop_id.map(|loop_id| builder.set_current_loop(Some(loop_id)));
+
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
+
                 assert_eq!(
                     input_location_id, *location_id,
                     "cycle_sink location mismatch"
                 );
 
-                graph_builders
-                    .entry(*location_id)
-                    .or_default()
-                    .add_statement(parse_quote! {
-                        #ident = #input_ident;
-                    });
+                let builder = graph_builders.entry(*location_id).or_default();
+                builder.add_statement(parse_quote! {
+                    #ident = #input_ident;
+                });
+
+                if let Some(outer_loop) = outer_loop {
+                    builder.set_current_loop(outer_loop);
+                }
             }
         }
     }
 }
 
+/// Returns the DFIR loop that an iteration scope (a tick nested in another tick) is emitted
+/// into, creating it on first use, or `None` if `location` is not an iteration scope.
+#[cfg(feature = "build")]
+fn iteration_loop(
+    builder: &mut FlatGraphBuilder,
+    built_loops: &mut HashMap<usize, GraphLoopId>,
+    location: &LocationId,
+) -> Option<GraphLoopId> {
+    match location {
+        LocationId::Tick(id, parent) if matches!(parent.as_ref(), LocationId::Tick(_, _)) => {
+            if let Some(loop_id) = built_loops.get(id) {
+                return Some(*loop_id);
+            }
+
+            let parent_loop = iteration_loop(builder, built_loops, parent);
+            let loop_id = builder.insert_loop(parent_loop);
+            built_loops.insert(*id, loop_id);
+            Some(loop_id)
+        }
+        _ => None,
+    }
+}
+
 type PrintedTees = RefCell<Option<(usize, HashMap<*const RefCell<HydroNode>, usize>)>>;
 thread_local! {
     static PRINTED_TEES: PrintedTees = const { RefCell::new(None) };
@@ -228,8 +354,111 @@ pub fn dbg_dedup_tee<T>(f: impl FnOnce() -> T) -> T {
     })
 }
 
+type SerializedTees = RefCell<Option<HashMap<*const RefCell<HydroNode>, usize>>>;
+type DeserializedTees = RefCell<Option<HashMap<usize, Rc<RefCell<HydroNode>>>>>;
+thread_local! {
+    static SERIALIZED_TEES: SerializedTees = const { RefCell::new(None) };
+    static DESERIALIZED_TEES: DeserializedTees = const { RefCell::new(None) };
+}
+
+/// Runs `f` in a context where shared [`TeeNode`]s are (de)serialized only once.
+///
+/// Later occurrences of a tee refer back to the first one by id, which is how the sharing
+/// of tees is preserved across a round trip. [`TeeNode`]s can only be (de)serialized inside
+/// this context. Contexts can be nested, and each one starts with no tees.
+pub fn serde_dedup_tee<T>(f: impl FnOnce() -> T) -> T {
+    let _restore = RestoreDedupTees {
+        serialized: SERIALIZED_TEES.with(|tees| tees.replace(Some(HashMap::new()))),
+        deserialized: DESERIALIZED_TEES.with(|tees| tees.replace(Some(HashMap::new()))),
+    };
+
+    f()
+}
+
+/// Restores the tees of the enclosing [`serde_dedup_tee`] context when dropped, including
+/// when unwinding from a panic.
+struct RestoreDedupTees {
+    serialized: Option<HashMap<*const RefCell<HydroNode>, usize>>,
+    deserialized: Option<HashMap<usize, Rc<RefCell<HydroNode>>>>,
+}
+
+impl Drop for RestoreDedupTees {
+    fn drop(&mut self) {
+        SERIALIZED_TEES.with(|tees| *tees.borrow_mut() = self.serialized.take());
+        DESERIALIZED_TEES.with(|tees| *tees.borrow_mut() = self.deserialized.take());
+    }
+}
+
 pub struct TeeNode(pub Rc<RefCell<HydroNode>>);
 
+#[derive(Serialize)]
+struct SerializeTee<'a> {
+    id: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    inner: Option<&'a HydroNode>,
+}
+
+#[derive(Deserialize)]
+struct DeserializeTee {
+    id: usize,
+    #[serde(default)]
+    inner: Option<HydroNode>,
+}
+
+impl Serialize for TeeNode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let ptr = self.0.as_ref() as *const RefCell<HydroNode>;
+        let (id, is_new) = SERIALIZED_TEES
+            .with(|tees| {
+                let mut tees = tees.borrow_mut();
+                let tees = tees.as_mut()?;
+                let next_id = tees.len();
+                let id = *tees.entry(ptr).or_insert(next_id);
+                Some((id, id == next_id))
+            })
+            .ok_or_else(|| S::Error::custom("tees must be serialized within `serde_dedup_tee`"))?;
+
+        if is_new {
+            SerializeTee {
+                id,
+                inner: Some(&self.0.borrow()),
+            }
+            .serialize(serializer)
+        } else {
+            SerializeTee { id, inner: None }.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for TeeNode {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let DeserializeTee { id, inner } = DeserializeTee::deserialize(deserializer)?;
+        DESERIALIZED_TEES
+            .with(|tees| {
+                let mut tees = tees.borrow_mut();
+                let tees = tees.as_mut().ok_or_else(|| {
+                    D::Error::custom("tees must be deserialized within `serde_dedup_tee`")
+                })?;
+
+                if let Some(inner) = inner {
+                    let cell = Rc::new(RefCell::new(inner));
+                    if tees.insert(id, cell.clone()).is_some() {
+                        return Err(D::Error::custom(format!(
+                            "duplicate definition of tee {}",
+                            id
+                        )));
+                    }
+                    Ok(cell)
+                } else {
+                    tees.get(&id).cloned().ok_or_else(|| {
+                        D::Error::custom(format!("reference to undefined tee {}", id))
+                    })
+                }
+            })
+            .map(TeeNode)
+    }
+}
+
 impl Debug for TeeNode {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         PRINTED_TEES.with(|printed_tees| {
@@ -261,9 +490,34 @@ impl Debug for TeeNode {
     }
 }
 
+/// The `unsafe` APIs that introduce non-determinism into a Hydro graph. Composite APIs
+/// such as `sample_every` and `timeout` are built out of these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum NondeterminismKind {
+    /// Non-deterministic batch boundaries, from `tick_batch`.
+    TickBatch,
+    /// Non-deterministic snapshots of a singleton or optional, from `latest_tick`.
+    LatestTick,
+    /// An ordering guarantee that is not checked by the type system, from `assume_ordering`.
+    AssumeOrdering,
+    /// Timestamps and timing of a clock, from `source_interval`.
+    SourceInterval,
+}
+
+impl std::fmt::Display for NondeterminismKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NondeterminismKind::TickBatch => write!(f, "tick_batch"),
+            NondeterminismKind::LatestTick => write!(f, "latest_tick"),
+            NondeterminismKind::AssumeOrdering => write!(f, "assume_ordering"),
+            NondeterminismKind::SourceInterval => write!(f, "source_interval"),
+        }
+    }
+}
+
 /// An intermediate node in a Hydro graph, which consumes data
 /// from upstream nodes and emits data to downstream nodes.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum HydroNode {
     Placeholder,
 
@@ -273,6 +527,7 @@ pub enum HydroNode {
     },
 
     CycleSource {
+        #[serde(with = "serde_ident")]
         ident: syn::Ident,
         location_kind: LocationId,
     },
@@ -319,6 +574,51 @@ pub enum HydroNode {
         input: Box<HydroNode>,
     },
 
+    /// Marks where an `unsafe` API introduces non-determinism. This has no effect on
+    /// the generated code, and is only used by analyses over the IR.
+    Nondeterministic {
+        kind: NondeterminismKind,
+        location_kind: LocationId,
+        /// Where the unsafe API was called, for diagnostics.
+        #[serde(skip)]
+        backtrace: Backtrace,
+        input: Box<HydroNode>,
+    },
+    /// Marks non-determinism upstream of this point as audited, with a reason. This has
+    /// no effect on the generated code, and is only used by analyses over the IR.
+    AllowNondeterminism {
+        reason: String,
+        input: Box<HydroNode>,
+    },
+    /// Lets the runtime choose which elements enter each batch of a `tick_batch` over local
+    /// data, holding back the rest until a later tick. Only inserted when compiling for a
+    /// deployment that controls batching, see [`crate::deploy::Deploy::local_batch_gate`].
+    BatchGate {
+        /// Evaluates to a gate with an `admit(tick: u64) -> bool` method.
+        gate: DebugExpr,
+        location_kind: LocationId,
+        input: Box<HydroNode>,
+    },
+
+    /// Brings a collection from the enclosing location into the iteration scope
+    /// `location_kind`, in every iteration or only in the first one.
+    EnterIteration {
+        first_iteration_only: bool,
+        limit: IterationLimit,
+        location_kind: LocationId,
+        input: Box<HydroNode>,
+    },
+    /// Carries a collection to the next iteration of its iteration scope.
+    NextIteration {
+        limit: IterationLimit,
+        input: Box<HydroNode>,
+    },
+    /// Brings the final iteration's collection out of the iteration scope `location_kind`.
+    ExitIteration {
+        location_kind: LocationId,
+        input: Box<HydroNode>,
+    },
+
     Unique(Box<HydroNode>),
 
     Sort(Box<HydroNode>),
@@ -510,6 +810,26 @@ impl<'a> HydroNode {
                 transform(input.as_mut(), seen_tees);
             }
 
+            HydroNode::Nondeterministic { input, .. } => {
+                transform(input.as_mut(), seen_tees);
+            }
+            HydroNode::AllowNondeterminism { input, .. } => {
+                transform(input.as_mut(), seen_tees);
+            }
+            HydroNode::BatchGate { input, .. } => {
+                transform(input.as_mut(), seen_tees);
+            }
+
+            HydroNode::EnterIteration { input, .. } => {
+                transform(input.as_mut(), seen_tees);
+            }
+            HydroNode::NextIteration { input, .. } => {
+                transform(input.as_mut(), seen_tees);
+            }
+            HydroNode::ExitIteration { input, .. } => {
+                transform(input.as_mut(), seen_tees);
+            }
+
             HydroNode::Unique(input) => {
                 transform(input.as_mut(), seen_tees);
             }
@@ -534,12 +854,131 @@ impl<'a> HydroNode {
         }
     }
 
+    /// Calls `f` on each of the direct inputs of this node, without modifying the graph.
+    /// For a [`HydroNode::Tee`], this visits the shared node behind the tee.
+    pub fn visit_inputs(&self, mut f: impl FnMut(&HydroNode)) {
+        match self {
+            HydroNode::Placeholder => {
+                panic!();
+            }
+
+            HydroNode::Source { .. } | HydroNode::CycleSource { .. } => {}
+
+            HydroNode::Tee { inner } => f(&inner.0.borrow()),
+
+            HydroNode::Persist(input)
+            | HydroNode::Unpersist(input)
+            | HydroNode::Delta(input)
+            | HydroNode::DeferTick(input)
+            | HydroNode::Unique(input)
+            | HydroNode::Sort(input)
+            | HydroNode::Map { input, .. }
+            | HydroNode::FlatMap { input, .. }
+            | HydroNode::Filter { input, .. }
+            | HydroNode::FilterMap { input, .. }
+            | HydroNode::Enumerate { input, .. }
+            | HydroNode::Inspect { input, .. }
+            | HydroNode::Nondeterministic { input, .. }
+            | HydroNode::AllowNondeterminism { input, .. }
+            | HydroNode::BatchGate { input, .. }
+            | HydroNode::EnterIteration { input, .. }
+            | HydroNode::NextIteration { input, .. }
+            | HydroNode::ExitIteration { input, .. }
+            | HydroNode::Fold { input, .. }
+            | HydroNode::FoldKeyed { input, .. }
+            | HydroNode::Reduce { input, .. }
+            | HydroNode::ReduceKeyed { input, .. }
+            | HydroNode::Network { input, .. } => f(input),
+
+            HydroNode::Chain(left, right)
+            | HydroNode::CrossProduct(left, right)
+            | HydroNode::CrossSingleton(left, right)
+            | HydroNode::Join(left, right)
+            | HydroNode::Difference(left, right)
+            | HydroNode::AntiJoin(left, right) => {
+                f(left);
+                f(right);
+            }
+        }
+    }
+
+    /// Prints only the operator at the root of this node, without its inputs.
+    pub fn print_root(&self) -> String {
+        match self {
+            HydroNode::Placeholder => "Placeholder()".to_string(),
+            HydroNode::Source { source, .. } => format!("Source({:?})", source),
+            HydroNode::CycleSource { ident, .. } => format!("CycleSource({})", ident),
+            HydroNode::Tee { .. } => "Tee()".to_string(),
+            HydroNode::Persist(_) => "Persist()".to_string(),
+            HydroNode::Unpersist(_) => "Unpersist()".to_string(),
+            HydroNode::Delta(_) => "Delta()".to_string(),
+            HydroNode::Chain(_, _) => "Chain()".to_string(),
+            HydroNode::CrossProduct(_, _) => "CrossProduct()".to_string(),
+            HydroNode::CrossSingleton(_, _) => "CrossSingleton()".to_string(),
+            HydroNode::Join(_, _) => "Join()".to_string(),
+            HydroNode::Difference(_, _) => "Difference()".to_string(),
+            HydroNode::AntiJoin(_, _) => "AntiJoin()".to_string(),
+            HydroNode::Map { f, .. } => format!("Map({:?})", f),
+            HydroNode::FlatMap { f, .. } => format!("FlatMap({:?})", f),
+            HydroNode::Filter { f, .. } => format!("Filter({:?})", f),
+            HydroNode::FilterMap { f, .. } => format!("FilterMap({:?})", f),
+            HydroNode::DeferTick(_) => "DeferTick()".to_string(),
+            HydroNode::Enumerate { is_static, .. } => format!("Enumerate({:?})", is_static),
+            HydroNode::Inspect { f, .. } => format!("Inspect({:?})", f),
+            HydroNode::Nondeterministic { kind, .. } => format!("Nondeterministic({:?})", kind),
+            HydroNode::AllowNondeterminism { reason, .. } => {
+                format!("AllowNondeterminism({:?})", reason)
+            }
+            HydroNode::BatchGate { gate, .. } => format!("BatchGate({:?})", gate),
+            HydroNode::EnterIteration {
+                first_iteration_only,
+                ..
+            } => format!("EnterIteration({:?})", first_iteration_only),
+            HydroNode::NextIteration { limit, .. } => format!("NextIteration({:?})", limit),
+            HydroNode::ExitIteration { .. } => "ExitIteration()".to_string(),
+            HydroNode::Unique(_) => "Unique()".to_string(),
+            HydroNode::Sort(_) => "Sort()".to_string(),
+            HydroNode::Fold { init, acc, .. } => format!("Fold({:?}, {:?})", init, acc),
+            HydroNode::FoldKeyed { init, acc, .. } => format!("FoldKeyed({:?}, {:?})", init, acc),
+            HydroNode::Reduce { f, .. } => format!("Reduce({:?})", f),
+            HydroNode::ReduceKeyed { f, .. } => format!("ReduceKeyed({:?})", f),
+            HydroNode::Network { to_location, .. } => format!("Network(to {:?})", to_location),
+        }
+    }
+
+    /// Where this operator was created, if it is an unsafe API or takes user code.
+    pub fn backtrace(&self) -> Option<&Backtrace> {
+        match self {
+            HydroNode::Map { f, .. }
+            | HydroNode::FlatMap { f, .. }
+            | HydroNode::Filter { f, .. }
+            | HydroNode::FilterMap { f, .. }
+            | HydroNode::Inspect { f, .. }
+            | HydroNode::Reduce { f, .. }
+            | HydroNode::ReduceKeyed { f, .. }
+            | HydroNode::Fold { acc: f, .. }
+            | HydroNode::FoldKeyed { acc: f, .. }
+            | HydroNode::Source {
+                source: HydroSource::Iter(f) | HydroSource::Stream(f),
+                ..
+            } => Some(&f.1),
+            HydroNode::Network {
+                serialize_fn: Some(f),
+                ..
+            } => Some(&f.1),
+            HydroNode::Nondeterministic { backtrace, .. } => Some(backtrace),
+            _ => None,
+        }
+    }
+
     #[cfg(feature = "build")]
     pub fn emit(
         &self,
         graph_builders: &mut BTreeMap<usize, FlatGraphBuilder>,
         built_tees: &mut HashMap<*const RefCell<HydroNode>, (syn::Ident, usize)>,
+        built_loops: &mut HashMap<usize, GraphLoopId>,
         next_stmt_id: &mut usize,
+        snapshots: bool,
     ) -> (syn::Ident, usize) {
         match self {
             HydroNode::Placeholder => {
@@ -547,7 +986,13 @@ impl<'a> HydroNode {
             }
 
             HydroNode::Persist(inner) => {
-                let (inner_ident, location) = inner.emit(graph_builders, built_tees, next_stmt_id);
+                let (inner_ident, location) = inner.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let persist_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -555,9 +1000,15 @@ impl<'a> HydroNode {
                 let persist_ident =
                     syn::Ident::new(&format!("stream_{}", persist_id), Span::call_site());
 
+                let persistence: syn::Lifetime = if snapshots {
+                    parse_quote!('durable)
+                } else {
+                    parse_quote!('static)
+                };
+
                 let builder = graph_builders.entry(location).or_default();
                 builder.add_statement(parse_quote! {
-                    #persist_ident = #inner_ident -> persist::<'static>();
+                    #persist_ident = #inner_ident -> persist::<#persistence>();
                 });
 
                 (persist_ident, location)
@@ -568,7 +1019,13 @@ impl<'a> HydroNode {
             }
 
             HydroNode::Delta(inner) => {
-                let (inner_ident, location) = inner.emit(graph_builders, built_tees, next_stmt_id);
+                let (inner_ident, location) = inner.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let delta_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -615,6 +1072,14 @@ impl<'a> HydroNode {
                             unreachable!()
                         }
 
+                        HydroSource::Iter(expr) if snapshots => {
+                            // the items were already emitted before the snapshot was taken
+                            parse_quote! {
+                                #source_ident = source_iter(#expr)
+                                    -> filter(|_| !__hydro_lang_snapshots.is_restored());
+                            }
+                        }
+
                         HydroSource::Iter(expr) => {
                             parse_quote! {
                                 #source_ident = source_iter(#expr);
@@ -656,11 +1121,13 @@ impl<'a> HydroNode {
                 {
                     ret.clone()
                 } else {
-                    let (inner_ident, inner_location_id) =
-                        inner
-                            .0
-                            .borrow()
-                            .emit(graph_builders, built_tees, next_stmt_id);
+                    let (inner_ident, inner_location_id) = inner.0.borrow().emit(
+                        graph_builders,
+                        built_tees,
+                        built_loops,
+                        next_stmt_id,
+                        snapshots,
+                    );
 
                     let tee_id = *next_stmt_id;
                     *next_stmt_id += 1;
@@ -683,10 +1150,20 @@ impl<'a> HydroNode {
             }
 
             HydroNode::Chain(left, right) => {
-                let (left_ident, left_location_id) =
-                    left.emit(graph_builders, built_tees, next_stmt_id);
-                let (right_ident, right_location_id) =
-                    right.emit(graph_builders, built_tees, next_stmt_id);
+                let (left_ident, left_location_id) = left.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
+                let (right_ident, right_location_id) = right.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 assert_eq!(
                     left_location_id, right_location_id,
@@ -716,10 +1193,20 @@ impl<'a> HydroNode {
             }
 
             HydroNode::CrossSingleton(left, right) => {
-                let (left_ident, left_location_id) =
-                    left.emit(graph_builders, built_tees, next_stmt_id);
-                let (right_ident, right_location_id) =
-                    right.emit(graph_builders, built_tees, next_stmt_id);
+                let (left_ident, left_location_id) = left.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
+                let (right_ident, right_location_id) = right.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 assert_eq!(
                     left_location_id, right_location_id,
@@ -760,24 +1247,36 @@ impl<'a> HydroNode {
                     unreachable!()
                 };
 
-                let (left_inner, left_was_persist) = if let HydroNode::Persist(left) = left.as_ref()
-                {
-                    (left, true)
-                } else {
-                    (left, false)
-                };
+                // when snapshotting, persisted inputs are emitted as separate `'durable` persists
+                // rather than being fused into the operator's (non-durable) `'static` state
+                let (left_inner, left_was_persist) =
+                    if let (HydroNode::Persist(left), false) = (left.as_ref(), snapshots) {
+                        (left, true)
+                    } else {
+                        (left, false)
+                    };
 
                 let (right_inner, right_was_persist) =
-                    if let HydroNode::Persist(right) = right.as_ref() {
+                    if let (HydroNode::Persist(right), false) = (right.as_ref(), snapshots) {
                         (right, true)
                     } else {
                         (right, false)
                     };
 
-                let (left_ident, left_location_id) =
-                    left_inner.emit(graph_builders, built_tees, next_stmt_id);
-                let (right_ident, right_location_id) =
-                    right_inner.emit(graph_builders, built_tees, next_stmt_id);
+                let (left_ident, left_location_id) = left_inner.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
+                let (right_ident, right_location_id) = right_inner.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 assert_eq!(
                     left_location_id, right_location_id,
@@ -838,16 +1337,27 @@ impl<'a> HydroNode {
                     unreachable!()
                 };
 
-                let (right, right_was_persist) = if let HydroNode::Persist(right) = right.as_ref() {
-                    (right, true)
-                } else {
-                    (right, false)
-                };
+                let (right, right_was_persist) =
+                    if let (HydroNode::Persist(right), false) = (right.as_ref(), snapshots) {
+                        (right, true)
+                    } else {
+                        (right, false)
+                    };
 
-                let (left_ident, left_location_id) =
-                    left.emit(graph_builders, built_tees, next_stmt_id);
-                let (right_ident, right_location_id) =
-                    right.emit(graph_builders, built_tees, next_stmt_id);
+                let (left_ident, left_location_id) = left.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
+                let (right_ident, right_location_id) = right.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 assert_eq!(
                     left_location_id, right_location_id,
@@ -884,8 +1394,13 @@ impl<'a> HydroNode {
             }
 
             HydroNode::Map { f, input } => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let map_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -901,8 +1416,13 @@ impl<'a> HydroNode {
             }
 
             HydroNode::FlatMap { f, input } => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let flat_map_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -919,8 +1439,13 @@ impl<'a> HydroNode {
             }
 
             HydroNode::Filter { f, input } => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let filter_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -937,8 +1462,13 @@ impl<'a> HydroNode {
             }
 
             HydroNode::FilterMap { f, input } => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let filter_map_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -955,8 +1485,13 @@ impl<'a> HydroNode {
             }
 
             HydroNode::Sort(input) => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let sort_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -972,8 +1507,13 @@ impl<'a> HydroNode {
             }
 
             HydroNode::DeferTick(input) => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let defer_tick_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -989,9 +1529,141 @@ impl<'a> HydroNode {
                 (defer_tick_ident, input_location_id)
             }
 
+            HydroNode::EnterIteration {
+                first_iteration_only,
+                limit,
+                location_kind,
+                input,
+            } => {
+                let LocationId::Tick(_, parent) = location_kind else {
+                    panic!("iteration scopes must be nested in a tick")
+                };
+
+                let root_id = location_kind.root().raw_id();
+                let builder = graph_builders.entry(root_id).or_default();
+                let inner_loop = iteration_loop(builder, built_loops, location_kind);
+                let parent_loop = iteration_loop(builder, built_loops, parent);
+                let prev_loop = builder.set_current_loop(parent_loop);
+
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
+
+                let enter_id = *next_stmt_id;
+                *next_stmt_id += 1;
+
+                let enter_ident =
+                    syn::Ident::new(&format!("stream_{}", enter_id), Span::call_site());
+
+                let builder = graph_builders.entry(input_location_id).or_default();
+                builder.set_current_loop(prev_loop);
+                let statements: Vec<HfStatement> = match (limit, first_iteration_only) {
+                    // `repeat_n` keeps the loop iterating for the given number of iterations.
+                    (IterationLimit::Times(n), false) => vec![parse_quote! {
+                        #enter_ident = #input_ident -> repeat_n(#n) -> flatten();
+                    }],
+                    (IterationLimit::Times(n), true) => vec![parse_quote! {
+                        #enter_ident = #input_ident
+                            -> repeat_n(#n)
+                            -> flatten()
+                            -> filter(|_| context.is_first_loop_iteration());
+                    }],
+                    // The loop is kept iterating by `until_fixpoint` on its loop-carried
+                    // collections, so replay the batch by carrying it to each next iteration.
+                    (IterationLimit::Fixpoint, false) => vec![
+                        parse_quote! {
+                            #enter_ident = union() -> tee();
+                        },
+                        parse_quote! {
+                            #input_ident -> batch() -> flatten() -> #enter_ident;
+                        },
+                        parse_quote! {
+                            #enter_ident -> next_iteration() -> #enter_ident;
+                        },
+                    ],
+                    (IterationLimit::Fixpoint, true) => vec![parse_quote! {
+                        #enter_ident = #input_ident -> batch() -> flatten();
+                    }],
+                };
+                for statement in statements {
+                    builder.add_statement_with_loop(statement, inner_loop);
+                }
+
+                (enter_ident, input_location_id)
+            }
+
+            HydroNode::NextIteration { limit, input } => {
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
+
+                let next_iteration_id = *next_stmt_id;
+                *next_stmt_id += 1;
+
+                let next_iteration_ident =
+                    syn::Ident::new(&format!("stream_{}", next_iteration_id), Span::call_site());
+
+                let builder = graph_builders.entry(input_location_id).or_default();
+                match limit {
+                    // The number of iterations is enforced by `repeat_n` on the way in.
+                    IterationLimit::Times(_) => builder.add_statement(parse_quote! {
+                        #next_iteration_ident = #input_ident -> next_iteration();
+                    }),
+                    IterationLimit::Fixpoint => builder.add_statement(parse_quote! {
+                        #next_iteration_ident = #input_ident -> until_fixpoint() -> next_iteration();
+                    }),
+                }
+
+                (next_iteration_ident, input_location_id)
+            }
+
+            HydroNode::ExitIteration {
+                location_kind,
+                input,
+            } => {
+                let root_id = location_kind.root().raw_id();
+                let builder = graph_builders.entry(root_id).or_default();
+                let inner_loop = iteration_loop(builder, built_loops, location_kind);
+                let outer_loop = builder.set_current_loop(inner_loop);
+
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
+
+                let exit_id = *next_stmt_id;
+                *next_stmt_id += 1;
+
+                let exit_ident = syn::Ident::new(&format!("stream_{}", exit_id), Span::call_site());
+
+                let builder = graph_builders.entry(input_location_id).or_default();
+                builder.set_current_loop(outer_loop);
+                builder.add_statement(parse_quote! {
+                    #exit_ident = #input_ident -> last_iteration();
+                });
+
+                (exit_ident, input_location_id)
+            }
+
             HydroNode::Enumerate { is_static, input } => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let enumerate_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -1001,7 +1673,11 @@ impl<'a> HydroNode {
 
                 let builder = graph_builders.entry(input_location_id).or_default();
 
-                if *is_static {
+                if *is_static && snapshots {
+                    builder.add_statement(parse_quote! {
+                        #enumerate_ident = #input_ident -> enumerate::<'durable>();
+                    });
+                } else if *is_static {
                     builder.add_statement(parse_quote! {
                         #enumerate_ident = #input_ident -> enumerate::<'static>();
                     });
@@ -1015,8 +1691,13 @@ impl<'a> HydroNode {
             }
 
             HydroNode::Inspect { f, input } => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let inspect_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -1032,9 +1713,71 @@ impl<'a> HydroNode {
                 (inspect_ident, input_location_id)
             }
 
+            HydroNode::Nondeterministic { input, .. }
+            | HydroNode::AllowNondeterminism { input, .. } => input.emit(
+                graph_builders,
+                built_tees,
+                built_loops,
+                next_stmt_id,
+                snapshots,
+            ),
+
+            HydroNode::BatchGate { gate, input, .. } => {
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
+
+                let gate_id = *next_stmt_id;
+                *next_stmt_id += 1;
+
+                let gate_ident = syn::Ident::new(&format!("stream_{}", gate_id), Span::call_site());
+                let union_ident =
+                    syn::Ident::new(&format!("batch_gate_{}_union", gate_id), Span::call_site());
+                let partition_ident = syn::Ident::new(
+                    &format!("batch_gate_{}_partition", gate_id),
+                    Span::call_site(),
+                );
+
+                // elements that are held back re-enter the gate in the next tick, ahead of
+                // any new elements
+                let builder = graph_builders.entry(input_location_id).or_default();
+                builder.add_statement(parse_quote! {
+                    #union_ident = union();
+                });
+                builder.add_statement(parse_quote! {
+                    #partition_ident = #union_ident -> partition(|_, [released, held]| {
+                        if #gate.admit(context.current_tick().0) {
+                            released
+                        } else {
+                            held
+                        }
+                    });
+                });
+                builder.add_statement(parse_quote! {
+                    #partition_ident[held] -> defer_tick_lazy() -> #union_ident;
+                });
+                builder.add_statement(parse_quote! {
+                    #input_ident -> #union_ident;
+                });
+                builder.add_statement(parse_quote! {
+                    #gate_ident = #partition_ident[released] -> identity();
+                });
+
+                (gate_ident, input_location_id)
+            }
+
             HydroNode::Unique(input) => {
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let unique_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -1063,14 +1806,20 @@ impl<'a> HydroNode {
                     unreachable!()
                 };
 
-                let (input, input_was_persist) = if let HydroNode::Persist(input) = input.as_ref() {
-                    (input, true)
-                } else {
-                    (input, false)
-                };
+                let (input, input_was_persist) =
+                    if let (HydroNode::Persist(input), false) = (input.as_ref(), snapshots) {
+                        (input, true)
+                    } else {
+                        (input, false)
+                    };
 
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let reduce_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -1104,14 +1853,20 @@ impl<'a> HydroNode {
                     unreachable!()
                 };
 
-                let (input, input_was_persist) = if let HydroNode::Persist(input) = input.as_ref() {
-                    (input, true)
-                } else {
-                    (input, false)
-                };
+                let (input, input_was_persist) =
+                    if let (HydroNode::Persist(input), false) = (input.as_ref(), snapshots) {
+                        (input, true)
+                    } else {
+                        (input, false)
+                    };
 
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
 
                 let reduce_id = *next_stmt_id;
                 *next_stmt_id += 1;
@@ -1134,7 +1889,7 @@ impl<'a> HydroNode {
             }
 
             HydroNode::Network {
-                from_location: _,
+                from_location,
                 from_key: _,
                 to_location,
                 to_key: _,
@@ -1153,12 +1908,65 @@ impl<'a> HydroNode {
                     }
                 };
 
-                let (input_ident, input_location_id) =
-                    input.emit(graph_builders, built_tees, next_stmt_id);
+                let (input_ident, input_location_id) = input.emit(
+                    graph_builders,
+                    built_tees,
+                    built_loops,
+                    next_stmt_id,
+                    snapshots,
+                );
+
+                // external processes do not take part in snapshots
+                let snapshot_edge = snapshots
+                    && !matches!(from_location, LocationId::ExternalProcess(_))
+                    && !matches!(to_location, LocationId::ExternalProcess(_));
 
                 let sender_builder = graph_builders.entry(input_location_id).or_default();
 
-                if let Some(serialize_pipeline) = serialize_pipeline {
+                if snapshot_edge {
+                    let chain_id = *next_stmt_id;
+                    *next_stmt_id += 1;
+
+                    let chain_ident =
+                        syn::Ident::new(&format!("stream_{}", chain_id), Span::call_site());
+
+                    // markers are framed like data, and sent to every recipient of the edge
+                    let (marker_pipeline, data_pipeline): (syn::Expr, syn::Expr) =
+                        if let LocationId::Cluster(to_id) = to_location {
+                            let ids_ident = syn::Ident::new(
+                                &format!("__hydro_lang_cluster_ids_{}", to_id),
+                                Span::call_site(),
+                            );
+                            (
+                                parse_quote!(flat_map(|id| #ids_ident.iter().map(move |member| (*member, hydro_lang::snapshot_runtime::marker_frame(id))))),
+                                parse_quote!(map(|(member, payload)| (
+                                    member,
+                                    hydro_lang::snapshot_runtime::data_frame(payload)
+                                ))),
+                            )
+                        } else {
+                            (
+                                parse_quote!(map(hydro_lang::snapshot_runtime::marker_frame)),
+                                parse_quote!(map(hydro_lang::snapshot_runtime::data_frame)),
+                            )
+                        };
+
+                    sender_builder.add_statement(parse_quote! {
+                        #chain_ident = chain() -> dest_sink(#sink_expr);
+                    });
+                    sender_builder.add_statement(parse_quote! {
+                        source_stream(__hydro_lang_snapshots.markers()) -> #marker_pipeline -> [0]#chain_ident;
+                    });
+                    if let Some(serialize_pipeline) = serialize_pipeline {
+                        sender_builder.add_statement(parse_quote! {
+                            #input_ident -> map(#serialize_pipeline) -> #data_pipeline -> [1]#chain_ident;
+                        });
+                    } else {
+                        sender_builder.add_statement(parse_quote! {
+                            #input_ident -> #data_pipeline -> [1]#chain_ident;
+                        });
+                    }
+                } else if let Some(serialize_pipeline) = serialize_pipeline {
                     sender_builder.add_statement(parse_quote! {
                         #input_ident -> map(#serialize_pipeline) -> dest_sink(#sink_expr);
                     });
@@ -1182,6 +1990,21 @@ impl<'a> HydroNode {
                 let receiver_stream_ident =
                     syn::Ident::new(&format!("stream_{}", receiver_stream_id), Span::call_site());
 
+                let source_expr: syn::Expr = if snapshot_edge {
+                    let senders: syn::Expr = if let LocationId::Cluster(from_id) = from_location {
+                        let ids_ident = syn::Ident::new(
+                            &format!("__hydro_lang_cluster_ids_{}", from_id),
+                            Span::call_site(),
+                        );
+                        parse_quote!(#ids_ident.iter().copied())
+                    } else {
+                        parse_quote!([0])
+                    };
+                    parse_quote!(__hydro_lang_snapshots.source(#receiver_stream_id, #senders, #source_expr))
+                } else {
+                    source_expr.clone()
+                };
+
                 if let Some(deserialize_pipeline) = deserialize_pipeline {
                     receiver_builder.add_statement(parse_quote! {
                         #receiver_stream_ident = source_stream(#source_expr) -> map(#deserialize_pipeline);
@@ -25,13 +25,24 @@ pub use optional::Optional;
 
 pub mod location;
 pub use location::cluster::CLUSTER_SELF_ID;
-pub use location::{Cluster, ClusterId, ExternalProcess, Location, Process, Tick, Timestamped};
+pub use location::{
+    Cluster, ClusterId, ExternalProcess, Iteration, IterationLimit, Location, Process, Tick,
+    Timestamped,
+};
 
 #[cfg(feature = "build")]
 pub mod deploy;
 
 pub mod deploy_runtime;
 
+#[cfg(feature = "sim")]
+pub mod sim_runtime;
+
+pub mod snapshot_runtime;
+
+#[cfg(feature = "sim")]
+pub mod model_check;
+
 pub mod cycle;
 
 pub mod builder;
@@ -39,8 +50,13 @@ pub use builder::FlowBuilder;
 
 pub mod ir;
 
+pub mod backtrace;
+
 pub mod rewrites;
 
+#[cfg(feature = "build")]
+pub mod viz;
+
 mod staging_util;
 
 #[stageleft::runtime]
@@ -2,9 +2,9 @@ use std::marker::PhantomData;
 
 use dfir_rs::bytes::Bytes;
 use serde::de::DeserializeOwned;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
-use super::{Location, LocationId, NoTick};
+use super::{CanSend, Location, LocationId, NoTick};
 use crate::builder::FlowState;
 use crate::ir::{HydroNode, HydroSource};
 use crate::staging_util::Invariant;
@@ -51,6 +51,60 @@ pub struct ExternalBincodeStream<T: DeserializeOwned> {
     pub(crate) _phantom: PhantomData<T>,
 }
 
+/// Identifies a request made to an [`ExternalService`], so that its response can be routed
+/// back to the caller.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestId(pub u64);
+
+/// The ports of a service created with [`ExternalProcess::source_external_service`], which
+/// external clients connect to after deployment.
+pub struct ExternalService<Req: Serialize, Resp: DeserializeOwned> {
+    #[cfg_attr(
+        not(feature = "build"),
+        expect(unused, reason = "unused without feature")
+    )]
+    pub(crate) requests: ExternalBincodeSink<(RequestId, Req)>,
+    #[cfg_attr(
+        not(feature = "build"),
+        expect(unused, reason = "unused without feature")
+    )]
+    pub(crate) responses: ExternalBincodeStream<(RequestId, Resp)>,
+}
+
+/// Routes the responses of a service created with
+/// [`ExternalProcess::source_external_service`] back to its external clients.
+pub struct ExternalServiceResponder<'a, P, Req: Serialize, Resp> {
+    external: ExternalProcess<'a, P>,
+    requests: ExternalBincodeSink<(RequestId, Req)>,
+    _phantom: PhantomData<Resp>,
+}
+
+impl<'a, P: 'a, Req: Serialize, Resp: Serialize + DeserializeOwned>
+    ExternalServiceResponder<'a, P, Req, Resp>
+{
+    /// Sends each `(id, response)` to the client that made the request `id`. Responses may be
+    /// sent in any order, and requests without a response eventually time out on the client.
+    pub fn respond<L, B, Order>(
+        self,
+        responses: Stream<(RequestId, Resp), L, B, Order>,
+    ) -> ExternalService<Req, Resp>
+    where
+        L: Location<'a>
+            + NoTick
+            + CanSend<
+                'a,
+                ExternalProcess<'a, P>,
+                In<(RequestId, Resp)> = (RequestId, Resp),
+                Out<(RequestId, Resp)> = (RequestId, Resp),
+            >,
+    {
+        ExternalService {
+            requests: self.requests,
+            responses: responses.send_bincode_external::<P, (RequestId, Resp)>(&self.external),
+        }
+    }
+}
+
 pub struct ExternalProcess<'a, P> {
     pub(crate) id: usize,
 
@@ -162,4 +216,33 @@ impl<'a, P> ExternalProcess<'a, P> {
             ),
         )
     }
+
+    /// Creates a service that external clients call with requests of type `Req` and receive
+    /// responses of type `Resp`. Each request arrives at `to` tagged with a [`RequestId`], and
+    /// the responses are routed back with [`ExternalServiceResponder::respond`].
+    ///
+    /// After deployment, connect a typed client with
+    /// [`DeployResult::connect_service`](crate::builder::deploy::DeployResult::connect_service).
+    #[expect(clippy::type_complexity, reason = "responder and requests")]
+    pub fn source_external_service<
+        L: Location<'a> + NoTick,
+        Req: Serialize + DeserializeOwned,
+        Resp,
+    >(
+        &self,
+        to: &L,
+    ) -> (
+        ExternalServiceResponder<'a, P, Req, Resp>,
+        Stream<(RequestId, Req), L, Unbounded>,
+    ) {
+        let (requests, stream) = self.source_external_bincode(to);
+        (
+            ExternalServiceResponder {
+                external: self.clone(),
+                requests,
+                _phantom: PhantomData,
+            },
+            stream,
+        )
+    }
 }
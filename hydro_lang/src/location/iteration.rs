@@ -0,0 +1,213 @@
+use std::hash::Hash;
+use std::marker::PhantomData;
+
+use proc_macro2::Span;
+use serde::{Deserialize, Serialize};
+
+use super::{check_matching_location, Location, LocationId, Tick};
+use crate::builder::FlowState;
+use crate::cycle::IterationCycle;
+use crate::ir::HydroNode;
+use crate::{Bounded, Stream};
+
+/// When an iteration scope stops iterating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum IterationLimit {
+    /// Iterate until the loop-carried collections no longer change.
+    Fixpoint,
+    /// Iterate exactly this many times.
+    Times(usize),
+}
+
+/// Marks the stream as being inside an iteration scope, which repeatedly runs within a single
+/// tick of the enclosing location, see [`Tick::iterate`].
+///
+/// An iteration scope is a clock domain nested inside its parent, so its [`LocationId`] is a
+/// [`LocationId::Tick`] whose parent is a tick (or another iteration scope).
+#[derive(Clone)]
+pub struct Iteration<L> {
+    pub(crate) id: usize,
+    pub(crate) limit: IterationLimit,
+    pub(crate) l: L,
+}
+
+impl<'a, L: Location<'a>> Location<'a> for Iteration<L> {
+    type Root = L::Root;
+
+    fn root(&self) -> Self::Root {
+        self.l.root()
+    }
+
+    fn id(&self) -> LocationId {
+        LocationId::Tick(self.id, Box::new(self.l.id()))
+    }
+
+    fn flow_state(&self) -> &FlowState {
+        self.l.flow_state()
+    }
+
+    fn is_top_level() -> bool {
+        false
+    }
+}
+
+impl<'a, L: Location<'a>> Tick<L> {
+    /// Runs `body` in an iteration scope that is repeated within each tick, until `limit` is
+    /// reached. Collections are carried between iterations with [`Iteration::loop_carried`],
+    /// and the results of the final iteration are brought back with
+    /// [`Stream::exit_iteration`].
+    ///
+    /// Iteration scopes are lowered to DFIR `loop { ... }` blocks.
+    pub fn iterate<R>(&self, limit: IterationLimit, body: impl FnOnce(&Iteration<Self>) -> R) -> R {
+        body(&Iteration::new(self, limit))
+    }
+}
+
+impl<'a, L: Location<'a>> Iteration<L> {
+    pub(crate) fn new(l: &L, limit: IterationLimit) -> Iteration<L> {
+        let next_id = l.flow_state().borrow_mut().next_clock_id;
+        l.flow_state().borrow_mut().next_clock_id += 1;
+        Iteration {
+            id: next_id,
+            limit,
+            l: l.clone(),
+        }
+    }
+
+    pub fn outer(&self) -> &L {
+        &self.l
+    }
+
+    pub fn limit(&self) -> IterationLimit {
+        self.limit
+    }
+
+    /// Runs `body` in an iteration scope nested within this one, which runs to completion in
+    /// each iteration of this scope.
+    pub fn iterate<R>(&self, limit: IterationLimit, body: impl FnOnce(&Iteration<Self>) -> R) -> R {
+        body(&Iteration::new(self, limit))
+    }
+
+    /// Creates a collection that starts out as `initial` in the first iteration, and in each
+    /// later iteration holds the collection passed to
+    /// [`IterationCycle::complete_next_iteration`] in the previous one.
+    ///
+    /// With [`IterationLimit::Fixpoint`], the scope stops iterating once the collection passed to
+    /// [`IterationCycle::complete_next_iteration`] is the same (as a multiset) in two consecutive
+    /// iterations.
+    #[expect(clippy::type_complexity, reason = "cycle handle and collection")]
+    pub fn loop_carried<T: Clone + Eq + Hash, Order>(
+        &self,
+        initial: Stream<T, L, Bounded, Order>,
+    ) -> (
+        IterationCycle<'a, Stream<T, Self, Bounded, Order>>,
+        Stream<T, Self, Bounded, Order>,
+    ) {
+        check_matching_location(&self.l, &initial.location);
+
+        let next_id = {
+            let on_id = self.root().id().raw_id();
+
+            let mut flow_state = self.flow_state().borrow_mut();
+            let next_id_entry = flow_state.cycle_counts.entry(on_id).or_default();
+
+            let id = *next_id_entry;
+            *next_id_entry += 1;
+            id
+        };
+
+        let ident = syn::Ident::new(&format!("cycle_{}", next_id), Span::call_site());
+
+        let location_id = self.id();
+        let stream = Stream::new(
+            self.clone(),
+            HydroNode::Chain(
+                Box::new(HydroNode::CycleSource {
+                    ident: ident.clone(),
+                    location_kind: location_id.clone(),
+                }),
+                Box::new(HydroNode::EnterIteration {
+                    first_iteration_only: true,
+                    limit: self.limit,
+                    location_kind: location_id.clone(),
+                    input: Box::new(initial.ir_node.into_inner()),
+                }),
+            ),
+        );
+
+        (
+            IterationCycle {
+                ident,
+                expected_location: location_id,
+                _phantom: PhantomData,
+            },
+            stream,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stageleft::*;
+
+    use super::*;
+    use crate::deploy::MultiGraph;
+    use crate::rewrites::persist_pullup::persist_pullup;
+    use crate::NoOrder;
+
+    #[test]
+    fn iterate_lowers_to_nested_loops() {
+        let flow = crate::builder::FlowBuilder::new();
+        let process = flow.process::<()>();
+        let tick = process.tick();
+
+        let edges = unsafe {
+            process
+                .source_iter(q!([(0, 1), (1, 2), (2, 3)]))
+                .timestamped(&tick)
+                .tick_batch()
+        };
+        let roots: Stream<_, _, _, NoOrder> = tick.singleton(q!(0)).into_stream().into();
+
+        let reached = tick.iterate(IterationLimit::Fixpoint, |iteration| {
+            let edges = edges.enter_iteration(iteration);
+            let (complete_reached, reached) = iteration.loop_carried(roots);
+
+            let next = reached
+                .clone()
+                .map(q!(|v| (v, ())))
+                .join(edges)
+                .map(q!(|(_, ((), next))| next))
+                .union(reached)
+                .unique();
+
+            let doubled = iteration.iterate(IterationLimit::Times(2), |inner| {
+                let (complete_doubled, doubled) =
+                    inner.loop_carried(next.clone().map(q!(|v| v * 2)));
+                complete_doubled.complete_next_iteration(doubled.clone().map(q!(|v| v * 2)));
+                doubled.exit_iteration()
+            });
+
+            complete_reached.complete_next_iteration(next.clone());
+            next.union(doubled).exit_iteration()
+        });
+
+        reached
+            .all_ticks()
+            .drop_timestamp()
+            .for_each(q!(|v| println!("{}", v)));
+
+        let built = flow.finalize().optimize_with(persist_pullup);
+        insta::assert_debug_snapshot!(built.ir());
+
+        for graph in built
+            .compile_no_network::<MultiGraph>()
+            .hydroflow_ir()
+            .values()
+        {
+            let loops = graph.loop_ids().collect::<Vec<_>>();
+            assert_eq!(loops.len(), 2);
+            assert_eq!(graph.loop_parent(loops[1]), Some(loops[0]));
+        }
+    }
+}
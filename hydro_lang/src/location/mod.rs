@@ -1,16 +1,19 @@
 use std::fmt::Debug;
+use std::future::Future;
 use std::marker::PhantomData;
 use std::time::Duration;
 
 use dfir_rs::futures::stream::Stream as FuturesStream;
+use dfir_rs::util::deploy;
 use dfir_rs::{tokio, tokio_stream};
 use proc_macro2::Span;
+use serde::{Deserialize, Serialize};
 use stageleft::{q, QuotedWithContext};
 
 use super::builder::FlowState;
 use crate::cycle::{CycleCollection, ForwardRef, ForwardRefMarker};
-use crate::ir::{HydroNode, HydroSource};
-use crate::{Singleton, Stream, Unbounded};
+use crate::ir::{HydroNode, HydroSource, NondeterminismKind};
+use crate::{Optional, Singleton, Stream, Unbounded};
 
 pub mod external_process;
 pub use external_process::ExternalProcess;
@@ -27,7 +30,10 @@ pub use can_send::CanSend;
 pub mod tick;
 pub use tick::{NoTick, Tick, Timestamped};
 
-#[derive(PartialEq, Eq, Clone, Debug)]
+pub mod iteration;
+pub use iteration::{Iteration, IterationLimit};
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub enum LocationId {
     Process(usize),
     Cluster(usize),
@@ -177,6 +183,7 @@ pub trait Location<'a>: Clone {
         self.source_stream(q!(tokio_stream::wrappers::IntervalStream::new(
             tokio::time::interval(interval)
         )))
+        .mark_nondeterministic(NondeterminismKind::SourceInterval)
     }
 
     /// Generates a stream with values emitted at a fixed interval (with an
@@ -200,6 +207,29 @@ pub trait Location<'a>: Clone {
         self.source_stream(q!(tokio_stream::wrappers::IntervalStream::new(
             tokio::time::interval_at(tokio::time::Instant::now() + delay, interval)
         )))
+        .mark_nondeterministic(NondeterminismKind::SourceInterval)
+    }
+
+    /// Becomes `Some(())` once this location has been asked to shut down gracefully, for example
+    /// by `Deployment::stop_graceful` in Hydro Deploy. Flows can use it to stop accepting new
+    /// input, so that the data already in flight drains before the location exits.
+    fn shutdown_signal(&self) -> Optional<(), Self, Unbounded>
+    where
+        Self: Sized + NoTick,
+    {
+        self.source_stream(q!(deploy::shutdown_signal())).first()
+    }
+
+    /// Registers `hook` to run when this location finishes a graceful shutdown, after all data
+    /// has drained and before its `dest_sink`s are flushed and closed.
+    fn on_shutdown<F: FnOnce() -> Fut + 'static, Fut: Future<Output = ()> + 'static>(
+        &self,
+        hook: impl QuotedWithContext<'a, F, Self> + 'a,
+    ) where
+        Self: Sized + NoTick,
+    {
+        self.source_iter(q!([hook]))
+            .for_each(q!(|hook| deploy::on_shutdown(hook)));
     }
 
     fn forward_ref<S: CycleCollection<'a, ForwardRefMarker, Location = Self>>(
@@ -106,7 +106,7 @@ impl<'a, L: Location<'a>> Tick<L> {
         unsafe {
             // SAFETY: at runtime, `spin` produces a single value per tick,
             // so each batch is guaranteed to be the same size.
-            out.tick_batch()
+            out.tick_batch_unmarked()
         }
     }
 
@@ -119,7 +119,10 @@ impl<'a, L: Location<'a>> Tick<L> {
     {
         unsafe {
             // SAFETY: a top-level singleton produces the same value each tick
-            self.outer().singleton(e).timestamped(self).latest_tick()
+            self.outer()
+                .singleton(e)
+                .timestamped(self)
+                .latest_tick_unmarked()
         }
     }
 
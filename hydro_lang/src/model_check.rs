@@ -0,0 +1,467 @@
+//! A bounded model checker that exhaustively explores the executions of a flow compiled with
+//! [`SimDeploy`](crate::deploy::SimDeploy).
+//!
+//! Where [`crate::sim_runtime`] samples a single interleaving per seed, the checker enumerates
+//! every order in which messages can be delivered, and every point at which an instance can
+//! run a tick over the messages it has received so far. This covers the batch boundaries that
+//! `tick_batch` (and the APIs built on it) can observe on network inputs, as well as message
+//! reorderings at each `Network` node.
+//!
+//! Elements of a `tick_batch` over local data (such as the samples taken by `sample_every`) are
+//! held back at a [`BatchGate`](crate::ir::HydroNode::BatchGate), and the checker explores
+//! releasing any number of them into each batch. With [`ModelChecker::with_clock_step`], it
+//! also explores advancing the paused clock at every point, which fires timers such as those
+//! behind `source_interval`.
+//!
+//! Executions are explored breadth-first, so the first counterexample found is a shortest one.
+//! Each instance is a deterministic function of the batches it has processed, so states are
+//! identified by those batches, the messages still in flight, and the outputs emitted so far,
+//! and states that were already reached through a different interleaving are pruned.
+//!
+//! Flows whose outputs depend on the iteration order of hash-based operators may not replay
+//! exactly.
+
+use std::collections::{BTreeMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
+use std::hash::Hash;
+use std::time::Duration;
+
+use dfir_rs::bytes::Bytes;
+use dfir_rs::scheduled::graph::Dfir;
+use dfir_rs::util::{collect_ready, unbounded_channel};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::sim_runtime::{SimAddress, SimConfig, SimInstances, SimPorts};
+
+/// A single scheduling decision made by the model checker.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ModelCheckStep {
+    /// Runs an instance until it has no more work available, processing all messages
+    /// that have been delivered to it so far as one batch.
+    Run(SimAddress),
+    /// Delivers the `nth` message in flight on the channel from `from` to `to` on `port`.
+    /// Unless reordering is enabled, this is always the oldest message (`nth == 0`).
+    Deliver {
+        from: SimAddress,
+        to: SimAddress,
+        port: String,
+        nth: usize,
+    },
+    /// Runs an instance for one tick in which `count` of the elements held back at one of its
+    /// batch gates enter the batch, and then until it has no more work available.
+    Release {
+        address: SimAddress,
+        gate: usize,
+        count: usize,
+    },
+    /// Advances the clock by the step set with [`ModelChecker::with_clock_step`].
+    AdvanceClock,
+}
+
+impl Display for ModelCheckStep {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ModelCheckStep::Run(address) => write!(f, "run {}", address),
+            ModelCheckStep::Deliver {
+                from,
+                to,
+                port,
+                nth,
+            } => {
+                write!(f, "deliver {} -> {} on {}", from, to, port)?;
+                if *nth > 0 {
+                    write!(f, " (message {} in flight)", nth)?;
+                }
+                Ok(())
+            }
+            ModelCheckStep::Release {
+                address,
+                gate,
+                count,
+            } => write!(f, "release {} held at gate {} on {}", count, gate, address),
+            ModelCheckStep::AdvanceClock => write!(f, "advance clock"),
+        }
+    }
+}
+
+/// A shortest execution that violates an invariant.
+#[derive(Debug, Clone)]
+pub struct Counterexample<T> {
+    pub trace: Vec<ModelCheckStep>,
+    /// The outputs emitted along the trace, in the order they were emitted.
+    pub outputs: Vec<T>,
+    pub message: String,
+}
+
+impl<T: std::fmt::Debug> Display for Counterexample<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "invariant violated: {}", self.message)?;
+        for (i, step) in self.trace.iter().enumerate() {
+            writeln!(f, "{:>3}: {}", i + 1, step)?;
+        }
+        write!(f, "outputs: {:?}", self.outputs)
+    }
+}
+
+/// Statistics about a completed exploration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelCheckStats {
+    /// The number of distinct states that were visited.
+    pub states: usize,
+    /// Whether some executions were cut off by the depth or state limits.
+    pub truncated: bool,
+}
+
+type Invariant<T> = Box<dyn Fn(&[T]) -> Result<(), String>>;
+
+/// Explores every interleaving of a small simulated deployment, checking invariants over the
+/// outputs sent to the channel passed to the flow.
+///
+/// ```rust,ignore
+/// let result = ModelChecker::new()
+///     .with_process(0)
+///     .with_cluster(1, 2)
+///     .invariant(|outputs: &[usize]| ...)
+///     .check(|ports, output| my_flow_runtime!(ports, output));
+/// ```
+pub struct ModelChecker<T> {
+    locations: Vec<(usize, Option<u32>)>,
+    reorder: bool,
+    clock_step: Option<Duration>,
+    max_depth: usize,
+    max_states: usize,
+    invariants: Vec<Invariant<T>>,
+    quiescent_invariants: Vec<Invariant<T>>,
+}
+
+impl<T> Default for ModelChecker<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> ModelChecker<T> {
+    pub fn new() -> ModelChecker<T> {
+        ModelChecker {
+            locations: vec![],
+            reorder: false,
+            clock_step: None,
+            max_depth: 64,
+            max_states: 100_000,
+            invariants: vec![],
+            quiescent_invariants: vec![],
+        }
+    }
+
+    /// Adds a process, identified by the raw id of its location in the flow.
+    pub fn with_process(mut self, location: usize) -> ModelChecker<T> {
+        self.locations.push((location, None));
+        self
+    }
+
+    /// Adds a cluster with the given number of members, identified by the raw id of its
+    /// location in the flow.
+    pub fn with_cluster(mut self, location: usize, members: u32) -> ModelChecker<T> {
+        self.locations.push((location, Some(members)));
+        self
+    }
+
+    /// Also explores executions where messages on the same channel are delivered out of order.
+    pub fn with_reordering(mut self) -> ModelChecker<T> {
+        self.reorder = true;
+        self
+    }
+
+    /// Also explores advancing the clock by `step` at every point, so that timers fire.
+    pub fn with_clock_step(mut self, step: Duration) -> ModelChecker<T> {
+        self.clock_step = Some(step);
+        self
+    }
+
+    pub fn with_max_depth(mut self, max_depth: usize) -> ModelChecker<T> {
+        self.max_depth = max_depth;
+        self
+    }
+
+    pub fn with_max_states(mut self, max_states: usize) -> ModelChecker<T> {
+        self.max_states = max_states;
+        self
+    }
+
+    /// Adds an invariant that must hold for the outputs emitted so far, in every reachable state.
+    pub fn invariant(
+        mut self,
+        f: impl Fn(&[T]) -> Result<(), String> + 'static,
+    ) -> ModelChecker<T> {
+        self.invariants.push(Box::new(f));
+        self
+    }
+
+    /// Adds an invariant that must hold for all outputs once no more steps are possible.
+    pub fn quiescent_invariant(
+        mut self,
+        f: impl Fn(&[T]) -> Result<(), String> + 'static,
+    ) -> ModelChecker<T> {
+        self.quiescent_invariants.push(Box::new(f));
+        self
+    }
+
+    /// Explores all executions of the flow, returning the shortest counterexample if any
+    /// invariant is violated.
+    ///
+    /// The `instantiate` function is called for every instance each time an execution is
+    /// replayed, and is typically a `stageleft::entry` macro that compiles the flow with
+    /// [`SimDeploy`](crate::deploy::SimDeploy) and sends its outputs to the given channel.
+    pub fn check<F>(&self, instantiate: F) -> Result<ModelCheckStats, Counterexample<T>>
+    where
+        T: Hash + Eq,
+        F: for<'a> Fn(&'a SimPorts, &'a UnboundedSender<T>) -> Dfir<'a>,
+    {
+        let mut visited = HashSet::new();
+        let mut queue = VecDeque::from([vec![]]);
+        let mut truncated = false;
+
+        while let Some(trace) = queue.pop_front() {
+            if visited.len() >= self.max_states {
+                truncated = true;
+                break;
+            }
+
+            let replayed = self.replay(&trace, &instantiate);
+            if let Err(message) = self.check_invariants(&replayed) {
+                return Err(Counterexample {
+                    trace,
+                    outputs: replayed.outputs,
+                    message,
+                });
+            }
+
+            let Replayed {
+                state,
+                enabled,
+                outputs,
+            } = replayed;
+            if !visited.insert((state, outputs)) {
+                continue;
+            }
+
+            if trace.len() >= self.max_depth {
+                truncated |= !enabled.is_empty();
+                continue;
+            }
+
+            for step in enabled {
+                let mut next = trace.clone();
+                next.push(step);
+                queue.push_back(next);
+            }
+        }
+
+        Ok(ModelCheckStats {
+            states: visited.len(),
+            truncated,
+        })
+    }
+
+    fn check_invariants(&self, replayed: &Replayed<T>) -> Result<(), String> {
+        for invariant in &self.invariants {
+            invariant(&replayed.outputs)?;
+        }
+
+        if replayed.enabled.is_empty() {
+            for invariant in &self.quiescent_invariants {
+                invariant(&replayed.outputs)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Runs a fresh simulation through the given trace, and returns the resulting state.
+    fn replay<F>(&self, trace: &[ModelCheckStep], instantiate: &F) -> Replayed<T>
+    where
+        F: for<'a> Fn(&'a SimPorts, &'a UnboundedSender<T>) -> Dfir<'a>,
+    {
+        let mut instances = SimInstances::new(SimConfig::new(0));
+        for (location, members) in &self.locations {
+            instances = match members {
+                Some(members) => instances.with_cluster(*location, *members),
+                None => instances.with_process(*location),
+            };
+        }
+
+        let (output_send, mut output_recv) = unbounded_channel();
+        let mut sim = instances.instantiate(|ports| instantiate(ports, &output_send));
+        sim.network().hold_local_batches();
+        let addresses = sim.addresses().collect::<Vec<_>>();
+
+        // Per instance: whether it has run, the batches it has processed, and the messages
+        // delivered to it since it last ran.
+        let mut ran = vec![false; addresses.len()];
+        let mut history = vec![Vec::<InstanceEvent>::new(); addresses.len()];
+        let mut pending = vec![Vec::<(SimAddress, String, Bytes)>::new(); addresses.len()];
+        let mut clock_fired = vec![false; addresses.len()];
+        let mut outputs = vec![];
+
+        for step in trace {
+            match step {
+                ModelCheckStep::Run(address) => {
+                    let i = addresses.iter().position(|a| a == address).unwrap();
+                    sim.run_instances(vec![i]);
+
+                    history[i].push(InstanceEvent::Run(std::mem::take(&mut pending[i])));
+                    ran[i] = true;
+                    clock_fired[i] = false;
+
+                    outputs.extend(collect_ready::<Vec<_>, _>(&mut output_recv));
+                }
+                ModelCheckStep::Deliver {
+                    from,
+                    to,
+                    port,
+                    nth,
+                } => {
+                    let (index, payload) = sim
+                        .network()
+                        .in_flight_messages()
+                        .into_iter()
+                        .enumerate()
+                        .filter(|(_, m)| m.0 == *from && m.1 == *to && m.2 == *port)
+                        .map(|(index, m)| (index, m.3))
+                        .nth(*nth)
+                        .unwrap();
+                    sim.network().deliver_now(index);
+
+                    let i = addresses.iter().position(|a| a == to).unwrap();
+                    pending[i].push((*from, port.clone(), payload));
+                }
+                ModelCheckStep::Release {
+                    address,
+                    gate,
+                    count,
+                } => {
+                    let i = addresses.iter().position(|a| a == address).unwrap();
+                    sim.network().release_batch(*address, *gate, *count);
+                    sim.run_instance_tick(i);
+                    sim.network().release_batch(*address, *gate, 0);
+
+                    history[i].push(InstanceEvent::Release {
+                        gate: *gate,
+                        count: *count,
+                        messages: std::mem::take(&mut pending[i]),
+                    });
+                    ran[i] = true;
+                    clock_fired[i] = false;
+
+                    outputs.extend(collect_ready::<Vec<_>, _>(&mut output_recv));
+                }
+                ModelCheckStep::AdvanceClock => {
+                    sim.advance_clock(self.clock_step.unwrap());
+                    for (i, instance) in history.iter_mut().enumerate() {
+                        instance.push(InstanceEvent::AdvanceClock);
+                        clock_fired[i] = true;
+                    }
+                }
+            }
+        }
+
+        let mut channels = BTreeMap::<_, Vec<Bytes>>::new();
+        for (from, to, port, payload) in sim.network().in_flight_messages() {
+            channels.entry((from, to, port)).or_default().push(payload);
+        }
+
+        let held = addresses
+            .iter()
+            .map(|address| sim.network().held_batches(*address))
+            .collect::<Vec<_>>();
+
+        let mut enabled = vec![];
+        for (i, address) in addresses.iter().enumerate() {
+            if !ran[i] || !pending[i].is_empty() || clock_fired[i] {
+                enabled.push(ModelCheckStep::Run(*address));
+            }
+
+            for (gate, held) in &held[i] {
+                for count in 1..=*held {
+                    enabled.push(ModelCheckStep::Release {
+                        address: *address,
+                        gate: *gate,
+                        count,
+                    });
+                }
+            }
+        }
+
+        for ((from, to, port), payloads) in &channels {
+            let candidates = if self.reorder { payloads.len() } else { 1 };
+            for nth in 0..candidates {
+                // delivering either of two identical messages leads to the same state
+                if payloads[..nth].contains(&payloads[nth]) {
+                    continue;
+                }
+
+                enabled.push(ModelCheckStep::Deliver {
+                    from: *from,
+                    to: *to,
+                    port: port.clone(),
+                    nth,
+                });
+            }
+        }
+
+        if self.clock_step.is_some() {
+            enabled.push(ModelCheckStep::AdvanceClock);
+        }
+
+        if self.reorder {
+            for payloads in channels.values_mut() {
+                payloads.sort();
+            }
+        }
+
+        Replayed {
+            state: ModelCheckState {
+                ran,
+                history,
+                pending,
+                clock_fired,
+                channels,
+            },
+            enabled,
+            outputs,
+        }
+    }
+}
+
+/// Something that happened to a single instance, which (along with the history of the
+/// instance before it) determines the state of that instance.
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum InstanceEvent {
+    /// The instance ran over the given newly delivered messages.
+    Run(Vec<(SimAddress, String, Bytes)>),
+    /// The instance ran a tick in which `count` held elements were released at `gate`.
+    Release {
+        gate: usize,
+        count: usize,
+        messages: Vec<(SimAddress, String, Bytes)>,
+    },
+    /// The clock advanced, possibly firing timers on the instance.
+    AdvanceClock,
+}
+
+/// Everything that determines the future executions from a point in the exploration, other
+/// than the outputs emitted so far.
+#[derive(PartialEq, Eq, Hash)]
+struct ModelCheckState {
+    ran: Vec<bool>,
+    history: Vec<Vec<InstanceEvent>>,
+    pending: Vec<Vec<(SimAddress, String, Bytes)>>,
+    clock_fired: Vec<bool>,
+    channels: BTreeMap<(SimAddress, SimAddress, String), Vec<Bytes>>,
+}
+
+struct Replayed<T> {
+    state: ModelCheckState,
+    enabled: Vec<ModelCheckStep>,
+    outputs: Vec<T>,
+}
@@ -6,9 +6,10 @@ use std::rc::Rc;
 use stageleft::{q, IntoQuotedMut, QuotedWithContext};
 use syn::parse_quote;
 
+use crate::backtrace::Backtrace;
 use crate::builder::FLOW_USED_MESSAGE;
 use crate::cycle::{CycleCollection, CycleComplete, DeferTick, ForwardRefMarker, TickCycleMarker};
-use crate::ir::{HydroLeaf, HydroNode, HydroSource, TeeNode};
+use crate::ir::{HydroLeaf, HydroNode, HydroSource, NondeterminismKind, TeeNode};
 use crate::location::tick::{NoTimestamp, Timestamped};
 use crate::location::{check_matching_location, LocationId, NoTick};
 use crate::singleton::ZipResult;
@@ -366,6 +367,18 @@ impl<'a, T, L: Location<'a>, B> Optional<T, L, B> {
 
         self.map(q!(|v| Some(v))).unwrap_or(none_singleton)
     }
+
+    /// Marks any non-determinism that flows into this optional as audited, with the given
+    /// reason. See [`Stream::allow_nondeterminism`].
+    pub fn allow_nondeterminism(self, reason: &str) -> Optional<T, L, B> {
+        Optional::new(
+            self.location,
+            HydroNode::AllowNondeterminism {
+                reason: reason.to_string(),
+                input: Box::new(self.ir_node.into_inner()),
+            },
+        )
+    }
 }
 
 impl<'a, T, L: Location<'a>> Optional<T, L, Bounded> {
@@ -408,9 +421,15 @@ impl<'a, T, L: Location<'a> + NoTick, B> Optional<T, Timestamped<L>, B> {
     /// the output optional has a non-deterministic value since the snapshot can be at an
     /// arbitrary point in time.
     pub unsafe fn latest_tick(self) -> Optional<T, Tick<L>, Bounded> {
+        let location_kind = self.location.tick.id();
         Optional::new(
             self.location.tick,
-            HydroNode::Unpersist(Box::new(self.ir_node.into_inner())),
+            HydroNode::Nondeterministic {
+                kind: NondeterminismKind::LatestTick,
+                location_kind,
+                backtrace: Backtrace::capture(),
+                input: Box::new(HydroNode::Unpersist(Box::new(self.ir_node.into_inner()))),
+            },
         )
     }
 
@@ -0,0 +1,73 @@
+use crate::ir::*;
+
+struct BatchGates<'a> {
+    gate: &'a dyn Fn(usize) -> Option<syn::Expr>,
+    next_id: usize,
+}
+
+/// Whether the elements reach this node straight from the network, in which case their
+/// batches are already determined by when messages are delivered.
+fn is_network_input(node: &HydroNode) -> bool {
+    match node {
+        HydroNode::Network { .. } => true,
+        HydroNode::Tee { inner } => is_network_input(&inner.0.borrow()),
+        HydroNode::Persist(input)
+        | HydroNode::Unpersist(input)
+        | HydroNode::Map { input, .. }
+        | HydroNode::FlatMap { input, .. }
+        | HydroNode::Filter { input, .. }
+        | HydroNode::FilterMap { input, .. }
+        | HydroNode::Inspect { input, .. }
+        | HydroNode::Nondeterministic { input, .. }
+        | HydroNode::AllowNondeterminism { input, .. } => is_network_input(input),
+        _ => false,
+    }
+}
+
+fn insert_batch_gate(node: &mut HydroNode, gates: &mut BatchGates) {
+    if let HydroNode::Nondeterministic {
+        kind: NondeterminismKind::TickBatch,
+        location_kind,
+        input,
+        ..
+    } = node
+    {
+        if is_network_input(input) {
+            return;
+        }
+
+        if let Some(gate) = (gates.gate)(gates.next_id) {
+            gates.next_id += 1;
+            let input_node = std::mem::replace(input.as_mut(), HydroNode::Placeholder);
+            **input = HydroNode::BatchGate {
+                gate: gate.into(),
+                location_kind: location_kind.clone(),
+                input: Box::new(input_node),
+            };
+        }
+    }
+}
+
+/// Inserts a [`HydroNode::BatchGate`] before each `tick_batch` over local data.
+///
+/// Data that comes straight from the network is left alone, and gates are numbered in the
+/// order they are found. Deployments that do not control batching return `None` from `gate`,
+/// which leaves the IR unchanged.
+pub fn insert_batch_gates(
+    ir: Vec<HydroLeaf>,
+    gate: impl Fn(usize) -> Option<syn::Expr>,
+) -> Vec<HydroLeaf> {
+    let mut seen_tees = Default::default();
+    let mut gates = BatchGates {
+        gate: &gate,
+        next_id: 0,
+    };
+    ir.into_iter()
+        .map(|l| {
+            l.transform_children(
+                |n, s| n.transform_bottom_up(insert_batch_gate, s, &mut gates),
+                &mut seen_tees,
+            )
+        })
+        .collect()
+}
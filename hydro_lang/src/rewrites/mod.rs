@@ -1,3 +1,5 @@
+pub mod batch_gates;
+pub mod nondeterminism;
 pub mod persist_pullup;
 pub mod profiler;
 pub mod properties;
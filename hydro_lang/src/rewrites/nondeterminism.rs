@@ -0,0 +1,432 @@
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::{Display, Formatter};
+
+use crate::ir::{HydroLeaf, HydroNode, NondeterminismKind};
+use crate::location::LocationId;
+
+/// An unsafe API call that introduced non-determinism into the dataflow.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NondeterminismSite {
+    pub kind: NondeterminismKind,
+    pub location: LocationId,
+    /// The `file:line:column` of the unsafe API call, if it is known.
+    pub source: Option<String>,
+}
+
+impl Display for NondeterminismSite {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} at {:?}", self.kind, self.location)?;
+        if let Some(source) = &self.source {
+            write!(f, " ({})", source)?;
+        }
+        Ok(())
+    }
+}
+
+/// An output of the dataflow whose contents may be affected by non-determinism.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NondeterminismSink {
+    ForEach {
+        location: LocationId,
+    },
+    DestSink {
+        location: LocationId,
+    },
+    Network {
+        from_location: LocationId,
+        to_location: LocationId,
+    },
+}
+
+impl Display for NondeterminismSink {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NondeterminismSink::ForEach { location } => {
+                write!(f, "output of for_each at {:?}", location)
+            }
+            NondeterminismSink::DestSink { location } => {
+                write!(f, "output of dest_sink at {:?}", location)
+            }
+            NondeterminismSink::Network {
+                from_location,
+                to_location,
+            } => write!(f, "network from {:?} to {:?}", from_location, to_location),
+        }
+    }
+}
+
+/// Reports that a sink may observe the non-determinism introduced at a site.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NondeterminismDiagnostic {
+    pub sink: NondeterminismSink,
+    pub site: NondeterminismSite,
+}
+
+impl Display for NondeterminismDiagnostic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} depends on {}", self.sink, self.site)
+    }
+}
+
+/// Non-determinism that was audited with an `allow_nondeterminism` annotation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AllowedNondeterminism {
+    pub reason: String,
+    pub sites: Vec<NondeterminismSite>,
+}
+
+#[derive(Debug, Default)]
+pub struct NondeterminismReport {
+    pub diagnostics: Vec<NondeterminismDiagnostic>,
+    pub allowed: Vec<AllowedNondeterminism>,
+}
+
+impl NondeterminismReport {
+    pub fn is_deterministic(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+}
+
+impl Display for NondeterminismReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for diagnostic in &self.diagnostics {
+            writeln!(f, "{}", diagnostic)?;
+        }
+
+        for allowed in &self.allowed {
+            let sites = allowed
+                .sites
+                .iter()
+                .map(|s| s.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+            writeln!(f, "allowed ({}): {}", allowed.reason, sites)?;
+        }
+
+        Ok(())
+    }
+}
+
+type Taint = BTreeSet<usize>;
+
+#[derive(Default)]
+struct CheckerState {
+    sites: Vec<NondeterminismSite>,
+    site_ids: HashMap<*const HydroNode, usize>,
+    cycles: HashMap<(usize, String), Taint>,
+    seen_tees: HashMap<*const RefCell<HydroNode>, (Taint, Option<LocationId>)>,
+    diagnostics: Vec<(NondeterminismSink, usize)>,
+    allowed: Vec<(String, Taint)>,
+}
+
+fn check_leaf(state: &mut CheckerState, leaf: &HydroLeaf) -> bool {
+    match leaf {
+        HydroLeaf::ForEach { input, .. } => {
+            let (taint, location) = check_node(state, input);
+            if let Some(location) = location {
+                let sink = NondeterminismSink::ForEach {
+                    location: location.root().clone(),
+                };
+                report(state, sink, &taint);
+            }
+            false
+        }
+        HydroLeaf::DestSink { input, .. } => {
+            let (taint, location) = check_node(state, input);
+            if let Some(location) = location {
+                let sink = NondeterminismSink::DestSink {
+                    location: location.root().clone(),
+                };
+                report(state, sink, &taint);
+            }
+            false
+        }
+        HydroLeaf::CycleSink {
+            ident,
+            location_kind,
+            input,
+        } => {
+            let (taint, _) = check_node(state, input);
+            let existing = state
+                .cycles
+                .entry((location_kind.root().raw_id(), ident.to_string()))
+                .or_default();
+            let before = existing.len();
+            existing.extend(taint);
+            existing.len() != before
+        }
+    }
+}
+
+fn report(state: &mut CheckerState, sink: NondeterminismSink, taint: &Taint) {
+    for site in taint {
+        state.diagnostics.push((sink.clone(), *site));
+    }
+}
+
+fn check_node(state: &mut CheckerState, node: &HydroNode) -> (Taint, Option<LocationId>) {
+    match node {
+        HydroNode::Source { location_kind, .. } => (Taint::new(), Some(location_kind.clone())),
+        HydroNode::CycleSource {
+            ident,
+            location_kind,
+        } => (
+            state
+                .cycles
+                .get(&(location_kind.root().raw_id(), ident.to_string()))
+                .cloned()
+                .unwrap_or_default(),
+            Some(location_kind.clone()),
+        ),
+        HydroNode::Tee { inner } => {
+            let ptr = inner.0.as_ref() as *const RefCell<HydroNode>;
+            if let Some(result) = state.seen_tees.get(&ptr) {
+                return result.clone();
+            }
+
+            let result = check_node(state, &inner.0.borrow());
+            state.seen_tees.insert(ptr, result.clone());
+            result
+        }
+        HydroNode::Nondeterministic {
+            kind,
+            location_kind,
+            backtrace,
+            input,
+        } => {
+            let (mut taint, _) = check_node(state, input);
+            let next_id = state.sites.len();
+            let site = *state
+                .site_ids
+                .entry(node as *const HydroNode)
+                .or_insert(next_id);
+            if site == next_id {
+                state.sites.push(NondeterminismSite {
+                    kind: *kind,
+                    location: location_kind.clone(),
+                    source: backtrace.user_location(),
+                });
+            }
+            taint.insert(site);
+            (taint, Some(location_kind.clone()))
+        }
+        HydroNode::AllowNondeterminism { reason, input } => {
+            let (taint, location) = check_node(state, input);
+            if !taint.is_empty() {
+                state.allowed.push((reason.clone(), taint));
+            }
+            (Taint::new(), location)
+        }
+        HydroNode::Network {
+            from_location,
+            to_location,
+            input,
+            ..
+        } => {
+            let (taint, _) = check_node(state, input);
+            let sink = NondeterminismSink::Network {
+                from_location: from_location.clone(),
+                to_location: to_location.clone(),
+            };
+            report(state, sink, &taint);
+            (taint, Some(to_location.clone()))
+        }
+        _ => {
+            let mut taint = Taint::new();
+            let mut location = None;
+            node.visit_inputs(|input| {
+                let (input_taint, input_location) = check_node(state, input);
+                taint.extend(input_taint);
+                location = location.take().or(input_location);
+            });
+            (taint, location)
+        }
+    }
+}
+
+/// Traces the non-determinism introduced by unsafe APIs such as `tick_batch`, `latest_tick`,
+/// `assume_ordering`, and `source_interval` to the outputs and network channels it can affect.
+///
+/// Composite APIs such as `sample_every` and `timeout` are reported through the primitives
+/// they are built from. Non-determinism that passes through an `allow_nondeterminism`
+/// annotation is listed in [`NondeterminismReport::allowed`] instead of producing diagnostics.
+pub fn check_nondeterminism(ir: &[HydroLeaf]) -> NondeterminismReport {
+    let mut checker = CheckerState::default();
+
+    // Non-determinism can flow backwards through cycles, so iterate until the taint
+    // of every cycle has stabilized, keeping only the results of the last pass.
+    loop {
+        checker.seen_tees.clear();
+        checker.diagnostics.clear();
+        checker.allowed.clear();
+
+        let mut changed = false;
+        for leaf in ir {
+            changed |= check_leaf(&mut checker, leaf);
+        }
+
+        if !changed {
+            break;
+        }
+    }
+
+    let mut diagnostics = Vec::new();
+    for (sink, site) in checker.diagnostics {
+        let diagnostic = NondeterminismDiagnostic {
+            sink,
+            site: checker.sites[site].clone(),
+        };
+
+        if !diagnostics.contains(&diagnostic) {
+            diagnostics.push(diagnostic);
+        }
+    }
+
+    let allowed = checker
+        .allowed
+        .into_iter()
+        .map(|(reason, taint)| AllowedNondeterminism {
+            reason,
+            sites: taint
+                .into_iter()
+                .map(|s| checker.sites[s].clone())
+                .collect(),
+        })
+        .collect();
+
+    NondeterminismReport {
+        diagnostics,
+        allowed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stageleft::*;
+
+    use super::*;
+    use crate::deploy::{DeployRuntime, MultiGraph};
+    use crate::location::Location;
+    use crate::rewrites::persist_pullup::persist_pullup;
+
+    #[test]
+    fn tick_batch_reaches_for_each() {
+        let flow = crate::builder::FlowBuilder::new();
+        let process = flow.process::<()>();
+        let tick = process.tick();
+
+        unsafe {
+            process
+                .source_iter(q!(0..10))
+                .timestamped(&tick)
+                .tick_batch()
+                .count()
+                .all_ticks()
+                .drop_timestamp()
+                .for_each(q!(|n| println!("{}", n)));
+        }
+
+        process
+            .source_iter(q!(0..10))
+            .for_each(q!(|n| println!("{}", n)));
+
+        let built = flow.finalize().optimize_with(persist_pullup);
+        let report = check_nondeterminism(built.ir());
+        insta::assert_snapshot!(report.to_string());
+        assert!(!report.is_deterministic());
+
+        let _ = built.compile_no_network::<MultiGraph>();
+    }
+
+    #[test]
+    fn nondeterminism_through_network_and_cycle() {
+        let flow = crate::builder::FlowBuilder::new();
+        let process = flow.process::<()>();
+        let cluster = flow.cluster::<()>();
+        let tick = process.tick();
+
+        let (complete_cycle, cycle) = tick.cycle::<crate::Stream<_, _, _>>();
+        let batch = unsafe {
+            process
+                .source_iter(q!(0..10))
+                .timestamped(&tick)
+                .tick_batch()
+        };
+        let state = cycle.union(batch);
+        complete_cycle.complete_next_tick(state.clone());
+
+        state
+            .all_ticks()
+            .drop_timestamp()
+            .broadcast_bincode(&cluster)
+            .for_each(q!(|n| println!("{}", n)));
+
+        let built = flow.finalize().optimize_with(persist_pullup);
+        let report = check_nondeterminism(built.ir());
+        insta::assert_snapshot!(report.to_string());
+
+        let _ = built.compile::<DeployRuntime>(&RuntimeData::new("FAKE"));
+    }
+
+    #[test]
+    fn allow_nondeterminism_is_listed() {
+        let flow = crate::builder::FlowBuilder::new();
+        let process = flow.process::<()>();
+        let tick = process.tick();
+
+        unsafe {
+            process
+                .source_iter(q!(0..10))
+                .timestamped(&tick)
+                .tick_batch()
+                .all_ticks()
+                .drop_timestamp()
+                .assume_ordering::<crate::stream::TotalOrder>()
+                .allow_nondeterminism("elements are printed in any order")
+                .for_each(q!(|n| println!("{}", n)));
+        }
+
+        let built = flow.finalize().optimize_with(persist_pullup);
+        let report = check_nondeterminism(built.ir());
+        insta::assert_snapshot!(report.to_string());
+        assert!(report.is_deterministic());
+
+        let _ = built.compile_no_network::<MultiGraph>();
+    }
+
+    #[test]
+    fn allow_nondeterminism_on_singleton_and_optional() {
+        let flow = crate::builder::FlowBuilder::new();
+        let process = flow.process::<()>();
+        let tick = process.tick();
+
+        let count = process.source_iter(q!(0..10)).count();
+        unsafe { count.clone().timestamped(&tick).latest_tick() }
+            .allow_nondeterminism("only used for logging")
+            .all_ticks()
+            .drop_timestamp()
+            .for_each(q!(|n| println!("{}", n)));
+
+        let max = process.source_iter(q!(0..10)).max();
+        unsafe { max.timestamped(&tick).latest_tick() }
+            .allow_nondeterminism("only used for logging")
+            .all_ticks()
+            .drop_timestamp()
+            .for_each(q!(|n| println!("{}", n)));
+
+        unsafe { count.timestamped(&tick).latest_tick() }
+            .all_ticks()
+            .drop_timestamp()
+            .for_each(q!(|n| println!("{}", n)));
+
+        let built = flow.finalize().optimize_with(persist_pullup);
+        let report = check_nondeterminism(built.ir());
+        assert_eq!(report.allowed.len(), 2);
+        assert_eq!(report.diagnostics.len(), 1);
+        let source = report.diagnostics[0].site.source.as_ref().unwrap();
+        assert!(source.contains("nondeterminism.rs"), "{}", source);
+
+        let _ = built.compile_no_network::<MultiGraph>();
+    }
+}
@@ -68,6 +68,26 @@ fn persist_pullup_node(
                 input: behind_persist,
             })),
 
+            HydroNode::Nondeterministic {
+                kind,
+                location_kind,
+                backtrace,
+                input: mb!(* HydroNode::Persist(behind_persist)),
+            } => HydroNode::Persist(Box::new(HydroNode::Nondeterministic {
+                kind,
+                location_kind,
+                backtrace,
+                input: behind_persist,
+            })),
+
+            HydroNode::AllowNondeterminism {
+                reason,
+                input: mb!(* HydroNode::Persist(behind_persist)),
+            } => HydroNode::Persist(Box::new(HydroNode::AllowNondeterminism {
+                reason,
+                input: behind_persist,
+            })),
+
             HydroNode::Network {
                 from_location,
                 from_key,
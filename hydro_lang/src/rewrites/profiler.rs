@@ -1,6 +1,15 @@
 use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "build")]
+use dfir_lang::graph::{DfirGraph, GraphNode};
 use dfir_rs::futures::channel::mpsc::UnboundedSender;
+use dfir_rs::serde_json;
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "build")]
+use slotmap::Key;
 use stageleft::*;
 
 use super::profiler as myself; // TODO(shadaj): stageleft does not support `self::...`
@@ -14,19 +23,45 @@ fn quoted_any_fn<'a, F: Fn(&usize) + 'a, Q: IntoQuotedMut<'a, F, ()>>(q: Q) -> Q
     q
 }
 
+/// An operator that was instrumented by [`profiling_with_metadata`] or [`profiling_stdout`],
+/// identified by the same id that its counts are reported with.
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProfiledOperator {
+    pub id: usize,
+    /// The operator and its (staged) arguments, as printed by [`HydroNode::print_root`].
+    pub operator: String,
+    /// The `file:line:column` of the user code that created the operator, if it is known.
+    pub location: Option<String>,
+}
+
+impl ProfiledOperator {
+    pub fn new(id: usize, node: &HydroNode) -> ProfiledOperator {
+        ProfiledOperator {
+            id,
+            operator: node.print_root(),
+            location: node.backtrace().and_then(|b| b.user_location()),
+        }
+    }
+}
+
 /// Add a profiling node before each node to count the cardinality of its input
 fn add_profiling_node<'a>(
     node: &mut HydroNode,
     counters: RuntimeData<&'a RefCell<Vec<u64>>>,
     counter_queue: RuntimeData<&'a RefCell<UnboundedSender<(usize, u64)>>>,
     id: &mut u32,
+    operators: &mut Vec<ProfiledOperator>,
     seen_tees: &mut SeenTees,
 ) {
     let my_id = *id;
     *id += 1;
 
+    operators.push(ProfiledOperator::new(my_id as usize, node));
+
     node.transform_children(
-        |node, seen_tees| add_profiling_node(node, counters, counter_queue, id, seen_tees),
+        |node, seen_tees| {
+            add_profiling_node(node, counters, counter_queue, id, operators, seen_tees)
+        },
         seen_tees,
     );
     let orig_node = std::mem::replace(node, HydroNode::Placeholder);
@@ -53,6 +88,19 @@ pub fn profiling<'a>(
     ir: Vec<HydroLeaf>,
     counters: RuntimeData<&'a RefCell<Vec<u64>>>,
     counter_queue: RuntimeData<&'a RefCell<UnboundedSender<(usize, u64)>>>,
+) -> Vec<HydroLeaf> {
+    profiling_with_metadata(ir, counters, counter_queue, &mut vec![])
+}
+
+/// Like [`profiling`], but also records which operator each counter id belongs to.
+///
+/// The recorded operators are used to turn the counts received on `counter_queue` into a
+/// [`ProfileReport`]. The `counters` vector must have at least `operators.len()` entries.
+pub fn profiling_with_metadata<'a>(
+    ir: Vec<HydroLeaf>,
+    counters: RuntimeData<&'a RefCell<Vec<u64>>>,
+    counter_queue: RuntimeData<&'a RefCell<UnboundedSender<(usize, u64)>>>,
+    operators: &mut Vec<ProfiledOperator>,
 ) -> Vec<HydroLeaf> {
     let mut id = 0;
     let mut seen_tees = Default::default();
@@ -60,7 +108,7 @@ pub fn profiling<'a>(
         .map(|l| {
             l.transform_children(
                 |node, seen_tees| {
-                    add_profiling_node(node, counters, counter_queue, &mut id, seen_tees)
+                    add_profiling_node(node, counters, counter_queue, &mut id, operators, seen_tees)
                 },
                 &mut seen_tees,
             )
@@ -68,11 +116,354 @@ pub fn profiling<'a>(
         .collect()
 }
 
+/// Instruments each operator like [`profiling_with_metadata`], but aggregates in each process.
+///
+/// The counts and timings are periodically printed to stdout as a line starting with
+/// [`PROFILE_PREFIX`], which is collected by [`crate::deploy::ProfileCollector`].
+pub fn profiling_stdout(
+    ir: Vec<HydroLeaf>,
+    operators: &mut Vec<ProfiledOperator>,
+) -> Vec<HydroLeaf> {
+    let mut id = 0;
+    let mut seen_tees = Default::default();
+    ir.into_iter()
+        .map(|l| {
+            l.transform_children(
+                |node, seen_tees| add_stdout_profiling_node(node, &mut id, operators, seen_tees),
+                &mut seen_tees,
+            )
+        })
+        .collect()
+}
+
+fn add_stdout_profiling_node(
+    node: &mut HydroNode,
+    id: &mut usize,
+    operators: &mut Vec<ProfiledOperator>,
+    seen_tees: &mut SeenTees,
+) {
+    let my_id = *id;
+    *id += 1;
+
+    operators.push(ProfiledOperator::new(my_id, node));
+
+    node.transform_children(
+        |node, seen_tees| add_stdout_profiling_node(node, id, operators, seen_tees),
+        seen_tees,
+    );
+    let inspector: syn::Expr =
+        syn::parse_quote!(hydro_lang::rewrites::profiler::ProfileCounters::inspector(#my_id));
+    let orig_node = std::mem::replace(node, HydroNode::Placeholder);
+    *node = HydroNode::Inspect {
+        f: inspector.into(),
+        input: Box::new(orig_node),
+    }
+}
+
+/// Prefix of the stdout lines that carry the profiles aggregated by [`ProfileCounters`].
+pub const PROFILE_PREFIX: &str = "[hydro profile] ";
+
+/// How often [`ProfileCounters`] prints the profiles aggregated so far.
+const PRINT_INTERVAL: Duration = Duration::from_secs(1);
+
+struct PendingRun {
+    started: Instant,
+    count: u64,
+    last_element: Option<Instant>,
+}
+
+/// Per-process aggregation of the operators instrumented by [`profiling_stdout`].
+#[derive(Default)]
+pub struct ProfileCounters {
+    operators: BTreeMap<usize, OperatorProfile>,
+    pending: BTreeMap<usize, PendingRun>,
+    last_print: Option<Instant>,
+}
+
+impl ProfileCounters {
+    /// Called by the generated code each time the subgraph containing operator `id` runs. The
+    /// previous run of the operator is recorded, and the returned closure counts the elements of
+    /// this run along with how long after the start of the run the last one arrived.
+    pub fn inspector<T>(id: usize) -> impl FnMut(&T) {
+        thread_local! {
+            static COUNTERS: RefCell<ProfileCounters> = RefCell::new(ProfileCounters::default());
+        }
+
+        COUNTERS.with_borrow_mut(|counters| {
+            let now = Instant::now();
+            if let Some(run) = counters.pending.remove(&id) {
+                counters
+                    .operators
+                    .entry(id)
+                    .or_insert_with(|| OperatorProfile::new(id, "", None))
+                    .record(
+                        run.count,
+                        run.last_element
+                            .map(|last| last.duration_since(run.started)),
+                    );
+            }
+
+            counters.pending.insert(
+                id,
+                PendingRun {
+                    started: now,
+                    count: 0,
+                    last_element: None,
+                },
+            );
+
+            if counters
+                .last_print
+                .is_none_or(|last| now.duration_since(last) >= PRINT_INTERVAL)
+                && !counters.operators.is_empty()
+            {
+                counters.last_print = Some(now);
+                let profiles = counters.operators.values().collect::<Vec<_>>();
+                println!(
+                    "{}{}",
+                    PROFILE_PREFIX,
+                    serde_json::to_string(&profiles).unwrap()
+                );
+            }
+        });
+
+        move |_| {
+            COUNTERS.with_borrow_mut(|counters| {
+                if let Some(run) = counters.pending.get_mut(&id) {
+                    run.count += 1;
+                    run.last_element = Some(Instant::now());
+                }
+            })
+        }
+    }
+}
+
+/// Number of buckets in [`OperatorProfile::histogram`] and [`OperatorProfile::latency_histogram`].
+///
+/// Bucket `0` counts ticks where the value was zero, and bucket `i > 0` counts ticks with a
+/// value in `[2^(i-1), 2^i)` (the last bucket is unbounded above).
+pub const HISTOGRAM_BUCKETS: usize = 33;
+
+fn histogram_bucket(value: u64) -> usize {
+    ((u64::BITS - value.leading_zeros()) as usize).min(HISTOGRAM_BUCKETS - 1)
+}
+
+/// Aggregated per-tick cardinalities and timings for a single profiled operator.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct OperatorProfile {
+    pub id: usize,
+    pub operator: String,
+    pub location: Option<String>,
+    /// Total number of elements that flowed into the operator.
+    pub total: u64,
+    /// Number of ticks in which the operator was run.
+    pub ticks: u64,
+    pub max_per_tick: u64,
+    /// Log2-bucketed histogram of the number of elements per tick.
+    pub histogram: Vec<u64>,
+    /// Number of ticks with a recorded latency, which is the time from the start of the
+    /// operator's subgraph to the last element it saw. Ticks without elements have no latency.
+    pub timed_ticks: u64,
+    pub total_latency_nanos: u64,
+    pub max_latency_nanos: u64,
+    /// Log2-bucketed histogram of the latency per tick, in nanoseconds.
+    pub latency_histogram: Vec<u64>,
+}
+
+impl OperatorProfile {
+    fn new(id: usize, operator: &str, location: Option<String>) -> OperatorProfile {
+        OperatorProfile {
+            id,
+            operator: operator.to_string(),
+            location,
+            total: 0,
+            ticks: 0,
+            max_per_tick: 0,
+            histogram: vec![0; HISTOGRAM_BUCKETS],
+            timed_ticks: 0,
+            total_latency_nanos: 0,
+            max_latency_nanos: 0,
+            latency_histogram: vec![0; HISTOGRAM_BUCKETS],
+        }
+    }
+
+    fn record(&mut self, count: u64, latency: Option<Duration>) {
+        self.total += count;
+        self.ticks += 1;
+        self.max_per_tick = self.max_per_tick.max(count);
+        self.histogram[histogram_bucket(count)] += 1;
+
+        if let Some(latency) = latency {
+            let nanos = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX);
+            self.timed_ticks += 1;
+            self.total_latency_nanos = self.total_latency_nanos.saturating_add(nanos);
+            self.max_latency_nanos = self.max_latency_nanos.max(nanos);
+            self.latency_histogram[histogram_bucket(nanos)] += 1;
+        }
+    }
+
+    /// Adds the counts and timings of `other`, which is usually the same operator in another
+    /// process.
+    fn merge(&mut self, other: &OperatorProfile) {
+        self.total += other.total;
+        self.ticks += other.ticks;
+        self.max_per_tick = self.max_per_tick.max(other.max_per_tick);
+        self.timed_ticks += other.timed_ticks;
+        self.total_latency_nanos = self
+            .total_latency_nanos
+            .saturating_add(other.total_latency_nanos);
+        self.max_latency_nanos = self.max_latency_nanos.max(other.max_latency_nanos);
+        for (mine, theirs) in self.histogram.iter_mut().zip(&other.histogram) {
+            *mine += theirs;
+        }
+        for (mine, theirs) in self
+            .latency_histogram
+            .iter_mut()
+            .zip(&other.latency_histogram)
+        {
+            *mine += theirs;
+        }
+    }
+}
+
+/// A profile collected from the counter queue populated by [`profiling_with_metadata`], or
+/// from the processes instrumented by [`profiling_stdout`].
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct ProfileReport {
+    pub operators: Vec<OperatorProfile>,
+}
+
+impl ProfileReport {
+    pub fn new(operators: &[ProfiledOperator]) -> ProfileReport {
+        ProfileReport {
+            operators: operators
+                .iter()
+                .map(|op| OperatorProfile::new(op.id, &op.operator, op.location.clone()))
+                .collect(),
+        }
+    }
+
+    /// Records the count flushed for operator `id` at the start of a tick. Counts for ids that
+    /// are not in the report are ignored.
+    pub fn record(&mut self, id: usize, count: u64) {
+        if let Some(profile) = self.operators.iter_mut().find(|op| op.id == id) {
+            profile.record(count, None);
+        }
+    }
+
+    /// Adds a profile printed by a process instrumented with [`profiling_stdout`]. Profiles for
+    /// ids that are not in the report are ignored.
+    pub fn merge(&mut self, profile: &OperatorProfile) {
+        if let Some(mine) = self.operators.iter_mut().find(|op| op.id == profile.id) {
+            mine.merge(profile);
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    /// Renders one row per operator, with the histograms omitted.
+    pub fn to_csv(&self) -> String {
+        let mut out =
+            "id,operator,location,total,ticks,max_per_tick,total_latency_nanos,max_latency_nanos\n"
+                .to_string();
+        for op in &self.operators {
+            writeln!(
+                out,
+                "{},\"{}\",{},{},{},{},{},{}",
+                op.id,
+                op.operator.replace('"', "\"\""),
+                op.location.as_deref().unwrap_or(""),
+                op.total,
+                op.ticks,
+                op.max_per_tick,
+                op.total_latency_nanos,
+                op.max_latency_nanos
+            )
+            .unwrap();
+        }
+        out
+    }
+
+    /// Renders `graph` as mermaid, with each operator inserted by [`profiling_stdout`] annotated
+    /// with its counts and timings from this report.
+    #[cfg(feature = "build")]
+    pub fn to_mermaid(&self, graph: &DfirGraph) -> String {
+        let mut out = graph.to_mermaid(&Default::default());
+        writeln!(
+            out,
+            "classDef profileClass fill:#dfd,stroke:#000,text-align:left,white-space:pre"
+        )
+        .unwrap();
+
+        for (node_id, node) in graph.nodes() {
+            let GraphNode::Operator(operator) = node else {
+                continue;
+            };
+            let Some(profile) = profiler_inspector_id(operator)
+                .and_then(|id| self.operators.iter().find(|op| op.id == id))
+            else {
+                continue;
+            };
+
+            let mean_latency = profile
+                .total_latency_nanos
+                .checked_div(profile.timed_ticks)
+                .unwrap_or(0);
+            writeln!(
+                out,
+                "profile{id}[\"total: {total}<br>ticks: {ticks}<br>max/tick: {max}<br>mean latency: {mean}ns\"]:::profileClass",
+                id = profile.id,
+                total = profile.total,
+                ticks = profile.ticks,
+                max = profile.max_per_tick,
+                mean = mean_latency,
+            )
+            .unwrap();
+            writeln!(out, "profile{} -.- {:?}", profile.id, node_id.data()).unwrap();
+        }
+
+        out
+    }
+}
+
+/// The operator id of an `inspect` inserted by [`profiling_stdout`].
+#[cfg(feature = "build")]
+fn profiler_inspector_id(operator: &dfir_lang::parse::Operator) -> Option<usize> {
+    if operator.name_string() != "inspect" {
+        return None;
+    }
+
+    let Some(syn::Expr::Call(call)) = operator.args.first() else {
+        return None;
+    };
+    let syn::Expr::Path(func) = call.func.as_ref() else {
+        return None;
+    };
+    if func.path.segments.last()?.ident != "inspector" {
+        return None;
+    }
+    let Some(syn::Expr::Lit(syn::ExprLit {
+        lit: syn::Lit::Int(id),
+        ..
+    })) = call.args.first()
+    else {
+        return None;
+    };
+    id.base10_parse().ok()
+}
+
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
+    use dfir_rs::serde_json;
+    use hydro_deploy::Deployment;
     use stageleft::*;
 
-    use crate::deploy::MultiGraph;
+    use super::{OperatorProfile, ProfileReport, ProfiledOperator};
+    use crate::deploy::{MultiGraph, ProfileCollector};
     use crate::location::Location;
 
     #[test]
@@ -98,12 +489,151 @@ mod tests {
         let counters = RuntimeData::new("Fake");
         let counter_queue = RuntimeData::new("Fake");
 
+        let mut operators = vec![];
         let pushed_down = built
             .optimize_with(crate::rewrites::persist_pullup::persist_pullup)
-            .optimize_with(|ir| super::profiling(ir, counters, counter_queue));
+            .optimize_with(|ir| {
+                super::profiling_with_metadata(ir, counters, counter_queue, &mut operators)
+            });
 
         insta::assert_debug_snapshot!(&pushed_down.ir());
+        assert_eq!(operators.len(), 2);
+        assert!(operators[0].operator.starts_with("Map("));
+        assert!(operators[1].operator.starts_with("Source(Iter("));
 
         let _ = pushed_down.compile_no_network::<MultiGraph>();
     }
+
+    #[test]
+    fn profile_report_histogram() {
+        let mut report = ProfileReport::new(&[
+            ProfiledOperator {
+                id: 0,
+                operator: "Map(f)".to_string(),
+                location: Some("src/lib.rs:1:2".to_string()),
+            },
+            ProfiledOperator {
+                id: 1,
+                operator: "Source(\"x\")".to_string(),
+                location: None,
+            },
+        ]);
+
+        // unknown ids are ignored
+        for (id, count) in [(0, 0), (0, 1), (0, 5), (1, 1000), (7, 1)] {
+            report.record(id, count);
+        }
+
+        let map = &report.operators[0];
+        assert_eq!((map.total, map.ticks, map.max_per_tick), (6, 3, 5));
+        assert_eq!(&map.histogram[..4], &[1, 1, 0, 1]);
+        assert_eq!(report.operators[1].histogram[10], 1);
+
+        assert_eq!(
+            report.to_csv(),
+            "id,operator,location,total,ticks,max_per_tick,total_latency_nanos,max_latency_nanos\n\
+             0,\"Map(f)\",src/lib.rs:1:2,6,3,5,0,0\n\
+             1,\"Source(\"\"x\"\")\",,1000,1,1000,0,0\n"
+        );
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json()).unwrap();
+        assert_eq!(json["operators"][1]["total"], 1000);
+    }
+
+    #[test]
+    fn profile_report_merges_latencies() {
+        let mut report = ProfileReport::new(&[ProfiledOperator {
+            id: 0,
+            operator: "Map(f)".to_string(),
+            location: None,
+        }]);
+
+        for member in 0..2 {
+            let mut profile = OperatorProfile::new(0, "", None);
+            profile.record(3, Some(Duration::from_nanos(100 * (member + 1))));
+            profile.record(0, None);
+            report.merge(&profile);
+            report.merge(&OperatorProfile::new(5, "", None));
+        }
+
+        let map = &report.operators[0];
+        assert_eq!((map.total, map.ticks, map.timed_ticks), (6, 4, 2));
+        assert_eq!((map.total_latency_nanos, map.max_latency_nanos), (300, 200));
+        assert_eq!(map.latency_histogram[7], 1);
+        assert_eq!(map.latency_histogram[8], 1);
+    }
+
+    #[test]
+    fn profiling_stdout_mermaid() {
+        let flow = crate::builder::FlowBuilder::new();
+        let process = flow.process::<()>();
+
+        process
+            .source_iter(q!(0..10))
+            .map(q!(|v| v + 1))
+            .for_each(q!(|n| println!("{}", n)));
+
+        let mut operators = vec![];
+        let built = flow
+            .finalize()
+            .optimize_with(crate::rewrites::persist_pullup::persist_pullup)
+            .optimize_with(|ir| super::profiling_stdout(ir, &mut operators));
+
+        assert_eq!(operators.len(), 2);
+        for op in &operators {
+            assert!(op.location.as_ref().unwrap().contains("profiler.rs"));
+        }
+
+        let mut report = ProfileReport::new(&operators);
+        report.record(0, 10);
+
+        let compiled = built.compile_no_network::<MultiGraph>();
+        let mermaid = report.to_mermaid(compiled.hydroflow_ir().values().next().unwrap());
+        assert!(mermaid.contains("profile0[\"total: 10<br>"));
+        assert!(mermaid.contains("profile1[\"total: 0<br>"));
+        assert_eq!(mermaid.matches("-.-").count(), 2);
+    }
+
+    #[tokio::test]
+    async fn profiling_stdout_collects_from_processes() {
+        let mut deployment = Deployment::new();
+
+        let flow = crate::builder::FlowBuilder::new();
+        let process = flow.process::<()>();
+
+        unsafe {
+            // SAFETY: only the number of elements is observed
+            process.source_interval(q!(Duration::from_millis(50)))
+        }
+        .map(q!(|_| 1))
+        .for_each(q!(|_| {}));
+
+        let mut operators = vec![];
+        let nodes = flow
+            .finalize()
+            .optimize_with(crate::rewrites::persist_pullup::persist_pullup)
+            .optimize_with(|ir| super::profiling_stdout(ir, &mut operators))
+            .with_process(&process, deployment.Localhost())
+            .deploy(&mut deployment);
+
+        deployment.deploy().await.unwrap();
+
+        let collector = ProfileCollector::new();
+        collector
+            .watch("process", nodes.get_process(&process))
+            .await;
+
+        deployment.start().await.unwrap();
+
+        loop {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+            let report = collector.report(&operators);
+            let map = &report.operators[0];
+            if map.total > 1 {
+                assert!(map.operator.starts_with("Map("));
+                assert_eq!(map.timed_ticks, map.total);
+                break;
+            }
+        }
+    }
 }
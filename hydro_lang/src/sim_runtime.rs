@@ -0,0 +1,810 @@
+//! Runtime support for [`SimDeploy`](crate::deploy::SimDeploy), which runs every location of a
+//! flow inside a single-threaded, seeded simulator.
+//!
+//! Each process and each cluster member is instantiated as its own [`Dfir`] with a
+//! [`SimPorts`] handle, and all network channels are routed through a shared [`SimNetwork`].
+//! The network decides when (and whether) each message is delivered using a seeded RNG, so
+//! a failing run can be replayed exactly by re-using its seed.
+//!
+//! Timers run on a paused Tokio clock, which only moves forward by
+//! [`SimConfig::step_duration`] at the start of each step, so flows that use
+//! `source_interval` replay exactly as well.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+use dfir_rs::bytes::{Bytes, BytesMut};
+use dfir_rs::futures::{Sink, Stream, StreamExt};
+use dfir_rs::scheduled::graph::Dfir;
+use dfir_rs::util::unbounded_channel;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use stageleft::{q, QuotedWithContext, RuntimeData};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Configuration for the faults injected by the simulated network.
+#[derive(Debug, Clone)]
+pub struct SimConfig {
+    /// Seed for all random choices made by the simulator.
+    pub seed: u64,
+    /// Probability that each message is dropped when it is sent.
+    pub drop_probability: f64,
+    /// Minimum number of simulation steps before a message is delivered.
+    pub min_delay: u64,
+    /// Maximum number of simulation steps before a message is delivered.
+    pub max_delay: u64,
+    /// Whether messages on the same channel may be delivered out of order.
+    pub reorder: bool,
+    /// How far the (paused) clock seen by timers advances in each simulation step.
+    pub step_duration: Duration,
+}
+
+impl SimConfig {
+    /// A reliable, in-order network with no delays, using the given seed.
+    pub fn new(seed: u64) -> SimConfig {
+        SimConfig {
+            seed,
+            drop_probability: 0.0,
+            min_delay: 0,
+            max_delay: 0,
+            reorder: false,
+            step_duration: Duration::from_millis(1),
+        }
+    }
+
+    pub fn with_drop_probability(mut self, drop_probability: f64) -> SimConfig {
+        check_drop_probability(drop_probability);
+        self.drop_probability = drop_probability;
+        self
+    }
+
+    pub fn with_delay(mut self, min_delay: u64, max_delay: u64) -> SimConfig {
+        assert!(
+            min_delay <= max_delay,
+            "min_delay must be at most max_delay"
+        );
+        self.min_delay = min_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_reordering(mut self) -> SimConfig {
+        self.reorder = true;
+        self
+    }
+
+    pub fn with_step_duration(mut self, step_duration: Duration) -> SimConfig {
+        self.step_duration = step_duration;
+        self
+    }
+}
+
+fn check_drop_probability(drop_probability: f64) {
+    assert!(
+        (0.0..=1.0).contains(&drop_probability),
+        "drop_probability must be between 0.0 and 1.0, got {}",
+        drop_probability
+    );
+}
+
+/// The address of a single instance in the simulation: a process, or one member of a cluster.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SimAddress {
+    pub location: usize,
+    pub member: Option<u32>,
+}
+
+impl SimAddress {
+    pub fn process(location: usize) -> SimAddress {
+        SimAddress {
+            location,
+            member: None,
+        }
+    }
+
+    pub fn cluster_member(location: usize, member: u32) -> SimAddress {
+        SimAddress {
+            location,
+            member: Some(member),
+        }
+    }
+}
+
+impl Display for SimAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.member {
+            Some(member) => write!(f, "{}/{}", self.location, member),
+            None => write!(f, "{}", self.location),
+        }
+    }
+}
+
+/// Counters for the messages handled by the simulated network.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SimStats {
+    pub sent: usize,
+    pub delivered: usize,
+    pub dropped: usize,
+}
+
+struct SimMessage {
+    from: SimAddress,
+    to: SimAddress,
+    port: String,
+    payload: Bytes,
+    deliver_at: u64,
+}
+
+/// A gate in front of a `tick_batch` over local data, see
+/// [`HydroNode::BatchGate`](crate::ir::HydroNode::BatchGate).
+struct BatchGateState {
+    /// How many more elements may enter a batch, or `None` if all of them may.
+    budget: Option<usize>,
+    /// The last tick in which elements were offered to the gate.
+    tick: u64,
+    /// How many elements were held back in that tick.
+    held: usize,
+}
+
+type SimInbox = (
+    UnboundedSender<(Option<u32>, Bytes)>,
+    Option<dfir_rs::tokio_stream::wrappers::UnboundedReceiverStream<(Option<u32>, Bytes)>>,
+);
+
+struct SimNetworkState {
+    config: SimConfig,
+    rng: StdRng,
+    now: u64,
+    in_flight: Vec<SimMessage>,
+    inboxes: HashMap<(SimAddress, String), SimInbox>,
+    last_delivery: HashMap<(SimAddress, SimAddress, String), u64>,
+    partitions: HashSet<(SimAddress, SimAddress)>,
+    stats: SimStats,
+    batch_gates: BTreeMap<(SimAddress, usize), BatchGateState>,
+    /// The budget of gates that have not been offered any elements yet.
+    default_batch_budget: Option<usize>,
+}
+
+impl SimNetworkState {
+    fn inbox(&mut self, address: SimAddress, port: &str) -> &mut SimInbox {
+        self.inboxes
+            .entry((address, port.to_string()))
+            .or_insert_with(|| {
+                let (send, recv) = unbounded_channel();
+                (send, Some(recv))
+            })
+    }
+
+    fn send(&mut self, from: SimAddress, to: SimAddress, port: &str, payload: Bytes) {
+        self.stats.sent += 1;
+        if self.config.drop_probability > 0.0 && self.rng.gen_bool(self.config.drop_probability) {
+            self.stats.dropped += 1;
+            return;
+        }
+
+        let mut deliver_at = self.now
+            + self
+                .rng
+                .gen_range(self.config.min_delay..=self.config.max_delay);
+        if !self.config.reorder {
+            let last = self
+                .last_delivery
+                .entry((from, to, port.to_string()))
+                .or_default();
+            deliver_at = deliver_at.max(*last);
+            *last = deliver_at;
+        }
+
+        self.in_flight.push(SimMessage {
+            from,
+            to,
+            port: port.to_string(),
+            payload,
+            deliver_at,
+        });
+    }
+
+    fn deliver(&mut self, message: SimMessage) {
+        let (send, _) = self.inbox(message.to, &message.port);
+        send.send((message.from.member, message.payload)).unwrap();
+        self.stats.delivered += 1;
+    }
+
+    fn is_partitioned(&self, a: SimAddress, b: SimAddress) -> bool {
+        self.partitions.contains(&(a, b)) || self.partitions.contains(&(b, a))
+    }
+
+    /// Advances time by one step and delivers all messages that are due, returning the number
+    /// of messages that were delivered.
+    fn deliver_ready(&mut self) -> usize {
+        self.now += 1;
+        let now = self.now;
+
+        let (mut ready, pending): (Vec<_>, Vec<_>) = std::mem::take(&mut self.in_flight)
+            .into_iter()
+            .partition(|m| m.deliver_at <= now);
+        self.in_flight = pending;
+
+        if self.config.reorder {
+            ready.shuffle(&mut self.rng);
+        } else {
+            // stable, so messages due at the same time stay in the order they were sent
+            ready.sort_by_key(|m| m.deliver_at);
+        }
+
+        let mut delivered = 0;
+        for message in ready {
+            if self.is_partitioned(message.from, message.to) {
+                self.stats.dropped += 1;
+                continue;
+            }
+
+            self.deliver(message);
+            delivered += 1;
+        }
+
+        delivered
+    }
+}
+
+/// The virtual network shared by all instances in a simulation.
+#[derive(Clone)]
+pub struct SimNetwork {
+    state: Rc<RefCell<SimNetworkState>>,
+}
+
+impl SimNetwork {
+    pub fn new(config: SimConfig) -> SimNetwork {
+        check_drop_probability(config.drop_probability);
+        SimNetwork {
+            state: Rc::new(RefCell::new(SimNetworkState {
+                rng: StdRng::seed_from_u64(config.seed),
+                config,
+                now: 0,
+                in_flight: vec![],
+                inboxes: HashMap::new(),
+                last_delivery: HashMap::new(),
+                partitions: HashSet::new(),
+                stats: SimStats::default(),
+                batch_gates: BTreeMap::new(),
+                default_batch_budget: None,
+            })),
+        }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.state.borrow().config.seed
+    }
+
+    /// The number of simulation steps that have elapsed.
+    pub fn now(&self) -> u64 {
+        self.state.borrow().now
+    }
+
+    pub fn stats(&self) -> SimStats {
+        self.state.borrow().stats
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.state.borrow().in_flight.len()
+    }
+
+    /// The `(from, to, port, payload)` of each message in flight, in the order they were sent.
+    pub(crate) fn in_flight_messages(&self) -> Vec<(SimAddress, SimAddress, String, Bytes)> {
+        self.state
+            .borrow()
+            .in_flight
+            .iter()
+            .map(|m| (m.from, m.to, m.port.clone(), m.payload.clone()))
+            .collect()
+    }
+
+    /// Immediately delivers the in-flight message at the given index, regardless of its delay.
+    pub(crate) fn deliver_now(&self, index: usize) {
+        let mut state = self.state.borrow_mut();
+        let message = state.in_flight.remove(index);
+        state.deliver(message);
+    }
+
+    pub fn set_drop_probability(&self, drop_probability: f64) {
+        check_drop_probability(drop_probability);
+        self.state.borrow_mut().config.drop_probability = drop_probability;
+    }
+
+    /// Drops all messages between the two instances (in both directions) until healed.
+    pub fn partition(&self, a: SimAddress, b: SimAddress) {
+        self.state.borrow_mut().partitions.insert((a, b));
+    }
+
+    pub fn heal(&self, a: SimAddress, b: SimAddress) {
+        let mut state = self.state.borrow_mut();
+        state.partitions.remove(&(a, b));
+        state.partitions.remove(&(b, a));
+    }
+
+    pub fn heal_all(&self) {
+        self.state.borrow_mut().partitions.clear();
+    }
+
+    /// Holds back all elements at the gates in front of each `tick_batch` over local data,
+    /// until they are released with [`SimNetwork::release_batch`].
+    pub(crate) fn hold_local_batches(&self) {
+        let mut state = self.state.borrow_mut();
+        state.default_batch_budget = Some(0);
+        for gate in state.batch_gates.values_mut() {
+            gate.budget = Some(0);
+        }
+    }
+
+    /// The `(gate, held)` number of elements held back at each gate of the given instance.
+    pub(crate) fn held_batches(&self, address: SimAddress) -> Vec<(usize, usize)> {
+        self.state
+            .borrow()
+            .batch_gates
+            .range((address, 0)..=(address, usize::MAX))
+            .filter(|(_, gate)| gate.held > 0)
+            .map(|((_, id), gate)| (*id, gate.held))
+            .collect()
+    }
+
+    /// Lets the next `count` elements offered to the given gate enter a batch.
+    pub(crate) fn release_batch(&self, address: SimAddress, gate: usize, count: usize) {
+        if let Some(gate) = self
+            .state
+            .borrow_mut()
+            .batch_gates
+            .get_mut(&(address, gate))
+        {
+            gate.budget = Some(count);
+        }
+    }
+}
+
+/// A sink that synchronously hands items to the simulated network.
+pub struct SimSink<T> {
+    send: Box<dyn FnMut(T)>,
+}
+
+impl<T> Sink<T> for SimSink<T> {
+    type Error = std::io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: T) -> Result<(), Self::Error> {
+        (self.get_mut().send)(item);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// The gate in front of a `tick_batch` over local data on one instance.
+///
+/// See [`HydroNode::BatchGate`](crate::ir::HydroNode::BatchGate). Unless the batches are
+/// controlled by the [`model_check`](crate::model_check) checker, every element is admitted.
+pub struct SimBatchGate {
+    network: SimNetwork,
+    key: (SimAddress, usize),
+}
+
+impl SimBatchGate {
+    /// Whether an element offered in the given tick enters the current batch, or is held
+    /// back until the next tick.
+    pub fn admit(&self, tick: u64) -> bool {
+        let mut state = self.network.state.borrow_mut();
+        let default_budget = state.default_batch_budget;
+        let gate = state
+            .batch_gates
+            .entry(self.key)
+            .or_insert_with(|| BatchGateState {
+                budget: default_budget,
+                tick,
+                held: 0,
+            });
+
+        if gate.tick != tick {
+            gate.tick = tick;
+            gate.held = 0;
+        }
+
+        match &mut gate.budget {
+            None => true,
+            Some(0) => {
+                gate.held += 1;
+                false
+            }
+            Some(budget) => {
+                *budget -= 1;
+                true
+            }
+        }
+    }
+}
+
+/// The handle passed to the compiled flow of each instance, which plays the role of
+/// [`DeployPorts`](dfir_rs::util::deploy::DeployPorts) in a simulation.
+pub struct SimPorts {
+    network: SimNetwork,
+    pub subgraph_id: usize,
+    pub cluster_id: Option<u32>,
+    pub clusters: HashMap<usize, Vec<u32>>,
+}
+
+impl SimPorts {
+    pub fn address(&self) -> SimAddress {
+        SimAddress {
+            location: self.subgraph_id,
+            member: self.cluster_id,
+        }
+    }
+
+    pub fn direct_sink(&self, to_location: usize, port: &str) -> SimSink<Bytes> {
+        let network = self.network.clone();
+        let from = self.address();
+        let port = port.to_string();
+        SimSink {
+            send: Box::new(move |payload| {
+                network.state.borrow_mut().send(
+                    from,
+                    SimAddress::process(to_location),
+                    &port,
+                    payload,
+                )
+            }),
+        }
+    }
+
+    pub fn demux_sink(&self, to_location: usize, port: &str) -> SimSink<(u32, Bytes)> {
+        let network = self.network.clone();
+        let from = self.address();
+        let port = port.to_string();
+        SimSink {
+            send: Box::new(move |(member, payload)| {
+                network.state.borrow_mut().send(
+                    from,
+                    SimAddress::cluster_member(to_location, member),
+                    &port,
+                    payload,
+                )
+            }),
+        }
+    }
+
+    pub fn batch_gate(&self, id: usize) -> SimBatchGate {
+        SimBatchGate {
+            network: self.network.clone(),
+            key: (self.address(), id),
+        }
+    }
+
+    fn take_inbox(
+        &self,
+        port: &str,
+    ) -> dfir_rs::tokio_stream::wrappers::UnboundedReceiverStream<(Option<u32>, Bytes)> {
+        self.network
+            .state
+            .borrow_mut()
+            .inbox(self.address(), port)
+            .1
+            .take()
+            .expect("port was already connected")
+    }
+
+    pub fn direct_source(
+        &self,
+        port: &str,
+    ) -> impl Stream<Item = Result<BytesMut, std::io::Error>> + Unpin {
+        self.take_inbox(port)
+            .map(|(_, payload)| Ok(BytesMut::from(&payload[..])))
+    }
+
+    pub fn tagged_source(
+        &self,
+        port: &str,
+    ) -> impl Stream<Item = Result<(u32, BytesMut), std::io::Error>> + Unpin {
+        self.take_inbox(port).map(|(from, payload)| {
+            Ok((
+                from.expect("tagged messages must come from a cluster member"),
+                BytesMut::from(&payload[..]),
+            ))
+        })
+    }
+}
+
+/// The set of instances to simulate, which owns the [`SimPorts`] borrowed by each instance.
+pub struct SimInstances {
+    network: SimNetwork,
+    ports: Vec<SimPorts>,
+}
+
+impl SimInstances {
+    pub fn new(config: SimConfig) -> SimInstances {
+        SimInstances {
+            network: SimNetwork::new(config),
+            ports: vec![],
+        }
+    }
+
+    /// Adds a process, identified by the raw id of its location in the flow.
+    pub fn with_process(mut self, location: usize) -> SimInstances {
+        self.ports.push(SimPorts {
+            network: self.network.clone(),
+            subgraph_id: location,
+            cluster_id: None,
+            clusters: HashMap::new(),
+        });
+        self.update_clusters()
+    }
+
+    /// Adds a cluster with the given number of members, identified by the raw id of its
+    /// location in the flow.
+    pub fn with_cluster(mut self, location: usize, members: u32) -> SimInstances {
+        for member in 0..members {
+            self.ports.push(SimPorts {
+                network: self.network.clone(),
+                subgraph_id: location,
+                cluster_id: Some(member),
+                clusters: HashMap::new(),
+            });
+        }
+        self.update_clusters()
+    }
+
+    fn update_clusters(mut self) -> SimInstances {
+        let mut clusters = HashMap::<usize, Vec<u32>>::new();
+        for ports in &self.ports {
+            if let Some(member) = ports.cluster_id {
+                clusters.entry(ports.subgraph_id).or_default().push(member);
+            }
+        }
+
+        for ports in &mut self.ports {
+            ports.clusters = clusters.clone();
+        }
+
+        self
+    }
+
+    /// Instantiates every process and cluster member with the given function, which is
+    /// typically a `stageleft::entry` macro that compiles the flow with
+    /// [`SimDeploy`](crate::deploy::SimDeploy).
+    pub fn instantiate<'a>(
+        &'a self,
+        mut f: impl FnMut(&'a SimPorts) -> Dfir<'a>,
+    ) -> Simulation<'a> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .start_paused(true)
+            .build()
+            .unwrap();
+        let local = tokio::task::LocalSet::new();
+
+        // timers and tasks created while building the graphs belong to the simulation
+        let instances = {
+            let _runtime = runtime.enter();
+            let _local = local.enter();
+            self.ports
+                .iter()
+                .map(|ports| (ports.address(), f(ports)))
+                .collect()
+        };
+
+        Simulation {
+            instances,
+            network: self.network.clone(),
+            local,
+            runtime,
+        }
+    }
+}
+
+/// A running simulation, which is advanced one step at a time.
+///
+/// In each step, the clock advances, the network delivers all messages that are due, and then
+/// every instance (in a random order) runs until it has no more work available.
+pub struct Simulation<'a> {
+    instances: Vec<(SimAddress, Dfir<'a>)>,
+    network: SimNetwork,
+    local: tokio::task::LocalSet,
+    runtime: tokio::runtime::Runtime,
+}
+
+impl Simulation<'_> {
+    pub fn network(&self) -> &SimNetwork {
+        &self.network
+    }
+
+    /// Runs a single step of the simulation. Returns `true` if any messages were delivered,
+    /// any instance did work, or there are still messages in flight.
+    pub fn step(&mut self) -> bool {
+        let step_duration = self.network.state.borrow().config.step_duration;
+        self.advance_clock(step_duration);
+
+        let delivered = self.network.state.borrow_mut().deliver_ready();
+
+        let mut order = (0..self.instances.len()).collect::<Vec<_>>();
+        order.shuffle(&mut self.network.state.borrow_mut().rng);
+
+        let work_done = self.run_instances(order);
+
+        delivered > 0 || work_done || self.network.in_flight() > 0
+    }
+
+    /// Runs the given instances, in order, until each has no more work available.
+    pub(crate) fn run_instances(&mut self, order: Vec<usize>) -> bool {
+        let _runtime = self.runtime.enter();
+        let _local = self.local.enter();
+
+        let mut work_done = false;
+        for i in order {
+            work_done |= self.instances[i].1.run_available();
+
+            // Let the tasks behind `dest_sink` hand their outputs to the network before
+            // moving on, so that sends are attributed to this step.
+            run_until_idle(&mut self.local);
+        }
+        work_done
+    }
+
+    /// Runs the given instance for one tick, even if it has not received any new inputs, and
+    /// then until it has no more work available.
+    pub(crate) fn run_instance_tick(&mut self, i: usize) {
+        let _runtime = self.runtime.enter();
+        let _local = self.local.enter();
+
+        self.instances[i].1.run_tick();
+        self.instances[i].1.run_available();
+        run_until_idle(&mut self.local);
+    }
+
+    /// Moves the paused clock forward, firing any timers that are due.
+    pub(crate) fn advance_clock(&mut self, duration: Duration) {
+        self.runtime.block_on(tokio::time::advance(duration));
+    }
+
+    /// Runs steps until the simulation makes no more progress, returning `false` if it is
+    /// still making progress after `max_steps` steps.
+    pub fn run_until_quiescent(&mut self, max_steps: usize) -> bool {
+        for _ in 0..max_steps {
+            if !self.step() {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    pub fn addresses(&self) -> impl Iterator<Item = SimAddress> + '_ {
+        self.instances.iter().map(|(address, _)| *address)
+    }
+}
+
+/// Records whether a [`LocalSet`](tokio::task::LocalSet) asked to be polled again.
+pub struct WokenFlag(AtomicBool);
+
+impl WokenFlag {
+    pub fn new() -> Arc<WokenFlag> {
+        Arc::new(WokenFlag(AtomicBool::new(false)))
+    }
+
+    /// Returns whether the flag was woken since the last call, and resets it.
+    pub fn take(&self) -> bool {
+        self.0.swap(false, Ordering::SeqCst)
+    }
+}
+
+impl Wake for WokenFlag {
+    fn wake(self: Arc<Self>) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Polls the tasks in `local` until none of them can make progress without outside input.
+///
+/// Polling a `LocalSet` runs a bounded batch of its ready tasks, and wakes it again if more
+/// are ready, so it is idle once a poll finishes without waking it.
+fn run_until_idle(local: &mut tokio::task::LocalSet) {
+    let woken = WokenFlag::new();
+    let waker = Waker::from(woken.clone());
+    let mut cx = Context::from_waker(&waker);
+
+    loop {
+        if Pin::new(&mut *local).poll(&mut cx).is_ready() || !woken.take() {
+            break;
+        }
+    }
+}
+
+/// Runs the given simulation test with each seed. If a seed fails, the test panics with a
+/// message that includes the seed, so that the run can be replayed with [`SimConfig::new`].
+pub fn fuzz_seeds(seeds: impl IntoIterator<Item = u64>, mut test: impl FnMut(u64)) {
+    for seed in seeds {
+        if let Err(e) = std::panic::catch_unwind(AssertUnwindSafe(|| test(seed))) {
+            let message = e
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| e.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("non-string panic payload");
+            panic!("simulation failed with seed {}: {}", seed, message);
+        }
+    }
+}
+
+pub fn cluster_members(
+    ports: RuntimeData<&SimPorts>,
+    of_cluster: usize,
+) -> impl QuotedWithContext<&Vec<u32>, ()> + Copy {
+    q!(ports.clusters.get(&of_cluster).unwrap())
+}
+
+pub fn cluster_self_id(ports: RuntimeData<&SimPorts>) -> impl QuotedWithContext<u32, ()> + Copy {
+    q!(ports
+        .cluster_id
+        .expect("Tried to read Cluster ID on a non-cluster node"))
+}
+
+pub fn sim_batch_gate(ports: RuntimeData<&SimPorts>, id: usize) -> syn::Expr {
+    q!(ports.batch_gate(id)).splice_untyped_ctx(&())
+}
+
+pub fn sim_o2o(
+    ports: RuntimeData<&SimPorts>,
+    to_location: usize,
+    port: &str,
+) -> (syn::Expr, syn::Expr) {
+    (
+        q!(ports.direct_sink(to_location, port)).splice_untyped_ctx(&()),
+        q!(ports.direct_source(port)).splice_untyped_ctx(&()),
+    )
+}
+
+pub fn sim_o2m(
+    ports: RuntimeData<&SimPorts>,
+    to_location: usize,
+    port: &str,
+) -> (syn::Expr, syn::Expr) {
+    (
+        q!(ports.demux_sink(to_location, port)).splice_untyped_ctx(&()),
+        q!(ports.direct_source(port)).splice_untyped_ctx(&()),
+    )
+}
+
+pub fn sim_m2o(
+    ports: RuntimeData<&SimPorts>,
+    to_location: usize,
+    port: &str,
+) -> (syn::Expr, syn::Expr) {
+    (
+        q!(ports.direct_sink(to_location, port)).splice_untyped_ctx(&()),
+        q!(ports.tagged_source(port)).splice_untyped_ctx(&()),
+    )
+}
+
+pub fn sim_m2m(
+    ports: RuntimeData<&SimPorts>,
+    to_location: usize,
+    port: &str,
+) -> (syn::Expr, syn::Expr) {
+    (
+        q!(ports.demux_sink(to_location, port)).splice_untyped_ctx(&()),
+        q!(ports.tagged_source(port)).splice_untyped_ctx(&()),
+    )
+}
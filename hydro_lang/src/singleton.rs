@@ -5,12 +5,13 @@ use std::rc::Rc;
 
 use stageleft::{q, IntoQuotedMut, QuotedWithContext};
 
+use crate::backtrace::Backtrace;
 use crate::builder::FLOW_USED_MESSAGE;
 use crate::cycle::{
     CycleCollection, CycleCollectionWithInitial, CycleComplete, DeferTick, ForwardRefMarker,
     TickCycleMarker,
 };
-use crate::ir::{HydroLeaf, HydroNode, TeeNode};
+use crate::ir::{HydroLeaf, HydroNode, NondeterminismKind, TeeNode};
 use crate::location::tick::{NoTimestamp, Timestamped};
 use crate::location::{check_matching_location, Location, LocationId, NoTick, Tick};
 use crate::{Bounded, Optional, Stream, Unbounded};
@@ -309,6 +310,18 @@ impl<'a, T, L: Location<'a>, B> Singleton<T, L, B> {
     {
         self.continue_if(other.into_stream().count().filter(q!(|c| *c == 0)))
     }
+
+    /// Marks any non-determinism that flows into this singleton as audited, with the given
+    /// reason. See [`Stream::allow_nondeterminism`].
+    pub fn allow_nondeterminism(self, reason: &str) -> Singleton<T, L, B> {
+        Singleton::new(
+            self.location,
+            HydroNode::AllowNondeterminism {
+                reason: reason.to_string(),
+                input: Box::new(self.ir_node.into_inner()),
+            },
+        )
+    }
 }
 
 impl<'a, T, L: Location<'a> + NoTick, B> Singleton<T, Timestamped<L>, B> {
@@ -321,6 +334,21 @@ impl<'a, T, L: Location<'a> + NoTick, B> Singleton<T, Timestamped<L>, B> {
     /// the output singleton has a non-deterministic value since the snapshot can be at an
     /// arbitrary point in time.
     pub unsafe fn latest_tick(self) -> Singleton<T, Tick<L>, Bounded> {
+        let location_kind = self.location.tick.id();
+        Singleton::new(
+            self.location.tick,
+            HydroNode::Nondeterministic {
+                kind: NondeterminismKind::LatestTick,
+                location_kind,
+                backtrace: Backtrace::capture(),
+                input: Box::new(HydroNode::Unpersist(Box::new(self.ir_node.into_inner()))),
+            },
+        )
+    }
+
+    /// Like [`Singleton::latest_tick`], but for internal uses where the value does not
+    /// change across ticks, so this is not reported as a source of non-determinism.
+    pub(crate) unsafe fn latest_tick_unmarked(self) -> Singleton<T, Tick<L>, Bounded> {
         Singleton::new(
             self.location.tick,
             HydroNode::Unpersist(Box::new(self.ir_node.into_inner())),
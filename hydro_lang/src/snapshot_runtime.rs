@@ -0,0 +1,787 @@
+//! Runtime support for consistent distributed snapshots of a deployed flow.
+//!
+//! Snapshots are taken with the Chandy-Lamport algorithm, see
+//! [`DeployFlow::with_snapshots`](crate::builder::deploy::DeployFlow::with_snapshots). Every process and cluster member of the flow is a participant with its own
+//! [`SnapshotRuntime`]. Messages on each network edge are framed so that snapshot markers can
+//! be sent in-band, in order with the data. A snapshot proceeds as follows:
+//!
+//! 1. The initiator process periodically starts a new snapshot, which it takes at the end of the
+//!    current tick.
+//! 2. A participant that receives the first marker of a snapshot stops delivering messages from
+//!    that sender and takes its local snapshot at the end of the current tick.
+//! 3. Taking a local snapshot records all `'durable` operator state (see
+//!    [`StateBackend`]) and sends a marker on every outgoing network edge.
+//! 4. Messages received afterwards on an edge, but before the marker of that edge, were in
+//!    flight when the snapshot was taken and are recorded as part of it.
+//! 5. Once markers have arrived on all incoming edges, the participant writes its local snapshot
+//!    to `{dir}/{id}/{participant}.snap`. The last participant to do so also writes
+//!    `{dir}/{id}/manifest.json`, which marks the snapshot as complete.
+//!
+//! Restoring from a [`SnapshotManifest`] restores the operator state of every participant and
+//! re-delivers the recorded in-flight messages before any new ones.
+//!
+//! All participants must be able to read and write the snapshot directory, so deployments across
+//! several machines need a shared filesystem.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use dfir_rs::bytes::{Buf, BufMut, Bytes, BytesMut};
+use dfir_rs::futures::Stream;
+use dfir_rs::scheduled::graph::Dfir;
+use dfir_rs::scheduled::state::StateBackend;
+use dfir_rs::tokio_stream::wrappers::UnboundedReceiverStream;
+use dfir_rs::util::unbounded_channel;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc::UnboundedSender;
+
+/// Frame tag of a message carrying data.
+const TAG_DATA: u8 = 0;
+/// Frame tag of a snapshot marker, followed by the snapshot ID.
+const TAG_MARKER: u8 = 1;
+
+/// File name of the manifest of a complete snapshot.
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Configuration for periodic distributed snapshots.
+#[derive(Debug, Clone)]
+pub struct SnapshotConfig {
+    /// The directory that snapshots are written to, one subdirectory per snapshot.
+    pub dir: PathBuf,
+    /// How often the initiator starts a new snapshot. A snapshot is only started once the
+    /// previous one is complete.
+    pub interval: Duration,
+}
+
+impl SnapshotConfig {
+    pub fn new(dir: impl Into<PathBuf>, interval: Duration) -> SnapshotConfig {
+        SnapshotConfig {
+            dir: dir.into(),
+            interval,
+        }
+    }
+}
+
+/// Describes a complete snapshot, which can be restored with
+/// [`DeployFlow::restore_snapshot`](crate::builder::deploy::DeployFlow::restore_snapshot).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotManifest {
+    pub id: u64,
+    /// The names of all participants, each with a local snapshot in [`Self::dir`].
+    pub participants: Vec<String>,
+    /// The directory containing the snapshot, i.e. the directory of the manifest file.
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+impl SnapshotManifest {
+    /// Loads the manifest file at `path`.
+    pub fn load(path: impl AsRef<Path>) -> io::Result<SnapshotManifest> {
+        let path = path.as_ref();
+        let mut manifest: SnapshotManifest =
+            dfir_rs::serde_json::from_slice(&std::fs::read(path)?)?;
+        manifest.dir = path.parent().unwrap_or(Path::new("")).to_owned();
+        Ok(manifest)
+    }
+
+    /// Finds the most recent complete snapshot in the snapshot directory `dir`, if any.
+    pub fn latest(dir: impl AsRef<Path>) -> io::Result<Option<SnapshotManifest>> {
+        let dir = dir.as_ref();
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+
+        let mut latest = None;
+        for entry in entries {
+            let path = entry?.path();
+            let Some(id) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.parse::<u64>().ok())
+            else {
+                continue;
+            };
+            if latest.is_none_or(|latest| latest < id) && path.join(MANIFEST_FILE).exists() {
+                latest = Some(id);
+            }
+        }
+
+        latest
+            .map(|id| SnapshotManifest::load(snapshot_dir(dir, id).join(MANIFEST_FILE)))
+            .transpose()
+    }
+}
+
+/// The part of a snapshot taken by a single participant.
+#[derive(Serialize, Deserialize, Default, Debug)]
+struct LocalSnapshot {
+    /// The checkpointed `'durable` operator state, by key.
+    states: BTreeMap<String, Vec<u8>>,
+    /// The payloads that were in flight on each incoming `(channel, sender)` edge.
+    channels: BTreeMap<(usize, u32), Vec<Vec<u8>>>,
+}
+
+/// A local snapshot that is waiting for markers on some incoming edges.
+struct InProgress {
+    id: u64,
+    snapshot: LocalSnapshot,
+    /// The incoming edges whose marker has not arrived yet.
+    open: HashSet<(usize, u32)>,
+}
+
+struct SnapshotState {
+    dir: PathBuf,
+    participant: String,
+    participants: Vec<String>,
+    /// How often to start a new snapshot, if this participant is the initiator.
+    interval: Option<Duration>,
+    /// The local snapshot this participant was restored from.
+    restored: Option<LocalSnapshot>,
+    /// The latest operator state stored by the flow.
+    states: BTreeMap<String, Vec<u8>>,
+    /// All incoming `(channel, sender)` edges.
+    channels: BTreeSet<(usize, u32)>,
+    /// Senders of snapshot IDs, one for each outgoing edge.
+    marker_senders: Vec<UnboundedSender<u64>>,
+    /// The ID of the most recent snapshot this participant has seen.
+    last_id: Option<u64>,
+    /// The snapshot to take at the end of the current tick.
+    requested: Option<u64>,
+    /// Incoming edges whose marker for the requested snapshot has arrived. Messages from these
+    /// are held back until the local snapshot is taken.
+    marked: HashSet<(usize, u32)>,
+    in_progress: Option<InProgress>,
+    /// Wakers of sources holding back messages.
+    wakers: Vec<Waker>,
+}
+
+/// The snapshot state of a single participant, shared by its network edges and used as the
+/// [`StateBackend`] of its flow.
+pub struct SnapshotRuntime {
+    state: RefCell<SnapshotState>,
+}
+
+impl SnapshotRuntime {
+    /// Creates the runtime for `participant`, restoring from the snapshot in `restore` if given.
+    ///
+    /// Snapshots are written to `dir`. Only the initiator should be given an `interval`.
+    pub fn new(
+        dir: impl Into<PathBuf>,
+        participant: String,
+        participants: Vec<String>,
+        interval: Option<Duration>,
+        restore: Option<(u64, &Path)>,
+    ) -> io::Result<SnapshotRuntime> {
+        let (last_id, restored) = match restore {
+            Some((id, restore_dir)) => {
+                let bytes = std::fs::read(local_snapshot_path(restore_dir, &participant))?;
+                let restored = bincode::deserialize(&bytes)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+                (Some(id), Some(restored))
+            }
+            None => (None, None),
+        };
+
+        Ok(SnapshotRuntime {
+            state: RefCell::new(SnapshotState {
+                dir: dir.into(),
+                participant,
+                participants,
+                interval,
+                restored,
+                states: BTreeMap::new(),
+                channels: BTreeSet::new(),
+                marker_senders: Vec::new(),
+                last_id,
+                requested: None,
+                marked: HashSet::new(),
+                in_progress: None,
+                wakers: Vec::new(),
+            }),
+        })
+    }
+
+    /// Creates the runtime of this process (see [`Self::new`]) and installs it, so that
+    /// [`launch_flow`] uses it to checkpoint and restore the flow.
+    pub fn install(
+        dir: &str,
+        participant: String,
+        participants: Vec<String>,
+        interval: Option<Duration>,
+        restore: Option<(u64, &str)>,
+    ) -> &'static SnapshotRuntime {
+        let runtime = SnapshotRuntime::new(
+            dir,
+            participant,
+            participants,
+            interval,
+            restore.map(|(id, restore_dir)| (id, Path::new(restore_dir))),
+        )
+        .expect("Failed to load snapshot.");
+        let runtime = &*Box::leak(Box::new(runtime));
+        SnapshotRuntime::with_installed(|installed| installed.set(Some(runtime)));
+        runtime
+    }
+
+    /// The runtime installed by [`Self::install`] on this thread, if any.
+    pub fn installed() -> Option<&'static SnapshotRuntime> {
+        SnapshotRuntime::with_installed(|installed| installed.get())
+    }
+
+    fn with_installed<R>(f: impl FnOnce(&Cell<Option<&'static SnapshotRuntime>>) -> R) -> R {
+        thread_local! {
+            static INSTALLED: Cell<Option<&'static SnapshotRuntime>> = const { Cell::new(None) };
+        }
+        INSTALLED.with(f)
+    }
+
+    /// Whether the flow was restored from a snapshot.
+    pub fn is_restored(&self) -> bool {
+        self.state.borrow().restored.is_some()
+    }
+
+    /// Registers an outgoing edge, returning the IDs of the snapshots to send markers for.
+    pub fn markers(&'static self) -> UnboundedReceiverStream<u64> {
+        let (send, recv) = unbounded_channel();
+        self.state.borrow_mut().marker_senders.push(send);
+        recv
+    }
+
+    /// Registers the incoming edges of `channel`, one for each of `senders`, and wraps its
+    /// network `source` to handle markers.
+    pub fn source<S>(
+        &'static self,
+        channel: usize,
+        senders: impl IntoIterator<Item = u32>,
+        source: S,
+    ) -> SnapshotSource<S>
+    where
+        S: Stream,
+        S::Item: SnapshotItem,
+    {
+        let mut state = self.state.borrow_mut();
+        let mut replay = VecDeque::new();
+        for sender in senders {
+            state.channels.insert((channel, sender));
+            if let Some(payloads) = state
+                .restored
+                .as_mut()
+                .and_then(|restored| restored.channels.remove(&(channel, sender)))
+            {
+                replay.extend(
+                    payloads
+                        .into_iter()
+                        .map(|payload| (sender, BytesMut::from(&*payload))),
+                );
+            }
+        }
+
+        SnapshotSource {
+            runtime: self,
+            channel,
+            source,
+            replay,
+            held: VecDeque::new(),
+        }
+    }
+
+    /// A stream which starts a new snapshot every interval, for the initiator's flow.
+    pub fn initiator(&'static self) -> SnapshotInitiator {
+        let interval = self
+            .state
+            .borrow()
+            .interval
+            .expect("Only the snapshot initiator has an interval.");
+        SnapshotInitiator {
+            runtime: self,
+            period: interval,
+            interval: None,
+        }
+    }
+
+    /// Requests a new snapshot, unless the previous one is not complete yet.
+    fn initiate(&self) {
+        let mut state = self.state.borrow_mut();
+        if state.requested.is_some() || state.in_progress.is_some() {
+            return;
+        }
+        if let Some(last_id) = state.last_id {
+            if !snapshot_dir(&state.dir, last_id)
+                .join(MANIFEST_FILE)
+                .exists()
+            {
+                return;
+            }
+        }
+        let id = state.last_id.map_or(0, |id| id + 1);
+        state.last_id = Some(id);
+        state.requested = Some(id);
+    }
+
+    fn on_marker(&self, channel: usize, sender: u32, id: u64) -> io::Result<()> {
+        let mut state = self.state.borrow_mut();
+        if let Some(in_progress) = state.in_progress.as_mut() {
+            if in_progress.id == id {
+                in_progress.open.remove(&(channel, sender));
+                if in_progress.open.is_empty() {
+                    state.finish()?;
+                }
+                return Ok(());
+            }
+        }
+
+        if state.requested == Some(id) || state.last_id.is_none_or(|last_id| last_id < id) {
+            state.last_id = Some(id);
+            state.requested = Some(id);
+            state.marked.insert((channel, sender));
+        }
+        Ok(())
+    }
+
+    /// Whether messages on the edge must be held back until the local snapshot is taken.
+    fn is_held(&self, channel: usize, sender: u32) -> bool {
+        self.state.borrow().marked.contains(&(channel, sender))
+    }
+
+    fn on_data(&self, channel: usize, sender: u32, payload: &[u8]) {
+        let mut state = self.state.borrow_mut();
+        if let Some(in_progress) = state.in_progress.as_mut() {
+            if in_progress.open.contains(&(channel, sender)) {
+                in_progress
+                    .snapshot
+                    .channels
+                    .entry((channel, sender))
+                    .or_default()
+                    .push(payload.to_vec());
+            }
+        }
+    }
+}
+
+impl SnapshotState {
+    /// Takes the local snapshot `id` and sends markers on all outgoing edges.
+    fn take(&mut self, id: u64) -> io::Result<()> {
+        let marked = std::mem::take(&mut self.marked);
+        self.in_progress = Some(InProgress {
+            id,
+            snapshot: LocalSnapshot {
+                states: self.states.clone(),
+                channels: BTreeMap::new(),
+            },
+            open: self
+                .channels
+                .iter()
+                .filter(|edge| !marked.contains(edge))
+                .copied()
+                .collect(),
+        });
+        for marker_sender in self.marker_senders.iter() {
+            let _ = marker_sender.send(id);
+        }
+        for waker in self.wakers.drain(..) {
+            waker.wake();
+        }
+
+        if self.in_progress.as_ref().unwrap().open.is_empty() {
+            self.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the completed local snapshot, and the manifest if all participants are done.
+    fn finish(&mut self) -> io::Result<()> {
+        let InProgress { id, snapshot, .. } = self.in_progress.take().unwrap();
+        let dir = snapshot_dir(&self.dir, id);
+        std::fs::create_dir_all(&dir)?;
+        write_atomic(
+            &local_snapshot_path(&dir, &self.participant),
+            &self.participant,
+            &bincode::serialize(&snapshot).map_err(io::Error::other)?,
+        )?;
+
+        if self
+            .participants
+            .iter()
+            .all(|participant| local_snapshot_path(&dir, participant).exists())
+        {
+            let manifest = SnapshotManifest {
+                id,
+                participants: self.participants.clone(),
+                dir: dir.clone(),
+            };
+            write_atomic(
+                &dir.join(MANIFEST_FILE),
+                &self.participant,
+                &dfir_rs::serde_json::to_vec_pretty(&manifest)?,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl StateBackend for &'static SnapshotRuntime {
+    fn load(&mut self, key: &str) -> io::Result<Option<Vec<u8>>> {
+        let mut state = self.state.borrow_mut();
+        let value = state
+            .restored
+            .as_ref()
+            .and_then(|restored| restored.states.get(key))
+            .cloned();
+        if let Some(value) = &value {
+            state.states.insert(key.to_owned(), value.clone());
+        }
+        Ok(value)
+    }
+
+    fn store(&mut self, key: &str, value: &[u8]) -> io::Result<()> {
+        self.state
+            .borrow_mut()
+            .states
+            .insert(key.to_owned(), value.to_vec());
+        Ok(())
+    }
+
+    /// Called at the end of each tick, which is when a requested local snapshot is taken.
+    fn commit(&mut self) -> io::Result<()> {
+        let mut state = self.state.borrow_mut();
+        if let Some(id) = state.requested.take() {
+            state.take(id)?;
+        }
+        Ok(())
+    }
+}
+
+/// An item of a network source: a payload, tagged with the ID of its sender if the sender is a
+/// cluster.
+pub trait SnapshotItem: Sized {
+    /// Splits the item into its sender (`0` if untagged) and payload, or returns errors as-is.
+    fn into_frame(self) -> Result<(u32, BytesMut), Self>;
+
+    fn from_frame(sender: u32, payload: BytesMut) -> Self;
+
+    fn from_error(err: io::Error) -> Self;
+}
+
+impl SnapshotItem for Result<BytesMut, io::Error> {
+    fn into_frame(self) -> Result<(u32, BytesMut), Self> {
+        match self {
+            Ok(payload) => Ok((0, payload)),
+            Err(err) => Err(Err(err)),
+        }
+    }
+
+    fn from_frame(_sender: u32, payload: BytesMut) -> Self {
+        Ok(payload)
+    }
+
+    fn from_error(err: io::Error) -> Self {
+        Err(err)
+    }
+}
+
+impl SnapshotItem for Result<(u32, BytesMut), io::Error> {
+    fn into_frame(self) -> Result<(u32, BytesMut), Self> {
+        match self {
+            Ok(frame) => Ok(frame),
+            Err(err) => Err(Err(err)),
+        }
+    }
+
+    fn from_frame(sender: u32, payload: BytesMut) -> Self {
+        Ok((sender, payload))
+    }
+
+    fn from_error(err: io::Error) -> Self {
+        Err(err)
+    }
+}
+
+/// Wraps a network source, handling snapshot markers and recording in-flight messages. See
+/// [`SnapshotRuntime::source`].
+pub struct SnapshotSource<S> {
+    runtime: &'static SnapshotRuntime,
+    channel: usize,
+    source: S,
+    /// Recorded in-flight messages to deliver first, after restoring.
+    replay: VecDeque<(u32, BytesMut)>,
+    /// Messages received after a marker, held back until the local snapshot is taken.
+    held: VecDeque<(u32, BytesMut)>,
+}
+
+impl<S> Stream for SnapshotSource<S>
+where
+    S: Stream + Unpin,
+    S::Item: SnapshotItem,
+{
+    type Item = S::Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if let Some((sender, payload)) = this.replay.pop_front() {
+            return Poll::Ready(Some(S::Item::from_frame(sender, payload)));
+        }
+
+        if let Some(i) = this
+            .held
+            .iter()
+            .position(|(sender, _)| !this.runtime.is_held(this.channel, *sender))
+        {
+            let (sender, payload) = this.held.remove(i).unwrap();
+            this.runtime.on_data(this.channel, sender, &payload);
+            return Poll::Ready(Some(S::Item::from_frame(sender, payload)));
+        }
+
+        loop {
+            let item = match Pin::new(&mut this.source).poll_next(cx) {
+                Poll::Ready(Some(item)) => item,
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => {
+                    if !this.held.is_empty() {
+                        this.runtime
+                            .state
+                            .borrow_mut()
+                            .wakers
+                            .push(cx.waker().clone());
+                    }
+                    return Poll::Pending;
+                }
+            };
+
+            let (sender, mut frame) = match item.into_frame() {
+                Ok(frame) => frame,
+                Err(err) => return Poll::Ready(Some(err)),
+            };
+            match frame.first().copied() {
+                Some(TAG_DATA) => {
+                    frame.advance(1);
+                    if this.runtime.is_held(this.channel, sender) {
+                        this.held.push_back((sender, frame));
+                        continue;
+                    }
+                    this.runtime.on_data(this.channel, sender, &frame);
+                    return Poll::Ready(Some(S::Item::from_frame(sender, frame)));
+                }
+                Some(TAG_MARKER) if frame.len() == 9 => {
+                    frame.advance(1);
+                    let id = frame.get_u64_le();
+                    if let Err(err) = this.runtime.on_marker(this.channel, sender, id) {
+                        return Poll::Ready(Some(S::Item::from_error(err)));
+                    }
+                }
+                _ => {
+                    return Poll::Ready(Some(S::Item::from_error(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Invalid snapshot frame.",
+                    ))));
+                }
+            }
+        }
+    }
+}
+
+/// A stream which periodically starts a new snapshot. See [`SnapshotRuntime::initiator`].
+pub struct SnapshotInitiator {
+    runtime: &'static SnapshotRuntime,
+    period: Duration,
+    /// Created on first poll, as it needs to be inside a Tokio runtime.
+    interval: Option<tokio::time::Interval>,
+}
+
+impl Stream for SnapshotInitiator {
+    type Item = ();
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let period = this.period;
+        let interval = this.interval.get_or_insert_with(|| {
+            let mut interval =
+                tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+            interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+            interval
+        });
+        match interval.poll_tick(cx) {
+            Poll::Ready(_) => {
+                this.runtime.initiate();
+                Poll::Ready(Some(()))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Frames a serialized message sent on a network edge.
+pub fn data_frame(payload: Bytes) -> Bytes {
+    let mut frame = BytesMut::with_capacity(1 + payload.len());
+    frame.put_u8(TAG_DATA);
+    frame.put(payload);
+    frame.freeze()
+}
+
+/// Frames the marker of snapshot `id`.
+pub fn marker_frame(id: u64) -> Bytes {
+    let mut frame = BytesMut::with_capacity(9);
+    frame.put_u8(TAG_MARKER);
+    frame.put_u64_le(id);
+    frame.freeze()
+}
+
+/// Runs a deployed flow, like [`dfir_rs::util::deploy::launch_flow`], but checkpointing it to
+/// (and restoring it from) the snapshots of the installed [`SnapshotRuntime`].
+pub async fn launch_flow(mut flow: Dfir<'_>) {
+    let runtime = SnapshotRuntime::installed().expect("No snapshot runtime was installed.");
+    flow.restore_from(runtime)
+        .expect("Failed to restore from snapshot.");
+    dfir_rs::util::deploy::launch_flow(flow).await
+}
+
+fn snapshot_dir(dir: &Path, id: u64) -> PathBuf {
+    dir.join(id.to_string())
+}
+
+fn local_snapshot_path(snapshot_dir: &Path, participant: &str) -> PathBuf {
+    snapshot_dir.join(format!("{}.snap", participant))
+}
+
+/// Writes `contents` to a temporary file (unique to `participant`) and moves it to `path`.
+fn write_atomic(path: &Path, participant: &str, contents: &[u8]) -> io::Result<()> {
+    let tmp_path = path.with_extension(format!("{}.tmp", participant));
+    std::fs::write(&tmp_path, contents)?;
+    std::fs::rename(&tmp_path, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use dfir_rs::bytes::BytesMut;
+    use dfir_rs::futures::{FutureExt, StreamExt};
+    use dfir_rs::scheduled::state::StateBackend;
+    use dfir_rs::util::{collect_ready, unbounded_channel};
+
+    use super::{data_frame, marker_frame, SnapshotManifest, SnapshotRuntime};
+
+    type Item = Result<(u32, BytesMut), io::Error>;
+
+    fn frame(sender: u32, bytes: dfir_rs::bytes::Bytes) -> Item {
+        Ok((sender, BytesMut::from(&*bytes)))
+    }
+
+    fn payloads(items: Vec<Item>) -> Vec<(u32, Vec<u8>)> {
+        items
+            .into_iter()
+            .map(|item| {
+                let (sender, payload) = item.unwrap();
+                (sender, payload.to_vec())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn snapshot_records_channels_and_restores() {
+        let dir = tempfile::tempdir().unwrap();
+        let participants = vec!["p".to_owned()];
+
+        let runtime: &'static SnapshotRuntime = Box::leak(Box::new(
+            SnapshotRuntime::new(dir.path(), "p".to_owned(), participants.clone(), None, None)
+                .unwrap(),
+        ));
+        let (send, recv) = unbounded_channel::<Item>();
+        let mut source = runtime.source(7, [0, 1], recv);
+        let mut markers = runtime.markers();
+
+        send.send(frame(0, data_frame("a".into()))).unwrap();
+        send.send(frame(0, marker_frame(0))).unwrap();
+        send.send(frame(0, data_frame("b".into()))).unwrap();
+        send.send(frame(1, data_frame("c".into()))).unwrap();
+
+        // "b" is held back until the local snapshot is taken
+        assert_eq!(
+            vec![(0, b"a".to_vec()), (1, b"c".to_vec())],
+            payloads(collect_ready(&mut source))
+        );
+
+        let mut backend = runtime;
+        backend.store("state", b"value").unwrap();
+        backend.commit().unwrap();
+        assert_eq!(vec![0], collect_ready::<Vec<_>, _>(&mut markers));
+
+        // sender 1 has not sent its marker yet, so "d" is recorded
+        send.send(frame(1, data_frame("d".into()))).unwrap();
+        assert_eq!(
+            vec![(0, b"b".to_vec()), (1, b"d".to_vec())],
+            payloads(collect_ready(&mut source))
+        );
+        assert!(SnapshotManifest::latest(dir.path()).unwrap().is_none());
+
+        send.send(frame(1, marker_frame(0))).unwrap();
+        send.send(frame(1, data_frame("e".into()))).unwrap();
+        assert_eq!(
+            vec![(1, b"e".to_vec())],
+            payloads(collect_ready(&mut source))
+        );
+
+        let manifest = SnapshotManifest::latest(dir.path()).unwrap().unwrap();
+        assert_eq!(0, manifest.id);
+        assert_eq!(participants, manifest.participants);
+
+        let restored: &'static SnapshotRuntime = Box::leak(Box::new(
+            SnapshotRuntime::new(
+                dir.path(),
+                "p".to_owned(),
+                participants,
+                None,
+                Some((manifest.id, &manifest.dir)),
+            )
+            .unwrap(),
+        ));
+        assert!(restored.is_restored());
+        let mut backend = restored;
+        assert_eq!(Some(b"value".to_vec()), backend.load("state").unwrap());
+
+        let (_send, recv) = unbounded_channel::<Item>();
+        let mut source = restored.source(7, [0, 1], recv);
+        assert_eq!(
+            vec![(1, b"d".to_vec())],
+            payloads(collect_ready(&mut source))
+        );
+    }
+
+    #[tokio::test]
+    async fn initiator_waits_for_previous_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let participants = vec!["leader".to_owned(), "follower".to_owned()];
+
+        let runtime: &'static SnapshotRuntime = Box::leak(Box::new(
+            SnapshotRuntime::new(
+                dir.path(),
+                "leader".to_owned(),
+                participants,
+                Some(Duration::from_millis(1)),
+                None,
+            )
+            .unwrap(),
+        ));
+        let mut markers = runtime.markers();
+        let mut initiator = runtime.initiator();
+        let mut backend = runtime;
+
+        initiator.next().await.unwrap();
+        backend.commit().unwrap();
+        assert_eq!(Some(0), markers.next().await);
+
+        // the follower never completes snapshot 0, so no new snapshot is started
+        initiator.next().await.unwrap();
+        backend.commit().unwrap();
+        assert!(dir.path().join("0").join("leader.snap").exists());
+        assert!(SnapshotManifest::latest(dir.path()).unwrap().is_none());
+        assert!(markers.next().now_or_never().is_none());
+    }
+}
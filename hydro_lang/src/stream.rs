@@ -12,14 +12,19 @@ use stageleft::{q, IntoQuotedMut, QuotedWithContext};
 use syn::parse_quote;
 use tokio::time::Instant;
 
+use crate::backtrace::Backtrace;
 use crate::builder::FLOW_USED_MESSAGE;
-use crate::cycle::{CycleCollection, CycleComplete, DeferTick, ForwardRefMarker, TickCycleMarker};
-use crate::ir::{DebugInstantiate, HydroLeaf, HydroNode, TeeNode};
+use crate::cycle::{
+    CycleCollection, CycleComplete, DeferTick, ForwardRefMarker, IterationCycleMarker,
+    TickCycleMarker,
+};
+use crate::ir::{DebugInstantiate, HydroLeaf, HydroNode, NondeterminismKind, TeeNode};
 use crate::location::cluster::CLUSTER_SELF_ID;
 use crate::location::external_process::{ExternalBincodeStream, ExternalBytesPort};
 use crate::location::tick::{NoTimestamp, Timestamped};
 use crate::location::{
-    check_matching_location, CanSend, ExternalProcess, Location, LocationId, NoTick, Tick,
+    check_matching_location, CanSend, ExternalProcess, Iteration, Location, LocationId, NoTick,
+    Tick,
 };
 use crate::staging_util::get_this_crate;
 use crate::{Bounded, Cluster, ClusterId, Optional, Process, Singleton, Unbounded};
@@ -68,7 +73,7 @@ impl MinOrder<TotalOrder> for NoOrder {
 /// - `Order`: the ordering of the stream, which is either [`TotalOrder`]
 ///   or [`NoOrder`] (default is [`TotalOrder`])
 pub struct Stream<T, L, B, Order = TotalOrder> {
-    location: L,
+    pub(crate) location: L,
     pub(crate) ir_node: RefCell<HydroNode>,
 
     _phantom: PhantomData<(T, L, B, Order)>,
@@ -88,6 +93,19 @@ impl<'a, T, L: Location<'a>, B, Order> Stream<T, L, B, Order> {
     fn location_kind(&self) -> LocationId {
         self.location.id()
     }
+
+    pub(crate) fn mark_nondeterministic(self, kind: NondeterminismKind) -> Self {
+        let location_kind = self.location_kind();
+        Stream::new(
+            self.location,
+            HydroNode::Nondeterministic {
+                kind,
+                location_kind,
+                backtrace: Backtrace::capture(),
+                input: Box::new(self.ir_node.into_inner()),
+            },
+        )
+    }
 }
 
 impl<'a, T, L: Location<'a>, Order> DeferTick for Stream<T, Tick<L>, Bounded, Order> {
@@ -407,8 +425,29 @@ impl<'a, T, L: Location<'a>, B, Order> Stream<T, L, B, Order> {
     /// provided ordering guarantee will propogate into the guarantees
     /// for the rest of the program.
     pub unsafe fn assume_ordering<O>(self) -> Stream<T, L, B, O> {
+        let marked = self.mark_nondeterministic(NondeterminismKind::AssumeOrdering);
+        unsafe { marked.assume_ordering_unmarked() }
+    }
+
+    /// Like [`Stream::assume_ordering`], but for internal uses where the ordering is
+    /// guaranteed, so this is not reported as a source of non-determinism.
+    pub(crate) unsafe fn assume_ordering_unmarked<O>(self) -> Stream<T, L, B, O> {
         Stream::new(self.location, self.ir_node.into_inner())
     }
+
+    /// Marks any non-determinism that flows into this stream as audited, with the given
+    /// reason. Audited non-determinism is listed separately by
+    /// [`check_nondeterminism`](crate::rewrites::nondeterminism::check_nondeterminism)
+    /// instead of being reported as a diagnostic for downstream outputs.
+    pub fn allow_nondeterminism(self, reason: &str) -> Stream<T, L, B, Order> {
+        Stream::new(
+            self.location,
+            HydroNode::AllowNondeterminism {
+                reason: reason.to_string(),
+                input: Box::new(self.ir_node.into_inner()),
+            },
+        )
+    }
 }
 
 impl<'a, T, L: Location<'a>, B, Order> Stream<T, L, B, Order>
@@ -608,8 +647,8 @@ impl<'a, T, L: Location<'a> + NoTick + NoTimestamp> Stream<T, L, Unbounded, NoOr
             // SAFETY: Because the inputs and outputs are unordered,
             // we can interleave batches from both streams.
             self.timestamped(&tick)
-                .tick_batch()
-                .union(other.timestamped(&tick).tick_batch())
+                .tick_batch_unmarked()
+                .union(other.timestamped(&tick).tick_batch_unmarked())
                 .all_ticks()
                 .drop_timestamp()
         }
@@ -758,6 +797,12 @@ impl<'a, T, L: Location<'a> + NoTick, B, Order> Stream<T, Timestamped<L>, B, Ord
     /// # Safety
     /// The batch boundaries are non-deterministic and may change across executions.
     pub unsafe fn tick_batch(self) -> Stream<T, Tick<L>, Bounded, Order> {
+        unsafe { self.tick_batch_unmarked() }.mark_nondeterministic(NondeterminismKind::TickBatch)
+    }
+
+    /// Like [`Stream::tick_batch`], but for internal uses where the batch boundaries are
+    /// not observable, so this is not reported as a source of non-determinism.
+    pub(crate) unsafe fn tick_batch_unmarked(self) -> Stream<T, Tick<L>, Bounded, Order> {
         Stream::new(
             self.location.tick,
             HydroNode::Unpersist(Box::new(self.ir_node.into_inner())),
@@ -922,6 +967,66 @@ impl<'a, T, L: Location<'a>, Order> Stream<T, Tick<L>, Bounded, Order> {
     }
 }
 
+impl<'a, T, L: Location<'a>, Order> Stream<T, L, Bounded, Order> {
+    /// Makes this collection available in every iteration of the given iteration scope.
+    pub fn enter_iteration(
+        self,
+        iteration: &Iteration<L>,
+    ) -> Stream<T, Iteration<L>, Bounded, Order> {
+        check_matching_location(&self.location, &iteration.l);
+        Stream::new(
+            iteration.clone(),
+            HydroNode::EnterIteration {
+                first_iteration_only: false,
+                limit: iteration.limit,
+                location_kind: iteration.id(),
+                input: Box::new(self.ir_node.into_inner()),
+            },
+        )
+    }
+}
+
+impl<'a, T, L: Location<'a>, Order> Stream<T, Iteration<L>, Bounded, Order> {
+    /// Brings the elements of this collection in the final iteration out of the iteration scope.
+    pub fn exit_iteration(self) -> Stream<T, L, Bounded, Order> {
+        let location_kind = self.location.id();
+        Stream::new(
+            self.location.l,
+            HydroNode::ExitIteration {
+                location_kind,
+                input: Box::new(self.ir_node.into_inner()),
+            },
+        )
+    }
+}
+
+impl<'a, T: Clone + Eq + Hash, L: Location<'a>, Order> CycleComplete<'a, IterationCycleMarker>
+    for Stream<T, Iteration<L>, Bounded, Order>
+{
+    fn complete(self, ident: syn::Ident, expected_location: LocationId) {
+        assert_eq!(
+            self.location.id(),
+            expected_location,
+            "locations do not match"
+        );
+        let limit = self.location.limit;
+        self.location
+            .flow_state()
+            .borrow_mut()
+            .leaves
+            .as_mut()
+            .expect(FLOW_USED_MESSAGE)
+            .push(HydroLeaf::CycleSink {
+                ident,
+                location_kind: self.location_kind(),
+                input: Box::new(HydroNode::NextIteration {
+                    limit,
+                    input: Box::new(self.ir_node.into_inner()),
+                }),
+            });
+    }
+}
+
 fn serialize_bincode<T: Serialize>(is_demux: bool) -> syn::Expr {
     let root = get_this_crate();
 
@@ -985,7 +1090,7 @@ impl<'a, T, C1, B, Order> Stream<T, Cluster<'a, C1>, B, Order> {
 
         unsafe {
             // SAFETY: this is safe because we are mapping clusters 1:1
-            sent.assume_ordering()
+            sent.assume_ordering_unmarked()
         }
     }
 }
@@ -0,0 +1,670 @@
+//! Renders the Hydro IR as Mermaid or DOT graphs, with operators clustered by location.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use dfir_lang::graph::{
+    escape_dot, escape_mermaid, DOT_FONTS, MERMAID_INIT, MERMAID_LINK_STYLE, MERMAID_NODE_STYLE,
+    MERMAID_OTHER_FILL,
+};
+
+use crate::ir::{HydroLeaf, HydroNode};
+use crate::location::LocationId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VizEdgeKind {
+    /// A regular dataflow edge within a location.
+    Data,
+    /// A network channel, which crosses location boundaries.
+    Network,
+    /// A back-edge from a cycle sink to the matching cycle source.
+    Cycle,
+}
+
+/// How the location of a node is determined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placement {
+    /// The node records its output location in the IR.
+    Fixed,
+    /// The node is at the location of its first input, unless a consumer requires otherwise.
+    Inherited,
+    /// A `Persist`, which is an `all_ticks` (leaving the tick of its input) when a consumer
+    /// requires its output at the location enclosing that tick.
+    Persist,
+    /// An `Unpersist`, whose input is always outside of any tick, but which is inside a tick
+    /// when it is part of a `tick_batch`.
+    Unpersist,
+}
+
+struct VizNode {
+    label: String,
+    location: Option<LocationId>,
+    placement: Placement,
+    inputs: Vec<usize>,
+    /// Whether the location was required by a consumer, so it is no longer inherited.
+    pinned: bool,
+}
+
+struct VizEdge {
+    src: usize,
+    dst: usize,
+    kind: VizEdgeKind,
+    label: Option<String>,
+}
+
+/// A location that a consumer requires one of its inputs to be at.
+enum Requirement {
+    At(LocationId),
+    /// Outside of any tick, at the root of the location the node is currently at.
+    TopLevel,
+}
+
+#[derive(Default)]
+struct VizGraph {
+    nodes: Vec<VizNode>,
+    edges: Vec<VizEdge>,
+    requirements: Vec<(usize, Requirement)>,
+    seen_tees: HashMap<*const RefCell<HydroNode>, usize>,
+    cycle_sources: HashMap<(usize, String), usize>,
+    cycle_sinks: Vec<((usize, String), usize)>,
+}
+
+fn add_node(
+    graph: &mut VizGraph,
+    label: String,
+    location: Option<LocationId>,
+    placement: Placement,
+    inputs: Vec<usize>,
+) -> usize {
+    graph.nodes.push(VizNode {
+        label,
+        location,
+        placement,
+        inputs,
+        pinned: false,
+    });
+    graph.nodes.len() - 1
+}
+
+fn add_edge(
+    graph: &mut VizGraph,
+    src: usize,
+    dst: usize,
+    kind: VizEdgeKind,
+    label: Option<String>,
+) {
+    graph.edges.push(VizEdge {
+        src,
+        dst,
+        kind,
+        label,
+    });
+}
+
+/// Adds a node (and its inputs) to the graph, returning its id. Nodes shared through a tee are
+/// only added once, so all consumers of a tee point at the same node.
+fn visit_node(graph: &mut VizGraph, node: &HydroNode) -> usize {
+    match node {
+        HydroNode::Tee { inner } => {
+            let ptr = inner.0.as_ref() as *const RefCell<HydroNode>;
+            if let Some(id) = graph.seen_tees.get(&ptr) {
+                return *id;
+            }
+
+            let id = visit_node(graph, &inner.0.borrow());
+            graph.seen_tees.insert(ptr, id);
+            id
+        }
+
+        HydroNode::Network {
+            from_location,
+            to_location,
+            input,
+            ..
+        } => {
+            let input_id = visit_node(graph, input);
+            let id = add_node(
+                graph,
+                node.print_root(),
+                Some(to_location.clone()),
+                Placement::Fixed,
+                vec![input_id],
+            );
+            graph
+                .requirements
+                .push((input_id, Requirement::At(from_location.clone())));
+            add_edge(
+                graph,
+                input_id,
+                id,
+                VizEdgeKind::Network,
+                Some(format!("{:?} to {:?}", from_location, to_location)),
+            );
+            id
+        }
+
+        _ => {
+            let mut input_ids = vec![];
+            node.visit_inputs(|input| input_ids.push(visit_node(graph, input)));
+
+            let (location, placement) = match node {
+                HydroNode::Source { location_kind, .. }
+                | HydroNode::CycleSource { location_kind, .. }
+                | HydroNode::Nondeterministic { location_kind, .. }
+                | HydroNode::EnterIteration { location_kind, .. }
+                | HydroNode::ExitIteration { location_kind, .. } => {
+                    (Some(location_kind.clone()), Placement::Fixed)
+                }
+                HydroNode::Persist(_) => (None, Placement::Persist),
+                HydroNode::Unpersist(_) => (None, Placement::Unpersist),
+                _ => (None, Placement::Inherited),
+            };
+
+            match node {
+                // `tick_batch` and `latest_tick` mark the `Unpersist` that brings their input
+                // into the tick
+                HydroNode::Nondeterministic {
+                    location_kind,
+                    input,
+                    ..
+                } if matches!(input.as_ref(), HydroNode::Unpersist(_)) => {
+                    graph
+                        .requirements
+                        .push((input_ids[0], Requirement::At(location_kind.clone())));
+                }
+                HydroNode::Unpersist(_) => {
+                    graph
+                        .requirements
+                        .push((input_ids[0], Requirement::TopLevel));
+                }
+                _ => {}
+            }
+
+            let id = add_node(
+                graph,
+                node.print_root(),
+                location,
+                placement,
+                input_ids.clone(),
+            );
+            if let HydroNode::CycleSource {
+                ident,
+                location_kind,
+            } = node
+            {
+                graph
+                    .cycle_sources
+                    .insert((location_kind.root().raw_id(), ident.to_string()), id);
+            }
+
+            let multiple_inputs = input_ids.len() > 1;
+            for (i, input_id) in input_ids.into_iter().enumerate() {
+                let label = if multiple_inputs {
+                    Some(i.to_string())
+                } else {
+                    None
+                };
+                add_edge(graph, input_id, id, VizEdgeKind::Data, label);
+            }
+
+            id
+        }
+    }
+}
+
+/// Places `id` at `location`, along with the upstream nodes that must be at the same location.
+fn relocate(graph: &mut VizGraph, id: usize, location: &LocationId) {
+    let node = &mut graph.nodes[id];
+    if node.placement == Placement::Fixed
+        || (node.pinned && node.location.as_ref() == Some(location))
+    {
+        return;
+    }
+
+    node.location = Some(location.clone());
+    node.pinned = true;
+
+    match node.placement {
+        // the input of an `Unpersist` is placed by its own requirement
+        Placement::Fixed | Placement::Unpersist => {}
+        Placement::Persist => {
+            for input in node.inputs.clone() {
+                let all_ticks = graph.nodes[input]
+                    .location
+                    .as_ref()
+                    .is_some_and(|l| location_parent(l) == Some(location));
+                if !all_ticks {
+                    relocate(graph, input, location);
+                }
+            }
+        }
+        Placement::Inherited => {
+            for input in node.inputs.clone() {
+                relocate(graph, input, location);
+            }
+        }
+    }
+}
+
+/// Places every node at its output location.
+///
+/// Most nodes do not record their location in the IR, so their location is inherited from
+/// their first input, and then corrected by consumers that require their inputs to be at a
+/// specific location, such as networks (which consume at their source), `tick_batch` (which
+/// consumes outside the tick), and cycle sinks.
+fn resolve_locations(graph: &mut VizGraph) {
+    let requirements = std::mem::take(&mut graph.requirements);
+    loop {
+        let mut changed = false;
+
+        for id in 0..graph.nodes.len() {
+            let node = &graph.nodes[id];
+            if node.placement == Placement::Fixed || node.pinned {
+                continue;
+            }
+
+            let inherited = node
+                .inputs
+                .first()
+                .and_then(|input| graph.nodes[*input].location.clone());
+            if inherited != graph.nodes[id].location {
+                graph.nodes[id].location = inherited;
+                changed = true;
+            }
+        }
+
+        for (id, requirement) in &requirements {
+            let location = match requirement {
+                Requirement::At(location) => location.clone(),
+                Requirement::TopLevel => match &graph.nodes[*id].location {
+                    Some(location) => location.root().clone(),
+                    None => continue,
+                },
+            };
+
+            if graph.nodes[*id].location.as_ref() != Some(&location) || !graph.nodes[*id].pinned {
+                let before = graph.nodes[*id].location.clone();
+                relocate(graph, *id, &location);
+                changed |= graph.nodes[*id].location != before;
+            }
+        }
+
+        if !changed {
+            break;
+        }
+    }
+}
+
+fn build_graph(ir: &[HydroLeaf]) -> VizGraph {
+    let mut graph = VizGraph::default();
+
+    for leaf in ir {
+        let input_id = visit_node(&mut graph, leaf.input());
+        let (location, placement) = match leaf {
+            HydroLeaf::CycleSink { location_kind, .. } => {
+                graph
+                    .requirements
+                    .push((input_id, Requirement::At(location_kind.clone())));
+                (Some(location_kind.clone()), Placement::Fixed)
+            }
+            _ => (None, Placement::Inherited),
+        };
+
+        let id = add_node(
+            &mut graph,
+            leaf.print_root(),
+            location,
+            placement,
+            vec![input_id],
+        );
+        add_edge(&mut graph, input_id, id, VizEdgeKind::Data, None);
+
+        if let HydroLeaf::CycleSink {
+            ident,
+            location_kind,
+            ..
+        } = leaf
+        {
+            graph
+                .cycle_sinks
+                .push(((location_kind.root().raw_id(), ident.to_string()), id));
+        }
+    }
+
+    for (key, sink_id) in std::mem::take(&mut graph.cycle_sinks) {
+        if let Some(source_id) = graph.cycle_sources.get(&key).copied() {
+            add_edge(
+                &mut graph,
+                sink_id,
+                source_id,
+                VizEdgeKind::Cycle,
+                Some(key.1),
+            );
+        }
+    }
+
+    resolve_locations(&mut graph);
+    graph
+}
+
+fn location_key(location: &LocationId) -> String {
+    match location {
+        LocationId::Process(id) => format!("loc_process_{}", id),
+        LocationId::Cluster(id) => format!("loc_cluster_{}", id),
+        LocationId::ExternalProcess(id) => format!("loc_external_{}", id),
+        LocationId::Tick(id, parent) => format!("{}_tick_{}", location_key(parent), id),
+    }
+}
+
+fn location_parent(location: &LocationId) -> Option<&LocationId> {
+    match location {
+        LocationId::Tick(_, parent) => Some(parent),
+        _ => None,
+    }
+}
+
+/// All locations that contain a node, along with their enclosing locations, in the order
+/// they were first used.
+fn graph_locations(graph: &VizGraph) -> Vec<LocationId> {
+    let mut locations = vec![];
+    for node in &graph.nodes {
+        let mut cur = node.location.as_ref();
+        while let Some(location) = cur {
+            if !locations.contains(location) {
+                locations.push(location.clone());
+            }
+            cur = location_parent(location);
+        }
+    }
+    locations
+}
+
+fn write_mermaid_location(
+    graph: &VizGraph,
+    locations: &[LocationId],
+    location: &LocationId,
+    depth: usize,
+    write: &mut impl Write,
+) -> std::fmt::Result {
+    let pad = "    ".repeat(depth);
+    writeln!(
+        write,
+        "{}subgraph {} [\"{}\"]",
+        pad,
+        location_key(location),
+        escape_mermaid(&format!("{:?}", location))
+    )?;
+
+    for (id, node) in graph.nodes.iter().enumerate() {
+        if node.location.as_ref() == Some(location) {
+            writeln!(
+                write,
+                "{}    n{}[\"(n{}) <code>{}</code>\"]:::nodeClass",
+                pad,
+                id,
+                id,
+                escape_mermaid(&node.label)
+            )?;
+        }
+    }
+
+    for child in locations {
+        if location_parent(child) == Some(location) {
+            write_mermaid_location(graph, locations, child, depth + 1, write)?;
+        }
+    }
+
+    writeln!(write, "{}end", pad)
+}
+
+fn write_mermaid(graph: &VizGraph, write: &mut impl Write) -> std::fmt::Result {
+    writeln!(write, "{}", MERMAID_INIT)?;
+    writeln!(write, "flowchart TD")?;
+    writeln!(
+        write,
+        "classDef nodeClass fill:{},{}",
+        MERMAID_OTHER_FILL, MERMAID_NODE_STYLE
+    )?;
+    writeln!(write, "linkStyle default {}", MERMAID_LINK_STYLE)?;
+
+    let locations = graph_locations(graph);
+    for location in &locations {
+        if location_parent(location).is_none() {
+            write_mermaid_location(graph, &locations, location, 0, write)?;
+        }
+    }
+
+    for (id, node) in graph.nodes.iter().enumerate() {
+        if node.location.is_none() {
+            writeln!(
+                write,
+                "n{}[\"(n{}) <code>{}</code>\"]:::nodeClass",
+                id,
+                id,
+                escape_mermaid(&node.label)
+            )?;
+        }
+    }
+
+    for (i, edge) in graph.edges.iter().enumerate() {
+        let arrow = match edge.kind {
+            VizEdgeKind::Data => "-->",
+            VizEdgeKind::Network => "==>",
+            VizEdgeKind::Cycle => "-.->",
+        };
+        let label = edge
+            .label
+            .as_ref()
+            .map(|label| format!("|{}|", escape_mermaid(label)))
+            .unwrap_or_default();
+        write!(write, "n{}{}{}n{}", edge.src, arrow, label, edge.dst)?;
+        match edge.kind {
+            VizEdgeKind::Data => {}
+            VizEdgeKind::Network => write!(write, "; linkStyle {} stroke:#00f", i)?,
+            VizEdgeKind::Cycle => write!(write, "; linkStyle {} stroke:red", i)?,
+        }
+        writeln!(write)?;
+    }
+
+    Ok(())
+}
+
+fn write_dot_location(
+    graph: &VizGraph,
+    locations: &[LocationId],
+    location: &LocationId,
+    depth: usize,
+    write: &mut impl Write,
+) -> std::fmt::Result {
+    let pad = "    ".repeat(depth + 1);
+    writeln!(
+        write,
+        "{}subgraph \"cluster {}\" {{",
+        pad,
+        location_key(location)
+    )?;
+    writeln!(write, "{}    fillcolor=\"#dddddd\"", pad)?;
+    writeln!(write, "{}    style=filled", pad)?;
+    writeln!(
+        write,
+        "{}    label = \"{}\"",
+        pad,
+        escape_dot(&format!("{:?}", location), "\\l")
+    )?;
+
+    for (id, node) in graph.nodes.iter().enumerate() {
+        if node.location.as_ref() == Some(location) {
+            writeln!(
+                write,
+                "{}    n{} [label=\"(n{}) {}\"]",
+                pad,
+                id,
+                id,
+                escape_dot(&node.label, "\\l")
+            )?;
+        }
+    }
+
+    for child in locations {
+        if location_parent(child) == Some(location) {
+            write_dot_location(graph, locations, child, depth + 1, write)?;
+        }
+    }
+
+    writeln!(write, "{}}}", pad)
+}
+
+fn write_dot(graph: &VizGraph, write: &mut impl Write) -> std::fmt::Result {
+    writeln!(write, "digraph {{")?;
+    writeln!(
+        write,
+        "    node [fontname={}, style=filled, shape=rectangle, fillcolor=\"#ffddcc\"];",
+        DOT_FONTS
+    )?;
+    writeln!(write, "    edge [fontname={}];", DOT_FONTS)?;
+
+    let locations = graph_locations(graph);
+    for location in &locations {
+        if location_parent(location).is_none() {
+            write_dot_location(graph, &locations, location, 0, write)?;
+        }
+    }
+
+    for (id, node) in graph.nodes.iter().enumerate() {
+        if node.location.is_none() {
+            writeln!(
+                write,
+                "    n{} [label=\"(n{}) {}\"]",
+                id,
+                id,
+                escape_dot(&node.label, "\\l")
+            )?;
+        }
+    }
+
+    for edge in &graph.edges {
+        let mut properties = vec![];
+        if let Some(label) = &edge.label {
+            properties.push(format!("label=\"{}\"", escape_dot(label, "\\l")));
+        }
+        match edge.kind {
+            VizEdgeKind::Data => {}
+            VizEdgeKind::Network => {
+                properties.push("color=blue".to_string());
+                properties.push("style=bold".to_string());
+            }
+            VizEdgeKind::Cycle => {
+                properties.push("color=red".to_string());
+                properties.push("style=dashed".to_string());
+            }
+        }
+
+        write!(write, "    n{} -> n{}", edge.src, edge.dst)?;
+        if !properties.is_empty() {
+            write!(write, " [{}]", properties.join(", "))?;
+        }
+        writeln!(write)?;
+    }
+
+    writeln!(write, "}}")
+}
+
+/// Renders the IR as a Mermaid flowchart, with a subgraph for each location.
+///
+/// Network channels are drawn as thick edges between locations and cycles as dotted
+/// back-edges. Nodes shared through a tee are drawn once. Each operator is drawn at the location
+/// of its output, so `tick_batch` is drawn inside its tick and `all_ticks` outside of it.
+pub fn ir_to_mermaid(ir: &[HydroLeaf]) -> String {
+    let mut output = String::new();
+    write_mermaid(&build_graph(ir), &mut output).unwrap();
+    output
+}
+
+/// Renders the IR as a DOT graph, with a cluster for each location.
+///
+/// Uses the same layout as [`ir_to_mermaid`], with network channels drawn as bold blue edges
+/// and cycles as dashed red back-edges.
+pub fn ir_to_dot(ir: &[HydroLeaf]) -> String {
+    let mut output = String::new();
+    write_dot(&build_graph(ir), &mut output).unwrap();
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use stageleft::*;
+
+    use crate::deploy::DeployRuntime;
+    use crate::location::{Location, LocationId};
+    use crate::FlowBuilder;
+
+    #[test]
+    fn viz_locations_network_tee_cycle() {
+        let flow = FlowBuilder::new();
+        let process = flow.process::<()>();
+        let cluster = flow.cluster::<()>();
+        let tick = process.tick();
+
+        let (complete_cycle, cycle) = tick.cycle::<crate::Stream<_, _, _>>();
+        let batch = unsafe {
+            process
+                .source_iter(q!(0..10))
+                .timestamped(&tick)
+                .tick_batch()
+        };
+        let state = cycle.union(batch).filter(q!(|v| *v < 5));
+        complete_cycle.complete_next_tick(state.clone());
+
+        let all = state.all_ticks().drop_timestamp();
+        all.clone().for_each(q!(|n| println!("{}", n)));
+        all.broadcast_bincode(&cluster)
+            .for_each(q!(|n| println!("{}", n)));
+
+        let built = flow.finalize();
+        insta::assert_snapshot!(built.to_mermaid());
+        insta::assert_snapshot!(built.to_dot());
+
+        let _ = built
+            .with_default_optimize::<DeployRuntime>()
+            .compile(&RuntimeData::new("FAKE"));
+    }
+
+    #[test]
+    fn viz_tick_persist_stays_in_tick() {
+        let flow = FlowBuilder::new();
+        let process = flow.process::<()>();
+        let tick = process.tick();
+
+        unsafe {
+            process
+                .source_iter(q!(0..10))
+                .timestamped(&tick)
+                .tick_batch()
+        }
+        .persist()
+        .all_ticks()
+        .drop_timestamp()
+        .for_each(q!(|n| println!("{}", n)));
+
+        let built = flow.finalize();
+        let graph = super::build_graph(built.ir());
+        let persists = graph
+            .nodes
+            .iter()
+            .filter(|node| node.label == "Persist()")
+            .map(|node| node.location.clone().unwrap())
+            .collect::<Vec<_>>();
+
+        let tick_id = LocationId::Tick(0, Box::new(LocationId::Process(0)));
+        // the source, the tick-level persist, and `all_ticks`
+        assert_eq!(
+            persists,
+            vec![LocationId::Process(0), tick_id, LocationId::Process(0)]
+        );
+
+        let _ = built
+            .with_default_optimize::<DeployRuntime>()
+            .compile(&RuntimeData::new("FAKE"));
+    }
+}
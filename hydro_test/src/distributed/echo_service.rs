@@ -0,0 +1,140 @@
+use hydro_lang::location::external_process::ExternalService;
+use hydro_lang::*;
+
+pub struct Server {}
+
+pub fn echo_service<'a>(
+    external: &ExternalProcess<'a, ()>,
+    server: &Process<'a, Server>,
+) -> ExternalService<String, String> {
+    let (responder, requests) = external.source_external_service::<_, String, String>(server);
+    responder.respond(requests.map(q!(|(id, s)| (id, s.to_uppercase()))))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::future::join_all;
+    use hydro_deploy::Deployment;
+    use hydro_lang::deploy::HttpGateway;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn http_request(addr: std::net::SocketAddr, request: &str) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    fn post(path: &str, body: &str) -> String {
+        format!(
+            "POST {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n\r\n{}",
+            path,
+            body.len(),
+            body
+        )
+    }
+
+    #[tokio::test]
+    async fn echo_service() {
+        let mut deployment = Deployment::new();
+
+        let builder = hydro_lang::FlowBuilder::new();
+        let external = builder.external_process();
+        let server = builder.process();
+        let service = super::echo_service(&external, &server);
+
+        let nodes = builder
+            .with_default_optimize()
+            .with_process(&server, deployment.Localhost())
+            .with_external(&external, deployment.Localhost())
+            .deploy(&mut deployment);
+
+        deployment.deploy().await.unwrap();
+
+        let client = nodes
+            .connect_service(service)
+            .await
+            .with_timeout(Duration::from_secs(10))
+            .with_max_concurrency(4);
+
+        deployment.start().await.unwrap();
+
+        let words = ["alpha", "beta", "gamma", "delta", "epsilon", "zeta"];
+        let responses = join_all(words.iter().map(|w| client.call(w.to_string()))).await;
+        for (word, response) in words.iter().zip(responses) {
+            assert_eq!(response.unwrap(), word.to_uppercase());
+        }
+    }
+
+    #[tokio::test]
+    async fn echo_service_http() {
+        let mut deployment = Deployment::new();
+
+        let builder = hydro_lang::FlowBuilder::new();
+        let external = builder.external_process();
+        let server = builder.process();
+        let service = super::echo_service(&external, &server);
+
+        let nodes = builder
+            .with_default_optimize()
+            .with_process(&server, deployment.Localhost())
+            .with_external(&external, deployment.Localhost())
+            .deploy(&mut deployment);
+
+        deployment.deploy().await.unwrap();
+
+        let gateway = HttpGateway::new().route(
+            "POST",
+            "/echo",
+            nodes
+                .connect_service(service)
+                .await
+                .with_timeout(Duration::from_secs(10)),
+        );
+
+        deployment.start().await.unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let requests = async {
+            let ok = http_request(addr, &post("/echo?verbose", "\"hello\"")).await;
+            assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"), "{}", ok);
+            assert!(ok.ends_with("\r\n\r\n\"HELLO\""), "{}", ok);
+
+            let bad_body = http_request(addr, &post("/echo", "hello")).await;
+            assert!(
+                bad_body.starts_with("HTTP/1.1 400 Bad Request\r\n"),
+                "{}",
+                bad_body
+            );
+
+            let not_found = http_request(addr, &post("/missing", "\"hello\"")).await;
+            assert!(
+                not_found.starts_with("HTTP/1.1 404 Not Found\r\n"),
+                "{}",
+                not_found
+            );
+
+            let wrong_method = http_request(
+                addr,
+                "GET /echo HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+            )
+            .await;
+            assert!(
+                wrong_method.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"),
+                "{}",
+                wrong_method
+            );
+        };
+
+        tokio::select! {
+            result = gateway.serve(listener) => panic!("gateway stopped: {:?}", result),
+            _ = requests => {}
+        }
+    }
+}
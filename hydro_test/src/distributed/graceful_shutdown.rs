@@ -0,0 +1,65 @@
+use hydro_lang::*;
+
+pub struct Sender {}
+pub struct Receiver {}
+
+pub fn graceful_shutdown<'a>(sender: &Process<'a, Sender>, receiver: &Process<'a, Receiver>) {
+    sender
+        .source_iter(q!(["hello".to_string()]))
+        .send_bincode(receiver)
+        .for_each(q!(|s| println!("{}", s)));
+
+    let tick = sender.tick();
+    unsafe {
+        // SAFETY: the signal only fires once, so the batch it lands in does not matter
+        sender.shutdown_signal().timestamped(&tick).latest_tick()
+    }
+    .delta()
+    .map(q!(|_| "goodbye".to_string()))
+    .all_ticks()
+    .drop_timestamp()
+    .send_bincode(receiver)
+    .for_each(q!(|s| println!("{}", s)));
+
+    receiver.on_shutdown(q!(|| async { println!("shutdown hook ran") }));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use hydro_deploy::Deployment;
+    use hydro_lang::deploy::DeployCrateWrapper;
+
+    #[tokio::test]
+    async fn graceful_shutdown() {
+        let mut deployment = Deployment::new();
+
+        let builder = hydro_lang::FlowBuilder::new();
+        let sender = builder.process();
+        let receiver = builder.process();
+        super::graceful_shutdown(&sender, &receiver);
+
+        let nodes = builder
+            .with_process(&sender, deployment.Localhost())
+            .with_process(&receiver, deployment.Localhost())
+            .deploy(&mut deployment);
+
+        deployment.deploy().await.unwrap();
+
+        let mut receiver_stdout = nodes.get_process(&receiver).stdout().await;
+
+        deployment.start().await.unwrap();
+        assert_eq!(receiver_stdout.recv().await.unwrap(), "hello");
+
+        let report = deployment
+            .stop_graceful(Duration::from_secs(30))
+            .await
+            .unwrap();
+        assert!(report.drained);
+        assert_eq!(report.in_flight, 0);
+
+        assert_eq!(receiver_stdout.recv().await.unwrap(), "goodbye");
+        assert_eq!(receiver_stdout.recv().await.unwrap(), "shutdown hook ran");
+    }
+}
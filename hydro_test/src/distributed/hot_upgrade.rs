@@ -0,0 +1,78 @@
+use std::time::Duration;
+
+use hydro_lang::*;
+
+pub struct Sender {}
+pub struct Receiver {}
+
+pub fn hot_upgrade<'a>(
+    sender: &Process<'a, Sender>,
+    receiver: &Process<'a, Receiver>,
+    version: u32,
+) {
+    unsafe {
+        // SAFETY: only used to pace the sender
+        sender.source_interval(q!(Duration::from_millis(100)))
+    }
+    .enumerate()
+    .map(q!(|(i, _)| i))
+    .send_bincode(receiver)
+    .for_each(q!(move |n| println!("v{}: {}", version, n)));
+}
+
+#[cfg(test)]
+mod tests {
+    use hydro_deploy::Deployment;
+    use hydro_lang::deploy::DeployCrateWrapper;
+    use hydro_lang::location::Location;
+
+    fn parse(line: &str, version: u32) -> usize {
+        let prefix = format!("v{}: ", version);
+        line.strip_prefix(&prefix)
+            .unwrap_or_else(|| panic!("expected `{}` from version {}", line, version))
+            .parse()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn hot_upgrade() {
+        let mut deployment = Deployment::new();
+
+        let builder = hydro_lang::FlowBuilder::new();
+        let sender = builder.process();
+        let receiver = builder.process();
+        super::hot_upgrade(&sender, &receiver, 1);
+
+        let mut nodes = builder
+            .with_process(&sender, deployment.Localhost())
+            .with_process(&receiver, deployment.Localhost())
+            .deploy(&mut deployment);
+
+        deployment.deploy().await.unwrap();
+
+        let mut receiver_stdout = nodes.get_process(&receiver).stdout().await;
+
+        deployment.start().await.unwrap();
+        let before = parse(&receiver_stdout.recv().await.unwrap(), 1);
+
+        let builder = hydro_lang::FlowBuilder::new();
+        let sender = builder.process();
+        let receiver = builder.process();
+        super::hot_upgrade(&sender, &receiver, 2);
+
+        let diff = builder
+            .with_default_optimize()
+            .with_process(&sender, deployment.Localhost())
+            .with_process(&receiver, deployment.Localhost())
+            .upgrade(&mut nodes, &mut deployment)
+            .unwrap();
+        assert_eq!(diff.changed, vec![receiver.id()]);
+
+        deployment.upgrade().await.unwrap();
+
+        // the sender kept running, so it continues counting where it left off
+        let mut receiver_stdout = nodes.get_process(&receiver).stdout().await;
+        let after = parse(&receiver_stdout.recv().await.unwrap(), 2);
+        assert!(after > before, "{} should be after {}", after, before);
+    }
+}
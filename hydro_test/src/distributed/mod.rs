@@ -1 +1,5 @@
+pub mod echo_service;
 pub mod first_ten;
+pub mod graceful_shutdown;
+pub mod hot_upgrade;
+pub mod snapshot_counter;
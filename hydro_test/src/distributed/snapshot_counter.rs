@@ -0,0 +1,129 @@
+use hydro_lang::*;
+use location::external_process::ExternalBincodeSink;
+
+pub struct Leader {}
+pub struct Worker {}
+
+/// Broadcasts numbers from an external process to all workers, which each keep a running total
+/// and report it back to the leader.
+pub fn snapshot_counter<'a>(
+    external: &ExternalProcess<'a, ()>,
+    leader: &Process<'a, Leader>,
+    workers: &Cluster<'a, Worker>,
+) -> ExternalBincodeSink<u32> {
+    let (numbers_port, numbers) = external.source_external_bincode(leader);
+
+    let totals = numbers
+        .broadcast_bincode(workers)
+        .fold(q!(|| 0), q!(|total, n| *total += n));
+
+    unsafe {
+        // SAFETY: intentional non-determinism
+        totals.sample_eager()
+    }
+    .send_bincode(leader)
+    .for_each(q!(|(id, total)| println!("total {}: {}", id.raw_id, total)));
+
+    numbers_port
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::time::Duration;
+
+    use futures::SinkExt;
+    use hydro_deploy::Deployment;
+    use hydro_lang::builder::deploy::DeployResult;
+    use hydro_lang::deploy::{DeployCrateWrapper, HydroDeploy};
+    use hydro_lang::snapshot_runtime::{SnapshotConfig, SnapshotManifest};
+
+    /// Deploys the counter, restoring from `restore` if given, and sends it `numbers`, returning
+    /// once both workers have reported `expected_total`. The returned [`DeployResult`] must be
+    /// kept alive for the processes to keep running.
+    async fn run_counter(
+        deployment: &mut Deployment,
+        snapshot_dir: &Path,
+        restore: Option<SnapshotManifest>,
+        numbers: impl IntoIterator<Item = u32>,
+        expected_total: u32,
+    ) -> DeployResult<'static, HydroDeploy> {
+        let builder = hydro_lang::FlowBuilder::new();
+        let external = builder.external_process();
+        let leader = builder.process();
+        let workers = builder.cluster();
+        let numbers_port = super::snapshot_counter(&external, &leader, &workers);
+
+        let mut built = builder
+            .with_default_optimize()
+            .with_process(&leader, deployment.Localhost())
+            .with_cluster(&workers, vec![deployment.Localhost(); 2])
+            .with_external(&external, deployment.Localhost())
+            .with_snapshots(
+                &leader,
+                SnapshotConfig::new(snapshot_dir, Duration::from_millis(100)),
+            );
+        if let Some(manifest) = restore {
+            built = built.restore_snapshot(manifest);
+        }
+        let nodes = built.deploy(deployment);
+
+        deployment.deploy().await.unwrap();
+
+        let mut numbers_port = nodes.connect_sink_bincode(numbers_port).await;
+        let mut leader_stdout = nodes.get_process(&leader).stdout().await;
+
+        deployment.start().await.unwrap();
+
+        for n in numbers {
+            numbers_port.send(n).await.unwrap();
+        }
+
+        // workers report every intermediate total, so wait for the final ones
+        let mut reported = [false; 2];
+        while !reported.iter().all(|r| *r) {
+            let line = leader_stdout.recv().await.unwrap();
+            for (member, reported) in reported.iter_mut().enumerate() {
+                if line == format!("total {}: {}", member, expected_total) {
+                    *reported = true;
+                }
+            }
+        }
+
+        nodes
+    }
+
+    #[tokio::test]
+    async fn snapshot_counter_restore() {
+        let snapshot_dir = tempfile::tempdir().unwrap();
+
+        let mut deployment = Deployment::new();
+        let nodes = run_counter(&mut deployment, snapshot_dir.path(), None, 1..=5, 15).await;
+
+        // wait for a snapshot started after the totals were reported
+        let seen = SnapshotManifest::latest(snapshot_dir.path())
+            .unwrap()
+            .map(|manifest| manifest.id);
+        let manifest = loop {
+            if let Some(manifest) = SnapshotManifest::latest(snapshot_dir.path()).unwrap() {
+                if seen.map_or(1, |id| id + 2) <= manifest.id {
+                    break manifest;
+                }
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        };
+        assert_eq!(vec!["loc1", "loc2_0", "loc2_1"], manifest.participants);
+        drop(nodes);
+        drop(deployment);
+
+        let mut deployment = Deployment::new();
+        run_counter(
+            &mut deployment,
+            snapshot_dir.path(),
+            Some(manifest),
+            [6],
+            21,
+        )
+        .await;
+    }
+}
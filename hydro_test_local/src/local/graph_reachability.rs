@@ -44,6 +44,50 @@ pub fn graph_reachability<'a>(
     flow.compile_no_network::<SingleProcessGraph>()
 }
 
+#[stageleft::entry]
+pub fn graph_reachability_fixpoint<'a>(
+    flow: FlowBuilder<'a>,
+    roots: RuntimeData<UnboundedReceiverStream<u32>>,
+    edges: RuntimeData<UnboundedReceiverStream<(u32, u32)>>,
+    reached_out: RuntimeData<&'a UnboundedSender<u32>>,
+) -> impl Quoted<'a, Dfir<'a>> {
+    let process = flow.process::<()>();
+
+    let roots = process.source_stream(roots);
+    let edges = process.source_stream(edges);
+
+    let tick = process.tick();
+    let roots: Stream<_, _, _, NoOrder> = unsafe {
+        // SAFETY: each batch of roots is fixpointed within its tick
+        roots.timestamped(&tick).tick_batch().into()
+    };
+    let edges = unsafe {
+        // SAFETY: the edges received so far are used by each tick
+        edges.timestamped(&tick).tick_batch().persist()
+    };
+
+    let reached = tick.iterate(IterationLimit::Fixpoint, |iteration| {
+        let edges = edges.enter_iteration(iteration);
+        let (complete_reached, reached) = iteration.loop_carried(roots);
+
+        let next = reached
+            .clone()
+            .map(q!(|r| (r, ())))
+            .join(edges)
+            .map(q!(|(_from, (_, to))| to))
+            .union(reached)
+            .unique();
+        complete_reached.complete_next_iteration(next.clone());
+        next.exit_iteration()
+    });
+
+    reached.all_ticks().for_each(q!(|v| {
+        reached_out.send(v).unwrap();
+    }));
+
+    flow.compile_no_network::<SingleProcessGraph>()
+}
+
 #[stageleft::runtime]
 #[cfg(test)]
 mod tests {
@@ -77,4 +121,36 @@ mod tests {
             &[1, 2, 3, 4, 5]
         );
     }
+
+    #[test]
+    pub fn test_reachability_fixpoint() {
+        let (roots_send, roots) = dfir_rs::util::unbounded_channel();
+        let (edges_send, edges) = dfir_rs::util::unbounded_channel();
+        let (out, mut out_recv) = dfir_rs::util::unbounded_channel();
+
+        let mut reachability = super::graph_reachability_fixpoint!(roots, edges, &out);
+        assert_graphvis_snapshots!(reachability);
+
+        roots_send.send(1).unwrap();
+
+        edges_send.send((1, 2)).unwrap();
+        edges_send.send((2, 3)).unwrap();
+        edges_send.send((3, 4)).unwrap();
+        edges_send.send((5, 6)).unwrap();
+
+        // The fixpoint is reached within a single tick.
+        reachability.run_tick();
+
+        let mut reached = collect_ready::<Vec<_>, _>(&mut out_recv);
+        reached.sort();
+        assert_eq!(&*reached, &[1, 2, 3, 4]);
+
+        // Later ticks use the edges received so far.
+        roots_send.send(5).unwrap();
+        reachability.run_tick();
+
+        let mut reached = collect_ready::<Vec<_>, _>(&mut out_recv);
+        reached.sort();
+        assert_eq!(&*reached, &[5, 6]);
+    }
 }
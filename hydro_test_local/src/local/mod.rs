@@ -3,5 +3,9 @@ pub mod compute_pi;
 pub mod count_elems;
 pub mod first_ten;
 pub mod graph_reachability;
+pub mod model_check_batching;
 pub mod negation;
+pub mod nested_iteration;
+pub mod sim_echo;
+pub mod sim_two_pc;
 pub mod teed_join;
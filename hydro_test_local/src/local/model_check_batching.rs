@@ -0,0 +1,188 @@
+use dfir_rs::tokio::sync::mpsc::UnboundedSender;
+use hydro_lang::deploy::SimDeploy;
+use hydro_lang::dfir_rs::scheduled::graph::Dfir;
+use hydro_lang::sim_runtime::SimPorts;
+use hydro_lang::*;
+use stageleft::{Quoted, RuntimeData};
+
+pub struct Leader {}
+pub struct Worker {}
+
+#[stageleft::entry]
+pub fn batch_count_runtime<'a>(
+    flow: FlowBuilder<'a>,
+    ports: RuntimeData<&'a SimPorts>,
+    output: RuntimeData<&'a UnboundedSender<usize>>,
+) -> impl Quoted<'a, Dfir<'a>> {
+    let leader = flow.process::<Leader>();
+    let workers = flow.cluster::<Worker>();
+    let tick = leader.tick();
+
+    let votes = workers
+        .source_iter(q!(0..1u32))
+        .send_bincode(&leader)
+        .map(q!(|(_, v)| v));
+
+    unsafe {
+        // SAFETY: the batch boundaries are exactly what the model checker explores
+        votes.timestamped(&tick).tick_batch()
+    }
+    .count()
+    .filter(q!(|c| *c > 0))
+    .all_ticks()
+    .drop_timestamp()
+    .for_each(q!(|c| output.send(c).unwrap()));
+
+    flow.with_default_optimize::<SimDeploy>()
+        .compile(&ports)
+        .with_dynamic_id(q!(ports.subgraph_id))
+}
+
+#[stageleft::entry]
+pub fn local_batch_count_runtime<'a>(
+    flow: FlowBuilder<'a>,
+    ports: RuntimeData<&'a SimPorts>,
+    output: RuntimeData<&'a UnboundedSender<usize>>,
+) -> impl Quoted<'a, Dfir<'a>> {
+    let leader = flow.process::<Leader>();
+    let tick = leader.tick();
+
+    unsafe {
+        // SAFETY: the batch boundaries are exactly what the model checker explores
+        leader
+            .source_iter(q!(0..2u32))
+            .timestamped(&tick)
+            .tick_batch()
+    }
+    .count()
+    .filter(q!(|c| *c > 0))
+    .all_ticks()
+    .drop_timestamp()
+    .for_each(q!(|c| output.send(c).unwrap()));
+
+    flow.with_default_optimize::<SimDeploy>()
+        .compile(&ports)
+        .with_dynamic_id(q!(ports.subgraph_id))
+}
+
+#[stageleft::entry]
+pub fn interval_runtime<'a>(
+    flow: FlowBuilder<'a>,
+    ports: RuntimeData<&'a SimPorts>,
+    output: RuntimeData<&'a UnboundedSender<usize>>,
+) -> impl Quoted<'a, Dfir<'a>> {
+    let leader = flow.process::<Leader>();
+
+    unsafe {
+        // SAFETY: the timing of the samples is what the model checker explores
+        leader.source_interval(q!(std::time::Duration::from_millis(10)))
+    }
+    .for_each(q!(|_| output.send(1).unwrap()));
+
+    flow.with_default_optimize::<SimDeploy>()
+        .compile(&ports)
+        .with_dynamic_id(q!(ports.subgraph_id))
+}
+
+#[stageleft::runtime]
+#[cfg(test)]
+mod tests {
+    use hydro_lang::model_check::{ModelCheckStep, ModelChecker};
+    use hydro_lang::sim_runtime::SimAddress;
+
+    #[test]
+    fn model_check_total_is_preserved() {
+        let stats = ModelChecker::new()
+            .with_process(0)
+            .with_cluster(1, 2)
+            .invariant(|counts: &[usize]| {
+                if counts.iter().sum::<usize>() <= 2 {
+                    Ok(())
+                } else {
+                    Err(format!("counted too many votes: {:?}", counts))
+                }
+            })
+            .quiescent_invariant(|counts: &[usize]| {
+                if counts.iter().sum::<usize>() == 2 {
+                    Ok(())
+                } else {
+                    Err(format!("lost votes: {:?}", counts))
+                }
+            })
+            .check(|ports, output| super::batch_count_runtime!(ports, output))
+            .unwrap();
+
+        assert!(!stats.truncated);
+        assert!(stats.states > 1);
+    }
+
+    #[test]
+    fn model_check_finds_split_batch() {
+        let counterexample = ModelChecker::new()
+            .with_process(0)
+            .with_cluster(1, 2)
+            .invariant(|counts: &[usize]| {
+                if counts.iter().all(|c| *c == 2) {
+                    Ok(())
+                } else {
+                    Err(format!("votes were split across ticks: {:?}", counts))
+                }
+            })
+            .check(|ports, output| super::batch_count_runtime!(ports, output))
+            .unwrap_err();
+
+        assert_eq!(counterexample.outputs, vec![1]);
+        assert_eq!(counterexample.trace.len(), 3);
+        assert!(matches!(
+            counterexample.trace.last(),
+            Some(ModelCheckStep::Run(SimAddress { location: 0, .. }))
+        ));
+    }
+
+    #[test]
+    fn model_check_splits_local_batch() {
+        let counterexample = ModelChecker::new()
+            .with_process(0)
+            .invariant(|counts: &[usize]| {
+                if counts.iter().all(|c| *c == 2) {
+                    Ok(())
+                } else {
+                    Err(format!("local batch was split: {:?}", counts))
+                }
+            })
+            .check(|ports, output| super::local_batch_count_runtime!(ports, output))
+            .unwrap_err();
+
+        assert_eq!(counterexample.outputs, vec![1]);
+        assert_eq!(
+            counterexample.trace,
+            vec![
+                ModelCheckStep::Run(SimAddress::process(0)),
+                ModelCheckStep::Release {
+                    address: SimAddress::process(0),
+                    gate: 0,
+                    count: 1,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn model_check_advances_clock() {
+        let counterexample = ModelChecker::new()
+            .with_process(0)
+            .with_clock_step(std::time::Duration::from_millis(10))
+            .invariant(|samples: &[usize]| {
+                if samples.len() <= 1 {
+                    Ok(())
+                } else {
+                    Err(format!("sampled more than once: {:?}", samples))
+                }
+            })
+            .check(|ports, output| super::interval_runtime!(ports, output))
+            .unwrap_err();
+
+        assert_eq!(counterexample.outputs, vec![1, 1]);
+        assert!(counterexample.trace.contains(&ModelCheckStep::AdvanceClock));
+    }
+}
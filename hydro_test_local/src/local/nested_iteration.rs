@@ -0,0 +1,67 @@
+use dfir_rs::tokio::sync::mpsc::UnboundedSender;
+use dfir_rs::tokio_stream::wrappers::UnboundedReceiverStream;
+use hydro_lang::deploy::SingleProcessGraph;
+use hydro_lang::dfir_rs::scheduled::graph::Dfir;
+use hydro_lang::*;
+use stageleft::{Quoted, RuntimeData};
+
+// Each value received in a tick is repeatedly multiplied by four in an inner scope and
+// incremented in an outer one, using fixed numbers of iterations.
+#[stageleft::entry]
+pub fn nested_iteration<'a>(
+    flow: FlowBuilder<'a>,
+    input: RuntimeData<UnboundedReceiverStream<u32>>,
+    out: RuntimeData<&'a UnboundedSender<u32>>,
+) -> impl Quoted<'a, Dfir<'a>> {
+    let process = flow.process::<()>();
+    let tick = process.tick();
+
+    let batch = unsafe {
+        // SAFETY: each value is iterated on independently
+        process.source_stream(input).timestamped(&tick).tick_batch()
+    };
+
+    let result = tick.iterate(IterationLimit::Times(2), |outer| {
+        let (complete_value, value) = outer.loop_carried(batch);
+
+        let quadrupled = outer.iterate(IterationLimit::Times(3), |inner| {
+            let (complete_doubled, doubled) = inner.loop_carried(value);
+            complete_doubled.complete_next_iteration(doubled.clone().map(q!(|v| v * 2)));
+            doubled.exit_iteration()
+        });
+
+        complete_value.complete_next_iteration(quadrupled.clone().map(q!(|v| v + 1)));
+        quadrupled.exit_iteration()
+    });
+
+    result.all_ticks().drop_timestamp().for_each(q!(|v| {
+        out.send(v).unwrap();
+    }));
+
+    flow.compile_no_network::<SingleProcessGraph>()
+}
+
+#[stageleft::runtime]
+#[cfg(test)]
+mod tests {
+    use dfir_rs::util::collect_ready;
+
+    #[test]
+    pub fn test_nested_iteration() {
+        let (input_send, input) = dfir_rs::util::unbounded_channel();
+        let (out, mut out_recv) = dfir_rs::util::unbounded_channel();
+
+        let mut iteration = super::nested_iteration!(input, &out);
+
+        // The initial value only enters the first iteration of each scope:
+        // 1 -> 4 -> 5 -> 20
+        input_send.send(1).unwrap();
+        iteration.run_tick();
+        assert_eq!(&*collect_ready::<Vec<_>, _>(&mut out_recv), &[20]);
+
+        // Loop-carried state does not leak into the next tick: 3 -> 12 -> 13 -> 52
+        input_send.send(3).unwrap();
+        iteration.run_tick();
+        assert_eq!(&*collect_ready::<Vec<_>, _>(&mut out_recv), &[52]);
+    }
+}
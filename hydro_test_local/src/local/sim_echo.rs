@@ -0,0 +1,99 @@
+use dfir_rs::tokio::sync::mpsc::UnboundedSender;
+use hydro_lang::deploy::SimDeploy;
+use hydro_lang::dfir_rs::scheduled::graph::Dfir;
+use hydro_lang::sim_runtime::SimPorts;
+use hydro_lang::*;
+use stageleft::{Quoted, RuntimeData};
+
+pub struct Leader {}
+pub struct Worker {}
+
+#[stageleft::entry]
+pub fn sim_echo_runtime<'a>(
+    flow: FlowBuilder<'a>,
+    ports: RuntimeData<&'a SimPorts>,
+    output: RuntimeData<&'a UnboundedSender<(u32, u32)>>,
+) -> impl Quoted<'a, Dfir<'a>> {
+    let leader = flow.process::<Leader>();
+    let workers = flow.cluster::<Worker>();
+
+    leader
+        .source_iter(q!(0..3u32))
+        .broadcast_bincode(&workers)
+        .map(q!(|v| v * 10))
+        .send_bincode(&leader)
+        .for_each(q!(|(id, v)| output.send((id.raw_id, v)).unwrap()));
+
+    flow.with_default_optimize::<SimDeploy>()
+        .compile(&ports)
+        .with_dynamic_id(q!(ports.subgraph_id))
+}
+
+#[stageleft::runtime]
+#[cfg(test)]
+mod tests {
+    use dfir_rs::util::collect_ready;
+    use hydro_lang::sim_runtime::{fuzz_seeds, SimAddress, SimConfig, SimInstances};
+
+    fn run_echo(config: SimConfig) -> (Vec<(u32, u32)>, hydro_lang::sim_runtime::SimStats) {
+        let (out, mut out_recv) = dfir_rs::util::unbounded_channel();
+        let instances = SimInstances::new(config).with_process(0).with_cluster(1, 2);
+
+        let mut sim = instances.instantiate(|ports| super::sim_echo_runtime!(ports, &out));
+        assert!(sim.run_until_quiescent(100));
+
+        let stats = sim.network().stats();
+        (collect_ready::<Vec<_>, _>(&mut out_recv), stats)
+    }
+
+    #[test]
+    fn sim_echo_reliable() {
+        let (mut output, stats) = run_echo(SimConfig::new(0));
+        output.sort();
+        assert_eq!(
+            output,
+            vec![(0, 0), (0, 10), (0, 20), (1, 0), (1, 10), (1, 20)]
+        );
+        assert_eq!(stats.sent, 12);
+        assert_eq!(stats.dropped, 0);
+    }
+
+    #[test]
+    fn sim_echo_replays_seed() {
+        fuzz_seeds(0..10, |seed| {
+            let config = SimConfig::new(seed)
+                .with_delay(0, 5)
+                .with_reordering()
+                .with_drop_probability(0.2);
+
+            let first = run_echo(config.clone());
+            let second = run_echo(config);
+            assert_eq!(first, second);
+            assert!(first.0.len() <= 6);
+        });
+    }
+
+    #[test]
+    fn sim_echo_partition() {
+        let (out, mut out_recv) = dfir_rs::util::unbounded_channel();
+        let instances = SimInstances::new(SimConfig::new(0))
+            .with_process(0)
+            .with_cluster(1, 2);
+
+        let mut sim = instances.instantiate(|ports| super::sim_echo_runtime!(ports, &out));
+        sim.network()
+            .partition(SimAddress::process(0), SimAddress::cluster_member(1, 1));
+        assert!(sim.run_until_quiescent(100));
+
+        let mut output = collect_ready::<Vec<_>, _>(&mut out_recv);
+        output.sort();
+        assert_eq!(output, vec![(0, 0), (0, 10), (0, 20)]);
+        assert_eq!(sim.network().stats().dropped, 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "drop_probability must be between 0.0 and 1.0")]
+    fn sim_rejects_invalid_drop_probability() {
+        let _ = SimConfig::new(0).with_drop_probability(1.5);
+    }
+}
@@ -0,0 +1,126 @@
+use dfir_rs::tokio::sync::mpsc::UnboundedSender;
+use hydro_lang::deploy::SimDeploy;
+use hydro_lang::dfir_rs::scheduled::graph::Dfir;
+use hydro_lang::sim_runtime::SimPorts;
+use hydro_lang::*;
+use hydro_std::quorum::collect_quorum;
+use stageleft::{Quoted, RuntimeData};
+
+pub struct Client {}
+pub struct Coordinator {}
+pub struct Participants {}
+
+// Two-phase commit over three transactions, where participant 1 votes to abort
+// transaction 1. The coordinator reports each transaction it commits once every
+// participant has acknowledged it, and each `(transaction, participant)` acknowledgement
+// of an abort.
+#[stageleft::entry]
+pub fn sim_two_pc_runtime<'a>(
+    flow: FlowBuilder<'a>,
+    ports: RuntimeData<&'a SimPorts>,
+    num_participants: usize,
+    committed: RuntimeData<&'a UnboundedSender<u32>>,
+    aborted: RuntimeData<&'a UnboundedSender<(u32, u32)>>,
+) -> impl Quoted<'a, Dfir<'a>> {
+    let client = flow.process::<Client>();
+    let coordinator = flow.process::<Coordinator>();
+    let participants = flow.cluster::<Participants>();
+
+    let c_transactions = client.source_iter(q!(0..3u32)).send_bincode(&coordinator);
+
+    let c_votes = c_transactions
+        .broadcast_bincode(&participants)
+        .map(q!(move |t| (
+            t,
+            if t == 1 && CLUSTER_SELF_ID.raw_id == 1 {
+                Err(CLUSTER_SELF_ID.raw_id)
+            } else {
+                Ok(())
+            }
+        )))
+        .send_bincode(&coordinator)
+        .map(q!(|(_, vote)| vote));
+
+    let coordinator_tick = coordinator.tick();
+    let (c_all_commit, c_voted_abort) = collect_quorum(
+        c_votes.timestamped(&coordinator_tick),
+        num_participants,
+        num_participants,
+    );
+
+    c_voted_abort
+        .drop_timestamp()
+        .broadcast_bincode(&participants)
+        .send_bincode(&coordinator)
+        .for_each(q!(|(id, (t, _))| aborted.send((t, id.raw_id)).unwrap()));
+
+    let c_commit_acks = c_all_commit
+        .drop_timestamp()
+        .broadcast_bincode(&participants)
+        .send_bincode(&coordinator)
+        .map(q!(|(_, t)| (t, Ok::<(), ()>(()))));
+    let (c_acked, _) = collect_quorum(
+        c_commit_acks.timestamped(&coordinator_tick),
+        num_participants,
+        num_participants,
+    );
+    c_acked
+        .drop_timestamp()
+        .for_each(q!(|t| committed.send(t).unwrap()));
+
+    flow.with_default_optimize::<SimDeploy>()
+        .compile(&ports)
+        .with_dynamic_id(q!(ports.subgraph_id))
+}
+
+#[stageleft::runtime]
+#[cfg(test)]
+mod tests {
+    use dfir_rs::util::collect_ready;
+    use hydro_lang::sim_runtime::{fuzz_seeds, SimConfig, SimInstances, SimStats};
+
+    fn run_two_pc(config: SimConfig) -> (Vec<u32>, Vec<(u32, u32)>, SimStats) {
+        let (committed, mut committed_recv) = dfir_rs::util::unbounded_channel();
+        let (aborted, mut aborted_recv) = dfir_rs::util::unbounded_channel();
+        let instances = SimInstances::new(config)
+            .with_process(0)
+            .with_process(1)
+            .with_cluster(2, 3);
+
+        let mut sim = instances
+            .instantiate(|ports| super::sim_two_pc_runtime!(ports, 3, &committed, &aborted));
+        // the votes for an aborted transaction never reach a quorum, so the coordinator keeps
+        // re-checking them in each tick and the simulation never becomes quiescent
+        for _ in 0..200 {
+            sim.step();
+        }
+        assert_eq!(sim.network().in_flight(), 0);
+
+        let mut committed = collect_ready::<Vec<_>, _>(&mut committed_recv);
+        committed.sort();
+        let mut aborted = collect_ready::<Vec<_>, _>(&mut aborted_recv);
+        aborted.sort();
+        (committed, aborted, sim.network().stats())
+    }
+
+    #[test]
+    fn sim_two_pc_reliable() {
+        let (committed, aborted, stats) = run_two_pc(SimConfig::new(0));
+        assert_eq!(committed, vec![0, 2]);
+        assert_eq!(aborted, vec![(1, 0), (1, 1), (1, 2)]);
+        // 3 transactions, 9 prepares, 9 votes, 3 aborts + 3 acks, 6 commits + 6 acks
+        assert_eq!(stats.sent, 39);
+        assert_eq!(stats.delivered, 39);
+    }
+
+    #[test]
+    fn sim_two_pc_reordered() {
+        fuzz_seeds(0..20, |seed| {
+            let config = SimConfig::new(seed).with_delay(0, 10).with_reordering();
+            let (committed, aborted, stats) = run_two_pc(config);
+            assert_eq!(committed, vec![0, 2]);
+            assert_eq!(aborted, vec![(1, 0), (1, 1), (1, 2)]);
+            assert_eq!(stats.delivered, 39);
+        });
+    }
+}